@@ -0,0 +1,37 @@
+#include <stdint.h>
+
+/* Context of the programs executed on the request payload, registered using
+ *   POST /routes/shout/0?in-place
+ * The region pointed to by payload starts with the request payload, the
+ * program overwrites it with the response payload and returns its length. */
+typedef struct {
+    uint64_t payload;     /* ptr to the payload region */
+    uint32_t request_len; /* length of the request payload */
+    uint32_t capacity;    /* size of the payload region */
+    uint64_t path_id;     /* path id of the route */
+} bpf_payload_ctx_t;
+
+/* Requests starting with this character make the program report a response
+ * longer than the region, which the server needs to reject. */
+#define OVERFLOW_REQUEST '!'
+
+/* Echoes the request payload in upper case followed by an exclamation mark. */
+uint32_t coap_payload_in_place_test(bpf_payload_ctx_t *ctx)
+{
+    uint8_t *payload = (uint8_t *)(uintptr_t)ctx->payload;
+    uint32_t len = ctx->request_len;
+
+    if (len > 0 && payload[0] == OVERFLOW_REQUEST) {
+        return ctx->capacity + 1;
+    }
+
+    for (uint32_t i = 0; i < len; i++) {
+        if (payload[i] >= 'a' && payload[i] <= 'z') {
+            payload[i] -= 'a' - 'A';
+        }
+    }
+    if (len < ctx->capacity) {
+        payload[len++] = '!';
+    }
+    return len;
+}
@@ -0,0 +1,55 @@
+#include <stdint.h>
+#include "../helpers.h"
+
+#define COAP_OPT_FINISH_PAYLOAD (0x0001)
+
+/* Path ids under which the program is registered, e.g.:
+ *   POST /routes/temperature/0
+ *   POST /routes/humidity/1
+ * both with the same execution request (slot, jit enabled). The requests
+ * sent to /routed/temperature and /routed/humidity are then handled by
+ * this program, which responds with the name of the route it was reached
+ * through. */
+#define PATH_TEMPERATURE 0
+#define PATH_HUMIDITY 1
+
+typedef struct {
+    uint32_t hdr_p;       /* ptr to raw packet */
+    uint32_t payload_p;   /* ptr to payload    */
+    uint32_t token_p;     /* ptr to token      */
+    uint16_t payload_len; /* length of payload */
+    uint16_t options_len; /* length of options */
+} bpf_coap_pkt_t;
+
+int coap_routes_test(bpf_coap_ctx_t *gcoap, uint32_t path_id)
+{
+    bpf_coap_pkt_t *pkt = gcoap->pkt;
+
+    const char *response;
+    size_t len;
+    switch (path_id) {
+    case PATH_TEMPERATURE:
+        response = "temperature";
+        len = 11;
+        break;
+    case PATH_HUMIDITY:
+        response = "humidity";
+        len = 8;
+        break;
+    default:
+        bpf_printf("Unexpected path id: %d\n", path_id);
+        return -1;
+    }
+
+    bpf_gcoap_resp_init(gcoap, (2 << 5) | 5);
+    bpf_coap_add_format(gcoap, 0);
+    ssize_t pdu_len = bpf_coap_opt_finish(gcoap, COAP_OPT_FINISH_PAYLOAD);
+
+    uint8_t *payload = (uint8_t *)(intptr_t)(pkt->payload_p);
+    if (pkt->payload_len >= len) {
+        bpf_memcpy(payload, response, len);
+        return pdu_len + len;
+    }
+
+    return -1;
+}
@@ -0,0 +1,25 @@
+#include "../helpers.h"
+#include <stdint.h>
+
+// Key of the global storage under which the number of starts is kept.
+#define STARTS_KEY 7
+// Address outside of the memory regions that the program can access.
+#define FORBIDDEN_ADDR 0x10
+
+// Counts how many times it was started and then crashes by reading memory it
+// isn't allowed to access. When executed as a long-running program on
+// a server built with SUPERVISOR_MAX_RESTARTS=<n>, it is started n + 1 times
+// in total, which can be checked using the `/logs` endpoint.
+int test_crash_restart(void *ctx)
+{
+    (void)ctx;
+
+    uint32_t starts = 0;
+    bpf_fetch_global(STARTS_KEY, &starts);
+    starts++;
+    bpf_store_global(STARTS_KEY, starts);
+    bpf_printf("crash-restart start %d\n", starts);
+
+    volatile uint32_t *forbidden = (volatile uint32_t *)FORBIDDEN_ADDR;
+    return *forbidden;
+}
@@ -0,0 +1,12 @@
+#include "../helpers.h"
+#include <stdint.h>
+
+// Calls bpf_ztimer_now, which should be left out of the allowed helpers of
+// the execution request. The JIT then doesn't map the helper, so the program
+// needs to be rejected before it is compiled instead of faulting when the
+// call is reached, see scripts/test-jit-unmapped-helper.sh.
+uint32_t test_jit_unmapped_helper(void *ctx)
+{
+    (void)ctx;
+    return bpf_ztimer_now();
+}
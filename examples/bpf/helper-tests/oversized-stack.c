@@ -0,0 +1,18 @@
+#include <stdint.h>
+#include <bpf/bpf_helpers.h>
+
+/*
+ * Declares a stack requirement larger than the 512B stack provided by the
+ * rBPF VM, so it is rejected when verified (e.g. using /storage/verify/<slot>)
+ * instead of corrupting memory when executed.
+ */
+SEC(".stack_size") uint32_t stack_size = 1024;
+
+int oversized_stack(void *ctx)
+{
+    volatile uint8_t buffer[1024];
+    for (int i = 0; i < 1024; i++) {
+        buffer[i] = i;
+    }
+    return buffer[1023];
+}
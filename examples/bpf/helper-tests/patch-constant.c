@@ -0,0 +1,16 @@
+#include <stdint.h>
+
+// Marker value which is easy to find in the object file, the test script
+// patches it using /storage/patch/<slot>/<offset> and checks that the
+// program returns the new value.
+#define INITIAL_THRESHOLD 0x1234abcd
+
+// Volatile so that the constant is read from .rodata instead of being
+// inlined into the instructions.
+static const volatile uint32_t threshold = INITIAL_THRESHOLD;
+
+int test_patch_constant(void *ctx)
+{
+    (void)ctx;
+    return threshold;
+}
@@ -0,0 +1,13 @@
+#include <stdint.h>
+#include "../helpers.h"
+
+/*
+ * Calls a single read-only helper. It is allowed to run when only the
+ * non-mutating helpers are on its allowed list, but it is rejected by the
+ * verifier when the server is built with STRICT_SANDBOX=1, where no helper
+ * calls are allowed regardless of the list in the request.
+ */
+uint32_t sandbox_helper_call(void *ctx)
+{
+    return bpf_ztimer_now();
+}
@@ -0,0 +1,16 @@
+#include <stdint.h>
+#include "../helpers.h"
+
+#define COAP_CODE_CONTENT 69
+
+/*
+ * Calls a CoAP packet helper without having access to the packet. When it is
+ * executed as a short-lived program (e.g. using /vm/exec) with the pre-flight
+ * helper access verification, it fails the verification because the packet
+ * helpers are only available to programs executed on the CoAP packet.
+ */
+int short_lived_packet_helper(void *ctx)
+{
+    bpf_gcoap_resp_init(ctx, COAP_CODE_CONTENT);
+    return 0;
+}
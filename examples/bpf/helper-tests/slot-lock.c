@@ -0,0 +1,21 @@
+#include "../helpers.h"
+#include <stdint.h>
+
+#define PERIOD_US (500 * 1000)
+#define PERIODS 4
+
+// Prints a line when it starts and when it finishes and sleeps for 2 seconds
+// in between. Executions of the same slot are serialized, so the lines of two
+// executions must never interleave in the execution log.
+int test_slot_lock(void *ctx)
+{
+    (void)ctx;
+
+    bpf_printf("slot-lock start\n");
+    uint32_t last_wakeup = bpf_ztimer_now();
+    for (int i = 0; i < PERIODS; i++) {
+        bpf_ztimer_periodic_wakeup(&last_wakeup, PERIOD_US);
+    }
+    bpf_printf("slot-lock end\n");
+    return 0;
+}
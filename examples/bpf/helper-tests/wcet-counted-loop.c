@@ -0,0 +1,17 @@
+#include <stdint.h>
+
+#define ITERATIONS 100
+
+// Counting loop whose number of iterations is known statically, the WCET
+// estimate counts its body ITERATIONS times.
+int test_wcet_counted_loop(void *ctx)
+{
+    (void)ctx;
+    // Volatile so that the loop isn't computed at compile time.
+    volatile uint32_t sum = 0;
+#pragma nounroll
+    for (uint32_t i = 0; i < ITERATIONS; i++) {
+        sum += i;
+    }
+    return sum;
+}
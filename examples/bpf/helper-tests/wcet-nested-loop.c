@@ -0,0 +1,20 @@
+#include <stdint.h>
+
+#define ROWS 10
+#define COLUMNS 20
+
+// Nested counting loops, the WCET estimate counts the body of the inner loop
+// ROWS * COLUMNS times.
+int test_wcet_nested_loop(void *ctx)
+{
+    (void)ctx;
+    volatile uint32_t sum = 0;
+#pragma nounroll
+    for (uint32_t row = 0; row < ROWS; row++) {
+#pragma nounroll
+        for (uint32_t column = 0; column < COLUMNS; column++) {
+            sum += row * column;
+        }
+    }
+    return sum;
+}
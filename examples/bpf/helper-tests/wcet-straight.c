@@ -0,0 +1,13 @@
+#include <stdint.h>
+
+// Program without any branches, its estimated WCET is just the number of its
+// instructions multiplied by the cost of an instruction.
+int test_wcet_straight(void *ctx)
+{
+    (void)ctx;
+    volatile uint32_t a = 3;
+    volatile uint32_t b = 5;
+    uint32_t sum = a + b;
+    uint32_t product = a * b;
+    return sum * product - a;
+}
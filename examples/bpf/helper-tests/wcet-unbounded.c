@@ -0,0 +1,17 @@
+#include <stdint.h>
+
+// The number of iterations of the loop depends on the data (it computes the
+// number of steps of the Collatz sequence), so the WCET can't be estimated
+// and the program is reported as unbounded.
+int test_wcet_unbounded(void *ctx)
+{
+    (void)ctx;
+    volatile uint32_t start = 27;
+    uint32_t n = start;
+    uint32_t steps = 0;
+    while (n != 1) {
+        n = (n % 2 == 0) ? n / 2 : 3 * n + 1;
+        steps++;
+    }
+    return steps;
+}
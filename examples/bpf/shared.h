@@ -67,7 +67,6 @@ enum {
   BPF_FUNC_HD44780_CLEAR = 0x81,
   BPF_FUNC_HD44780_PRINT = 0x82,
   BPF_FUNC_HD44780_SET_CURSOR = 0x83,
-
 };
 
 /* Helper structs */
@@ -3,7 +3,16 @@ use std::{process::Command, str::FromStr, time::Duration};
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+/// VM preselected in the forms and used by the server functions when the
+/// target isn't specified, it should match the default of the server build
+/// (reported by its `/capabilities` endpoint).
+pub const DEFAULT_TARGET_VM: &str = if cfg!(feature = "femtocontainer-default") {
+    "FemtoContainer"
+} else {
+    "rBPF"
+};
 
 #[component]
 pub fn App() -> impl IntoView {
@@ -44,8 +53,10 @@ fn WeatherStation() -> impl IntoView {
 fn AdminPage() -> impl IntoView {
     view! {
         <h1>"µBPF Admin Tools"</h1>
+        <ServerVersion/>
         <DeployForm/>
         <ExecuteForm/>
+        <ReloadEnvironmentButton/>
     }
 }
 
@@ -73,7 +84,7 @@ fn NotFound() -> impl IntoView {
 fn ExecuteForm() -> impl IntoView {
     let (slot, set_slot) = create_signal(0);
     let (response, set_response) = create_signal("Loading".to_string());
-    let (target_vm, set_target_vm) = create_signal("rBPF".to_string());
+    let (target_vm, set_target_vm) = create_signal(DEFAULT_TARGET_VM.to_string());
     let (binary_layout, set_binary_layout) = create_signal("RawObjectFile".to_string());
     let (execution_model, set_execution_model) = create_signal("LongRunning".to_string());
     let (use_jit, set_use_jit) = create_signal(false);
@@ -114,11 +125,13 @@ fn ExecuteForm() -> impl IntoView {
             <text>"< Binary format"</text>
         </div>
         <div>
+            // The JIT only supports rBPF, see the /capabilities endpoint.
             <input
                 type="checkbox"
                 on:input=move |_| { set_use_jit(!use_jit.get()) }
 
                 prop:checked=use_jit
+                prop:disabled=move || target_vm.get() != "rBPF"
             />
             <text>"Use JIT"</text>
         </div>
@@ -148,7 +161,7 @@ fn ExecuteForm() -> impl IntoView {
                     binary_layout.get(),
                     slot.get() as usize,
                     execution_model.get(),
-                    use_jit.get(),
+                    use_jit.get() && target_vm.get() == "rBPF",
                     jit_compile.get(),
                     benchmark.get(),
                 ));
@@ -303,15 +316,13 @@ fn ApplicationStart() -> impl IntoView {
 fn DeployForm() -> impl IntoView {
     let (name, set_name) = create_signal("display-update-thread.c".to_string());
     let (slot, set_slot) = create_signal(0);
-    let (target_vm, set_target_vm) = create_signal("rBPF".to_string());
+    let (target_vm, set_target_vm) = create_signal(DEFAULT_TARGET_VM.to_string());
     let (binary_layout, set_binary_layout) = create_signal("RawObjectFile".to_string());
 
 
     let send_deploy_request = create_action(|input: &(String, String, String, usize)| {
         let (source_file, target_vm, binary_layout, storage_slot) = input.to_owned();
-        async move {
-            let _ = deploy(source_file, target_vm, binary_layout, storage_slot).await;
-        }
+        async move { deploy(source_file, target_vm, binary_layout, storage_slot).await }
     });
 
     view! {
@@ -353,6 +364,63 @@ fn DeployForm() -> impl IntoView {
             send_deploy_request
                 .dispatch((name.get(), target_vm.get(), binary_layout.get(), slot.get() as usize));
         }>"Deploy"</button>
+        <text>
+            {move || match send_deploy_request.value().get() {
+                Some(Ok(result)) => format!(" Deployed: {}", result),
+                Some(Err(e)) => format!(" Deploy failed: {}", e),
+                None => "".to_string(),
+            }}
+        </text>
+    }
+}
+
+#[component]
+fn ReloadEnvironmentButton() -> impl IntoView {
+    let (admin_token, set_admin_token) = create_signal("".to_string());
+    let reload = create_action(|token: &String| {
+        let token = token.to_owned();
+        async move { reload_environment(token).await }
+    });
+
+    view! {
+        <div>
+            <input
+                type="password"
+                on:input=move |ev| {
+                    set_admin_token(event_target_value(&ev));
+                }
+                prop:value=admin_token
+            />
+            <text>"< Admin token"</text>
+        </div>
+        <div>
+            <button on:click=move |_| {
+                reload.dispatch(admin_token.get());
+            }>"Reload environment"</button>
+            <text>
+                {move || match reload.value().get() {
+                    Some(Ok(_)) => " Reloaded".to_string(),
+                    Some(Err(e)) => format!(" Reload failed: {}", e),
+                    None => "".to_string(),
+                }}
+            </text>
+        </div>
+    }
+}
+
+/// Shows which build of the server the tools are connected to.
+#[component]
+fn ServerVersion() -> impl IntoView {
+    let version = create_resource(|| (), |_| async move { get_server_version().await });
+
+    view! {
+        <p>
+            {move || match version.get() {
+                Some(Ok(version)) => format!("Server version: {}", version),
+                Some(Err(e)) => format!("Server version unavailable: {}", e),
+                None => "".to_string(),
+            }}
+        </p>
     }
 }
 
@@ -387,14 +455,32 @@ pub fn BinaryLayoutSelector(binary_layout: ReadSignal<String>, set_binary_layout
 
 #[component]
 pub fn ExecutionModelSelector(execution_model: ReadSignal<String>, set_execution_model: WriteSignal<String>) -> impl IntoView {
+    // The options are rendered from the execution models reported by the
+    // server so that the form stays in sync with its capabilities.
+    let execution_models = create_resource(|| (), |_| async move {
+        get_execution_models().await.unwrap_or_default()
+    });
+
     view! {
         <select on:change=move |ev| {
             let new_value = event_target_value(&ev);
             set_execution_model(new_value);
         }>
-            <SelectOption value=execution_model is="ShortLived"/>
-            <SelectOption value=execution_model is="WithAccessToCoapPacket"/>
-            <SelectOption value=execution_model is="LongRunning"/>
+            {move || {
+                execution_models
+                    .get()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, description)| {
+                        let is = name.clone();
+                        view! {
+                            <option value=name.clone() title=description selected=move || execution_model() == is>
+                                {name}
+                            </option>
+                        }
+                    })
+                    .collect_view()
+            }}
         </select>
     }
 }
@@ -409,23 +495,90 @@ pub fn SelectOption(is: &'static str, value: ReadSignal<String>) -> impl IntoVie
 }
 
 
+/// Summary of a successful deployment, it is relayed from the result of
+/// `micro_bpf_tools::deploy` so that the deploy form can confirm what exactly
+/// was deployed. Failed deployments are reported as errors instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployResult {
+    pub slot: usize,
+    /// Size of the binary that was pulled by the device.
+    pub bytes_flashed: usize,
+    pub layout: String,
+    /// Helpers that the program calls, as found when processing the binary.
+    pub helpers_detected: Vec<String>,
+}
+
+impl std::fmt::Display for DeployResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [B] ({}) in slot {}, helpers: [{}]",
+            self.bytes_flashed,
+            self.layout,
+            self.slot,
+            self.helpers_detected.join(", ")
+        )
+    }
+}
+
+/// Parses the target VM sent by the client, an empty value selects
+/// [`DEFAULT_TARGET_VM`].
+#[cfg(feature = "ssr")]
+fn parse_target_vm(target_vm: &str) -> Result<micro_bpf_common::TargetVM, ServerFnError> {
+    let target_vm = if target_vm.is_empty() { DEFAULT_TARGET_VM } else { target_vm };
+    micro_bpf_common::TargetVM::from_str(target_vm)
+        .map_err(|_| ServerFnError::new(format!("Invalid target VM: {}", target_vm)))
+}
+
+/// Parses the binary layout sent by the client. The selectors emit the same
+/// strings that `BinaryFileLayout::from_str` accepts, a mismatch is reported
+/// to the client instead of panicking the server function.
+#[cfg(feature = "ssr")]
+fn parse_binary_layout(
+    binary_layout: &str,
+) -> Result<micro_bpf_common::BinaryFileLayout, ServerFnError> {
+    micro_bpf_common::BinaryFileLayout::from_str(binary_layout)
+        .map_err(|_| ServerFnError::new(format!("Invalid binary layout: {}", binary_layout)))
+}
+
+/// Names and descriptions of the execution models known to the tools, the
+/// names match the ones accepted by `ExecutionModel::from_str`.
+#[cfg(feature = "ssr")]
+const EXECUTION_MODELS: &[(&str, &str)] = &[
+    ("ShortLived", "Runs to completion on a worker thread and returns the result"),
+    ("WithAccessToCoapPacket", "Runs in the CoAP handler and writes the response"),
+    ("LongRunning", "Runs on a dedicated worker until it is stopped"),
+];
+
+/// Parses the execution model sent by the client, the options of the selector
+/// are the names reported by [`get_execution_models`].
+#[cfg(feature = "ssr")]
+fn parse_execution_model(
+    execution_model: &str,
+) -> Result<micro_bpf_common::ExecutionModel, ServerFnError> {
+    micro_bpf_common::ExecutionModel::from_str(execution_model)
+        .map_err(|_| ServerFnError::new(format!("Invalid execution model: {}", execution_model)))
+}
+
 #[server(DeployRequest, "/deploy")]
-pub async fn deploy(source_file: String, target_vm: String, binary_layout: String, storage_slot: usize) -> Result<(), ServerFnError> {
-    use micro_bpf_common::{BinaryFileLayout, TargetVM};
+pub async fn deploy(source_file: String, target_vm: String, binary_layout: String, storage_slot: usize) -> Result<DeployResult, ServerFnError> {
     use micro_bpf_common::*;
     use micro_bpf_tools::*;
-    let environment: Environment = load_env();
+    let environment = crate::environment::get();
 
     println!("Env: {:?}", environment);
     println!("Source file: {}", source_file);
     println!("Target VM: {}", target_vm);
     println!("Binary file layout: {}", binary_layout);
     println!("Storage slot: {}", storage_slot);
+    let source_path = format!("{}/{}", &environment.src_dir, source_file);
+    let target_vm = parse_target_vm(&target_vm)?;
+    let binary_layout = parse_binary_layout(&binary_layout)?;
     let deploy_response = deploy(
-        &format!("{}/{}", &environment.src_dir, source_file),
+        &source_path,
         &environment.out_dir,
-        TargetVM::from_str(&target_vm).unwrap(),
-        BinaryFileLayout::from_str(&binary_layout).unwrap(),
+        target_vm,
+        binary_layout,
         &environment.coap_root_dir,
         storage_slot,
         &environment.riot_instance_net_if,
@@ -441,30 +594,131 @@ pub async fn deploy(source_file: String, target_vm: String, binary_layout: Strin
     )
     .await;
 
-    Ok(deploy_response.unwrap())
+    let result = deploy_response.map_err(|e| ServerFnError::new(e))?;
+    Ok(crate::app::DeployResult {
+        slot: result.slot,
+        bytes_flashed: result.bytes_flashed,
+        layout: format!("{:?}", result.layout),
+        helpers_detected: result.helpers_detected.iter().map(|h| format!("{:?}", h)).collect(),
+    })
+}
+
+/// Reloads the cached environment configuration without restarting the server.
+/// Only the administrators can reload it (see `environment::is_admin`),
+/// the configuration itself isn't sent back as it contains the device addresses.
+#[server(ReloadEnvironment, "/admin/reload-env")]
+pub async fn reload_environment(admin_token: String) -> Result<bool, ServerFnError> {
+    if !crate::environment::is_admin(&admin_token) {
+        return Err(ServerFnError::new("Not authorized to reload the environment"));
+    }
+    crate::environment::reload();
+    Ok(true)
 }
 
 #[server(RunningVMsRequest, "/get_running_vms")]
 pub async fn get_running_vms() -> Result<[bool; 4], ServerFnError> {
-    use micro_bpf_tools::*;
-    let environment: Environment = load_env();
+    let environment = crate::environment::get();
+
+    let base_url = crate::environment::coap_url(&environment, crate::coap_paths::RUNNING_VM);
+
+    let running_vms = crate::retry::with_default_backoff(|| async {
+        let output = Command::new("aiocoap-client")
+            .arg("-m")
+            .arg("GET")
+            .arg(base_url.clone())
+            .output()
+            .map_err(|e| format!("Failed to run aiocoap-client: {}", e))?;
+        let response = String::from_utf8(output.stdout)
+            .map_err(|e| format!("Invalid response: {}", e))?;
+        serde_json::from_str::<[bool; 4]>(&response)
+            .map_err(|e| format!("Unable to parse response '{}': {}", response, e))
+    })
+    .await;
+
+    running_vms.map_err(|e| ServerFnError::new(e))
+}
 
-    let base_url = format!("coap://[{}%{}]/running_vm", environment.riot_instance_ip, environment.host_net_if);
+/// Returns the git commit, build time and ABI version reported by the
+/// `/version` endpoint of the server, formatted for display.
+#[server(ServerVersionRequest, "/get_server_version")]
+pub async fn get_server_version() -> Result<String, ServerFnError> {
+    let environment = crate::environment::get();
+
+    let base_url = crate::environment::coap_url(&environment, crate::coap_paths::VERSION);
+
+    let version = crate::retry::with_default_backoff(|| async {
+        let output = Command::new("aiocoap-client")
+            .arg("-m")
+            .arg("GET")
+            .arg(base_url.clone())
+            .output()
+            .map_err(|e| format!("Failed to run aiocoap-client: {}", e))?;
+        let response = String::from_utf8(output.stdout)
+            .map_err(|e| format!("Invalid response: {}", e))?;
+        let version = serde_json::from_str::<serde_json::Value>(response.trim_matches('\0'))
+            .map_err(|e| format!("Unable to parse response '{}': {}", response, e))?;
+        Ok::<_, String>(format!(
+            "{} (built at {}, ABI v{}, VMs: {})",
+            version["git"].as_str().unwrap_or("unknown"),
+            version["built"],
+            version["abi"],
+            version["vm_targets"]
+        ))
+    })
+    .await;
 
-    let response = String::from_utf8(Command::new("aiocoap-client")
-        .arg("-m")
-        .arg("GET")
-        .arg(base_url.clone())
-        .output().unwrap().stdout).unwrap();
+    version.map_err(|e| ServerFnError::new(e))
+}
+
+/// Returns the names and descriptions of the execution models supported by
+/// the server. If the server can't be reached, all models known to the
+/// tools are returned instead.
+#[server(ExecutionModelsRequest, "/get_execution_models")]
+pub async fn get_execution_models() -> Result<Vec<(String, String)>, ServerFnError> {
+    let environment = crate::environment::get();
+
+    let base_url = crate::environment::coap_url(&environment, crate::coap_paths::CAPABILITIES);
+
+    let supported = crate::retry::with_default_backoff(|| async {
+        let output = Command::new("aiocoap-client")
+            .arg("-m")
+            .arg("GET")
+            .arg(base_url.clone())
+            .output()
+            .map_err(|e| format!("Failed to run aiocoap-client: {}", e))?;
+        let response = String::from_utf8(output.stdout)
+            .map_err(|e| format!("Invalid response: {}", e))?;
+        let capabilities = serde_json::from_str::<serde_json::Value>(response.trim_matches('\0'))
+            .map_err(|e| format!("Unable to parse response '{}': {}", response, e))?;
+        capabilities["execution_models"]
+            .as_array()
+            .map(|models| {
+                models
+                    .iter()
+                    .filter_map(|m| {
+                        EXECUTION_MODELS.iter().find(|(name, _)| Some(*name) == m.as_str())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .ok_or_else(|| format!("No execution models in response '{}'", response))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        println!("Falling back to all execution models: {}", e);
+        EXECUTION_MODELS.iter().collect()
+    });
 
-    Ok(serde_json::from_str(&response).unwrap())
+    Ok(supported
+        .into_iter()
+        .map(|(name, description)| (name.to_string(), description.to_string()))
+        .collect())
 }
 
 #[server(ExecuteRequest, "/execute")]
 pub async fn execute(target_vm: String, binary_layout: String, storage_slot: usize, execution_model: String, use_jit: bool, jit_compile: bool, benchmark: bool) -> Result<String, ServerFnError> {
     use micro_bpf_common::*;
     use micro_bpf_tools::*;
-    let environment: Environment = load_env();
+    let environment = crate::environment::get();
 
     println!("Env: {:?}", environment);
     println!("Target VM: {}", target_vm);
@@ -475,31 +729,35 @@ pub async fn execute(target_vm: String, binary_layout: String, storage_slot: usi
     println!("JIT recompile: {}", jit_compile);
     println!("Benchmark: {}", benchmark);
 
+    let target_vm = parse_target_vm(&target_vm)?;
+    let binary_layout = parse_binary_layout(&binary_layout)?;
+    let execution_model = parse_execution_model(&execution_model)?;
+    let allowed_helpers = vec![];
     let execution_response = execute(
         &environment.riot_instance_ip,
-        TargetVM::from_str(&target_vm).unwrap(),
-        BinaryFileLayout::from_str(&binary_layout).unwrap(),
+        target_vm,
+        binary_layout,
         storage_slot,
         &environment.host_net_if,
-        ExecutionModel::from_str(&execution_model).unwrap(),
+        execution_model,
         HelperAccessVerification::PreFlight,
         HelperAccessListSource::ExecuteRequest,
-        &vec![],
+        &allowed_helpers,
         use_jit,
         jit_compile,
         benchmark
     )
     .await;
-    Ok(execution_response.unwrap())
+    execution_response.map_err(|e| ServerFnError::new(e))
 }
 
 #[server(BootstrapApplication, "/weather-station-deploy")]
 pub async fn bootstrap_application() -> Result<String, ServerFnError> {
     use micro_bpf_common::*;
     use micro_bpf_tools::*;
-    let environment: Environment = load_env();
+    let environment = crate::environment::get();
 
-    let target_vm = TargetVM::Rbpf;
+    let target_vm = parse_target_vm(DEFAULT_TARGET_VM)?;
     let binary_layout = BinaryFileLayout::RawObjectFile;
 
     let application_source = vec![
@@ -507,8 +765,9 @@ pub async fn bootstrap_application() -> Result<String, ServerFnError> {
     ];
 
     for (i, file) in application_source.iter().enumerate() {
+        let source_path = format!("{}/{}", &environment.src_dir, file);
         let deploy_response = deploy(
-            &format!("{}/{}", &environment.src_dir, file),
+            &source_path,
             &environment.out_dir,
             target_vm,
             binary_layout,
@@ -551,6 +810,7 @@ pub async fn bootstrap_application() -> Result<String, ServerFnError> {
     // Now we send execution requests (only the long-running programs are started)
     for (i, file) in application_source.iter().take(3).enumerate() {
         println!("Executing program: {}...", file);
+        let allowed_helpers = vec![];
         let execution_response = execute(
             &environment.riot_instance_ip,
             target_vm,
@@ -560,7 +820,7 @@ pub async fn bootstrap_application() -> Result<String, ServerFnError> {
             ExecutionModel::LongRunning,
             HelperAccessVerification::PreFlight,
             HelperAccessListSource::ExecuteRequest,
-            &vec![],
+            &allowed_helpers,
             false,
             false,
             false
@@ -0,0 +1,59 @@
+//! Caches the environment configuration used by the server functions.
+//!
+//! Reading the environment on every request is wasteful, so it is loaded once
+//! and shared between all requests. Each request takes a snapshot (an `Arc`)
+//! of the current configuration so that a concurrent reload doesn't change
+//! the configuration halfway through processing a request.
+//!
+//! Reloading the configuration is restricted to the administrators, who need
+//! to provide the token set in the `ADMIN_TOKEN` environment variable of the
+//! web server. If it isn't set, the configuration can't be reloaded.
+
+use std::{
+    ffi::CStr,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use micro_bpf_tools::{load_env, Environment};
+
+/// Environment variable holding the token required by the admin-only server
+/// functions.
+const ADMIN_TOKEN_VAR: &str = "ADMIN_TOKEN";
+
+static ENVIRONMENT: OnceLock<RwLock<Arc<Environment>>> = OnceLock::new();
+
+fn cell() -> &'static RwLock<Arc<Environment>> {
+    ENVIRONMENT.get_or_init(|| RwLock::new(Arc::new(load_env())))
+}
+
+/// Returns a snapshot of the current environment configuration, it is loaded
+/// on first use.
+pub fn get() -> Arc<Environment> {
+    cell().read().unwrap().clone()
+}
+
+/// Returns the URL of the CoAP resource of the server under `path` (see
+/// [`crate::coap_paths`]).
+pub fn coap_url(environment: &Environment, path: &CStr) -> String {
+    format!(
+        "coap://[{}%{}]{}",
+        environment.riot_instance_ip,
+        environment.host_net_if,
+        path.to_str().unwrap()
+    )
+}
+
+/// Re-reads the environment configuration and replaces the cached one.
+/// Requests that are already being processed keep using their old snapshot.
+pub fn reload() -> Arc<Environment> {
+    let environment = Arc::new(load_env());
+    *cell().write().unwrap() = environment.clone();
+    environment
+}
+
+/// Checks the token sent with a request to an admin-only server function.
+/// The token is read from the process environment rather than from the
+/// cached configuration so that it can't be changed by a reload.
+pub fn is_admin(token: &str) -> bool {
+    std::env::var(ADMIN_TOKEN_VAR).is_ok_and(|expected| !expected.is_empty() && expected == token)
+}
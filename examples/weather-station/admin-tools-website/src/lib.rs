@@ -1,4 +1,13 @@
 pub mod app;
+/// Paths of the CoAP resources exposed by the server, shared with the server
+/// so that the two can't diverge.
+#[cfg(feature = "ssr")]
+#[path = "../../../../micro-bpf-server/src/coap_server/paths.rs"]
+pub mod coap_paths;
+#[cfg(feature = "ssr")]
+pub mod environment;
+#[cfg(feature = "ssr")]
+pub mod retry;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -0,0 +1,60 @@
+//! Retrying of the CoAP requests sent to the RIOT instance.
+//!
+//! The link to the constrained device is lossy (e.g. 6LoWPAN), so a single
+//! lost packet shouldn't cause the whole admin UI action to fail. Requests
+//! are instead retried a few times with an exponentially growing delay.
+//!
+//! Only the idempotent GET requests (e.g. `/running_vm` or `/version`) are
+//! retried. Deployment and execution requests change the state of the device
+//! and a request whose response got lost may have already been processed, so
+//! retrying them could e.g. start a long-running program twice.
+
+use std::{fmt::Debug, future::Future, time::Duration};
+
+/// Number of attempts made by the server functions before giving up.
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+/// Delay before the first retry, it is doubled after each failed attempt.
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(250);
+
+/// Calls `operation` until it succeeds, at most `attempts` times. After the
+/// n-th failed attempt it waits for `base_delay * 2^(n-1)` before trying again.
+/// If all attempts fail, the error returned by the last one is returned.
+pub async fn with_backoff<T, E, F, Fut>(
+    attempts: u32,
+    base_delay: Duration,
+    mut operation: F,
+) -> Result<T, E>
+where
+    E: Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delay = base_delay;
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Ok(result) => return Ok(result),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => {
+                println!(
+                    "Attempt {}/{} failed: {:?}, retrying in {:?}",
+                    attempt, attempts, e, delay
+                );
+                actix_web::rt::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Convenience wrapper around [`with_backoff`] which uses the default
+/// number of attempts and base delay.
+pub async fn with_default_backoff<T, E, F, Fut>(operation: F) -> Result<T, E>
+where
+    E: Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    with_backoff(DEFAULT_ATTEMPTS, DEFAULT_BASE_DELAY, operation).await
+}
@@ -0,0 +1,38 @@
+//! Embeds the information identifying the exact firmware build, it is reported
+//! by the `/version` endpoint.
+
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let dirty = Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .output()
+        .map_or(false, |output| output.status.success() && !output.stdout.is_empty());
+
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    println!(
+        "cargo:rustc-env=MICRO_BPF_GIT_HASH={}{}",
+        git_hash,
+        if dirty { "-dirty" } else { "" }
+    );
+    println!("cargo:rustc-env=MICRO_BPF_BUILD_TIMESTAMP={}", build_timestamp);
+    // Rerun whenever a new commit is checked out.
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+}
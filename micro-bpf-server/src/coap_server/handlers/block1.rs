@@ -0,0 +1,265 @@
+//! Reassembly of request payloads sent using CoAP block-wise transfers
+//! (RFC 7959). Payloads which don't fit into a single CoAP packet (e.g. inline
+//! programs sent to `/run`) are split by the client into several requests
+//! carrying the Block1 option. The server acknowledges each of them with
+//! 2.31 Continue and the request is processed once the last block arrives.
+
+use alloc::{borrow::Cow, format, vec::Vec};
+use core::convert::TryInto;
+
+use coap_message::{MessageOption, MutableWritableMessage, ReadableMessage};
+
+use super::util::HandlerError;
+
+/// Largest block size exponent, blocks have 2^(szx + 4) bytes. The value 7 is
+/// reserved by RFC 7959.
+const MAX_SZX: u8 = 6;
+
+/// Value of the Block1 option of a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockOption {
+    /// Sequence number of the block within the payload.
+    pub num: u32,
+    /// Set if more blocks follow.
+    pub more: bool,
+    /// Exponent of the block size.
+    pub szx: u8,
+}
+
+impl BlockOption {
+    /// Decodes the option value, which is an unsigned integer of at most 3
+    /// bytes.
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        if value.len() > 3 {
+            return None;
+        }
+        let value = value.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+        let szx = (value & 0x7) as u8;
+        if szx > MAX_SZX {
+            return None;
+        }
+        Some(Self {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx,
+        })
+    }
+
+    /// Encodes the option value using the minimal number of bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let value = (self.num << 4) | ((self.more as u32) << 3) | self.szx as u32;
+        let bytes = value.to_be_bytes();
+        let leading_zeros = bytes.iter().take_while(|b| **b == 0).count();
+        bytes[leading_zeros..].to_vec()
+    }
+
+    pub fn size(&self) -> usize {
+        1 << (self.szx + 4)
+    }
+
+    /// Offset of the first byte of the block within the payload.
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+}
+
+/// Returns the Block1 option of the request, `None` if the payload was sent
+/// in a single request.
+pub fn block1_option(request: &impl ReadableMessage) -> Result<Option<BlockOption>, HandlerError> {
+    let Some(option) = request
+        .options()
+        .find(|o| o.number() == coap_numbers::option::BLOCK1)
+    else {
+        return Ok(None);
+    };
+    BlockOption::decode(option.value())
+        .map(Some)
+        .ok_or_else(|| HandlerError::bad_request("Invalid Block1 option".into()))
+}
+
+/// Adds the Block1 option acknowledging the block to the response. It needs
+/// to be called after setting the code and before setting the payload.
+pub fn set_block1_option(response: &mut impl MutableWritableMessage, block: Option<BlockOption>) {
+    if let Some(block) = block {
+        let number = coap_numbers::option::BLOCK1
+            .try_into()
+            .map_err(|_| ())
+            .unwrap();
+        response.add_option(number, &block.encode());
+    }
+}
+
+/// Outcome of receiving a request which may carry a block of the payload.
+pub enum Block1Status<'a> {
+    /// The whole payload was received.
+    Complete(Cow<'a, [u8]>),
+    /// More blocks are expected, the server responds with 2.31 Continue.
+    Incomplete,
+}
+
+/// Accumulates the blocks of a payload until its last block arrives. The
+/// blocks are expected in order, a retransmitted block which was already
+/// received is acknowledged again without being stored. The buffer is only
+/// allocated while a transfer is in progress.
+pub struct Block1Assembler {
+    buffer: Vec<u8>,
+    capacity: usize,
+}
+
+impl Block1Assembler {
+    /// Creates an assembler which rejects payloads longer than `capacity`.
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Processes the payload of a request, which is returned right away if it
+    /// doesn't carry the Block1 option. An error aborts the transfer in
+    /// progress.
+    pub fn receive<'a>(
+        &mut self,
+        block: Option<BlockOption>,
+        payload: &'a [u8],
+    ) -> Result<Block1Status<'a>, HandlerError> {
+        let result = self.store(block, payload);
+        if !matches!(result, Ok(Block1Status::Incomplete)) {
+            self.buffer = Vec::new();
+        }
+        result
+    }
+
+    fn store<'a>(
+        &mut self,
+        block: Option<BlockOption>,
+        payload: &'a [u8],
+    ) -> Result<Block1Status<'a>, HandlerError> {
+        let Some(block) = block else {
+            return Ok(Block1Status::Complete(Cow::Borrowed(payload)));
+        };
+        if block.more && payload.len() != block.size() {
+            Err(HandlerError::bad_request(format!(
+                "Block {} has {} [B] instead of {} [B]",
+                block.num,
+                payload.len(),
+                block.size()
+            )))?;
+        }
+        if block.num == 0 {
+            self.buffer.clear();
+        }
+
+        let offset = block.offset();
+        let end = offset + payload.len();
+        if block.more && end <= self.buffer.len() {
+            // The acknowledgement of the block got lost and the client sent
+            // it again.
+            return Ok(Block1Status::Incomplete);
+        }
+        if offset != self.buffer.len() {
+            Err(HandlerError::new(
+                coap_numbers::code::REQUEST_ENTITY_INCOMPLETE,
+                format!(
+                    "Expected the block at offset {}, got {}",
+                    self.buffer.len(),
+                    offset
+                ),
+            ))?;
+        }
+        if end > self.capacity {
+            Err(HandlerError::new(
+                coap_numbers::code::REQUEST_ENTITY_TOO_LARGE,
+                format!("The payload exceeds the limit of {} [B]", self.capacity),
+            ))?;
+        }
+
+        self.buffer.extend_from_slice(payload);
+        if block.more {
+            return Ok(Block1Status::Incomplete);
+        }
+        Ok(Block1Status::Complete(Cow::Owned(core::mem::take(
+            &mut self.buffer,
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(num: u32, more: bool) -> Option<BlockOption> {
+        Some(BlockOption { num, more, szx: 0 })
+    }
+
+    #[test]
+    fn option_round_trip() {
+        for value in [
+            &[][..],
+            &[0x08],
+            &[0x1e],
+            &[0x01, 0x0a],
+            &[0x12, 0x34, 0x5e],
+        ] {
+            let option = BlockOption::decode(value).unwrap();
+            assert_eq!(option.encode(), value);
+        }
+        let option = BlockOption::decode(&[0x2e]).unwrap();
+        assert_eq!((option.num, option.more, option.size()), (2, true, 1024));
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        assert!(BlockOption::decode(&[0x07]).is_none());
+        assert!(BlockOption::decode(&[0, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn blocks_are_reassembled() {
+        let mut assembler = Block1Assembler::new(64);
+        let first = [1u8; 16];
+        assert!(matches!(
+            assembler.receive(block(0, true), &first),
+            Ok(Block1Status::Incomplete)
+        ));
+        // Retransmission of the first block.
+        assert!(matches!(
+            assembler.receive(block(0, true), &first),
+            Ok(Block1Status::Incomplete)
+        ));
+        match assembler.receive(block(1, false), &[2, 3]) {
+            Ok(Block1Status::Complete(payload)) => {
+                assert_eq!(payload.len(), 18);
+                assert_eq!(&payload[16..], &[2, 3]);
+            }
+            _ => panic!("The payload should be complete"),
+        }
+    }
+
+    #[test]
+    fn missing_block_aborts_the_transfer() {
+        let mut assembler = Block1Assembler::new(64);
+        assert!(assembler.receive(block(0, true), &[0; 16]).is_ok());
+        let error = assembler.receive(block(2, false), &[0]).err().unwrap();
+        assert_eq!(error.code, coap_numbers::code::REQUEST_ENTITY_INCOMPLETE);
+        // The transfer needs to be restarted from the first block.
+        assert!(assembler.receive(block(1, false), &[0]).is_err());
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected() {
+        let mut assembler = Block1Assembler::new(20);
+        assert!(assembler.receive(block(0, true), &[0; 16]).is_ok());
+        let error = assembler.receive(block(1, true), &[0; 16]).err().unwrap();
+        assert_eq!(error.code, coap_numbers::code::REQUEST_ENTITY_TOO_LARGE);
+    }
+
+    #[test]
+    fn payload_without_option_is_returned() {
+        let mut assembler = Block1Assembler::new(4);
+        match assembler.receive(None, &[1, 2]) {
+            Ok(Block1Status::Complete(payload)) => assert_eq!(&payload[..], &[1, 2]),
+            _ => panic!("The payload should be complete"),
+        }
+    }
+}
@@ -15,7 +15,7 @@ use crate::infra::suit_storage::{self, SUIT_STORAGE_SLOT_SIZE};
 pub struct JitTestHandler {
     jit_compilation_time: u32,
     execution_time: u32,
-    result: i64,
+    result: u64,
     jit_prog_size: u32,
     prog_size: u32,
 }
@@ -37,7 +37,7 @@ impl JitTestHandler {
     }
 }
 
-use crate::coap_server::handlers::util::preprocess_request_raw;
+use crate::coap_server::handlers::util::{self, preprocess_request_raw};
 use crate::vm::middleware;
 use crate::vm::middleware::helpers::HelperFunction;
 
@@ -53,6 +53,10 @@ static JIT_MEMORY: Mutex<[u8; JIT_SLOT_SIZE]> = Mutex::new([0; JIT_SLOT_SIZE]);
 /// The reason for doing this is that we want to be able to discard the source
 /// eBPF program after we jit-compile it and thus save memory as jitted programs
 /// are substantially smaller.
+///
+/// Note: the buffer is only used by the (currently disabled) test handler
+/// below, [`crate::vm::rbpf_jit::RbpfJIT`] compiles the program directly into
+/// its JIT storage slot, so concurrent compilations don't contend on it.
 static PROGRAM_COPY_BUFFER: Mutex<[u8; JIT_SLOT_SIZE]> = Mutex::new([0; JIT_SLOT_SIZE]);
 
 impl coap_handler::Handler for JitTestHandler {
@@ -127,7 +131,7 @@ impl coap_handler::Handler for JitTestHandler {
             ret = jitted_fn(0 as *mut u8, 0, 0 as *mut u8, 0);
         }
         self.execution_time = Self::time_now(clock) - start;
-        self.result = ret as i64;
+        self.result = ret as u64;
 
         jit_prog_storage::free_storage_slot(jit_slot);
         debug!("JIT execution successful: {}", ret);
@@ -145,6 +149,6 @@ impl coap_handler::Handler for JitTestHandler {
             "{{\"prog_size\": {}, \"jit_prog_size\": {}, \"jit_comp_time\": {}, \"run_time\": {}, \"result\": {}}}",
             self.prog_size, self.jit_prog_size, self.jit_compilation_time, self.execution_time, self.result
         );
-        response.set_payload(resp.as_bytes());
+        util::set_json_payload(response, resp);
     }
 }
@@ -1,9 +1,31 @@
-use alloc::format;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 use coap_message::{MutableWritableMessage, ReadableMessage};
 use core::{convert::TryInto, ops::DerefMut};
 use riot_wrappers::{riot_sys, stdio::println};
 
-use crate::vm::RUNNING_WORKERS;
+use crate::{
+    coap_server::handlers::util::{self, HandlerError},
+    infra::{
+        autostart, crash_diagnostics, execution_log, heap_stats,
+        last_results::{self, LastResult},
+        result_cache, suit_storage,
+    },
+    vm::{
+        helper_policy,
+        middleware::{helpers::HelperAccessList, ALL_HELPERS},
+        DEFAULT_VM_TARGET_NAME, RUNNING_WORKERS,
+    },
+};
+
+/// Names of the execution models supported by the server, they match the ones
+/// accepted by `ExecutionModel::from_str`. They are listed here because
+/// `ExecutionModel` in micro-bpf-common doesn't provide a way of enumerating
+/// its variants.
+const EXECUTION_MODELS: &[&str] = &["ShortLived", "WithAccessToCoapPacket", "LongRunning"];
 
 pub struct RiotBoardHandler;
 impl coap_handler::Handler for RiotBoardHandler {
@@ -62,6 +84,187 @@ impl coap_handler::Handler for RunningVMHandler {
     }
 }
 
+/// Reports the capabilities of the running instance. Currently those are the
+/// helper functions that were compiled in for the target board, the VM
+/// targets that support JIT compilation, the VM target used when the client
+/// doesn't specify one and the supported execution models.
+/// The helper IDs are returned in the same compact hex format that is used for
+/// specifying the allowed helpers in the execution requests.
+pub struct CapabilitiesHandler;
+impl coap_handler::Handler for CapabilitiesHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        return coap_numbers::code::VALID;
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+
+        let helpers = ALL_HELPERS
+            .iter()
+            .map(|h| format!("{:02x}", h.id as u8))
+            .collect::<String>();
+        // The descriptions are looked up by the clients to keep the response short.
+        let execution_models = EXECUTION_MODELS
+            .iter()
+            .map(|m| format!("\"{}\"", m))
+            .collect::<Vec<String>>()
+            .join(", ");
+        // Only rBPF programs can be JIT-compiled, FemtoContainer JIT requests
+        // are rejected when constructing the VM.
+        util::set_json_payload(
+            response,
+            format!(
+                concat!(
+                    "{{\"helpers\": \"{}\", \"helper_count\": {}, \"helper_capacity\": {}, ",
+                    "\"jit\": [\"rBPF\"], \"default_vm\": \"{}\", \"execution_models\": [{}]}}"
+                ),
+                helpers,
+                HelperAccessList::registered_count(),
+                HelperAccessList::capacity(),
+                DEFAULT_VM_TARGET_NAME,
+                execution_models
+            ),
+        );
+    }
+}
+
+/// Version of the interface between the server and the eBPF programs, i.e. the
+/// helper function IDs and their calling convention, and the supported program
+/// layouts. It needs to be bumped whenever a change breaks the programs
+/// compiled against the previous version.
+pub const ABI_VERSION: u32 = 1;
+
+/// Identifies the exact build of the firmware, as opposed to
+/// [`CapabilitiesHandler`] which only reports what the build supports. The
+/// build timestamp (seconds since the UNIX epoch) and the git commit hash are
+/// embedded by the build script.
+pub struct VersionHandler;
+impl coap_handler::Handler for VersionHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        return coap_numbers::code::VALID;
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        util::set_json_payload(
+            response,
+            format!(
+                concat!(
+                    "{{\"git\": \"{}\", \"built\": {}, \"abi\": {}, ",
+                    "\"vm_targets\": [\"rBPF\", \"FemtoContainer\"]}}"
+                ),
+                env!("MICRO_BPF_GIT_HASH"),
+                env!("MICRO_BPF_BUILD_TIMESTAMP"),
+                ABI_VERSION
+            ),
+        );
+    }
+}
+
+/// Reports which SUIT storage slots were being executed by the VM workers when
+/// the device was last reset. See [`crash_diagnostics`] for more details.
+pub struct LastCrashHandler;
+impl coap_handler::Handler for LastCrashHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        return coap_numbers::code::VALID;
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+
+        let resp = match crash_diagnostics::last_crash() {
+            Some(slots) => format!("{{\"crashed\": true, \"running_slots\": {:?}}}", slots),
+            None => format!("{{\"crashed\": false}}"),
+        };
+        util::set_json_payload(response, resp);
+    }
+}
+
+/// Reports the heap usage (see [`heap_stats`]), querying it repeatedly
+/// between executions of the same program allows for spotting leaks. The free
+/// heap is `null` if the size of the heap isn't known for the platform. If the
+/// firmware was built without the heap statistics, 5.01 is returned.
+pub struct HeapStatsHandler;
+impl coap_handler::Handler for HeapStatsHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        return coap_numbers::code::CONTENT;
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        if request != coap_numbers::code::CONTENT {
+            response.set_code(request.try_into().map_err(|_| ()).unwrap());
+            return;
+        }
+
+        match heap_stats::sample() {
+            Ok(stats) => {
+                response.set_code(request.try_into().map_err(|_| ()).unwrap());
+                let free = stats.free.map_or(String::from("null"), |f| format!("{}", f));
+                util::set_json_payload(
+                    response,
+                    format!(
+                        "{{\"used\": {}, \"peak\": {}, \"free\": {}}}",
+                        stats.used, stats.peak, free
+                    ),
+                );
+            }
+            Err(e) => util::error_response(response, coap_numbers::code::NOT_IMPLEMENTED, &e),
+        }
+    }
+}
+
 pub struct ConsoleWriteHandler;
 impl coap_handler::Handler for ConsoleWriteHandler {
     type RequestData = u8;
@@ -80,7 +283,7 @@ impl coap_handler::Handler for ConsoleWriteHandler {
     }
 
     fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
-        1
+        util::COAP_RESPONSE_PAYLOAD_SIZE
     }
 
     fn build_response(
@@ -88,8 +291,318 @@ impl coap_handler::Handler for ConsoleWriteHandler {
         response: &mut impl MutableWritableMessage,
         request: Self::RequestData,
     ) {
+        match request {
+            coap_numbers::code::CHANGED => {
+                response.set_code(request.try_into().map_err(|_| ()).unwrap());
+                response.set_payload("Success".as_bytes());
+            }
+            coap_numbers::code::BAD_REQUEST => {
+                util::error_response(response, request, "Payload is not valid UTF-8")
+            }
+            _ => response.set_code(request.try_into().map_err(|_| ()).unwrap()),
+        }
+    }
+}
+
+/// Manages the programs started after the device is reset, see
+/// [`autostart`] for more details. GET lists the slots of the programs,
+/// POST adds a program and DELETE removes all of them. The payload of the
+/// POST request is the encoded SUIT pull request followed by a newline
+/// and the encoded execution request.
+pub struct AutostartConfigHandler {
+    last_request_status: Result<String, String>,
+}
+
+impl AutostartConfigHandler {
+    pub fn new() -> Self {
+        Self {
+            last_request_status: Err("No requests processed yet".to_string()),
+        }
+    }
+
+    fn handle(&mut self, request: &impl ReadableMessage) -> Result<(u8, String), (u8, String)> {
+        let slots_json = || format!("{{\"slots\": {:?}}}", autostart::autostart_slots());
+        match request.code().into() {
+            coap_numbers::code::GET => Ok((coap_numbers::code::CONTENT, slots_json())),
+            coap_numbers::code::DELETE => {
+                autostart::clear();
+                Ok((coap_numbers::code::DELETED, slots_json()))
+            }
+            coap_numbers::code::POST => {
+                let bad_request = |e: String| (coap_numbers::code::BAD_REQUEST, e);
+                let payload = core::str::from_utf8(request.payload())
+                    .map_err(|_| bad_request("Payload is not valid UTF-8".to_string()))?;
+                let Some((pull_request, execution_request)) = payload.trim().split_once('\n')
+                else {
+                    return Err(bad_request("Expected two requests separated by a newline".into()));
+                };
+                autostart::add_entry(pull_request.trim(), execution_request.trim())
+                    .map_err(bad_request)?;
+                Ok((coap_numbers::code::CHANGED, slots_json()))
+            }
+            _ => Err((
+                coap_numbers::code::METHOD_NOT_ALLOWED,
+                "Method not allowed".to_string(),
+            )),
+        }
+    }
+}
+
+impl coap_handler::Handler for AutostartConfigHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        match self.handle(request) {
+            Ok((code, json)) => {
+                self.last_request_status = Ok(json);
+                code
+            }
+            Err((code, e)) => {
+                self.last_request_status = Err(e);
+                code
+            }
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        match &self.last_request_status {
+            Ok(json) => {
+                response.set_code(request.try_into().map_err(|_| ()).unwrap());
+                util::set_json_payload(response, json.clone())
+            }
+            Err(e) => util::error_response(response, request, e),
+        }
+    }
+}
+
+/// Manages the operator-level helper policy, see [`helper_policy`] for more
+/// details. GET returns the policy, POST replaces it and DELETE removes it.
+/// The payload of the POST request is the list of the helpers that the
+/// programs may call, encoded the same way as in the execution requests (a hex
+/// string with two characters per helper ID). An empty payload forbids all
+/// helpers.
+pub struct HelperPolicyHandler {
+    error: Option<HandlerError>,
+}
+
+impl HelperPolicyHandler {
+    pub fn new() -> Self {
+        Self { error: None }
+    }
+
+    fn handle_request(&mut self, request: &impl ReadableMessage) -> Result<u8, HandlerError> {
+        match request.code().into() {
+            coap_numbers::code::GET => Ok(coap_numbers::code::CONTENT),
+            coap_numbers::code::DELETE => {
+                helper_policy::clear_policy();
+                Ok(coap_numbers::code::DELETED)
+            }
+            coap_numbers::code::POST => {
+                let payload = core::str::from_utf8(request.payload())
+                    .map_err(|_| HandlerError::bad_request("Payload is not valid UTF-8".into()))?;
+                let helpers = HelperAccessList::try_from_hex(payload.trim())
+                    .map_err(HandlerError::bad_request)?;
+                helper_policy::set_policy(helpers.0.iter().map(|h| h.id).collect());
+                Ok(coap_numbers::code::CHANGED)
+            }
+            _ => Err(HandlerError::new(
+                coap_numbers::code::METHOD_NOT_ALLOWED,
+                "Method not allowed",
+            )),
+        }
+    }
+}
+
+impl coap_handler::Handler for HelperPolicyHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        let result = self.handle_request(request);
+        util::response_code(result, &mut self.error)
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        if let Some(e) = self.error.as_ref() {
+            return util::error_response(response, e.code, &e.message);
+        }
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let json = match helper_policy::policy() {
+            Some(helpers) => format!(
+                "{{\"policy\": \"{}\"}}",
+                helper_policy::encode_helpers(&helpers)
+            ),
+            None => "{\"policy\": null}".to_string(),
+        };
+        util::set_json_payload(response, json);
+    }
+}
+
+/// Returns the debug output of the executed programs kept in the execution log
+/// (see [`execution_log`]). Each line starts with its sequence number, the PID
+/// of the thread which executed the program and its SUIT storage slot. The
+/// lines are returned starting from the sequence number specified as the last
+/// segment of the path: `/logs/<sequence>` (or from the oldest one if it is
+/// omitted), only as many of them as fit into the response. The clients can
+/// then continue reading from the sequence number following the last line.
+pub struct ExecutionLogHandler {
+    from_sequence: u32,
+}
+
+impl ExecutionLogHandler {
+    pub fn new() -> Self {
+        Self { from_sequence: 0 }
+    }
+}
+
+impl coap_handler::Handler for ExecutionLogHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        // Without the sequence segment, the last segment is `logs`.
+        self.from_sequence = util::last_uri_path_segment(request)
+            .and_then(|s| s.parse::<u32>().ok())
+            .unwrap_or(0);
+        coap_numbers::code::CONTENT
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        if request != coap_numbers::code::CONTENT {
+            return;
+        }
+        let mut lines = String::new();
+        for line in execution_log::lines_since(self.from_sequence) {
+            let line = line.format();
+            if lines.len() + line.len() > util::COAP_RESPONSE_PAYLOAD_SIZE {
+                break;
+            }
+            lines.push_str(&line);
+        }
+        response.set_payload(lines.as_bytes());
+    }
+}
+
+/// Reports whether the result cache is enabled together with the number of
+/// cache hits and misses (see [`result_cache`]). Every miss of a cacheable
+/// request is followed by an execution of the program, so comparing the
+/// counters before and after a request shows whether it was executed.
+pub struct ResultCacheStatsHandler;
+impl coap_handler::Handler for ResultCacheStatsHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        return coap_numbers::code::CONTENT;
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        if request != coap_numbers::code::CONTENT {
+            return;
+        }
+        let stats = result_cache::stats();
+        util::set_json_payload(
+            response,
+            format!(
+                "{{\"enabled\": {}, \"hits\": {}, \"misses\": {}}}",
+                result_cache::RESULT_CACHE,
+                stats.hits,
+                stats.misses
+            ),
+        );
+    }
+}
+
+/// Returns the most recent execution result of the program in a SUIT storage
+/// slot (see [`last_results`]): `/result/last/<slot>`. It allows for showing
+/// the latest known value without executing the program again. If the program
+/// hasn't been executed since it was deployed, the status is `never_executed`.
+pub struct LastResultHandler {
+    last_request: Option<(usize, Option<LastResult>)>,
+    error: Option<HandlerError>,
+}
+
+impl LastResultHandler {
+    pub fn new() -> Self {
+        Self {
+            last_request: None,
+            error: None,
+        }
+    }
+
+    fn handle_request(&mut self, request: &impl ReadableMessage) -> Result<u8, HandlerError> {
+        if request.code().into() != coap_numbers::code::GET {
+            Err(HandlerError::from(coap_numbers::code::METHOD_NOT_ALLOWED))?;
+        }
+        let slot = util::last_uri_path_segment(request)
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|s| suit_storage::is_suit_slot(*s))
+            .ok_or_else(|| HandlerError::bad_request("Invalid SUIT storage slot".to_string()))?;
+        self.last_request = Some((slot, last_results::get(slot)));
+        Ok(coap_numbers::code::CONTENT)
+    }
+}
+
+impl coap_handler::Handler for LastResultHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        self.last_request = None;
+        let result = self.handle_request(request);
+        util::response_code(result, &mut self.error)
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        if let Some(e) = self.error.as_ref() {
+            return util::error_response(response, e.code, &e.message);
+        }
         response.set_code(request.try_into().map_err(|_| ()).unwrap());
-        let result = "Success";
-        response.set_payload(result.as_bytes());
+        let Some((slot, last)) = self.last_request else {
+            return;
+        };
+        let json = match last {
+            Some(last) => format!(
+                "{{\"slot\": {}, {}, \"execution_time\": {}, \"age_ms\": {}}}",
+                slot,
+                last.result.json_fields(),
+                last.elapsed_us,
+                last.age_ms()
+            ),
+            None => format!("{{\"slot\": {}, \"status\": \"never_executed\"}}", slot),
+        };
+        util::set_json_payload(response, json);
     }
 }
@@ -1,6 +1,9 @@
+mod block1;
 mod jit_deploy_handler;
 pub mod miscellaneous;
 mod native_fletcher16_endpoint;
+mod request_deduplication;
+mod request_quota;
 pub mod suit_pull_endpoint;
 mod util;
 mod vm_benchmark_handlers;
@@ -8,8 +11,16 @@ mod vm_long_execution_handler;
 mod vm_short_execution_handlers;
 
 pub use jit_deploy_handler::JitTestHandler;
-pub use native_fletcher16_endpoint::Fletcher16NativeTestHandler;
+pub use native_fletcher16_endpoint::{Fletcher16NativeTestHandler, NativeFunctionHandler};
+pub use request_deduplication::DeduplicatingHandler;
+pub use request_quota::QuotaListener;
 pub use util::TimedHandler;
-pub use vm_benchmark_handlers::{VMExecutionBenchmarkHandler, VMExecutionOnCoapPktBenchmarkHandler};
-pub use vm_long_execution_handler::VMLongExecutionHandler;
-pub use vm_short_execution_handlers::{VMExecutionNoDataHandler, VMExecutionOnCoapPktHandler};
+pub use vm_benchmark_handlers::{
+    BenchmarkExportHandler, VMComparisonBenchmarkHandler, VMExecutionBenchmarkHandler,
+    VMExecutionOnCoapPktBenchmarkHandler, VMRelocationBenchmarkHandler,
+};
+pub use vm_long_execution_handler::{VMLongExecutionHandler, VMReloadHandler};
+pub use vm_short_execution_handlers::{
+    CoapRouteRegistrationHandler, VMExecutionNoDataHandler, VMExecutionOnCoapPktHandler,
+    VMExecutionWithOutputHandler, VMInlineExecutionHandler, VMRoutedExecutionHandler,
+};
@@ -10,13 +10,18 @@ use log::debug;
 use micro_bpf_common::{BinaryFileLayout, VMExecutionRequest};
 use riot_wrappers::mutex::Mutex;
 
-use crate::infra::suit_storage::{self, SUIT_STORAGE_SLOT_SIZE};
+use crate::infra::{
+    native_functions::{self, NativeFunction},
+    suit_storage::{self, SUIT_STORAGE_SLOT_SIZE},
+};
+use crate::util::logger::targets;
 /// This handler is responsible for executing a requested fletcher 16 checksumming
 /// program. It is used for benchmarking the interpreters and the JIT against the
 /// native baseline.
 pub struct Fletcher16NativeTestHandler {
     execution_time: u32,
-    result: i64,
+    result: u64,
+    error: Option<HandlerError>,
 }
 
 impl Fletcher16NativeTestHandler {
@@ -24,70 +29,145 @@ impl Fletcher16NativeTestHandler {
         Self {
             execution_time: 0,
             result: 0,
+            error: None,
         }
     }
 
-    #[inline(always)]
-    fn time_now(clock: *mut riot_sys::inline::ztimer_clock_t) -> u32 {
-        unsafe { riot_sys::inline::ztimer_now(clock) }
+    fn handle_request(&mut self, request: &impl ReadableMessage) -> Result<u8, HandlerError> {
+        let request_data = preprocess_request_raw(request)?;
+
+        let request = VMExecutionRequest::decode(request_data)
+            .map_err(|e| HandlerError::bad_request(format!("Invalid request: {}", e)))?;
+
+        // We use a quick hack here where the size of checksummed data
+        // is encoded in the length of the allowed helpers list. 1 corresponds to
+        // 80B, 2 corresponds to 160B, and so on.
+        let data_size = request.allowed_helpers.len();
+
+        let name = match data_size {
+            1 => "fletcher_16_80B",
+            2 => "fletcher_16_160B",
+            3 => "fletcher_16_320B",
+            4 => "fletcher_16_640B",
+            5 => "fletcher_16_1280B",
+            6 => "fletcher_16_2560B",
+            _ => Err(HandlerError::bad_request(format!("Invalid data size: {}", data_size)))?,
+        };
+
+        let Some(test_fn) = native_functions::get_native_fn(name) else {
+            return Err(HandlerError::new(
+                coap_numbers::code::NOT_FOUND,
+                format!("Native function not found: {}", name),
+            ));
+        };
+
+        let (execution_time, ret) = time_native_fn(test_fn);
+        self.execution_time = execution_time;
+        debug!(target: targets::COAP, "JIT execution successful: {}", ret);
+        self.result = ret as u64;
+
+        Ok(coap_numbers::code::CHANGED)
     }
 }
 
-use crate::coap_server::handlers::util::preprocess_request_raw;
-use crate::vm::middleware;
-use crate::vm::middleware::helpers::HelperFunction;
+#[inline(always)]
+fn time_now(clock: *mut riot_sys::inline::ztimer_clock_t) -> u32 {
+    unsafe { riot_sys::inline::ztimer_now(clock) }
+}
 
-extern "C" {
-    fn fletcher_16_80B() -> u32;
-    fn fletcher_16_160B() -> u32;
-    fn fletcher_16_320B() -> u32;
-    fn fletcher_16_640B() -> u32;
-    fn fletcher_16_1280B() -> u32;
-    fn fletcher_16_2560B() -> u32;
+/// Runs the native function and returns its execution time in microseconds
+/// together with its return value.
+fn time_native_fn(function: NativeFunction) -> (u32, u32) {
+    let clock = unsafe { riot_sys::ZTIMER_USEC as *mut riot_sys::inline::ztimer_clock_t };
+    let start: u32 = time_now(clock);
+    let ret = unsafe { function() };
+    (time_now(clock) - start, ret)
 }
 
+use crate::coap_server::handlers::util::{self, preprocess_request_raw, HandlerError};
+use crate::vm::middleware;
+use crate::vm::middleware::helpers::HelperFunction;
+
 impl coap_handler::Handler for Fletcher16NativeTestHandler {
     type RequestData = u8;
 
     fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
-        let request_data = match preprocess_request_raw(request) {
-            Ok(request_data) => request_data,
-            Err(code) => return code,
+        let result = self.handle_request(request);
+        util::response_code(result, &mut self.error)
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        1
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        if let Some(e) = self.error.as_ref() {
+            return util::error_response(response, e.code, &e.message);
+        }
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let resp = format!(
+            "{{\"execution_time\": {}, \"result\": {}}}",
+            self.execution_time, self.result
+        );
+        util::set_json_payload(response, resp);
+    }
+}
+
+/// Executes one of the native functions registered in [`native_functions`].
+/// The function is selected using the last segment of the request path, i.e.
+/// a POST request to `/native/fletcher_16_80B` runs the function registered
+/// under the name `fletcher_16_80B`. The response has the same format as the
+/// one of [`Fletcher16NativeTestHandler`].
+pub struct NativeFunctionHandler {
+    execution_time: u32,
+    result: u64,
+    error: Option<HandlerError>,
+}
+
+impl NativeFunctionHandler {
+    pub fn new() -> Self {
+        Self {
+            execution_time: 0,
+            result: 0,
+            error: None,
+        }
+    }
+
+    fn handle_request(&mut self, request: &impl ReadableMessage) -> Result<u8, HandlerError> {
+        if request.code().into() != coap_numbers::code::POST {
+            return Err(HandlerError::new(
+                coap_numbers::code::METHOD_NOT_ALLOWED,
+                "Native functions are executed using POST",
+            ));
+        }
+
+        let Some(name) = util::last_uri_path_segment(request) else {
+            return Err(HandlerError::bad_request("Expected /native/<name>".into()));
         };
 
-        let Ok(request) = VMExecutionRequest::decode(request_data) else {
-            return coap_numbers::code::BAD_REQUEST;
+        let Some(function) = native_functions::get_native_fn(&name) else {
+            debug!(target: targets::COAP, "Native function not found: {}", name);
+            return Err(HandlerError::new(
+                coap_numbers::code::NOT_FOUND,
+                format!("Native function not found: {}", name),
+            ));
         };
 
-        // We use a quick hack here where the size of checksummed data
-        // is encoded in the length of the allowed helpers list. 1 corresponds to
-        // 80B, 2 corresponds to 160B, and so on.
-        let data_size = request.allowed_helpers.len();
+        let (execution_time, ret) = time_native_fn(function);
+        self.execution_time = execution_time;
+        self.result = ret as u64;
+        debug!(target: targets::COAP, "Native function {} returned: {}", name, ret);
 
-        let test_fn = match data_size {
-            1 => fletcher_16_80B,
-            2 => fletcher_16_160B,
-            3 => fletcher_16_320B,
-            4 => fletcher_16_640B,
-            5 => fletcher_16_1280B,
-            6 => fletcher_16_2560B,
-            _ => {
-            debug!("Invalid data size: {}", data_size);
-            return coap_numbers::code::BAD_REQUEST;
-            }
-        };
+        Ok(coap_numbers::code::CHANGED)
+    }
+}
 
-        let clock = unsafe { riot_sys::ZTIMER_USEC as *mut riot_sys::inline::ztimer_clock_t };
-        let mut ret: u32;
-        let start: u32 = Self::time_now(clock);
-        unsafe {
-            ret = test_fn();
-        }
-        self.execution_time = Self::time_now(clock) - start;
-        debug!("JIT execution successful: {}", ret);
-        self.result = ret as i64;
+impl coap_handler::Handler for NativeFunctionHandler {
+    type RequestData = u8;
 
-        coap_numbers::code::CHANGED
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        let result = self.handle_request(request);
+        util::response_code(result, &mut self.error)
     }
 
     fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
@@ -95,11 +175,14 @@ impl coap_handler::Handler for Fletcher16NativeTestHandler {
     }
 
     fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        if let Some(e) = self.error.as_ref() {
+            return util::error_response(response, e.code, &e.message);
+        }
         response.set_code(request.try_into().map_err(|_| ()).unwrap());
         let resp = format!(
             "{{\"execution_time\": {}, \"result\": {}}}",
             self.execution_time, self.result
         );
-        response.set_payload(resp.as_bytes());
+        util::set_json_payload(response, resp);
     }
 }
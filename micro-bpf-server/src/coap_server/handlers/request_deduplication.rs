@@ -0,0 +1,133 @@
+//! Deduplication of retransmitted CoAP requests.
+//!
+//! When a response gets lost, the client retransmits the request using the same
+//! message ID and token. The message IDs and tokens are only unique per
+//! client, so the address of the client is compared as well. Without deduplication, the server would execute the
+//! eBPF program again, which is problematic for stateful programs (e.g. ones
+//! using the global storage helpers). This module provides a wrapper handler
+//! which remembers the responses to the most recent requests and replays them
+//! when a duplicate arrives within the deduplication window.
+
+use alloc::vec::Vec;
+use log::debug;
+use macros::set_env_or_default;
+use riot_wrappers::gcoap::PacketBuffer;
+
+use crate::util::logger::targets;
+use crate::{
+    infra::client_quota::ClientId,
+    vm::{clock, middleware::CoapContext},
+};
+
+use super::request_quota;
+
+/// Number of most recent responses that are remembered by each handler.
+const DEDUP_CACHE_SIZE: usize = set_env_or_default!("COAP_DEDUP_CACHE_SIZE", 4);
+
+/// Duration (in milliseconds) for which a response is replayed for duplicate
+/// requests. It should cover the whole retransmission window of the client;
+/// the default CoAP parameters (ACK_TIMEOUT=2s, MAX_RETRANSMIT=4) give
+/// a MAX_TRANSMIT_SPAN of 45s, however the clients used with the server give
+/// up much sooner, so a shorter window is used to save memory.
+const DEDUP_WINDOW_MS: usize = set_env_or_default!("COAP_DEDUP_WINDOW_MS", 10000);
+
+/// Maximum CoAP token length.
+const MAX_TOKEN_LEN: usize = 8;
+
+struct CachedResponse {
+    /// Address of the client, `None` if the handler isn't registered using a
+    /// `QuotaListener` which provides it.
+    client: Option<ClientId>,
+    message_id: u16,
+    token: [u8; MAX_TOKEN_LEN],
+    token_len: usize,
+    received_at: u32,
+    /// The whole response PDU as it was written into the packet buffer.
+    response: Vec<u8>,
+}
+
+/// Wraps another gcoap handler and replays its cached response if a request with
+/// the same message ID and token is received again from the same client within
+/// [`DEDUP_WINDOW_MS`]. The handler needs to be registered using a
+/// `QuotaListener`, which provides the address of the client.
+/// Only the last [`DEDUP_CACHE_SIZE`] responses are stored, the oldest ones
+/// are overwritten first.
+pub struct DeduplicatingHandler<'a> {
+    handler: &'a mut dyn riot_wrappers::gcoap::Handler,
+    cache: [Option<CachedResponse>; DEDUP_CACHE_SIZE],
+    next_slot: usize,
+}
+
+impl<'a> DeduplicatingHandler<'a> {
+    pub fn new(handler: &'a mut impl riot_wrappers::gcoap::Handler) -> Self {
+        Self {
+            handler,
+            cache: core::array::from_fn(|_| None),
+            next_slot: 0,
+        }
+    }
+}
+
+/// Reads the message ID and the token from the header of the received packet.
+pub(super) fn request_identity(pkt: &mut PacketBuffer) -> (u16, [u8; MAX_TOKEN_LEN], usize) {
+    let mut token = [0; MAX_TOKEN_LEN];
+    unsafe {
+        let ctx = pkt as *mut _ as *mut CoapContext;
+        let hdr = (*(*ctx).pkt).hdr as *const u8;
+        // The header is: Ver | T | TKL (1 byte), Code (1 byte), Message ID (2 bytes),
+        // followed by the token.
+        let token_len = ((*hdr & 0x0f) as usize).min(MAX_TOKEN_LEN);
+        let message_id = u16::from_be_bytes([*hdr.add(2), *hdr.add(3)]);
+        core::ptr::copy_nonoverlapping(hdr.add(4), token.as_mut_ptr(), token_len);
+        (message_id, token, token_len)
+    }
+}
+
+impl riot_wrappers::gcoap::Handler for DeduplicatingHandler<'_> {
+    fn handle(&mut self, pkt: &mut PacketBuffer) -> isize {
+        let (message_id, token, token_len) = request_identity(pkt);
+        let client = request_quota::current_client();
+        let now = clock::now_ms();
+
+        let duplicate = self.cache.iter().flatten().find(|c| {
+            c.client == client
+                && c.message_id == message_id
+                && c.token[..c.token_len] == token[..token_len]
+                && now.wrapping_sub(c.received_at) < DEDUP_WINDOW_MS as u32
+        });
+
+        if let Some(cached) = duplicate {
+            debug!(
+                target: targets::COAP,
+                "Duplicate request (message ID: {}), replaying cached response",
+                message_id
+            );
+            unsafe {
+                let ctx = pkt as *mut _ as *mut CoapContext;
+                let len = cached.response.len().min((*ctx).len);
+                core::ptr::copy_nonoverlapping(cached.response.as_ptr(), (*ctx).buf, len);
+                return len as isize;
+            }
+        }
+
+        let response_len = self.handler.handle(pkt);
+
+        if response_len > 0 {
+            let response = unsafe {
+                let ctx = pkt as *mut _ as *mut CoapContext;
+                core::slice::from_raw_parts((*ctx).buf, response_len as usize).to_vec()
+            };
+            self.cache[self.next_slot] = Some(CachedResponse {
+                client,
+                message_id,
+                token,
+                token_len,
+                received_at: now,
+                response,
+            });
+            self.next_slot = (self.next_slot + 1) % DEDUP_CACHE_SIZE;
+        }
+
+        response_len
+    }
+}
@@ -0,0 +1,121 @@
+//! Enforcement of the per-client execution quotas (see [`client_quota`]).
+//!
+//! The clients are identified by the address of the remote endpoint which
+//! sent the request. The gcoap handlers only receive the packet, so the quotas
+//! are enforced by a listener whose resource handler also gets the request
+//! context holding the remote endpoint. The server can't verify the address,
+//! so the quotas limit tenants sharing a device rather than an attacker able
+//! to spoof the source address. The address is also made available to the
+//! handlers using [`current_client`].
+
+use core::{ffi::CStr, marker::PhantomData};
+
+use log::warn;
+use riot_wrappers::{
+    gcoap::{Handler, ListenerProvider, PacketBuffer},
+    mutex::Mutex,
+};
+
+use crate::{
+    infra::client_quota::{self, ClientId},
+    util::logger::targets,
+    vm::middleware::CoapContext,
+};
+
+use super::util;
+
+/// Client whose request is being handled by one of the listeners, gcoap
+/// handles the requests one at a time.
+static CURRENT_CLIENT: Mutex<Option<ClientId>> = Mutex::new(None);
+
+/// Returns the client whose request is being handled, `None` if the request
+/// wasn't received through a [`QuotaListener`].
+pub fn current_client() -> Option<ClientId> {
+    *CURRENT_CLIENT.lock()
+}
+
+/// Listener for a single resource, which passes its requests to the handler.
+/// If the quotas are enabled, the requests of clients that have used up their
+/// quota are rejected with the TOO_MANY_REQUESTS code, the programs executed
+/// by the handler are charged to the client otherwise. Used instead of
+/// `SingleHandlerListener`, which doesn't expose the remote endpoint.
+pub struct QuotaListener<'a, H> {
+    resource: riot_sys::coap_resource_t,
+    listener: riot_sys::gcoap_listener_t,
+    _handler: PhantomData<&'a mut H>,
+}
+
+impl<'a, H: Handler> QuotaListener<'a, H> {
+    pub fn new(path: &'a CStr, methods: u32, handler: &'a mut H) -> Self {
+        // The remaining fields (e.g. the link encoder) are filled in with the
+        // defaults by gcoap when the listener is registered.
+        let mut resource: riot_sys::coap_resource_t = unsafe { core::mem::zeroed() };
+        resource.path = path.as_ptr();
+        resource.methods = methods as _;
+        resource.handler = Some(Self::call_handler);
+        resource.context = handler as *mut H as *mut core::ffi::c_void;
+        Self {
+            resource,
+            listener: unsafe { core::mem::zeroed() },
+            _handler: PhantomData,
+        }
+    }
+
+    unsafe extern "C" fn call_handler(
+        pkt: *mut riot_sys::coap_pkt_t,
+        buf: *mut u8,
+        len: riot_sys::size_t,
+        request_ctx: *mut riot_sys::coap_request_ctx_t,
+    ) -> riot_sys::ssize_t {
+        let handler = &mut *(riot_sys::coap_request_ctx_get_context(request_ctx) as *mut H);
+        let remote = riot_sys::coap_request_ctx_get_remote_udp(request_ctx);
+        // Requests without a remote endpoint (not received over UDP) share
+        // the quota of an empty address.
+        let client = if remote.is_null() {
+            ClientId::new(&[])
+        } else {
+            ClientId::new(&(*remote).addr.ipv6)
+        };
+        // The packet buffer has the same layout as the context, see the
+        // other handlers casting it to CoapContext.
+        let mut coap_ctx = CoapContext {
+            pkt,
+            buf,
+            len: len as usize,
+        };
+        let pkt = &mut *(&mut coap_ctx as *mut CoapContext as *mut PacketBuffer);
+        *CURRENT_CLIENT.lock() = Some(client);
+        let response_len = handle_with_quota(handler, pkt, client);
+        *CURRENT_CLIENT.lock() = None;
+        response_len as riot_sys::ssize_t
+    }
+}
+
+impl<H: Handler> ListenerProvider for QuotaListener<'_, H> {
+    unsafe fn get_listener(&mut self) -> &mut riot_sys::gcoap_listener_t {
+        self.listener.resources = &self.resource;
+        self.listener.resources_len = 1;
+        self.listener.next = core::ptr::null_mut();
+        &mut self.listener
+    }
+}
+
+fn handle_with_quota(
+    handler: &mut impl Handler,
+    pkt: &mut PacketBuffer,
+    client: ClientId,
+) -> isize {
+    if !client_quota::CLIENT_QUOTA_ENABLED {
+        return handler.handle(pkt);
+    }
+
+    if let Err(e) = client_quota::admit(client) {
+        warn!(target: targets::COAP, "Request rejected: {}", e);
+        return util::raw_error_response(pkt, coap_numbers::code::TOO_MANY_REQUESTS, &e);
+    }
+
+    client_quota::start_request(client);
+    let response_len = handler.handle(pkt);
+    client_quota::finish_request();
+    response_len
+}
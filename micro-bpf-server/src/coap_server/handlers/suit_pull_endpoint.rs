@@ -6,19 +6,36 @@ use alloc::{
 use core::convert::TryInto;
 use log::{debug, error};
 use micro_bpf_common::{
-    BinaryFileLayout, HelperAccessListSource, HelperAccessVerification, SuitPullRequest,
-    VMConfiguration,
+    BinaryFileLayout, HelperAccessListSource, HelperAccessVerification, HelperFunctionID,
+    SuitPullRequest, VMConfiguration, VMExecutionRequest,
 };
 use micro_bpf_elf_utils::extract_allowed_helpers;
 
-use coap_message::{MutableWritableMessage, ReadableMessage};
+use coap_message::{MessageOption, MutableWritableMessage, ReadableMessage};
 
+#[cfg(feature = "jit-dump")]
+use crate::infra::jit_prog_storage::{JitProgramDump, JIT_SLOT_SIZE, JIT_STORAGE_SLOTS_NUM};
+use crate::util::logger::targets;
 use crate::{
-    infra::suit_storage::{self, SUIT_STORAGE_SLOT_SIZE},
-    vm::{middleware::helpers::HelperAccessList, rbpf_vm},
+    infra::{
+        jit_prog_storage,
+        program_analysis::{self, ProgramReport},
+        program_metadata::{self, ProgramMetadata},
+        suit_storage::{self, SuitStorageSlotStatus, SUIT_STORAGE_SLOT_SIZE},
+        wcet::{self, WcetEstimate},
+    },
+    util::json,
+    vm::{
+        construct_vm,
+        middleware::helpers::{self, HelperAccessList},
+        rbpf_vm,
+    },
 };
 
-use super::util::preprocess_request_raw;
+use super::{
+    block1::{self, Block1Assembler, Block1Status, BlockOption},
+    util::{self, preprocess_request_raw, HandlerError},
+};
 
 pub struct SuitPullHandler {
     /// Status of the last processed request, if successful it will contain
@@ -52,6 +69,7 @@ impl coap_handler::Handler for SuitPullHandler {
         let config = VMConfiguration::decode(request.config);
 
         debug!(
+            target: targets::COAP,
             "Received SUIT pull request: {:?}, config: {:?}",
             request, config
         );
@@ -66,10 +84,12 @@ impl coap_handler::Handler for SuitPullHandler {
         );
 
         if let Ok(()) = fetch_result {
-            debug!("SUIT fetch successful.");
+            debug!(target: targets::COAP, "SUIT fetch successful.");
         } else {
+            // suit_fetch erases the slot if the program couldn't be fetched or
+            // prepared, other errors leave the existing program untouched.
             let err = format!("SUIT fetch failed: {:?}", fetch_result.err().unwrap());
-            debug!("{}", err);
+            debug!(target: targets::COAP, "{}", err);
             self.last_request_status = Err(err);
             return coap_numbers::code::BAD_REQUEST;
         }
@@ -79,11 +99,17 @@ impl coap_handler::Handler for SuitPullHandler {
             let program = suit_storage::load_program(&mut program_buffer, config.suit_slot);
 
             let helper_idxs: Vec<u32> = match config.helper_access_list_source {
-                HelperAccessListSource::ExecuteRequest => HelperAccessList::from(request.helpers)
-                    .0
-                    .into_iter()
-                    .map(|f| f.id as u32)
-                    .collect(),
+                HelperAccessListSource::ExecuteRequest => {
+                    match HelperAccessList::try_from_hex(&request.helpers) {
+                        Ok(helpers) => helpers.0.into_iter().map(|f| f.id as u32).collect(),
+                        Err(e) => {
+                            error!(target: targets::COAP, "{}", e);
+                            self.last_request_status = Err(e);
+                            let _ = suit_storage::suit_erase(config.suit_slot);
+                            return coap_numbers::code::BAD_REQUEST;
+                        }
+                    }
+                }
                 HelperAccessListSource::BinaryMetadata => {
                     if config.binary_layout == BinaryFileLayout::ExtendedHeader {
                         extract_allowed_helpers(&program)
@@ -92,20 +118,23 @@ impl coap_handler::Handler for SuitPullHandler {
                             .collect()
                     } else {
                         let error_msg = "Tried to extract allowed helper functions from an incompatible binary file.";
-                        error!("{}", error_msg);
+                        error!(target: targets::COAP, "{}", error_msg);
                         self.last_request_status = Err(error_msg.to_string());
                         let _ = suit_storage::suit_erase(config.suit_slot);
                         return coap_numbers::code::BAD_REQUEST;
                     }
                 }
             };
+            // No helpers can be called in the strict sandbox, regardless of
+            // the list in the request.
+            let helper_idxs = if helpers::STRICT_SANDBOX { Vec::new() } else { helper_idxs };
 
             let interpreter = rbpf_vm::map_interpreter(config.binary_layout);
 
             if let Err(e) = rbpf::check_helpers(program, &helper_idxs, interpreter)
                 .map_err(|e| format!("Helper verification failed: {}", e.error))
             {
-                error!("{}", e);
+                error!(target: targets::COAP, "{}", e);
                 self.last_request_status = Err(e);
                 let _ = suit_storage::suit_erase(config.suit_slot);
                 return coap_numbers::code::BAD_REQUEST;
@@ -125,19 +154,777 @@ impl coap_handler::Handler for SuitPullHandler {
         response: &mut impl MutableWritableMessage,
         request: Self::RequestData,
     ) {
+        let suit_manifest = match &self.last_request_status {
+            Ok(suit_manifest) => suit_manifest,
+            Err(e) => {
+                let message = format!("SUIT pull request failed: {}", e);
+                return util::error_response(response, request, &message);
+            }
+        };
         response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let res = format!(
+            "SUIT pull request processed successfully for manifest: {}",
+            suit_manifest
+        );
+        response.set_payload(res.as_bytes());
+    }
+}
+
+/// Erases the program from a SUIT storage slot, the slot is specified as the
+/// last segment of the path: `/storage/erase/<slot>`. After erasing, the slot
+/// is free and can be loaded with a new program without setting the `erase`
+/// flag in the pull request. Slots used by a running program are not erased
+/// and the CONFLICT code is returned instead.
+pub struct StorageEraseHandler {
+    last_request_status: Result<usize, String>,
+}
+
+impl StorageEraseHandler {
+    pub fn new() -> Self {
+        Self {
+            last_request_status: Err("No requests processed yet".to_string()),
+        }
+    }
+
+    fn erase(&mut self, request: &impl ReadableMessage) -> Result<usize, (u8, String)> {
+        if request.code().into() != coap_numbers::code::POST {
+            return Err((
+                coap_numbers::code::METHOD_NOT_ALLOWED,
+                "Method not allowed".to_string(),
+            ));
+        }
+
+        let slot = util::last_uri_path_segment(request)
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|s| suit_storage::is_suit_slot(*s))
+            .ok_or((
+                coap_numbers::code::BAD_REQUEST,
+                "Invalid SUIT storage slot".to_string(),
+            ))?;
+
+        match suit_storage::suit_slot_status(slot) {
+            SuitStorageSlotStatus::Running => Err((
+                coap_numbers::code::CONFLICT,
+                format!("Slot {} is used by a running program", slot),
+            ))?,
+            SuitStorageSlotStatus::Free => Err((
+                coap_numbers::code::NOT_FOUND,
+                format!("Slot {} is already empty", slot),
+            ))?,
+            SuitStorageSlotStatus::Occupied => {}
+        }
+
+        // The slot could have been claimed by a worker or an execution request
+        // in the meantime, in which case suit_erase refuses to erase it.
+        suit_storage::suit_erase(slot).map_err(|e| (coap_numbers::code::CONFLICT, e))?;
+        Ok(slot)
+    }
+}
+
+impl coap_handler::Handler for StorageEraseHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        match self.erase(request) {
+            Ok(slot) => {
+                debug!(target: targets::COAP, "Erased SUIT storage slot {}", slot);
+                self.last_request_status = Ok(slot);
+                coap_numbers::code::DELETED
+            }
+            Err((code, e)) => {
+                error!(target: targets::COAP, "Failed to erase SUIT storage slot: {}", e);
+                self.last_request_status = Err(e);
+                code
+            }
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
 
-        let res = match &self.last_request_status {
-            Ok(suit_manifest) => {
-                format!(
-                    "SUIT pull request processed successfully for manifest: {}",
-                    suit_manifest
-                )
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        match &self.last_request_status {
+            Ok(slot) => {
+                response.set_code(request.try_into().map_err(|_| ()).unwrap());
+                response.set_payload(format!("SUIT storage slot {} erased", slot).as_bytes());
             }
             Err(e) => {
-                format!("SUIT pull request failed: {}", e)
+                util::error_response(response, request, &format!("Erase request failed: {}", e))
             }
+        }
+    }
+}
+
+/// Maximum number of bytes that can be patched in a single request. Patches
+/// which don't fit into a single CoAP packet are sent block-wise.
+const MAX_PATCH_LEN: usize = 1024;
+
+/// Overwrites a small range of the program in a SUIT storage slot in place,
+/// e.g. to tune a threshold constant without redeploying the whole program.
+/// The slot and the byte offset at which the patch starts are specified as the
+/// last segments of the path: `/storage/patch/<slot>/<offset>` and the payload
+/// contains the raw bytes to write (at most [`MAX_PATCH_LEN`]).
+///
+/// The offset refers to the program as it is stored in the slot, i.e. after
+/// the metadata header was stripped and the program was decompressed. Patches
+/// which don't fit into the program or would modify its relocations are
+/// rejected (see [`suit_storage::patch_program_in_slot`]). Programs which were
+/// compiled by the JIT need to be recompiled to pick up the patch.
+///
+/// Patches which don't fit into a single CoAP packet can be sent using the
+/// Block1 option, all blocks need to be sent to the same path. The patch is
+/// only written once the last block is received.
+pub struct StoragePatchHandler {
+    last_patch: Option<(usize, usize, usize)>,
+    error: Option<HandlerError>,
+    upload: Block1Assembler,
+    /// Block1 option of the last request, echoed in the response.
+    block1: Option<BlockOption>,
+}
+
+impl StoragePatchHandler {
+    pub fn new() -> Self {
+        Self {
+            last_patch: None,
+            error: None,
+            upload: Block1Assembler::new(MAX_PATCH_LEN),
+            block1: None,
+        }
+    }
+
+    fn handle_request(&mut self, request: &impl ReadableMessage) -> Result<u8, HandlerError> {
+        if request.code().into() != coap_numbers::code::POST {
+            Err(HandlerError::from(coap_numbers::code::METHOD_NOT_ALLOWED))?;
+        }
+        // The path is /storage/patch/<slot>/<offset>
+        let segments = request
+            .options()
+            .filter(|o| o.number() == coap_numbers::option::URI_PATH)
+            .filter_map(|o| core::str::from_utf8(o.value()).ok().map(String::from))
+            .skip(2)
+            .collect::<Vec<String>>();
+        let slot = segments
+            .first()
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|s| suit_storage::is_suit_slot(*s))
+            .ok_or_else(|| HandlerError::bad_request("Invalid SUIT storage slot".to_string()))?;
+        let offset = segments
+            .get(1)
+            .and_then(|s| s.parse::<usize>().ok())
+            .ok_or_else(|| HandlerError::bad_request("Invalid patch offset".to_string()))?;
+
+        let block = block1::block1_option(request)?;
+        let status = self.upload.receive(block, request.payload())?;
+        self.block1 = block;
+        let Block1Status::Complete(patch) = status else {
+            return Ok(coap_numbers::code::CONTINUE);
         };
-        response.set_payload(res.as_bytes());
+        if patch.is_empty() || patch.len() > MAX_PATCH_LEN {
+            Err(HandlerError::bad_request(format!(
+                "The patch needs to contain between 1 and {} bytes",
+                MAX_PATCH_LEN
+            )))?;
+        }
+
+        // Executions of the slot are excluded until the patch is written.
+        let _deployment = suit_storage::lock_slot_for_deployment(slot)
+            .map_err(HandlerError::service_unavailable)?;
+        match suit_storage::suit_slot_status(slot) {
+            SuitStorageSlotStatus::Running => Err(HandlerError::new(
+                coap_numbers::code::CONFLICT,
+                format!("Slot {} is used by a running program", slot),
+            ))?,
+            SuitStorageSlotStatus::Free => Err(HandlerError::new(
+                coap_numbers::code::NOT_FOUND,
+                format!("Slot {} is empty", slot),
+            ))?,
+            SuitStorageSlotStatus::Occupied => {}
+        }
+
+        suit_storage::patch_program_in_slot(slot, offset, &patch)
+            .map_err(HandlerError::bad_request)?;
+        self.last_patch = Some((slot, offset, patch.len()));
+        Ok(coap_numbers::code::CHANGED)
+    }
+}
+
+impl coap_handler::Handler for StoragePatchHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        self.last_patch = None;
+        self.block1 = None;
+        let result = self.handle_request(request);
+        util::response_code(result, &mut self.error)
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        if let Some(e) = self.error.as_ref() {
+            return util::error_response(response, e.code, &e.message);
+        }
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        block1::set_block1_option(response, self.block1);
+        let Some((slot, offset, length)) = self.last_patch else {
+            return;
+        };
+        let json = format!(
+            "{{\"slot\": {}, \"offset\": {}, \"length\": {}}}",
+            slot, offset, length
+        );
+        util::set_json_payload(response, json);
+    }
+}
+
+/// Outcome of verifying the program loaded into a slot.
+struct VerificationReport {
+    slot: usize,
+    result: Result<(), String>,
+    helpers: Vec<HelperFunctionID>,
+}
+
+/// Loads the program from a SUIT storage slot and runs the verifier on it
+/// without executing the program. It allows for checking that a deployed
+/// program is going to be accepted before it gets executed. The slot is
+/// specified as the last segment of the path: `/storage/verify/<slot>` and the
+/// payload is the same as for execution requests (the VM configuration and the
+/// allowed helpers). The relocations of raw object files are resolved when
+/// the program is pulled into the slot, so they are already in place here.
+///
+/// The response contains the result of the verification together with the
+/// list of helpers that the program was verified against.
+pub struct StorageVerifyHandler {
+    last_report: Result<VerificationReport, String>,
+}
+
+impl StorageVerifyHandler {
+    pub fn new() -> Self {
+        Self {
+            last_report: Err("No requests processed yet".to_string()),
+        }
+    }
+}
+
+/// Verifies the program in the slot given by the last segment of the request
+/// path. The payload of the request is the same as for execution requests.
+fn verify_program_in_slot(
+    request: &impl ReadableMessage,
+) -> Result<(VerificationReport, BinaryFileLayout), u8> {
+    let slot = util::last_uri_path_segment(request)
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|s| suit_storage::is_suit_slot(*s))
+        .ok_or(coap_numbers::code::BAD_REQUEST)?;
+
+    let mut request: VMExecutionRequest = util::parse_request(request)?;
+    request.configuration.suit_slot = slot;
+
+    if suit_storage::suit_slot_status(slot) == SuitStorageSlotStatus::Free {
+        return Err(coap_numbers::code::NOT_FOUND);
+    }
+    let _slot_lock =
+        suit_storage::lock_slot_for_execution(slot).map_err(util::service_unavailable)?;
+
+    let helpers = match request.configuration.helper_access_list_source {
+        HelperAccessListSource::ExecuteRequest => request.allowed_helpers.clone(),
+        // Initializing the VM fails for other layouts, so the helpers
+        // are only extracted from the compatible ones.
+        HelperAccessListSource::BinaryMetadata
+            if request.configuration.binary_layout == BinaryFileLayout::ExtendedHeader =>
+        {
+            extract_allowed_helpers(suit_storage::load_program_static(slot))
+        }
+        HelperAccessListSource::BinaryMetadata => Vec::new(),
+    };
+
+    let layout = request.configuration.binary_layout;
+    let mut vm = construct_vm(request.configuration, request.allowed_helpers)
+        .map_err(util::internal_server_error)?;
+    let result = vm.initialize_vm().and_then(|()| vm.verify());
+    if let Err(e) = &result {
+        error!(
+            target: targets::COAP,
+            "Verification of the program in slot {} failed: {}",
+            slot,
+            e,
+        );
+    }
+
+    Ok((
+        VerificationReport {
+            slot,
+            result,
+            helpers,
+        },
+        layout,
+    ))
+}
+
+impl coap_handler::Handler for StorageVerifyHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        match verify_program_in_slot(request) {
+            Ok((report, _)) => {
+                self.last_report = Ok(report);
+                coap_numbers::code::CHANGED
+            }
+            Err(code) => {
+                self.last_report = Err("Invalid verification request".to_string());
+                code
+            }
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        let report = match &self.last_report {
+            Ok(report) => report,
+            Err(e) => return util::error_response(response, request, e),
+        };
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let helpers = report
+            .helpers
+            .iter()
+            .map(|h| format!("{:02x}", *h as u8))
+            .collect::<String>();
+        // The error is placed last so that it is the first field to be
+        // dropped if the response doesn't fit into the payload.
+        let json = match &report.result {
+            Ok(()) => format!(
+                "{{\"slot\": {}, \"verified\": true, \"helpers\": \"{}\"}}",
+                report.slot, helpers
+            ),
+            Err(e) => format!(
+                "{{\"slot\": {}, \"verified\": false, \"helpers\": \"{}\", \"error\": \"{}\"}}",
+                report.slot,
+                helpers,
+                json::escape(e)
+            ),
+        };
+        util::set_json_payload(response, json);
+    }
+}
+
+/// Lists the SUIT storage slots: their total number, the number of free ones
+/// and the indices of the slots holding a program (including the ones that
+/// are currently running). Clients can use it to check whether there is
+/// space for deploying another program before sending the pull request.
+/// The occupied slots holding programs that can be JIT-compiled are listed
+/// separately (see [`jit_prog_storage::jit_compatible`]), so that clients
+/// only offer the JIT for those.
+pub struct StorageListHandler;
+
+impl coap_handler::Handler for StorageListHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        coap_numbers::code::CONTENT
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        if request != coap_numbers::code::CONTENT {
+            return;
+        }
+
+        let occupied = suit_storage::suit_slots()
+            .filter(|s| suit_storage::suit_slot_status(*s) != SuitStorageSlotStatus::Free)
+            .collect::<Vec<usize>>();
+        let jit_compatible = occupied
+            .iter()
+            .copied()
+            .filter(|s| jit_prog_storage::jit_compatible(*s).unwrap_or(false))
+            .collect::<Vec<usize>>();
+        let json = format!(
+            "{{\"slots\": {}, \"free\": {}, \"occupied\": {:?}, \"jit_compatible\": {:?}}}",
+            suit_storage::suit_slots().count(),
+            suit_storage::free_slot_count(),
+            occupied,
+            jit_compatible
+        );
+        util::set_json_payload(response, json);
+    }
+}
+
+/// Reports the status of a SUIT storage slot together with the name and
+/// version of the program loaded into it (see [`program_metadata`]). The slot
+/// is specified as the last segment of the path: `/storage/info/<slot>`.
+/// Programs deployed without the metadata header are reported with `null`
+/// name and version.
+pub struct StorageInfoHandler {
+    last_request_status: Result<(usize, SuitStorageSlotStatus, Option<ProgramMetadata>), String>,
+}
+
+impl StorageInfoHandler {
+    pub fn new() -> Self {
+        Self {
+            last_request_status: Err("No requests processed yet".to_string()),
+        }
+    }
+}
+
+impl coap_handler::Handler for StorageInfoHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        let Some(slot) = util::last_uri_path_segment(request)
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|s| suit_storage::is_suit_slot(*s))
+        else {
+            self.last_request_status = Err("Invalid SUIT storage slot".to_string());
+            return coap_numbers::code::BAD_REQUEST;
+        };
+
+        let status = suit_storage::suit_slot_status(slot);
+        let metadata = program_metadata::get_slot_metadata(slot);
+        self.last_request_status = Ok((slot, status, metadata));
+        coap_numbers::code::CONTENT
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        let (slot, status, metadata) = match &self.last_request_status {
+            Ok(info) => info,
+            Err(e) => return util::error_response(response, request, e),
+        };
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let status = match status {
+            SuitStorageSlotStatus::Free => "free",
+            SuitStorageSlotStatus::Occupied => "occupied",
+            SuitStorageSlotStatus::Running => "running",
+        };
+        // The name and the version can contain any characters, so they need
+        // to be escaped.
+        let (name, version) = match metadata {
+            Some(m) => (
+                format!("\"{}\"", json::escape(&m.name)),
+                format!("\"{}\"", json::escape(&m.version)),
+            ),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        let json = format!(
+            "{{\"slot\": {}, \"status\": \"{}\", \"name\": {}, \"version\": {}}}",
+            slot, status, name, version
+        );
+        util::set_json_payload(response, json);
+    }
+}
+
+/// Returns the machine code that the JIT generated for a given slot so that it
+/// can be disassembled externally, e.g. using objdump. The slot and an optional
+/// byte offset are specified as the last segments of the path:
+/// `/storage/jit-dump/<slot>[/<offset>]`. The response contains as many program
+/// bytes (in hex) as fit into the payload together with the total length, so
+/// longer programs need to be read in several requests with increasing offsets.
+///
+/// The endpoint exposes the contents of executable memory, so it is only
+/// available when the server is built with the `jit-dump` feature.
+#[cfg(feature = "jit-dump")]
+pub struct JitDumpHandler {
+    last_request_status: Result<(usize, JitProgramDump), String>,
+}
+
+#[cfg(feature = "jit-dump")]
+impl JitDumpHandler {
+    pub fn new() -> Self {
+        Self {
+            last_request_status: Err("No requests processed yet".to_string()),
+        }
+    }
+
+    /// Formats the response for the chunk of the program starting at `offset`.
+    fn format_dump(offset: usize, dump: &JitProgramDump) -> String {
+        let hex = dump
+            .bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        format!(
+            "{{\"length\": {}, \"text_offset\": {}, \"offset\": {}, \"bytes\": \"{}\"}}",
+            dump.program_length, dump.text_offset, offset, hex
+        )
+    }
+}
+
+#[cfg(feature = "jit-dump")]
+impl coap_handler::Handler for JitDumpHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        // The path is /storage/jit-dump/<slot>[/<offset>]
+        let segments = request
+            .options()
+            .filter(|o| o.number() == coap_numbers::option::URI_PATH)
+            .filter_map(|o| core::str::from_utf8(o.value()).ok().map(String::from))
+            .skip(2)
+            .collect::<Vec<String>>();
+
+        let Some(slot) = segments
+            .first()
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|s| *s < JIT_STORAGE_SLOTS_NUM)
+        else {
+            self.last_request_status = Err("Invalid JIT storage slot".to_string());
+            return coap_numbers::code::BAD_REQUEST;
+        };
+        let Ok(offset) = segments.get(1).map_or(Ok(0), |s| s.parse::<usize>()) else {
+            self.last_request_status = Err("Invalid offset".to_string());
+            return coap_numbers::code::BAD_REQUEST;
+        };
+
+        // Each byte takes two hex digits, the rest of the payload is needed
+        // for the other fields of the response. Their values are bounded by
+        // the slot size, which is used here to make sure that they fit.
+        let header = Self::format_dump(
+            offset,
+            &JitProgramDump {
+                bytes: Vec::new(),
+                program_length: JIT_SLOT_SIZE,
+                text_offset: JIT_SLOT_SIZE,
+            },
+        );
+        let max_len = util::COAP_RESPONSE_PAYLOAD_SIZE.saturating_sub(header.len()) / 2;
+
+        self.last_request_status = jit_prog_storage::dump_program(slot, offset, max_len)
+            .map(|dump| (offset, dump));
+        match self.last_request_status {
+            Ok(_) => coap_numbers::code::CONTENT,
+            Err(_) => coap_numbers::code::NOT_FOUND,
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        if request == coap_numbers::code::METHOD_NOT_ALLOWED {
+            response.set_code(request.try_into().map_err(|_| ()).unwrap());
+            return;
+        }
+
+        match &self.last_request_status {
+            Ok((offset, dump)) => {
+                response.set_code(request.try_into().map_err(|_| ()).unwrap());
+                response.set_payload(Self::format_dump(*offset, dump).as_bytes())
+            }
+            Err(e) => util::error_response(response, request, e),
+        }
+    }
+}
+
+/// Verifies the program in a SUIT storage slot and, if it passes, reports its
+/// size and complexity (see [`program_analysis`]). The slot is specified as
+/// the last segment of the path: `/storage/analyze/<slot>` and the payload is
+/// the same as for `/storage/verify/<slot>`. Programs that fail verification
+/// are reported together with the error instead of the analysis.
+pub struct StorageAnalyzeHandler {
+    last_report: Result<(VerificationReport, Result<ProgramReport, String>), String>,
+}
+
+impl StorageAnalyzeHandler {
+    pub fn new() -> Self {
+        Self {
+            last_report: Err("No requests processed yet".to_string()),
+        }
+    }
+
+    fn analyze(
+        &mut self,
+        request: &impl ReadableMessage,
+    ) -> Result<(VerificationReport, Result<ProgramReport, String>), u8> {
+        let (verification, layout) = verify_program_in_slot(request)?;
+        if let Err(e) = &verification.result {
+            return Ok((verification, Err(e.clone())));
+        }
+        let program = suit_storage::load_program_static(verification.slot);
+        let analysis = program_analysis::analyze(program, layout);
+        if let Err(e) = &analysis {
+            error!(
+                target: targets::COAP,
+                "Analysis of the program in slot {} failed: {}",
+                verification.slot,
+                e,
+            );
+        }
+        Ok((verification, analysis))
+    }
+}
+
+impl coap_handler::Handler for StorageAnalyzeHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        match self.analyze(request) {
+            Ok(report) => {
+                self.last_report = Ok(report);
+                coap_numbers::code::CHANGED
+            }
+            Err(code) => {
+                self.last_report = Err("Invalid analysis request".to_string());
+                code
+            }
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        let (verification, analysis) = match &self.last_report {
+            Ok(report) => report,
+            Err(e) => return util::error_response(response, request, e),
+        };
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let json = match analysis {
+            Ok(report) => format!(
+                "{{\"slot\": {}, \"verified\": true, {}}}",
+                verification.slot,
+                report.json_fields()
+            ),
+            Err(e) => format!(
+                "{{\"slot\": {}, \"verified\": {}, \"error\": \"{}\"}}",
+                verification.slot,
+                verification.result.is_ok(),
+                json::escape(e)
+            ),
+        };
+        util::set_json_payload(response, json);
+    }
+}
+
+/// Verifies the program in a SUIT storage slot and, if it passes, reports the
+/// estimate of its worst-case execution time (see [`wcet`]). The slot is
+/// specified as the last segment of the path: `/storage/wcet/<slot>` and the
+/// payload is the same as for `/storage/verify/<slot>`. Programs whose
+/// execution time can't be bounded are reported together with the reason.
+pub struct StorageWcetHandler {
+    last_report: Result<(VerificationReport, Result<WcetEstimate, String>), String>,
+}
+
+impl StorageWcetHandler {
+    pub fn new() -> Self {
+        Self {
+            last_report: Err("No requests processed yet".to_string()),
+        }
+    }
+
+    fn estimate(
+        &mut self,
+        request: &impl ReadableMessage,
+    ) -> Result<(VerificationReport, Result<WcetEstimate, String>), u8> {
+        let (verification, layout) = verify_program_in_slot(request)?;
+        if let Err(e) = &verification.result {
+            return Ok((verification, Err(e.clone())));
+        }
+        let program = suit_storage::load_program_static(verification.slot);
+        let estimate = wcet::estimate_wcet(program, layout);
+        match &estimate {
+            Ok(estimate) => debug!(
+                target: targets::COAP,
+                "WCET of the program in slot {}: {}",
+                verification.slot,
+                wcet::describe(estimate),
+            ),
+            Err(e) => error!(
+                target: targets::COAP,
+                "WCET estimation of the program in slot {} failed: {}",
+                verification.slot,
+                e,
+            ),
+        }
+        Ok((verification, estimate))
+    }
+}
+
+impl coap_handler::Handler for StorageWcetHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        match self.estimate(request) {
+            Ok(report) => {
+                self.last_report = Ok(report);
+                coap_numbers::code::CHANGED
+            }
+            Err(code) => {
+                self.last_report = Err("Invalid WCET request".to_string());
+                code
+            }
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(
+        &mut self,
+        response: &mut impl MutableWritableMessage,
+        request: Self::RequestData,
+    ) {
+        let (verification, estimate) = match &self.last_report {
+            Ok(report) => report,
+            Err(e) => return util::error_response(response, request, e),
+        };
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let json = match estimate {
+            Ok(estimate) => format!(
+                "{{\"slot\": {}, \"verified\": true, {}}}",
+                verification.slot,
+                estimate.json_fields()
+            ),
+            Err(e) => format!(
+                "{{\"slot\": {}, \"verified\": {}, \"error\": \"{}\"}}",
+                verification.slot,
+                verification.result.is_ok(),
+                json::escape(e)
+            ),
+        };
+        util::set_json_payload(response, json);
     }
 }
@@ -1,13 +1,35 @@
-use alloc::string::{String, ToString};
-use coap_message::ReadableMessage;
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::convert::TryInto;
+use coap_message::{MessageOption, MutableWritableMessage, ReadableMessage};
+use macros::set_env_or_default;
 use micro_bpf_common::VMExecutionRequest;
 use riot_wrappers::gcoap::PacketBuffer;
 
 use log::{debug, error, info};
+use crate::{
+    util::{json, logger::targets},
+    vm::middleware::CoapContext,
+};
 
 // This module contains common utility functions that are used by the handler
 // implementations for all of the endpoints.
 
+/// Maximum number of payload bytes that can be written into a CoAP response.
+/// The whole response PDU needs to fit into the gcoap packet buffer, which is
+/// configured in the Makefile using `CONFIG_GCOAP_PDU_BUF_SIZE`, so this value
+/// needs to leave enough space for the CoAP header, token and options.
+/// It can be overridden at compile time by setting the `COAP_RESPONSE_PAYLOAD_SIZE`
+/// environment variable.
+pub const COAP_RESPONSE_PAYLOAD_SIZE: usize =
+    set_env_or_default!("COAP_RESPONSE_PAYLOAD_SIZE", 100);
+
+/// Appended to JSON responses that had to be shortened to fit into the
+/// response buffer.
+const TRUNCATION_MARKER: &str = "\"truncated\": true}";
+
 /// Allows for timing the request processing duration in CoAP servers.
 /// It is initialised with a dynamic implementation of another endpoint handler
 /// and then it handles request by timing the execution of the handle method
@@ -32,7 +54,7 @@ impl riot_wrappers::gcoap::Handler for TimedHandler<'_> {
         let payload_len = self.handler.handle(pkt);
 
         let end: u32 = unsafe { riot_sys::inline::ztimer_now(clock) };
-        info!("Total request processing time: {} [us]", end - start);
+        info!(target: targets::COAP, "Total request processing time: {} [us]", end - start);
 
         return payload_len;
     }
@@ -51,26 +73,232 @@ pub fn preprocess_request_raw<'a>(request: &'a impl ReadableMessage) -> Result<S
         return Err(coap_numbers::code::BAD_REQUEST);
     };
 
-    debug!("Request payload received: {}", s);
+    debug!(target: targets::COAP, "Request payload received: {}", s);
     Ok(s.to_string())
 }
 
+/// Returns the last segment of the request path. It is used by the endpoints
+/// that are registered for a whole subtree of paths (e.g. `/native/<name>`)
+/// to extract their argument.
+pub fn last_uri_path_segment(request: &impl ReadableMessage) -> Option<String> {
+    request
+        .options()
+        .filter(|o| o.number() == coap_numbers::option::URI_PATH)
+        .last()
+        .and_then(|o| core::str::from_utf8(o.value()).ok().map(String::from))
+}
+
+/// Name of the URI query parameter carrying the correlation ID of a request.
+const CORRELATION_ID_QUERY: &str = "cid=";
+
+/// Returns the correlation ID that the client attached to the request as the
+/// `cid=<id>` URI query parameter (e.g. `/long-running?cid=42`). The ID
+/// isn't interpreted by the server, it is echoed in the log lines and the
+/// response so that the clients issuing many concurrent requests can match
+/// them. Returns `Ok(None)` if the request doesn't have one and an error if
+/// it isn't a valid `u32`.
+pub fn correlation_id(request: &impl ReadableMessage) -> Result<Option<u32>, u8> {
+    let Some(value) = request
+        .options()
+        .filter(|o| o.number() == coap_numbers::option::URI_QUERY)
+        .find_map(|o| {
+            core::str::from_utf8(o.value())
+                .ok()
+                .and_then(|q| q.strip_prefix(CORRELATION_ID_QUERY))
+                .map(String::from)
+        })
+    else {
+        return Ok(None);
+    };
+    value
+        .parse::<u32>()
+        .map(Some)
+        .map_err(|_| bad_request(format!("Invalid correlation ID: {}", value)))
+}
+
+/// CoAP content formats of the execution requests, see [`decode_execution_request`].
+const CONTENT_FORMAT_JSON: u16 = 50;
+const CONTENT_FORMAT_CBOR: u16 = 60;
+
+/// Parses the execution request from the payload of a POST request. See
+/// [`decode_execution_request`] for the accepted encodings.
 pub fn parse_request(request: &impl ReadableMessage) -> Result<VMExecutionRequest, u8> {
-    let request_data = preprocess_request_raw(request)?;
-    let request = VMExecutionRequest::decode(request_data).map_err(bad_request)?;
-    Ok(request)
+    parse_request_with_error(request).map_err(|e| e.code)
+}
+
+/// Same as [`parse_request`], but the error keeps the reason why the request
+/// was rejected so that it can be reported using [`error_response`].
+pub fn parse_request_with_error(
+    request: &impl ReadableMessage,
+) -> Result<VMExecutionRequest, HandlerError> {
+    if request.code().into() != coap_numbers::code::POST {
+        return Err(HandlerError::new(
+            coap_numbers::code::METHOD_NOT_ALLOWED,
+            "Execution requests need to be sent using POST",
+        ));
+    }
+    decode_execution_request(request.payload(), content_format(request))
+        .map_err(HandlerError::bad_request)
+}
+
+/// Returns the value of the Content-Format option of the request, if present.
+fn content_format(request: &impl ReadableMessage) -> Option<u16> {
+    request
+        .options()
+        .find(|o| o.number() == coap_numbers::option::CONTENT_FORMAT)
+        .map(|o| o.value().iter().fold(0u16, |acc, b| (acc << 8) | *b as u16))
+}
+
+/// Decodes an execution request sent either in the compact encoding used by
+/// the tools (see `VMExecutionRequest::decode`) or as JSON. If the client
+/// specified the content format, only that encoding is tried, otherwise the
+/// compact encoding is tried first and JSON second. CBOR isn't supported as
+/// the server doesn't include a CBOR decoder.
+pub fn decode_execution_request(
+    payload: &[u8],
+    content_format: Option<u16>,
+) -> Result<VMExecutionRequest, String> {
+    let payload = core::str::from_utf8(payload)
+        .map_err(|_| String::from("Request payload is not valid UTF-8"))?;
+    debug!(target: targets::COAP, "Request payload received: {}", payload);
+
+    let decode_json = |payload: &str| {
+        serde_json_core::from_str::<VMExecutionRequest>(payload)
+            .map(|(request, _length)| request)
+            .map_err(|e| format!("Invalid JSON request: {:?}", e))
+    };
+
+    match content_format {
+        Some(CONTENT_FORMAT_JSON) => decode_json(payload),
+        Some(CONTENT_FORMAT_CBOR) => Err("CBOR requests are not supported".to_string()),
+        Some(format) => Err(format!("Unsupported content format: {}", format)),
+        None => VMExecutionRequest::decode(payload.to_string()).or_else(|compact_err| {
+            decode_json(payload).map_err(|json_err| {
+                format!(
+                    "Request is neither in the compact encoding ({}) nor JSON ({})",
+                    compact_err, json_err
+                )
+            })
+        }),
+    }
 }
 
 pub fn internal_server_error(e: String) -> u8 {
-    error!("Failed to initialize the VM: {}", e);
+    error!(target: targets::COAP, "Failed to initialize the VM: {}", e);
     coap_numbers::code::INTERNAL_SERVER_ERROR
 }
 
+/// Used when the request can't be processed at the moment, e.g. because the
+/// program slot is busy executing another request.
+pub fn service_unavailable(e: String) -> u8 {
+    error!(target: targets::COAP, "Service unavailable: {}", e);
+    coap_numbers::code::SERVICE_UNAVAILABLE
+}
+
 pub fn bad_request(e: String) -> u8 {
-    error!("Bad request: {}", e);
+    error!(target: targets::COAP, "Bad request: {}", e);
     coap_numbers::code::BAD_REQUEST
 }
 
+/// Error of a request handler, reported to the client using
+/// [`error_response`]. The constructors named after the response codes log
+/// the error in the same way as the corresponding functions above.
+#[derive(Debug, Clone)]
+pub struct HandlerError {
+    pub code: u8,
+    pub message: String,
+}
+
+impl HandlerError {
+    pub fn new(code: u8, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(e: String) -> Self {
+        Self::new(bad_request(e.clone()), e)
+    }
+
+    pub fn internal_server_error(e: String) -> Self {
+        Self::new(internal_server_error(e.clone()), e)
+    }
+
+    pub fn service_unavailable(e: String) -> Self {
+        Self::new(service_unavailable(e.clone()), e)
+    }
+}
+
+/// Errors which are only reported as a response code (e.g. by
+/// [`correlation_id`]) get a generic message.
+impl From<u8> for HandlerError {
+    fn from(code: u8) -> Self {
+        let message = match code {
+            coap_numbers::code::BAD_REQUEST => "Bad request",
+            coap_numbers::code::NOT_FOUND => "Not found",
+            coap_numbers::code::METHOD_NOT_ALLOWED => "Method not allowed",
+            coap_numbers::code::SERVICE_UNAVAILABLE => "Service unavailable",
+            _ => "Internal server error",
+        };
+        Self::new(code, message)
+    }
+}
+
+/// Stores the error of the handler (if any) so that it can be reported when
+/// the response is built and returns the response code.
+pub fn response_code(result: Result<u8, HandlerError>, error: &mut Option<HandlerError>) -> u8 {
+    match result {
+        Ok(code) => {
+            *error = None;
+            code
+        }
+        Err(e) => {
+            let code = e.code;
+            *error = Some(e);
+            code
+        }
+    }
+}
+
+/// Sets the response code and writes the uniform error body that all handlers
+/// use to report failures, see [`error_body`].
+pub fn error_response(response: &mut impl MutableWritableMessage, code: u8, message: &str) {
+    response.set_code(code.try_into().map_err(|_| ()).unwrap());
+    response.set_payload(error_body(code, message).as_bytes());
+}
+
+/// Formats the uniform error body: `{"error": "<message>", "code": <code>}`,
+/// where the code is written the same way as in the CoAP specification but
+/// without the dot, e.g. 404 for 4.04 Not Found. The message is escaped and
+/// shortened so that the body always fits into the response.
+pub fn error_body(code: u8, message: &str) -> String {
+    let code_number = (code >> 5) as u16 * 100 + (code & 0x1f) as u16;
+    let body = |message: &str| format!("{{\"error\": \"{}\", \"code\": {}}}", message, code_number);
+    let max_len = COAP_RESPONSE_PAYLOAD_SIZE.saturating_sub(body("").len());
+    body(&json::escape_truncated(message, max_len))
+}
+
+/// Writes a response with the given code and the uniform error body (see
+/// [`error_body`]) directly into the packet and returns its length. It is
+/// used by the gcoap handlers, which don't go through `build_response`.
+pub fn raw_error_response(pkt: &mut PacketBuffer, code: u8, message: &str) -> isize {
+    let body = error_body(code, message);
+    unsafe {
+        let ctx = pkt as *mut _ as *mut CoapContext;
+        riot_sys::gcoap_resp_init((*ctx).pkt, (*ctx).buf, (*ctx).len as u32, code as u32);
+        let header_len =
+            riot_sys::coap_opt_finish((*ctx).pkt, riot_sys::COAP_OPT_FINISH_PAYLOAD as u16);
+        if header_len < 0 {
+            return header_len as isize;
+        }
+        let pkt = (*ctx).pkt;
+        let body_len = body.len().min((*pkt).payload_len as usize);
+        core::ptr::copy_nonoverlapping(body.as_ptr(), (*pkt).payload, body_len);
+        header_len as isize + body_len as isize
+    }
+}
+
 pub fn preprocess_request<'a, T>(request: &'a impl ReadableMessage) -> Result<T, u8>
 where
     T: serde::de::Deserialize<'a>,
@@ -83,10 +311,139 @@ where
         return Err(coap_numbers::code::BAD_REQUEST);
     };
 
-    debug!("Request payload received: {}", s);
+    debug!(target: targets::COAP, "Request payload received: {}", s);
     let Ok((request_data, _length)): Result<(T, usize), _> = serde_json_core::from_str(s) else {
         return Err(coap_numbers::code::BAD_REQUEST);
     };
 
     Ok(request_data)
 }
+
+/// Writes a JSON object into the response payload, ensuring that it fits
+/// into the response buffer. See [`fit_json_payload`].
+pub fn set_json_payload(response: &mut impl MutableWritableMessage, json: String) {
+    response.set_payload(fit_json_payload(json).as_bytes());
+}
+
+/// Ensures that a serialized (flat) JSON object fits into the CoAP response
+/// payload. If it is too long, the trailing fields are dropped so that the
+/// object is cut at a field boundary and remains valid JSON. A `"truncated": true`
+/// field is then appended to let the client know that the response is incomplete.
+pub fn fit_json_payload(json: String) -> String {
+    if json.len() <= COAP_RESPONSE_PAYLOAD_SIZE {
+        return json;
+    }
+
+    // We need to leave space for the separating whitespace and the marker.
+    let limit = COAP_RESPONSE_PAYLOAD_SIZE.saturating_sub(TRUNCATION_MARKER.len() + 1);
+
+    // Find the last comma separating two top-level fields that fits within
+    // the limit, commas inside of strings or nested values are skipped.
+    let mut boundary = None;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in json.char_indices() {
+        if i >= limit {
+            break;
+        }
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' | '[' if !in_string => depth += 1,
+            '}' | ']' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 1 => boundary = Some(i),
+            _ => {}
+        }
+    }
+
+    debug!(
+        target: targets::COAP,
+        "Response of {} [B] truncated to fit into {} [B]",
+        json.len(),
+        COAP_RESPONSE_PAYLOAD_SIZE
+    );
+    match boundary {
+        Some(i) => format!("{} {}", &json[..=i], TRUNCATION_MARKER),
+        None => format!("{{{}", TRUNCATION_MARKER),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_json_is_not_modified() {
+        let json = String::from("{\"slot\": 1, \"free\": true}");
+        assert_eq!(fit_json_payload(json.clone()), json);
+    }
+
+    #[test]
+    fn long_json_is_cut_at_a_field_boundary() {
+        let value = "x".repeat(COAP_RESPONSE_PAYLOAD_SIZE);
+        let json = format!("{{\"slot\": 1, \"list\": [1, 2], \"long\": \"{}\"}}", value);
+        assert_eq!(
+            fit_json_payload(json),
+            format!("{{\"slot\": 1, \"list\": [1, 2], {}", TRUNCATION_MARKER)
+        );
+    }
+
+    #[test]
+    fn commas_in_strings_and_nested_values_are_skipped() {
+        let value = "x".repeat(COAP_RESPONSE_PAYLOAD_SIZE);
+        let json = format!(
+            "{{\"a\": \"b, \\\", c\", \"d\": [1, 2], \"e\": \"{}\"}}",
+            value
+        );
+        let fitted = fit_json_payload(json);
+        assert_eq!(
+            fitted,
+            format!(
+                "{{\"a\": \"b, \\\", c\", \"d\": [1, 2], {}",
+                TRUNCATION_MARKER
+            )
+        );
+        assert!(fitted.len() <= COAP_RESPONSE_PAYLOAD_SIZE);
+    }
+
+    #[test]
+    fn single_long_field_is_dropped() {
+        let json = format!(
+            "{{\"long\": \"{}\"}}",
+            "x".repeat(COAP_RESPONSE_PAYLOAD_SIZE)
+        );
+        assert_eq!(fit_json_payload(json), format!("{{{}", TRUNCATION_MARKER));
+    }
+
+    #[test]
+    fn error_body_reports_the_code_without_the_dot() {
+        assert_eq!(
+            error_body(coap_numbers::code::NOT_FOUND, "Slot 3 is empty"),
+            "{\"error\": \"Slot 3 is empty\", \"code\": 404}"
+        );
+        assert_eq!(
+            error_body(coap_numbers::code::SERVICE_UNAVAILABLE, ""),
+            "{\"error\": \"\", \"code\": 503}"
+        );
+    }
+
+    #[test]
+    fn error_body_escapes_the_message() {
+        assert_eq!(
+            error_body(coap_numbers::code::BAD_REQUEST, "Invalid \"slot\":\n\\1"),
+            "{\"error\": \"Invalid \\\"slot\\\":\\n\\\\1\", \"code\": 400}"
+        );
+    }
+
+    #[test]
+    fn error_body_fits_into_the_response() {
+        let message = "\"".repeat(COAP_RESPONSE_PAYLOAD_SIZE);
+        let body = error_body(coap_numbers::code::INTERNAL_SERVER_ERROR, &message);
+        assert!(body.len() <= COAP_RESPONSE_PAYLOAD_SIZE);
+        assert!(body.starts_with("{\"error\": \"\\\""));
+        // The last quote isn't cut in the middle of its escape sequence.
+        assert!(body.ends_with("\\\"\", \"code\": 500}"));
+    }
+}
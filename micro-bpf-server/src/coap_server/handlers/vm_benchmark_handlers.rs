@@ -3,14 +3,19 @@ use core::convert::TryInto;
 use micro_bpf_elf_utils::resolve_relocations;
 
 use log::{debug, error, info};
+use macros::set_env_or_default;
 
 use riot_wrappers::{gcoap::PacketBuffer, msg::v2 as msg, mutex::Mutex, riot_sys};
 
 use coap_message::{MutableWritableMessage, ReadableMessage};
 
+use crate::util::logger::targets;
 use crate::{
-    infra::suit_storage::SUIT_STORAGE_SLOT_SIZE,
-    model::requests::VMExecutionRequestIPC,
+    infra::{
+        benchmark_log, jit_prog_storage, program_analysis, relocation_check,
+        suit_storage::{SuitStorageSlotStatus, SUIT_STORAGE_SLOT_SIZE},
+    },
+    model::{requests::VMExecutionRequestIPC, results::ExecutionResult},
     vm::{construct_vm, timed_vm::BenchmarkResult, TimedVm},
 };
 
@@ -19,7 +24,9 @@ use micro_bpf_common::{BinaryFileLayout, TargetVM, VMExecutionRequest};
 use crate::{
     coap_server::handlers::util::preprocess_request_raw,
     infra::suit_storage,
-    vm::{middleware, FemtoContainerVm, RbpfVm, VirtualMachine, VM_EXEC_REQUEST},
+    vm::{
+        middleware, rbpf_jit::RbpfJIT, FemtoContainerVm, RbpfVm, VirtualMachine, VM_EXEC_REQUEST,
+    },
 };
 
 use super::util;
@@ -29,7 +36,7 @@ use super::util;
 pub struct VMExecutionBenchmarkHandler {
     time_results: BenchmarkResult,
     program_size: u32,
-    result: i64,
+    result: ExecutionResult,
 }
 
 impl VMExecutionBenchmarkHandler {
@@ -37,12 +44,14 @@ impl VMExecutionBenchmarkHandler {
         Self {
             time_results: Default::default(),
             program_size: 0,
-            result: 0,
+            result: Default::default(),
         }
     }
 
     fn handle_benchmark_execution(&mut self, request: VMExecutionRequest) -> Result<u8, u8> {
-
+        let config = request.configuration;
+        let _slot_lock = suit_storage::lock_slot_for_execution(config.suit_slot)
+            .map_err(util::service_unavailable)?;
         let mut vm = construct_vm(
             request.configuration,
             request.allowed_helpers,
@@ -51,9 +60,16 @@ impl VMExecutionBenchmarkHandler {
 
         let mut vm = TimedVm::new(vm);
 
-        self.result = vm.full_run().unwrap() as i64;
+        self.result = vm.full_run_with_status();
         self.time_results = vm.get_results();
         self.program_size = vm.get_program_length() as u32;
+        benchmark_log::record(
+            config.suit_slot,
+            config.binary_layout,
+            config.jit,
+            self.time_results.execution_time,
+            self.result,
+        );
 
         Ok(coap_numbers::code::CHANGED)
     }
@@ -82,15 +98,15 @@ impl coap_handler::Handler for VMExecutionBenchmarkHandler {
         response.set_code(request.try_into().map_err(|_| ()).unwrap());
         let results = self.time_results;
         let resp = format!(
-            "{{\"total\": {}, \"load\": {}, \"verif\": {}, \"exec\": {},\"prog\": {}, \"result\": {}}}",
+            "{{\"total\": {}, \"load\": {}, \"verif\": {}, \"exec\": {},\"prog\": {}, {}}}",
             results.total_time,
             results.load_time,
             results.verification_time,
             results.execution_time,
             self.program_size,
-            self.result
+            self.result.json_fields()
         );
-        response.set_payload(resp.as_bytes());
+        util::set_json_payload(response, resp);
     }
 }
 
@@ -128,6 +144,10 @@ impl VMExecutionOnCoapPktBenchmarkHandler {
         request: VMExecutionRequest,
         pkt: &mut PacketBuffer,
     ) -> isize {
+        let config = request.configuration;
+        let Ok(_slot_lock) = suit_storage::lock_slot_for_execution(config.suit_slot) else {
+            return Self::NO_BYTES_WRITTEN;
+        };
         let Ok(mut vm) = construct_vm(
             request.configuration,
             request.allowed_helpers,
@@ -141,14 +161,21 @@ impl VMExecutionOnCoapPktBenchmarkHandler {
         self.payload_written = vm.full_run_on_coap_pkt(pkt).unwrap() as isize;
         self.time_results = vm.get_results();
         self.log_results();
+        benchmark_log::record(
+            config.suit_slot,
+            config.binary_layout,
+            config.jit,
+            self.time_results.execution_time,
+            ExecutionResult::ok(self.payload_written as u64),
+        );
         self.payload_written
     }
 
     fn log_results(&self) {
-        info!("VM Execution benchmark results:");
-        info!("Timings: \n{:?}", self.time_results);
-        info!("Program size: {} [B]", self.program_size);
-        info!("Payload written: {}", self.payload_written);
+        info!(target: targets::COAP, "VM Execution benchmark results:");
+        info!(target: targets::COAP, "Timings: \n{:?}", self.time_results);
+        info!(target: targets::COAP, "Program size: {} [B]", self.program_size);
+        info!(target: targets::COAP, "Payload written: {}", self.payload_written);
     }
 }
 
@@ -162,8 +189,312 @@ impl riot_wrappers::gcoap::Handler for VMExecutionOnCoapPktBenchmarkHandler {
             return Self::NO_BYTES_WRITTEN;
         };
 
-        debug!("Received VM Execution Request: {:?}", request.configuration);
+        debug!(target: targets::COAP, "Received VM Execution Request: {:?}", request.configuration);
 
         self.handle_benchmark_execution(request, pkt)
     }
 }
+
+/// Number of times the program is executed by each VM when comparing the
+/// interpreter against the JIT.
+const COMPARISON_ITERATIONS: usize = set_env_or_default!("COMPARISON_ITERATIONS", 10);
+
+/// Results of comparing the interpreter against the JIT, all times are in [us].
+#[derive(Debug, Default, Clone, Copy)]
+struct ComparisonResult {
+    interpreter_mean: u32,
+    /// `None` if the JIT doesn't support the binary layout of the program.
+    jit: Option<JitComparisonResult>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct JitComparisonResult {
+    compilation_time: u32,
+    mean: u32,
+    /// Number of executions after which the JIT compilation pays off,
+    /// `None` if the jitted program isn't faster than the interpreter.
+    break_even: Option<u32>,
+}
+
+/// Compares the interpreter against the JIT by executing the same program
+/// multiple times using both of them. The request is sent to
+/// `/benchmark/compare/<slot>` where the slot specifies the SUIT storage slot
+/// of the program, the payload is the usual execution request which
+/// specifies the binary layout and the allowed helpers.
+///
+/// To make the comparison fair, both VMs are given the same program and use
+/// the same interpreter variant, so the relocations are resolved in the same
+/// way. As the JIT only supports the raw object file layout, for other layouts
+/// (or slots without a corresponding JIT storage slot) only the interpreter
+/// results are reported.
+pub struct VMComparisonBenchmarkHandler {
+    results: ComparisonResult,
+}
+
+impl VMComparisonBenchmarkHandler {
+    pub fn new() -> Self {
+        Self {
+            results: Default::default(),
+        }
+    }
+
+    #[inline(always)]
+    fn time_now(clock: *mut riot_sys::inline::ztimer_clock_t) -> u32 {
+        unsafe { riot_sys::inline::ztimer_now(clock) }
+    }
+
+    /// Executes the already initialised VM the configured number of times and
+    /// returns the mean execution time.
+    fn mean_execution_time(vm: &mut dyn VirtualMachine) -> Result<u32, String> {
+        let clock = unsafe { riot_sys::ZTIMER_USEC as *mut riot_sys::inline::ztimer_clock_t };
+        let start = Self::time_now(clock);
+        for _ in 0..COMPARISON_ITERATIONS {
+            vm.execute()?;
+        }
+        Ok((Self::time_now(clock) - start) / COMPARISON_ITERATIONS as u32)
+    }
+
+    fn handle_comparison(&mut self, request: VMExecutionRequest, slot: usize) -> Result<(), String> {
+        let mut config = request.configuration;
+        config.suit_slot = slot;
+        let _slot_lock = suit_storage::lock_slot_for_execution(slot)?;
+
+        let mut interpreter = RbpfVm::new(config, request.allowed_helpers.clone())?;
+        interpreter.initialize_vm()?;
+        interpreter.verify()?;
+        let interpreter_mean = Self::mean_execution_time(&mut interpreter)?;
+
+        // The jitted program is stored in the JIT storage slot with the same
+        // index as the SUIT slot, there are fewer of those available.
+        let jit_supported = config.binary_layout == BinaryFileLayout::RawObjectFile
+            && slot < jit_prog_storage::JIT_STORAGE_SLOTS_NUM;
+
+        let jit = if jit_supported {
+            config.jit = true;
+            config.jit_compile = true;
+            let mut jit = RbpfJIT::new(config, request.allowed_helpers);
+
+            let clock = unsafe { riot_sys::ZTIMER_USEC as *mut riot_sys::inline::ztimer_clock_t };
+            let start = Self::time_now(clock);
+            jit.initialize_vm()?;
+            let compilation_time = Self::time_now(clock) - start;
+            jit.verify()?;
+            let mean = Self::mean_execution_time(&mut jit)?;
+
+            // The JIT wins after n executions once:
+            // n * interpreter_mean > compilation_time + n * mean
+            let break_even = (mean < interpreter_mean)
+                .then(|| compilation_time / (interpreter_mean - mean) + 1);
+
+            Some(JitComparisonResult {
+                compilation_time,
+                mean,
+                break_even,
+            })
+        } else {
+            debug!(
+                target: targets::COAP,
+                "JIT not supported for layout {:?} in slot {}",
+                config.binary_layout, slot
+            );
+            None
+        };
+
+        self.results = ComparisonResult {
+            interpreter_mean,
+            jit,
+        };
+        info!(target: targets::COAP, "Interpreter vs JIT comparison results: {:?}", self.results);
+        Ok(())
+    }
+}
+
+impl coap_handler::Handler for VMComparisonBenchmarkHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        let Some(Ok(slot)) = util::last_uri_path_segment(request).map(|s| s.parse::<usize>())
+        else {
+            return coap_numbers::code::BAD_REQUEST;
+        };
+
+        let parsing_result = util::parse_request(request);
+        let Ok(request) = parsing_result else {
+            return parsing_result.unwrap_err();
+        };
+
+        match self.handle_comparison(request, slot) {
+            Ok(()) => coap_numbers::code::CHANGED,
+            Err(e) => util::internal_server_error(e),
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        if request != coap_numbers::code::CHANGED {
+            return;
+        }
+        let results = self.results;
+        let resp = match results.jit {
+            Some(jit) => format!(
+                "{{\"iters\": {}, \"interp\": {}, \"jit_comp\": {}, \"jit\": {}, \"break_even\": {}}}",
+                COMPARISON_ITERATIONS,
+                results.interpreter_mean,
+                jit.compilation_time,
+                jit.mean,
+                jit.break_even
+                    .map_or(String::from("null"), |n| format!("{}", n))
+            ),
+            None => format!(
+                "{{\"iters\": {}, \"interp\": {}, \"jit\": null}}",
+                COMPARISON_ITERATIONS, results.interpreter_mean
+            ),
+        };
+        util::set_json_payload(response, resp);
+    }
+}
+
+/// Number of times the relocations are resolved when benchmarking them.
+const RELOC_BENCHMARK_ITERATIONS: usize = set_env_or_default!("RELOC_BENCHMARK_ITERATIONS", 10);
+
+/// Measures the time it takes to resolve the relocations of a raw object file
+/// in isolation. The request is sent to `/benchmark/reloc/<slot>`, where the
+/// slot specifies the SUIT storage slot holding the program.
+///
+/// In each iteration the program is copied from the slot into a separate
+/// buffer, so that every resolution starts from the same state, and only the
+/// resolution itself is timed. The program in the slot is left unmodified.
+/// Note that the relocations are already resolved when the program is pulled
+/// into the slot, resolving them again still processes all relocation entries
+/// so the measured time is representative.
+pub struct VMRelocationBenchmarkHandler {
+    /// Mean resolution time in [us] and the number of relocations.
+    results: Result<(u32, usize), String>,
+}
+
+impl VMRelocationBenchmarkHandler {
+    pub fn new() -> Self {
+        Self {
+            results: Err("No requests processed yet".into()),
+        }
+    }
+
+    fn benchmark(&mut self, slot: usize) -> Result<(u32, usize), String> {
+        let _slot_lock = suit_storage::lock_slot_for_execution(slot)?;
+        let program = suit_storage::load_program_static(slot);
+        relocation_check::check_text_section(program)?;
+        let relocations =
+            program_analysis::analyze(program, BinaryFileLayout::RawObjectFile)?.relocations;
+
+        let mut buffer = program.to_vec();
+        let clock = unsafe { riot_sys::ZTIMER_USEC as *mut riot_sys::inline::ztimer_clock_t };
+        let mut total_time = 0;
+        for _ in 0..RELOC_BENCHMARK_ITERATIONS {
+            buffer.copy_from_slice(program);
+            let start = unsafe { riot_sys::inline::ztimer_now(clock) };
+            resolve_relocations(&mut buffer)?;
+            total_time += unsafe { riot_sys::inline::ztimer_now(clock) } - start;
+        }
+        let mean = total_time / RELOC_BENCHMARK_ITERATIONS as u32;
+        info!(
+            target: targets::COAP,
+            "Resolved {} relocations in slot {}, mean time: {} [us]",
+            relocations,
+            slot,
+            mean
+        );
+        Ok((mean, relocations))
+    }
+}
+
+impl coap_handler::Handler for VMRelocationBenchmarkHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        let Some(slot) = util::last_uri_path_segment(request)
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|s| suit_storage::is_suit_slot(*s))
+        else {
+            return coap_numbers::code::BAD_REQUEST;
+        };
+        if suit_storage::suit_slot_status(slot) == SuitStorageSlotStatus::Free {
+            return coap_numbers::code::NOT_FOUND;
+        }
+
+        self.results = self.benchmark(slot);
+        match &self.results {
+            Ok(_) => coap_numbers::code::CHANGED,
+            Err(e) => util::bad_request(e.clone()),
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let Ok((mean, relocations)) = self.results else {
+            return;
+        };
+        let resp = format!(
+            "{{\"iters\": {}, \"mean\": {}, \"relocations\": {}}}",
+            RELOC_BENCHMARK_ITERATIONS, mean, relocations
+        );
+        util::set_json_payload(response, resp);
+    }
+}
+
+/// Exports the benchmark runs recorded in the [`benchmark_log`] as CSV rows
+/// (with a header): `slot,layout,jit,iteration,time_us,result`. Only as many
+/// rows as fit into the response are returned, oldest first. The remaining
+/// ones can be requested using `/benchmark/export/<iteration>`, which returns
+/// the runs starting from the given sequence number.
+pub struct BenchmarkExportHandler {
+    from_iteration: u32,
+}
+
+impl BenchmarkExportHandler {
+    pub fn new() -> Self {
+        Self { from_iteration: 0 }
+    }
+}
+
+impl coap_handler::Handler for BenchmarkExportHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        if request.code().into() != coap_numbers::code::GET {
+            return coap_numbers::code::METHOD_NOT_ALLOWED;
+        }
+        // Without the iteration segment, the last segment is `export`.
+        self.from_iteration = util::last_uri_path_segment(request)
+            .and_then(|s| s.parse::<u32>().ok())
+            .unwrap_or(0);
+        coap_numbers::code::CONTENT
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        if request != coap_numbers::code::CONTENT {
+            return;
+        }
+        let mut csv = String::from(benchmark_log::CSV_HEADER);
+        for record in benchmark_log::records_since(self.from_iteration) {
+            let row = record.csv_row();
+            if csv.len() + row.len() > util::COAP_RESPONSE_PAYLOAD_SIZE {
+                break;
+            }
+            csv.push_str(&row);
+        }
+        response.set_payload(csv.as_bytes());
+    }
+}
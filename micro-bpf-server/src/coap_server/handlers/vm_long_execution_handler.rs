@@ -1,4 +1,4 @@
-use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
 use core::convert::TryInto;
 use micro_bpf_elf_utils::resolve_relocations;
 
@@ -8,9 +8,10 @@ use riot_wrappers::{gcoap::PacketBuffer, msg::v2 as msg, mutex::Mutex, riot_sys}
 
 use coap_message::{MutableWritableMessage, ReadableMessage};
 
+use crate::util::logger::targets;
 use crate::{
     infra::suit_storage::SUIT_STORAGE_SLOT_SIZE,
-    model::requests::VMExecutionRequestIPC,
+    model::requests::{correlation_tag, VMExecutionRequestIPC},
     vm::{construct_vm, timed_vm::BenchmarkResult, TimedVm},
 };
 
@@ -19,14 +20,16 @@ use micro_bpf_common::{BinaryFileLayout, TargetVM, VMExecutionRequest};
 use crate::{
     coap_server::handlers::util::preprocess_request_raw,
     infra::suit_storage,
-    vm::{middleware, FemtoContainerVm, RbpfVm, VirtualMachine, VM_EXEC_REQUEST},
+    vm::{hot_reload, middleware, FemtoContainerVm, RbpfVm, VirtualMachine, VM_EXEC_REQUEST},
 };
 
-use super::util;
+use super::util::{self, HandlerError};
 
 pub struct VMLongExecutionHandler {
     execution_send: Arc<Mutex<msg::SendPort<VMExecutionRequestIPC, { VM_EXEC_REQUEST }>>>,
-    last_request_successful: bool,
+    /// Correlation ID of the last request, it is echoed in the response.
+    correlation_id: Option<u32>,
+    error: Option<HandlerError>,
 }
 
 impl VMLongExecutionHandler {
@@ -35,45 +38,120 @@ impl VMLongExecutionHandler {
     ) -> Self {
         Self {
             execution_send,
-            last_request_successful: false,
+            correlation_id: None,
+            error: None,
         }
     }
+
+    fn handle_request(&mut self, request: &impl ReadableMessage) -> Result<u8, HandlerError> {
+        self.correlation_id = util::correlation_id(request)?;
+        let request = util::parse_request_with_error(request)?;
+
+        let message =
+            VMExecutionRequestIPC::new(request).with_correlation_id(self.correlation_id);
+
+        if self.execution_send.lock().try_send(message).is_err() {
+            error!(target: targets::COAP, "Failed to send execution request message.");
+            return Err(HandlerError::new(
+                coap_numbers::code::INTERNAL_SERVER_ERROR,
+                "Failed to send the execution request to the VM manager",
+            ));
+        }
+        info!(
+            target: targets::COAP,
+            "VM execution request sent successfully{}",
+            correlation_tag(self.correlation_id)
+        );
+        Ok(coap_numbers::code::CHANGED)
+    }
 }
 
 impl coap_handler::Handler for VMLongExecutionHandler {
     type RequestData = u8;
 
     fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
-        let parsing_result = util::parse_request(request);
-        let Ok(request) = parsing_result else {
-            return parsing_result.unwrap_err();
-        };
+        self.correlation_id = None;
+        let result = self.handle_request(request);
+        util::response_code(result, &mut self.error)
+    }
 
-        let message = VMExecutionRequestIPC {
-            request: Box::new(request),
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        util::COAP_RESPONSE_PAYLOAD_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        if let Some(e) = self.error.as_ref() {
+            return util::error_response(response, e.code, &e.message);
+        }
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let resp = format!(
+            "VM Execution request sent successfully!{}",
+            correlation_tag(self.correlation_id)
+        );
+        response.set_payload(resp.as_bytes());
+    }
+}
+
+/// Requests replacing a running long-running program with its new version
+/// without stopping the device: `/execute/reload/<slot>`, where `slot` is the
+/// slot of the running program. The payload is the index of the slot where the
+/// new version was deployed. The swap happens once the running program
+/// returns. If the new version fails verification, the old program keeps
+/// running.
+/// See [`hot_reload`] for more details.
+pub struct VMReloadHandler {
+    last_request_status: Result<(), String>,
+}
+
+impl VMReloadHandler {
+    pub fn new() -> Self {
+        Self {
+            last_request_status: Err("No requests processed yet".into()),
+        }
+    }
+}
+
+impl coap_handler::Handler for VMReloadHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        let payload = match preprocess_request_raw(request) {
+            Ok(payload) => payload,
+            Err(code) => return code,
+        };
+        let slot = util::last_uri_path_segment(request).and_then(|s| s.parse::<usize>().ok());
+        let (Some(slot), Ok(staging_slot)) = (slot, payload.trim().parse::<usize>()) else {
+            return coap_numbers::code::BAD_REQUEST;
         };
 
-        if let Ok(()) = self.execution_send.lock().try_send(message) {
-            info!("VM execution request sent successfully");
-            self.last_request_successful = true;
-            coap_numbers::code::CHANGED
-        } else {
-            error!("Failed to send execution request message.");
-            self.last_request_successful = false;
-            coap_numbers::code::INTERNAL_SERVER_ERROR
+        self.last_request_status = hot_reload::request_reload(slot, staging_slot);
+        match &self.last_request_status {
+            Ok(()) => {
+                info!(
+                    target: targets::COAP,
+                    "Reload of slot {} from slot {} requested",
+                    slot,
+                    staging_slot,
+                );
+                coap_numbers::code::CHANGED
+            }
+            Err(e) => {
+                error!(target: targets::COAP, "Reload request rejected: {}", e);
+                coap_numbers::code::CONFLICT
+            }
         }
     }
 
     fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
-        1
+        util::COAP_RESPONSE_PAYLOAD_SIZE
     }
 
     fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
         response.set_code(request.try_into().map_err(|_| ()).unwrap());
-        if self.last_request_successful {
-            response.set_payload(b"VM Execution request sent successfully!");
-        } else {
-            response.set_payload(b"Failed to send VM Execution request");
-        }
+        let resp = match &self.last_request_status {
+            Ok(()) => String::from("Reload requested"),
+            Err(e) => format!("Reload request failed: {}", e),
+        };
+        response.set_payload(resp.as_bytes());
     }
 }
@@ -1,28 +1,46 @@
 use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
 use core::convert::TryInto;
-use micro_bpf_elf_utils::resolve_relocations;
 
 use log::{debug, error, info};
+use macros::set_env_or_default;
 
 use riot_wrappers::{gcoap::PacketBuffer, msg::v2 as msg, mutex::Mutex, riot_sys};
 
 use coap_message::{MutableWritableMessage, ReadableMessage};
 
+use crate::util::logger::targets;
 use crate::{
     infra::suit_storage::SUIT_STORAGE_SLOT_SIZE,
-    model::requests::VMExecutionRequestIPC,
-    vm::{construct_vm, timed_vm::BenchmarkResult, TimedVm},
+    model::{
+        requests::{correlation_tag, VMExecutionRequestIPC},
+        results::{ExecutionResult, VmStatus},
+    },
+    vm::{
+        construct_vm, execution_result, run_short_lived,
+        timed_vm::BenchmarkResult, ExecutionTimer, TimedVm,
+    },
 };
 
-use micro_bpf_common::{BinaryFileLayout, TargetVM, VMExecutionRequest};
+use micro_bpf_common::{
+    ExecutionModel, HelperAccessListSource, HelperFunctionID, TargetVM, VMConfiguration,
+    VMExecutionRequest,
+};
 
 use crate::{
     coap_server::handlers::util::preprocess_request_raw,
-    infra::suit_storage,
-    vm::{middleware, FemtoContainerVm, RbpfVm, VirtualMachine, VM_EXEC_REQUEST},
+    infra::{
+        coap_routes::{self, CoapRoute},
+        last_results, relocation_check, result_cache, suit_storage,
+    },
+    vm::{
+        middleware, verification_worker, FemtoContainerVm, RbpfVm, VirtualMachine, VM_EXEC_REQUEST,
+    },
 };
 
-use super::util;
+use super::{
+    block1::{self, Block1Assembler, Block1Status, BlockOption},
+    util::{self, HandlerError},
+};
 
 /// Executes a chosen eBPF VM while passing in a pointer to the incoming packet
 /// to the executed program. The eBPF script can access the CoAP packet data.
@@ -46,76 +64,608 @@ impl riot_wrappers::gcoap::Handler for VMExecutionOnCoapPktHandler {
             return NO_BYTES_WRITTEN;
         };
 
-        debug!("Received VM Execution Request: {:?}", request.configuration);
+        debug!(target: targets::COAP, "Received VM Execution Request: {:?}", request.configuration);
+        run_on_coap_pkt(pkt, request.configuration, request.allowed_helpers, 0)
+    }
+}
+
+/// Executes the program on the packet and returns the length of the response
+/// written by it, 0 if the execution failed. The `path_id` is passed to the
+/// program in r2 (see [`coap_routes`]).
+fn run_on_coap_pkt(
+    pkt: &mut PacketBuffer,
+    configuration: VMConfiguration,
+    allowed_helpers: Vec<HelperFunctionID>,
+    path_id: u32,
+) -> isize {
+    const NO_BYTES_WRITTEN: isize = 0;
 
-        let init_result = construct_vm(
-            request.configuration,
-            request.allowed_helpers,
+    let Ok(_slot_lock) = suit_storage::lock_slot_for_execution(configuration.suit_slot) else {
+        error!(target: targets::COAP, "Slot {} is busy", configuration.suit_slot);
+        return NO_BYTES_WRITTEN;
+    };
+
+    let init_result = construct_vm(configuration, allowed_helpers);
+
+    let Ok(mut vm) = init_result else {
+        error!(
+            target: targets::COAP,
+            "Failed to initialize the VM: {}",
+            init_result.err().unwrap()
         );
+        return NO_BYTES_WRITTEN;
+    };
+    vm.set_execution_model(ExecutionModel::WithAccessToCoapPacket);
+    vm.set_args([path_id as u64, 0, 0, 0]);
 
-        let Ok(mut vm) = init_result else {
-            error!(
-                "Failed to initialize the VM: {}",
-                init_result.err().unwrap()
-            );
+    // It is very important that the program executing on the CoAP packet returns
+    // the length of the payload + PDU so that the handler can send the
+    // response accordingly. In case of error the response length should be set to 0.
+    let timer = ExecutionTimer::start();
+    let written = vm.full_run_on_coap_pkt(pkt).unwrap_or_else(|e| {
+        debug!(target: targets::COAP, "Error: {:?}", e);
+        0
+    });
+    timer.finish();
+    written as isize
+}
+
+/// Executes the program registered for the route `/routed/<name>` on the
+/// incoming packet. Unlike [`VMExecutionOnCoapPktHandler`], the request doesn't
+/// need to carry the execution request, the one sent when registering the
+/// route is used instead.
+pub struct VMRoutedExecutionHandler;
+
+impl riot_wrappers::gcoap::Handler for VMRoutedExecutionHandler {
+    fn handle(&mut self, pkt: &mut PacketBuffer) -> isize {
+        const NO_BYTES_WRITTEN: isize = 0;
+
+        let Some(name) = util::last_uri_path_segment(pkt) else {
             return NO_BYTES_WRITTEN;
         };
+        let Some(route) = coap_routes::get_route(&name) else {
+            error!(target: targets::COAP, "No program is registered for /{}", name);
+            return NO_BYTES_WRITTEN;
+        };
+
+        debug!(
+            target: targets::COAP,
+            "Dispatching /{} to slot {} (path id {})",
+            name,
+            route.configuration.suit_slot,
+            route.path_id
+        );
+        if route.in_place {
+            return run_on_payload(pkt, route.configuration, route.allowed_helpers, route.path_id);
+        }
+        run_on_coap_pkt(pkt, route.configuration, route.allowed_helpers, route.path_id)
+    }
+}
+
+/// Executes the program on the payload of the request, which it transforms
+/// into the response payload in place (see
+/// [`VirtualMachine::execute_on_payload`]), and returns the length of the
+/// response. Unlike [`run_on_coap_pkt`], the failures are reported using the
+/// uniform error responses, as the program doesn't write the response header.
+fn run_on_payload(
+    pkt: &mut PacketBuffer,
+    configuration: VMConfiguration,
+    allowed_helpers: Vec<HelperFunctionID>,
+    path_id: u32,
+) -> isize {
+    match execute_on_payload(pkt, configuration, allowed_helpers, path_id) {
+        Ok(response_len) => response_len,
+        Err(e) => util::raw_error_response(pkt, e.code, &e.message),
+    }
+}
+
+fn execute_on_payload(
+    pkt: &mut PacketBuffer,
+    configuration: VMConfiguration,
+    allowed_helpers: Vec<HelperFunctionID>,
+    path_id: u32,
+) -> Result<isize, HandlerError> {
+    let _slot_lock = suit_storage::lock_slot_for_execution(configuration.suit_slot)
+        .map_err(HandlerError::service_unavailable)?;
+    let mut vm = construct_vm(configuration, allowed_helpers)
+        .map_err(HandlerError::internal_server_error)?;
+    // The program doesn't receive the CoAP context, so the helpers working
+    // on the packet aren't available to it.
+    vm.set_execution_model(ExecutionModel::ShortLived);
+    vm.set_args([path_id as u64, 0, 0, 0]);
+
+    // Safety: the region is only used until the response is sent.
+    let (payload, request_len, header_len) =
+        unsafe { prepare_in_place_response(pkt) }.map_err(HandlerError::internal_server_error)?;
+    let capacity = payload.len();
+
+    let timer = ExecutionTimer::start();
+    let written = vm.full_run_on_payload(payload, request_len);
+    timer.finish();
+    let written = written.map_err(HandlerError::internal_server_error)?;
+
+    // Negative return values (errors) end up here as well.
+    if written > capacity as u64 {
+        return Err(HandlerError::internal_server_error(format!(
+            "Program reported writing {} [B] into a {} [B] payload region",
+            written, capacity
+        )));
+    }
+    // The payload marker can't be followed by an empty payload.
+    if written == 0 {
+        return Ok(header_len as isize - 1);
+    }
+    Ok((header_len + written as usize) as isize)
+}
+
+/// Initialises the response in the packet buffer while preserving the request
+/// payload, which is moved to the beginning of the payload region of the
+/// response. The rest of the region is zeroed so that the program can't read
+/// the leftovers of the previous packets. Returns the region, the length of
+/// the request payload at its beginning and the length of the response header
+/// (including the payload marker).
+///
+/// Safety: the returned region points into the packet buffer, it can only be
+/// used until the response is sent or overwritten by another response.
+unsafe fn prepare_in_place_response<'a>(
+    pkt: &mut PacketBuffer,
+) -> Result<(&'a mut [u8], usize, usize), String> {
+    let ctx = pkt as *mut _ as *mut middleware::CoapContext;
+    let (coap_pkt, buf, buf_len) = ((*ctx).pkt, (*ctx).buf, (*ctx).len);
+    let buf_end = buf as usize + buf_len;
+
+    let request_payload = (*coap_pkt).payload;
+    let request_len = (*coap_pkt).payload_len as usize;
+    // The response header is written over the request header and it isn't
+    // known how long it is going to be, so the request payload is first moved
+    // to the end of the buffer, where the header can't reach it.
+    let stash = (buf_end - request_len) as *mut u8;
+    if request_len > 0 {
+        if request_payload < buf || request_payload as usize + request_len > buf_end {
+            Err("The request payload isn't within the packet buffer")?;
+        }
+        core::ptr::copy(request_payload, stash, request_len);
+    }
+
+    let code = coap_numbers::code::CONTENT;
+    riot_sys::gcoap_resp_init(coap_pkt, buf, buf_len as u32, code as u32);
+    let header_len =
+        riot_sys::coap_opt_finish(coap_pkt, riot_sys::COAP_OPT_FINISH_PAYLOAD as u16);
+    if header_len < 0 {
+        Err(format!("Failed to initialise the response: {}", header_len))?;
+    }
+
+    let payload = (*coap_pkt).payload;
+    let capacity = (*coap_pkt).payload_len as usize;
+    if payload < buf || payload as usize + capacity > buf_end {
+        Err("The response payload isn't within the packet buffer")?;
+    }
+    if capacity < request_len {
+        Err(format!(
+            "The request payload ({} [B]) doesn't fit into the response ({} [B])",
+            request_len, capacity
+        ))?;
+    }
+    if request_len > 0 {
+        core::ptr::copy(stash, payload, request_len);
+    }
+    core::ptr::write_bytes(payload.add(request_len), 0, capacity - request_len);
+    Ok((
+        core::slice::from_raw_parts_mut(payload, capacity),
+        request_len,
+        header_len as usize,
+    ))
+}
+
+/// Registers the program that handles the requests sent to `/routed/<name>`.
+/// The name and the path id passed to the program are specified in the path:
+/// `/routes/<name>/<path_id>` and the payload is the execution request used
+/// to run it. Registering several routes with the same slot allows a single
+/// program to serve multiple endpoints. The programs registered with the
+/// `in-place` query (`/routes/<name>/<path_id>?in-place`) are executed on the
+/// request payload instead of the whole packet, see [`run_on_payload`].
+pub struct CoapRouteRegistrationHandler;
+
+/// URI query of the route registration selecting the in-place execution.
+const IN_PLACE_QUERY: &str = "in-place";
+
+impl CoapRouteRegistrationHandler {
+    fn register(request: &impl ReadableMessage) -> Result<u8, u8> {
+        // The path is /routes/<name>/<path_id>
+        let segments = request
+            .options()
+            .filter(|o| o.number() == coap_numbers::option::URI_PATH)
+            .filter_map(|o| core::str::from_utf8(o.value()).ok().map(String::from))
+            .skip(1)
+            .collect::<Vec<String>>();
+
+        let [name, path_id] = segments.as_slice() else {
+            return Err(util::bad_request("Expected /routes/<name>/<path_id>".into()));
+        };
+        let Ok(path_id) = path_id.parse::<u32>() else {
+            return Err(util::bad_request(format!("Invalid path id: {}", path_id)));
+        };
+        let in_place = request
+            .options()
+            .filter(|o| o.number() == coap_numbers::option::URI_QUERY)
+            .any(|o| o.value() == IN_PLACE_QUERY.as_bytes());
+        let request = util::parse_request(request)?;
 
-        // It is very important that the program executing on the CoAP packet returns
-        // the length of the payload + PDU so that the handler can send the
-        // response accordingly. In case of error the response length should be set to 0.
-        vm.full_run_on_coap_pkt(pkt).unwrap_or_else(|e| {
-            debug!("Error: {:?}", e);
-            0
-        }) as isize
+        let route = CoapRoute {
+            configuration: request.configuration,
+            allowed_helpers: request.allowed_helpers,
+            path_id,
+            in_place,
+        };
+        coap_routes::register_route(name, route).map_err(util::bad_request)?;
+        Ok(coap_numbers::code::CHANGED)
+    }
+}
+
+impl coap_handler::Handler for CoapRouteRegistrationHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        match Self::register(request) {
+            Ok(code) => code,
+            Err(code) => code,
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        1
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
     }
 }
 
 // Allows for executing an instance of the eBPF VM directly in the CoAP server
 // request handler callback. It stores the return value
 // of the program so that it can format the CoAP response accordingly.
+// If the result cache is enabled, the results of pure programs are cached
+// (see `result_cache`) and returned without executing them again.
 pub struct VMExecutionNoDataHandler {
-    result: i64,
+    result: ExecutionResult,
+    /// Correlation ID of the last request, it is echoed in the response.
+    correlation_id: Option<u32>,
+    error: Option<HandlerError>,
 }
 
 impl VMExecutionNoDataHandler {
     pub fn new() -> Self {
-        Self { result: 0 }
+        Self {
+            result: Default::default(),
+            correlation_id: None,
+            error: None,
+        }
     }
 
-    fn handle_vm_execution(&mut self, request: VMExecutionRequest) -> Result<u8, u8> {
-        let mut vm = construct_vm(
-            request.configuration,
-            request.allowed_helpers,
-        )
-        .map_err(util::internal_server_error)?;
+    fn handle_vm_execution(&mut self, request: VMExecutionRequest) -> Result<u8, HandlerError> {
+        let _slot_lock = suit_storage::lock_slot_for_execution(request.configuration.suit_slot)
+            .map_err(HandlerError::service_unavailable)?;
 
-        self.result = vm.full_run().unwrap() as i64;
+        // The allowed helpers are only known up front if they are sent in the
+        // request, the ones read from the binary metadata aren't checked.
+        let cacheable = result_cache::RESULT_CACHE
+            && request.configuration.helper_access_list_source
+                == HelperAccessListSource::ExecuteRequest
+            && result_cache::is_pure(&request.allowed_helpers);
+        let slot = request.configuration.suit_slot;
+        let program_key = cacheable
+            .then(|| result_cache::program_key(suit_storage::load_program_static(slot), slot));
+        if let Some(key) = program_key {
+            if let Some(result) = result_cache::lookup(key) {
+                debug!(target: targets::COAP, "Returning the cached result: {:?}", result);
+                self.result = result;
+                return Ok(coap_numbers::code::CHANGED);
+            }
+        }
+
+        let (result, elapsed_us) = run_short_lived(request.configuration, request.allowed_helpers)
+            .map_err(HandlerError::internal_server_error)?;
+        self.result = result;
+        last_results::record(request.configuration.suit_slot, self.result, elapsed_us);
+        if let Some(key) = program_key {
+            result_cache::insert(key, self.result);
+        }
+        if !self.result.is_ok() {
+            return Err(HandlerError::new(
+                coap_numbers::code::INTERNAL_SERVER_ERROR,
+                format!("Execution failed: {}", self.result.status.as_str()),
+            ));
+        }
         Ok(coap_numbers::code::CHANGED)
     }
+
+    fn handle_request(&mut self, request: &impl ReadableMessage) -> Result<u8, HandlerError> {
+        self.correlation_id = util::correlation_id(request)?;
+        let request = util::parse_request_with_error(request)?;
+        self.handle_vm_execution(request)
+    }
 }
 
 impl coap_handler::Handler for VMExecutionNoDataHandler {
     type RequestData = u8;
 
     fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
-        let parsing_result = util::parse_request(request);
-        let Ok(request) = parsing_result else {
-            return parsing_result.unwrap_err();
+        self.result = Default::default();
+        self.correlation_id = None;
+        let result = self.handle_request(request);
+        let code = util::response_code(result, &mut self.error);
+        debug!(
+            target: targets::COAP,
+            "Execution finished: {:?}{}",
+            self.result,
+            correlation_tag(self.correlation_id)
+        );
+        code
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        1
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        if let Some(e) = self.error.as_ref() {
+            return util::error_response(response, e.code, &e.message);
+        }
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        let resp = match self.correlation_id {
+            Some(id) => format!("{{{}, \"correlation_id\": {}}}", self.result.json_fields(), id),
+            None => format!("{{{}}}", self.result.json_fields()),
         };
-        match self.handle_vm_execution(request) {
+        util::set_json_payload(response, resp);
+    }
+}
+
+/// Size of the buffer into which the programs executed by the
+/// [`VMExecutionWithOutputHandler`] write their output. It is the same as the
+/// maximum response payload so that the whole output can be sent back.
+const OUTPUT_BUFFER_SIZE: usize = util::COAP_RESPONSE_PAYLOAD_SIZE;
+
+/// Executes the program giving it access to an output buffer (its address is
+/// passed in r1). The program writes its results into the buffer and returns
+/// the number of bytes written, those bytes are then sent back as the response
+/// payload. This allows programs to produce structured responses (e.g. JSON)
+/// instead of a single return value.
+pub struct VMExecutionWithOutputHandler {
+    output: [u8; OUTPUT_BUFFER_SIZE],
+    output_len: usize,
+    error: Option<HandlerError>,
+}
+
+impl VMExecutionWithOutputHandler {
+    pub fn new() -> Self {
+        Self {
+            output: [0; OUTPUT_BUFFER_SIZE],
+            output_len: 0,
+            error: None,
+        }
+    }
+
+    fn handle_vm_execution(&mut self, request: VMExecutionRequest) -> Result<u8, HandlerError> {
+        let _slot_lock = suit_storage::lock_slot_for_execution(request.configuration.suit_slot)
+            .map_err(HandlerError::service_unavailable)?;
+        let mut vm = construct_vm(request.configuration, request.allowed_helpers)
+            .map_err(HandlerError::internal_server_error)?;
+        vm.set_execution_model(ExecutionModel::ShortLived);
+
+        self.output = [0; OUTPUT_BUFFER_SIZE];
+        let timer = ExecutionTimer::start();
+        let written = vm.full_run_with_output(&mut self.output);
+        timer.finish();
+        let written = written.map_err(HandlerError::internal_server_error)?;
+
+        if written as usize > OUTPUT_BUFFER_SIZE {
+            return Err(HandlerError::internal_server_error(format!(
+                "Program reported writing {} [B] into a {} [B] output buffer",
+                written, OUTPUT_BUFFER_SIZE
+            )));
+        }
+        self.output_len = written as usize;
+        Ok(coap_numbers::code::CHANGED)
+    }
+}
+
+impl coap_handler::Handler for VMExecutionWithOutputHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        self.output_len = 0;
+        let result =
+            util::parse_request_with_error(request).and_then(|r| self.handle_vm_execution(r));
+        util::response_code(result, &mut self.error)
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        OUTPUT_BUFFER_SIZE
+    }
+
+    fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
+        if let Some(e) = self.error.as_ref() {
+            return util::error_response(response, e.code, &e.message);
+        }
+        response.set_code(request.try_into().map_err(|_| ()).unwrap());
+        response.set_payload(&self.output[..self.output_len]);
+    }
+}
+
+/// Maximum size of a program sent inline to the [`VMInlineExecutionHandler`].
+/// Requests which don't fit into a single CoAP packet are sent block-wise.
+const MAX_INLINE_PROGRAM_SIZE: usize = set_env_or_default!("MAX_INLINE_PROGRAM_SIZE", 512);
+
+/// Space reserved for the encoded `VMExecutionRequest` preceding the inline
+/// program in the payload.
+const MAX_INLINE_REQUEST_SIZE: usize = 128;
+
+/// Inline programs are copied into this buffer as the relocations of raw
+/// object files need to be resolved in place.
+static INLINE_PROGRAM_BUFFER: Mutex<[u8; MAX_INLINE_PROGRAM_SIZE]> =
+    Mutex::new([0; MAX_INLINE_PROGRAM_SIZE]);
+
+/// Executes a program sent in the request payload without deploying it into
+/// the SUIT storage first. It is intended for one-shot experimentation (e.g.
+/// from the admin UI). The payload consists of the encoded
+/// `VMExecutionRequest` followed by a NUL byte and the program bytes.
+///
+/// As the program isn't accompanied by any deployment metadata, the binary
+/// layout and the allowed helpers need to be specified in the request (the
+/// helper list can't be read from the program binary). Only the rBPF
+/// interpreter is supported and the `suit_slot` of the configuration is ignored.
+///
+/// Payloads which don't fit into a single CoAP packet can be sent using the
+/// Block1 option, the program is executed once the last block is received.
+pub struct VMInlineExecutionHandler {
+    result: ExecutionResult,
+    upload: Block1Assembler,
+    /// Block1 option of the last request, echoed in the response.
+    block1: Option<BlockOption>,
+}
+
+impl VMInlineExecutionHandler {
+    pub fn new() -> Self {
+        Self {
+            result: Default::default(),
+            upload: Block1Assembler::new(MAX_INLINE_REQUEST_SIZE + MAX_INLINE_PROGRAM_SIZE),
+            block1: None,
+        }
+    }
+
+    fn handle_inline_execution(&mut self, request: &impl ReadableMessage) -> Result<u8, u8> {
+        if request.code().into() != coap_numbers::code::POST {
+            return Err(coap_numbers::code::METHOD_NOT_ALLOWED);
+        }
+
+        let block = block1::block1_option(request).map_err(|e| e.code)?;
+        let status = self
+            .upload
+            .receive(block, request.payload())
+            .map_err(|e| e.code)?;
+        self.block1 = block;
+        let Block1Status::Complete(payload) = status else {
+            return Ok(coap_numbers::code::CONTINUE);
+        };
+
+        let Some(separator) = payload.iter().position(|b| *b == 0) else {
+            return Err(util::bad_request(
+                "Missing separator between the request and the program".to_string(),
+            ));
+        };
+        let (encoded, program) = (&payload[..separator], &payload[separator + 1..]);
+        let encoded = core::str::from_utf8(encoded).map_err(|_| coap_numbers::code::BAD_REQUEST)?;
+        let request = VMExecutionRequest::decode(encoded.to_string()).map_err(util::bad_request)?;
+        let configuration = request.configuration;
+
+        if !matches!(configuration.vm_target, TargetVM::Rbpf) || configuration.jit {
+            return Err(util::bad_request(
+                "Inline programs can only be executed by the rBPF interpreter".to_string(),
+            ));
+        }
+        if !matches!(
+            configuration.helper_access_list_source,
+            HelperAccessListSource::ExecuteRequest
+        ) {
+            return Err(util::bad_request(
+                "Allowed helpers of inline programs need to be specified in the request"
+                    .to_string(),
+            ));
+        }
+        if program.is_empty() {
+            return Err(util::bad_request("Empty program".to_string()));
+        }
+        if program.len() > MAX_INLINE_PROGRAM_SIZE {
+            error!(
+                target: targets::COAP,
+                "Inline program too large: {} [B], limit: {} [B]",
+                program.len(),
+                MAX_INLINE_PROGRAM_SIZE
+            );
+            return Err(coap_numbers::code::REQUEST_ENTITY_TOO_LARGE);
+        }
+
+        let Some(mut buffer) = INLINE_PROGRAM_BUFFER.try_lock() else {
+            return Err(util::service_unavailable(
+                "Another inline program is being executed".to_string(),
+            ));
+        };
+        let program_buffer = &mut buffer[..program.len()];
+        program_buffer.copy_from_slice(program);
+        relocation_check::prepare_program(program_buffer, configuration.binary_layout)
+            .map_err(util::bad_request)?;
+
+        let mut vm = RbpfVm::new(configuration, request.allowed_helpers.clone())
+            .map_err(util::internal_server_error)?;
+        vm.set_execution_model(ExecutionModel::ShortLived);
+        self.result = if let Err(e) = vm.initialize_vm_from_bytes(program_buffer) {
+            error!(target: targets::COAP, "Failed to initialize the VM: {}", e);
+            ExecutionResult::error(VmStatus::InitializationFailed)
+        } else if let Err(e) =
+            verify_inline_program(&vm, program_buffer, configuration, request.allowed_helpers)?
+        {
+            error!(target: targets::COAP, "Program verification failed: {}", e);
+            ExecutionResult::error(VmStatus::VerificationFailed)
+        } else {
+            let timer = ExecutionTimer::start();
+            let result = execution_result(vm.execute());
+            timer.finish();
+            result
+        };
+
+        if !self.result.is_ok() {
+            return Err(coap_numbers::code::INTERNAL_SERVER_ERROR);
+        }
+        Ok(coap_numbers::code::CHANGED)
+    }
+}
+
+/// Verifies the inline program loaded into the VM, either right away or on the
+/// verification worker if the verification is offloaded (see
+/// [`verification_worker`]). The program is only executed if the inner result
+/// is `Ok`, the outer error is the response code used when the program
+/// couldn't be verified at all.
+fn verify_inline_program(
+    vm: &RbpfVm,
+    program: &[u8],
+    configuration: VMConfiguration,
+    allowed_helpers: Vec<HelperFunctionID>,
+) -> Result<Result<(), String>, u8> {
+    if !verification_worker::OFFLOAD_VERIFICATION {
+        return Ok(vm.verify());
+    }
+    verification_worker::verify_on_worker(
+        program,
+        configuration,
+        allowed_helpers,
+        ExecutionModel::ShortLived,
+    )
+    .map_err(util::service_unavailable)
+}
+
+impl coap_handler::Handler for VMInlineExecutionHandler {
+    type RequestData = u8;
+
+    fn extract_request_data(&mut self, request: &impl ReadableMessage) -> Self::RequestData {
+        self.result = Default::default();
+        self.block1 = None;
+        match self.handle_inline_execution(request) {
             Ok(code) => code,
             Err(code) => code,
         }
     }
 
     fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
-        1
+        util::COAP_RESPONSE_PAYLOAD_SIZE
     }
 
     fn build_response(&mut self, response: &mut impl MutableWritableMessage, request: u8) {
         response.set_code(request.try_into().map_err(|_| ()).unwrap());
-        let resp = format!("{{\"result\": {}}}", self.result);
-        response.set_payload(resp.as_bytes());
+        block1::set_block1_option(response, self.block1);
+        if request == coap_numbers::code::CONTINUE {
+            return;
+        }
+        let resp = format!("{{{}}}", self.result.json_fields());
+        util::set_json_payload(response, resp);
     }
 }
@@ -1,5 +1,6 @@
 pub mod server;
 mod handlers;
+pub mod paths;
 
 pub use server::gcoap_server_main;
 
@@ -0,0 +1,109 @@
+//! Paths of all CoAP resources exposed by the server. Endpoints that take an
+//! argument (e.g. `/storage/erase/<slot>`) are registered for the whole
+//! subtree under the listed path.
+//!
+//! The admin website includes this module as well, so it only depends on
+//! `core`. When adding or renaming an endpoint, the tools need to be updated
+//! to use the same path.
+
+use core::ffi::CStr;
+
+macro_rules! coap_paths {
+    ($($(#[$doc:meta])* $name:ident = $path:literal $(, cfg($cfg:meta))?;)*) => {
+        $(
+            $(#[$doc])*
+            $(#[cfg($cfg)])?
+            pub const $name: &CStr =
+                match CStr::from_bytes_with_nul(concat!($path, "\0").as_bytes()) {
+                    Ok(path) => path,
+                    Err(_) => panic!("Invalid CoAP path"),
+                };
+        )*
+
+        /// All paths registered by the server.
+        pub const ALL: &[&CStr] = &[$($(#[cfg($cfg)])? $name),*];
+    };
+}
+
+coap_paths! {
+    CONSOLE_WRITE = "/console/write";
+    RUNNING_VM = "/running_vm";
+    CAPABILITIES = "/capabilities";
+    VERSION = "/version";
+    LAST_CRASH = "/diagnostics/last-crash";
+    HEAP = "/diagnostics/heap";
+    RESULT_CACHE = "/diagnostics/result-cache";
+    /// `/result/last/<slot>`
+    LAST_RESULT = "/result/last";
+    /// `/logs/<sequence>`, the sequence is optional
+    LOGS = "/logs";
+    AUTOSTART = "/config/autostart";
+    HELPER_POLICY = "/config/helper-policy";
+    JIT_EXEC = "/jit/exec";
+    NATIVE_EXEC = "/native/exec";
+    /// `/native/<name>`
+    NATIVE = "/native";
+    RIOT_BOARD = "/riot/board";
+    WITH_COAP_PKT = "/with_coap_pkt";
+    /// `/routed/<name>`
+    ROUTED = "/routed";
+    /// `/routes/<name>/<path_id>[?in-place]`
+    ROUTES = "/routes";
+    SHORT_EXECUTION = "/short-execution";
+    SHORT_EXECUTION_OUTPUT = "/short-execution/output";
+    RUN = "/run";
+    BENCHMARK_SHORT_EXECUTION = "/benchmark/short-execution";
+    /// `/benchmark/compare/<slot>`
+    BENCHMARK_COMPARE = "/benchmark/compare";
+    BENCHMARK_WITH_COAP_PKT = "/benchmark/with_coap_pkt";
+    /// `/benchmark/export/<iteration>`, the iteration is optional
+    BENCHMARK_EXPORT = "/benchmark/export";
+    /// `/benchmark/reloc/<slot>`
+    BENCHMARK_RELOC = "/benchmark/reloc";
+    LONG_RUNNING = "/long-running";
+    /// `/execute/reload/<slot>`
+    EXECUTE_RELOAD = "/execute/reload";
+    SUIT_PULL = "/suit/pull";
+    STORAGE_LIST = "/storage/list";
+    /// `/storage/erase/<slot>`
+    STORAGE_ERASE = "/storage/erase";
+    /// `/storage/patch/<slot>/<offset>`
+    STORAGE_PATCH = "/storage/patch";
+    /// `/storage/verify/<slot>`
+    STORAGE_VERIFY = "/storage/verify";
+    /// `/storage/info/<slot>`
+    STORAGE_INFO = "/storage/info";
+    /// `/storage/analyze/<slot>`
+    STORAGE_ANALYZE = "/storage/analyze";
+    /// `/storage/wcet/<slot>`
+    STORAGE_WCET = "/storage/wcet";
+    /// `/storage/jit-dump/<slot>[/<offset>]`
+    STORAGE_JIT_DUMP = "/storage/jit-dump", cfg(feature = "jit-dump");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_unique_and_absolute() {
+        for (i, path) in ALL.iter().enumerate() {
+            let path = path.to_str().unwrap();
+            assert!(path.starts_with('/') && !path.ends_with('/'), "{}", path);
+            assert!(!path.contains(['?', ' ']), "{}", path);
+            assert!(
+                ALL[..i].iter().all(|other| other.to_str().unwrap() != path),
+                "{} is registered twice",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn jit_dump_is_listed_only_with_the_feature() {
+        let listed = ALL
+            .iter()
+            .any(|path| path.to_bytes() == b"/storage/jit-dump");
+        assert_eq!(listed, cfg!(feature = "jit-dump"));
+    }
+}
@@ -1,7 +1,6 @@
 use alloc::sync::Arc;
 use riot_wrappers::{
     coap_handler::GcoapHandler,
-    cstr::cstr,
     gcoap::{self, SingleHandlerListener},
     gnrc,
     msg::v2::SendPort,
@@ -11,14 +10,33 @@ use riot_wrappers::{
     thread, ztimer,
 };
 
-use crate::{model::requests::VMExecutionRequestIPC, vm::VM_EXEC_REQUEST};
+use log::debug;
 
+use crate::{
+    infra::autostart, model::requests::VMExecutionRequestIPC, util::logger::targets,
+    vm::VM_EXEC_REQUEST,
+};
+
+use super::paths;
+
+#[cfg(feature = "jit-dump")]
+use super::handlers::suit_pull_endpoint::JitDumpHandler;
 use super::handlers::{
-    miscellaneous::{ConsoleWriteHandler, RiotBoardHandler, RunningVMHandler},
-    suit_pull_endpoint::SuitPullHandler,
-    Fletcher16NativeTestHandler, JitTestHandler, TimedHandler, VMExecutionBenchmarkHandler,
-    VMExecutionNoDataHandler, VMExecutionOnCoapPktBenchmarkHandler, VMExecutionOnCoapPktHandler,
-    VMLongExecutionHandler,
+    miscellaneous::{
+        AutostartConfigHandler, CapabilitiesHandler, ConsoleWriteHandler, ExecutionLogHandler,
+        HeapStatsHandler, HelperPolicyHandler, LastCrashHandler, LastResultHandler,
+        ResultCacheStatsHandler, RiotBoardHandler, RunningVMHandler, VersionHandler,
+    },
+    suit_pull_endpoint::{
+        StorageAnalyzeHandler, StorageEraseHandler, StorageInfoHandler, StorageListHandler,
+        StoragePatchHandler, StorageVerifyHandler, StorageWcetHandler, SuitPullHandler,
+    },
+    BenchmarkExportHandler, CoapRouteRegistrationHandler, DeduplicatingHandler,
+    Fletcher16NativeTestHandler, JitTestHandler, NativeFunctionHandler, QuotaListener, TimedHandler,
+    VMComparisonBenchmarkHandler, VMExecutionBenchmarkHandler, VMExecutionNoDataHandler,
+    VMExecutionOnCoapPktBenchmarkHandler, VMExecutionOnCoapPktHandler,
+    VMExecutionWithOutputHandler, VMInlineExecutionHandler, VMLongExecutionHandler,
+    VMRelocationBenchmarkHandler, VMReloadHandler, VMRoutedExecutionHandler,
 };
 
 pub fn gcoap_server_main(
@@ -32,96 +50,328 @@ pub fn gcoap_server_main(
     let mut console_write_handler = GcoapHandler(ConsoleWriteHandler);
     let mut riot_board_handler = GcoapHandler(RiotBoardHandler);
     let mut running_vm_handler = GcoapHandler(RunningVMHandler);
+    let mut capabilities_handler = GcoapHandler(CapabilitiesHandler);
+    let mut version_handler = GcoapHandler(VersionHandler);
+    let mut last_crash_handler = GcoapHandler(LastCrashHandler);
+    let mut heap_stats_handler = GcoapHandler(HeapStatsHandler);
+    let mut result_cache_handler = GcoapHandler(ResultCacheStatsHandler);
+    let mut last_result_handler = GcoapHandler(LastResultHandler::new());
+    let mut execution_log_handler = GcoapHandler(ExecutionLogHandler::new());
+    let mut autostart_handler = GcoapHandler(AutostartConfigHandler::new());
+    let mut helper_policy_handler = GcoapHandler(HelperPolicyHandler::new());
     let mut suit_pull_handler = GcoapHandler(SuitPullHandler::new());
+    let mut storage_erase_handler = GcoapHandler(StorageEraseHandler::new());
+    let mut storage_patch_handler = GcoapHandler(StoragePatchHandler::new());
+    let mut storage_verify_handler = GcoapHandler(StorageVerifyHandler::new());
+    let mut storage_info_handler = GcoapHandler(StorageInfoHandler::new());
+    let mut storage_list_handler = GcoapHandler(StorageListHandler);
+    let mut storage_analyze_handler = GcoapHandler(StorageAnalyzeHandler::new());
+    let mut storage_wcet_handler = GcoapHandler(StorageWcetHandler::new());
+    #[cfg(feature = "jit-dump")]
+    let mut jit_dump_handler = GcoapHandler(JitDumpHandler::new());
 
     let mut coap_pkt_execution_handler = VMExecutionOnCoapPktHandler;
     let mut coap_pkt_timed_execution_handler = TimedHandler::new(&mut coap_pkt_execution_handler);
+    let mut routed_execution_handler = VMRoutedExecutionHandler;
+    let mut route_registration_handler = GcoapHandler(CoapRouteRegistrationHandler);
     let mut no_data_execution_handler = GcoapHandler(VMExecutionNoDataHandler::new());
+    let mut output_execution_handler = GcoapHandler(VMExecutionWithOutputHandler::new());
+    let mut inline_execution_handler = GcoapHandler(VMInlineExecutionHandler::new());
     let mut benchmark_handler = GcoapHandler(VMExecutionBenchmarkHandler::new());
+    let mut comparison_handler = GcoapHandler(VMComparisonBenchmarkHandler::new());
+    let mut reloc_benchmark_handler = GcoapHandler(VMRelocationBenchmarkHandler::new());
+    let mut benchmark_export_handler = GcoapHandler(BenchmarkExportHandler::new());
     let mut jit_handler = GcoapHandler(JitTestHandler::new());
     let mut fletcher16_handler = GcoapHandler(Fletcher16NativeTestHandler::new());
+    let mut native_fn_handler = GcoapHandler(NativeFunctionHandler::new());
     let mut long_execution_handler =
         GcoapHandler(VMLongExecutionHandler::new(execution_send.clone()));
+    let mut reload_handler = GcoapHandler(VMReloadHandler::new());
     let mut benchmark_on_coap_pkt_handler = VMExecutionOnCoapPktBenchmarkHandler::new();
 
+    // Execution requests are deduplicated so that retransmitted requests
+    // don't cause the program to be executed twice. Their listeners need to
+    // be QuotaListeners, which provide the address of the client.
+    let mut dedup_execution_handler = DeduplicatingHandler::new(&mut no_data_execution_handler);
+    let mut dedup_long_execution_handler = DeduplicatingHandler::new(&mut long_execution_handler);
+
     let mut console_write_listener = SingleHandlerListener::new(
-        cstr!("/console/write"),
+        paths::CONSOLE_WRITE,
         riot_sys::COAP_POST,
         &mut console_write_handler,
     );
 
     let mut running_vm_listener = SingleHandlerListener::new(
-        cstr!("/running_vm"),
+        paths::RUNNING_VM,
         riot_sys::COAP_GET,
         &mut running_vm_handler,
     );
 
+    let mut capabilities_listener = SingleHandlerListener::new(
+        paths::CAPABILITIES,
+        riot_sys::COAP_GET,
+        &mut capabilities_handler,
+    );
+
+    let mut version_listener =
+        SingleHandlerListener::new(paths::VERSION, riot_sys::COAP_GET, &mut version_handler);
+
+    let mut last_crash_listener = SingleHandlerListener::new(
+        paths::LAST_CRASH,
+        riot_sys::COAP_GET,
+        &mut last_crash_handler,
+    );
+
+    let mut heap_stats_listener =
+        SingleHandlerListener::new(paths::HEAP, riot_sys::COAP_GET, &mut heap_stats_handler);
+
+    let mut result_cache_listener = SingleHandlerListener::new(
+        paths::RESULT_CACHE,
+        riot_sys::COAP_GET,
+        &mut result_cache_handler,
+    );
+
+    // Matches /result/last/<slot>
+    let mut last_result_listener = SingleHandlerListener::new(
+        paths::LAST_RESULT,
+        riot_sys::COAP_GET | riot_sys::COAP_MATCH_SUBTREE,
+        &mut last_result_handler,
+    );
+
+    // Matches /logs/<sequence>
+    let mut execution_log_listener = SingleHandlerListener::new(
+        paths::LOGS,
+        riot_sys::COAP_GET | riot_sys::COAP_MATCH_SUBTREE,
+        &mut execution_log_handler,
+    );
+
+    let mut autostart_listener = SingleHandlerListener::new(
+        paths::AUTOSTART,
+        riot_sys::COAP_GET | riot_sys::COAP_POST | riot_sys::COAP_DELETE,
+        &mut autostart_handler,
+    );
+
+    let mut helper_policy_listener = SingleHandlerListener::new(
+        paths::HELPER_POLICY,
+        riot_sys::COAP_GET | riot_sys::COAP_POST | riot_sys::COAP_DELETE,
+        &mut helper_policy_handler,
+    );
+
     let mut jit_listener =
-        SingleHandlerListener::new(cstr!("/jit/exec"), riot_sys::COAP_POST, &mut jit_handler);
+        SingleHandlerListener::new(paths::JIT_EXEC, riot_sys::COAP_POST, &mut jit_handler);
 
     // Mock endpoint for benchmarking native execution of Fletcher16 algorithm.
     // TODO: move this to a separate project to not clutter the main one
     let mut fletcher16_listener = SingleHandlerListener::new(
-        cstr!("/native/exec"),
+        paths::NATIVE_EXEC,
         riot_sys::COAP_POST,
         &mut fletcher16_handler,
     );
 
+    // Runs native functions by name, e.g. /native/fletcher_16_80B. It matches
+    // the whole /native subtree, so it needs to be registered after the
+    // /native/exec listener which would otherwise be shadowed by it.
+    let mut native_fn_listener = SingleHandlerListener::new(
+        paths::NATIVE,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut native_fn_handler,
+    );
+
     let mut riot_board_listener = SingleHandlerListener::new(
-        cstr!("/riot/board"),
+        paths::RIOT_BOARD,
         riot_sys::COAP_GET,
         &mut riot_board_handler,
     );
 
-    let mut coap_pkt_vm_listener = SingleHandlerListener::new(
-        cstr!("/with_coap_pkt"),
+    // The short-lived executions count towards the quotas of the clients
+    // (if they are enabled, see infra::client_quota).
+    let mut coap_pkt_vm_listener = QuotaListener::new(
+        paths::WITH_COAP_PKT,
         riot_sys::COAP_POST,
         &mut coap_pkt_timed_execution_handler,
     );
 
-    let mut vm_listener = SingleHandlerListener::new(
-        cstr!("/short-execution"),
+    // Matches /routed/<name>
+    let mut routed_vm_listener = QuotaListener::new(
+        paths::ROUTED,
+        riot_sys::COAP_GET | riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut routed_execution_handler,
+    );
+
+    // Matches /routes/<name>/<path_id>
+    let mut route_registration_listener = SingleHandlerListener::new(
+        paths::ROUTES,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut route_registration_handler,
+    );
+
+    let mut vm_listener = QuotaListener::new(
+        paths::SHORT_EXECUTION,
         riot_sys::COAP_POST,
-        &mut no_data_execution_handler,
+        &mut dedup_execution_handler,
+    );
+
+    let mut output_vm_listener = QuotaListener::new(
+        paths::SHORT_EXECUTION_OUTPUT,
+        riot_sys::COAP_POST,
+        &mut output_execution_handler,
+    );
+
+    let mut inline_vm_listener = QuotaListener::new(
+        paths::RUN,
+        riot_sys::COAP_POST,
+        &mut inline_execution_handler,
     );
 
     let mut benchmark_listener = SingleHandlerListener::new(
-        cstr!("/benchmark/short-execution"),
+        paths::BENCHMARK_SHORT_EXECUTION,
         riot_sys::COAP_POST,
         &mut benchmark_handler,
     );
 
+    // Matches /benchmark/compare/<slot>
+    let mut comparison_listener = SingleHandlerListener::new(
+        paths::BENCHMARK_COMPARE,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut comparison_handler,
+    );
+
+    // Matches /benchmark/export and /benchmark/export/<iteration>
+    let mut benchmark_export_listener = SingleHandlerListener::new(
+        paths::BENCHMARK_EXPORT,
+        riot_sys::COAP_GET | riot_sys::COAP_MATCH_SUBTREE,
+        &mut benchmark_export_handler,
+    );
+
+    // Matches /benchmark/reloc/<slot>
+    let mut reloc_benchmark_listener = SingleHandlerListener::new(
+        paths::BENCHMARK_RELOC,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut reloc_benchmark_handler,
+    );
+
     let mut benchmark_on_coap_listener = SingleHandlerListener::new(
-        cstr!("/benchmark/with_coap_pkt"),
+        paths::BENCHMARK_WITH_COAP_PKT,
         riot_sys::COAP_POST,
         &mut benchmark_on_coap_pkt_handler,
     );
 
-    let mut vm_spawn_listener = SingleHandlerListener::new(
-        cstr!("/long-running"),
+    let mut vm_spawn_listener = QuotaListener::new(
+        paths::LONG_RUNNING,
         riot_sys::COAP_POST,
-        &mut long_execution_handler,
+        &mut dedup_long_execution_handler,
+    );
+
+    // Matches /execute/reload/<slot>
+    let mut reload_listener = SingleHandlerListener::new(
+        paths::EXECUTE_RELOAD,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut reload_handler,
     );
 
     let mut suit_pull_listener = SingleHandlerListener::new(
-        cstr!("/suit/pull"),
+        paths::SUIT_PULL,
         riot_sys::COAP_POST,
         &mut suit_pull_handler,
     );
 
+    // Matches /storage/erase/<slot>
+    let mut storage_erase_listener = SingleHandlerListener::new(
+        paths::STORAGE_ERASE,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut storage_erase_handler,
+    );
+
+    // Matches /storage/patch/<slot>/<offset>
+    let mut storage_patch_listener = SingleHandlerListener::new(
+        paths::STORAGE_PATCH,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut storage_patch_handler,
+    );
+
+    let mut storage_list_listener = SingleHandlerListener::new(
+        paths::STORAGE_LIST,
+        riot_sys::COAP_GET,
+        &mut storage_list_handler,
+    );
+
+    // Matches /storage/verify/<slot>
+    let mut storage_verify_listener = SingleHandlerListener::new(
+        paths::STORAGE_VERIFY,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut storage_verify_handler,
+    );
+
+    // Matches /storage/info/<slot>
+    let mut storage_info_listener = SingleHandlerListener::new(
+        paths::STORAGE_INFO,
+        riot_sys::COAP_GET | riot_sys::COAP_MATCH_SUBTREE,
+        &mut storage_info_handler,
+    );
+
+    // Matches /storage/analyze/<slot>
+    let mut storage_analyze_listener = SingleHandlerListener::new(
+        paths::STORAGE_ANALYZE,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut storage_analyze_handler,
+    );
+
+    // Matches /storage/wcet/<slot>
+    let mut storage_wcet_listener = SingleHandlerListener::new(
+        paths::STORAGE_WCET,
+        riot_sys::COAP_POST | riot_sys::COAP_MATCH_SUBTREE,
+        &mut storage_wcet_handler,
+    );
+
+    // Matches /storage/jit-dump/<slot>[/<offset>]
+    #[cfg(feature = "jit-dump")]
+    let mut jit_dump_listener = SingleHandlerListener::new(
+        paths::STORAGE_JIT_DUMP,
+        riot_sys::COAP_GET | riot_sys::COAP_MATCH_SUBTREE,
+        &mut jit_dump_handler,
+    );
+
     gcoap::scope(|greg| {
         // Endpoint handlers are registered here.
         greg.register(&mut console_write_listener);
         greg.register(&mut riot_board_listener);
         greg.register(&mut coap_pkt_vm_listener);
+        greg.register(&mut routed_vm_listener);
+        greg.register(&mut route_registration_listener);
         greg.register(&mut jit_listener);
         greg.register(&mut fletcher16_listener);
+        greg.register(&mut native_fn_listener);
         greg.register(&mut running_vm_listener);
+        greg.register(&mut capabilities_listener);
+        greg.register(&mut version_listener);
+        greg.register(&mut last_crash_listener);
+        greg.register(&mut heap_stats_listener);
+        greg.register(&mut result_cache_listener);
+        greg.register(&mut last_result_listener);
+        greg.register(&mut execution_log_listener);
+        greg.register(&mut autostart_listener);
+        greg.register(&mut helper_policy_listener);
         greg.register(&mut vm_listener);
+        greg.register(&mut output_vm_listener);
+        greg.register(&mut inline_vm_listener);
         greg.register(&mut benchmark_listener);
         greg.register(&mut benchmark_on_coap_listener);
+        greg.register(&mut comparison_listener);
+        greg.register(&mut reloc_benchmark_listener);
+        greg.register(&mut benchmark_export_listener);
         greg.register(&mut vm_spawn_listener);
+        greg.register(&mut reload_listener);
         greg.register(&mut suit_pull_listener);
+        greg.register(&mut storage_erase_listener);
+        greg.register(&mut storage_patch_listener);
+        greg.register(&mut storage_list_listener);
+        greg.register(&mut storage_verify_listener);
+        greg.register(&mut storage_info_listener);
+        greg.register(&mut storage_analyze_listener);
+        greg.register(&mut storage_wcet_listener);
+        #[cfg(feature = "jit-dump")]
+        greg.register(&mut jit_dump_listener);
+        debug!(target: targets::COAP, "Registered CoAP resources: {:?}", paths::ALL);
 
         println!(
             "CoAP server ready; waiting for interfaces to settle before reporting addresses..."
@@ -131,6 +381,9 @@ pub fn gcoap_server_main(
         sectimer.sleep_ticks(2);
         print_network_interfaces();
 
+        // The programs can only be pulled once the network is up.
+        autostart::run(execution_send);
+
         // Sending main thread to sleep; can't return or the Gcoap handler would need to be
         // deregistered (which it can't).
         loop {
@@ -0,0 +1,35 @@
+#include <errno.h>
+#include <stdint.h>
+
+#include "kernel_defines.h"
+#if IS_USED(MODULE_MALLOC_MONITOR)
+#include "malloc_monitor.h"
+#endif
+
+#if IS_USED(MODULE_CORTEXM_COMMON)
+/* Bounds of the heap, defined in the Cortex-M linker script. */
+extern char _sheap, _eheap;
+#endif
+
+/// Reads the current and the peak number of bytes allocated on the heap. The
+/// total size of the heap is written into `total`, it is 0 if it isn't known
+/// for the platform. The statistics are only collected when the
+/// malloc_monitor module is used, otherwise -ENOTSUP is returned.
+int heap_stats_sample(uint32_t *used, uint32_t *peak, uint32_t *total)
+{
+#if IS_USED(MODULE_MALLOC_MONITOR)
+    *used = malloc_monitor_get_usage_current();
+    *peak = malloc_monitor_get_usage_high_watermark();
+#if IS_USED(MODULE_CORTEXM_COMMON)
+    *total = &_eheap - &_sheap;
+#else
+    *total = 0;
+#endif
+    return 0;
+#else
+    (void)used;
+    (void)peak;
+    (void)total;
+    return -ENOTSUP;
+#endif
+}
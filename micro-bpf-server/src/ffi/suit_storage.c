@@ -80,6 +80,28 @@ uint32_t get_storage_ptr(uint8_t *location_id, uint32_t *length_ret)
     return (uint32_t) mem_region;
 }
 
+/// Replaces the contents of a SUIT storage location with the provided bytes.
+/// It is used for storing the decompressed program in place of the compressed
+/// one that was fetched. Returns 0 on success.
+int write_bytes_to_suit_storage(uint8_t *location_id, const uint8_t *buff,
+                                uint32_t length)
+{
+    char *location = (char *)location_id;
+    suit_storage_t *storage = suit_storage_find_by_id(location);
+    assert(storage);
+
+    suit_storage_set_active_location(storage, location);
+    // The RAM storage backend doesn't use the manifest, so we don't need to
+    // provide one.
+    int res = suit_storage_start(storage, NULL, length);
+    if (res < 0) {
+        LOG_DEBUG("[SUIT storage]: unable to write %u bytes to %s\n",
+                  (unsigned)length, location);
+        return res;
+    }
+    return suit_storage_write(storage, NULL, buff, 0, length);
+}
+
 void handle_suit_storage_erase(uint8_t *location_id)
 {
 
@@ -0,0 +1,202 @@
+//! Restarting the long-running programs after the device is reset.
+//!
+//! The programs are loaded into the RAM SUIT storage, so they are lost on
+//! reset. Because of this, each autostart entry consists of the SUIT pull
+//! request used to deploy the program and the request used to execute it
+//! (both in the encoded form sent by the clients). On startup, once the
+//! network interfaces are up, each program is pulled into its slot again and
+//! sent to the VM execution manager.
+//!
+//! The entries are kept in a memory region that isn't initialised on startup
+//! (the `.noinit` section, see also [`super::crash_diagnostics`]), so they
+//! survive soft resets (e.g. watchdog resets or rebooting from the shell),
+//! however they are lost on a power-on reset.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{mem::MaybeUninit, ptr::addr_of_mut};
+
+use log::{error, info};
+use macros::set_env_or_default;
+use micro_bpf_common::{SuitPullRequest, VMConfiguration, VMExecutionRequest};
+use riot_wrappers::mutex::Mutex;
+
+use crate::{
+    infra::suit_storage, model::requests::VMExecutionRequestIPC, util::logger::targets,
+    vm::ExecutionSendPort,
+};
+
+pub const MAX_AUTOSTART_ENTRIES: usize = set_env_or_default!("MAX_AUTOSTART_ENTRIES", 2);
+const MAX_PULL_REQUEST_LEN: usize = 128;
+const MAX_EXECUTION_REQUEST_LEN: usize = 64;
+
+/// Marks the retained region as initialised ("mbas" in ASCII).
+const RETAINED_MAGIC: u32 = 0x6d62_6173;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct AutostartEntry {
+    pull_request_len: u16,
+    execution_request_len: u16,
+    pull_request: [u8; MAX_PULL_REQUEST_LEN],
+    execution_request: [u8; MAX_EXECUTION_REQUEST_LEN],
+}
+
+const EMPTY_ENTRY: AutostartEntry = AutostartEntry {
+    pull_request_len: 0,
+    execution_request_len: 0,
+    pull_request: [0; MAX_PULL_REQUEST_LEN],
+    execution_request: [0; MAX_EXECUTION_REQUEST_LEN],
+};
+
+impl AutostartEntry {
+    fn is_empty(&self) -> bool {
+        self.pull_request_len == 0
+    }
+
+    fn pull_request(&self) -> Option<&str> {
+        let bytes = self.pull_request.get(..self.pull_request_len as usize)?;
+        core::str::from_utf8(bytes).ok()
+    }
+
+    fn execution_request(&self) -> Option<&str> {
+        let bytes = self.execution_request.get(..self.execution_request_len as usize)?;
+        core::str::from_utf8(bytes).ok()
+    }
+}
+
+#[repr(C)]
+struct RetainedAutostart {
+    magic: u32,
+    entries: [AutostartEntry; MAX_AUTOSTART_ENTRIES],
+}
+
+#[link_section = ".noinit"]
+static mut RETAINED_AUTOSTART: MaybeUninit<RetainedAutostart> = MaybeUninit::uninit();
+
+/// Guards the access to the retained region.
+static AUTOSTART_LOCK: Mutex<()> = Mutex::new(());
+
+/// Runs the closure on the retained entries, initialising them first if the
+/// region contains garbage (i.e. after a power-on reset).
+fn with_entries<T>(f: impl FnOnce(&mut [AutostartEntry; MAX_AUTOSTART_ENTRIES]) -> T) -> T {
+    let _guard = AUTOSTART_LOCK.lock();
+    unsafe {
+        let state = (*addr_of_mut!(RETAINED_AUTOSTART)).as_mut_ptr();
+        if core::ptr::read_volatile(addr_of_mut!((*state).magic)) != RETAINED_MAGIC {
+            core::ptr::write_volatile(
+                addr_of_mut!((*state).entries),
+                [EMPTY_ENTRY; MAX_AUTOSTART_ENTRIES],
+            );
+            core::ptr::write_volatile(addr_of_mut!((*state).magic), RETAINED_MAGIC);
+        }
+        f(&mut *addr_of_mut!((*state).entries))
+    }
+}
+
+/// Adds a program to be started after reset. Both requests are validated
+/// before they are stored, an existing entry for the same slot is replaced.
+pub fn add_entry(pull_request: &str, execution_request: &str) -> Result<usize, String> {
+    if pull_request.len() > MAX_PULL_REQUEST_LEN
+        || execution_request.len() > MAX_EXECUTION_REQUEST_LEN
+    {
+        Err("Autostart request too long".to_string())?;
+    }
+    let pull = SuitPullRequest::decode(pull_request.to_string())
+        .map_err(|e| format!("Invalid SUIT pull request: {}", e))?;
+    let execution = VMExecutionRequest::decode(execution_request.to_string())
+        .map_err(|e| format!("Invalid execution request: {}", e))?;
+    let slot = VMConfiguration::decode(pull.config).suit_slot;
+    if slot != execution.configuration.suit_slot {
+        Err(format!(
+            "Slot mismatch: program pulled into slot {}, executed from slot {}",
+            slot, execution.configuration.suit_slot
+        ))?;
+    }
+
+    with_entries(|entries| {
+        let index = entries
+            .iter()
+            .position(|e| !e.is_empty() && entry_slot(e) == Some(slot))
+            .or_else(|| entries.iter().position(|e| e.is_empty()))
+            .ok_or_else(|| {
+                format!("At most {} programs can be autostarted", MAX_AUTOSTART_ENTRIES)
+            })?;
+
+        let mut entry = EMPTY_ENTRY;
+        entry.pull_request[..pull_request.len()].copy_from_slice(pull_request.as_bytes());
+        entry.pull_request_len = pull_request.len() as u16;
+        entry.execution_request[..execution_request.len()]
+            .copy_from_slice(execution_request.as_bytes());
+        entry.execution_request_len = execution_request.len() as u16;
+        entries[index] = entry;
+        Ok(slot)
+    })
+}
+
+/// Removes all autostart entries.
+pub fn clear() {
+    with_entries(|entries| *entries = [EMPTY_ENTRY; MAX_AUTOSTART_ENTRIES]);
+}
+
+/// Returns the slots of the programs that are started after reset.
+pub fn autostart_slots() -> Vec<usize> {
+    with_entries(|entries| {
+        entries
+            .iter()
+            .filter(|e| !e.is_empty())
+            .filter_map(entry_slot)
+            .collect()
+    })
+}
+
+fn entry_slot(entry: &AutostartEntry) -> Option<usize> {
+    let request = VMExecutionRequest::decode(entry.execution_request()?.to_string()).ok()?;
+    Some(request.configuration.suit_slot)
+}
+
+/// Pulls the programs of all autostart entries into their slots and requests
+/// their execution. It needs to be called once the network interfaces are up.
+pub fn run(execution_send: &ExecutionSendPort) {
+    let entries = with_entries(|entries| *entries);
+    for entry in entries.iter().filter(|e| !e.is_empty()) {
+        if let Err(e) = start_entry(entry, execution_send) {
+            error!(target: targets::WORKER, "Failed to autostart program: {}", e);
+        }
+    }
+}
+
+fn start_entry(entry: &AutostartEntry, execution_send: &ExecutionSendPort) -> Result<(), String> {
+    let (Some(pull_request), Some(execution_request)) =
+        (entry.pull_request(), entry.execution_request())
+    else {
+        Err("Corrupted autostart entry")?
+    };
+    let pull = SuitPullRequest::decode(pull_request.to_string())
+        .map_err(|e| format!("Invalid SUIT pull request: {}", e))?;
+    let execution = VMExecutionRequest::decode(execution_request.to_string())
+        .map_err(|e| format!("Invalid execution request: {}", e))?;
+    let config = VMConfiguration::decode(pull.config);
+
+    info!(
+        target: targets::WORKER,
+        "Autostarting program from {} in slot {}", pull.manifest, config.suit_slot
+    );
+    suit_storage::suit_fetch(
+        pull.ip.as_str(),
+        pull.riot_netif.as_str(),
+        pull.manifest.as_str(),
+        config.suit_slot,
+        true,
+        config.binary_layout,
+    )?;
+
+    let message = VMExecutionRequestIPC::new(execution);
+    execution_send
+        .lock()
+        .try_send(message)
+        .map_err(|_| "Failed to send the execution request".to_string())
+}
@@ -0,0 +1,95 @@
+//! Log of the most recent benchmark runs, it allows for exporting the results
+//! for offline analysis (see the `/benchmark/export` endpoint) instead of
+//! scraping them from the console output.
+//!
+//! The log is a ring buffer of [`BENCHMARK_LOG_SIZE`] records. Once it is
+//! full, each new run overwrites the oldest record. Every run is assigned
+//! a sequence number (the `iteration` column), gaps in those numbers between
+//! two exports mean that some runs were overwritten in the meantime.
+
+use alloc::{format, string::String, vec::Vec};
+use macros::set_env_or_default;
+use micro_bpf_common::BinaryFileLayout;
+use riot_wrappers::mutex::Mutex;
+
+use crate::model::results::ExecutionResult;
+
+/// Number of benchmark runs kept in the log.
+pub const BENCHMARK_LOG_SIZE: usize = set_env_or_default!("BENCHMARK_LOG_SIZE", 16);
+
+pub const CSV_HEADER: &str = "slot,layout,jit,iteration,time_us,result\n";
+
+#[derive(Debug, Clone, Copy)]
+pub struct BenchmarkRecord {
+    pub slot: usize,
+    pub layout: BinaryFileLayout,
+    pub jit: bool,
+    pub iteration: u32,
+    /// Execution time of the program.
+    pub time_us: u32,
+    pub result: ExecutionResult,
+}
+
+impl BenchmarkRecord {
+    /// Formats the record as a CSV row, the result column contains the value
+    /// returned by the program or the status if the execution failed.
+    pub fn csv_row(&self) -> String {
+        let result = if self.result.is_ok() {
+            format!("{}", self.result.value)
+        } else {
+            String::from(self.result.status.as_str())
+        };
+        format!(
+            "{},{:?},{},{},{},{}\n",
+            self.slot, self.layout, self.jit, self.iteration, self.time_us, result
+        )
+    }
+}
+
+struct BenchmarkLog {
+    records: [Option<BenchmarkRecord>; BENCHMARK_LOG_SIZE],
+    /// Sequence number assigned to the next run, the record is stored at
+    /// the index `next_iteration % BENCHMARK_LOG_SIZE`.
+    next_iteration: u32,
+}
+
+static BENCHMARK_LOG: Mutex<BenchmarkLog> = Mutex::new(BenchmarkLog {
+    records: [None; BENCHMARK_LOG_SIZE],
+    next_iteration: 0,
+});
+
+/// Adds a benchmark run to the log, overwriting the oldest one if it is full.
+pub fn record(
+    slot: usize,
+    layout: BinaryFileLayout,
+    jit: bool,
+    time_us: u32,
+    result: ExecutionResult,
+) {
+    let mut log = BENCHMARK_LOG.lock();
+    let iteration = log.next_iteration;
+    log.records[iteration as usize % BENCHMARK_LOG_SIZE] = Some(BenchmarkRecord {
+        slot,
+        layout,
+        jit,
+        iteration,
+        time_us,
+        result,
+    });
+    log.next_iteration = iteration.wrapping_add(1);
+}
+
+/// Returns the records of the runs with sequence numbers starting at
+/// `from_iteration`, oldest first.
+pub fn records_since(from_iteration: u32) -> Vec<BenchmarkRecord> {
+    let mut records = BENCHMARK_LOG
+        .lock()
+        .records
+        .iter()
+        .flatten()
+        .filter(|r| r.iteration >= from_iteration)
+        .copied()
+        .collect::<Vec<_>>();
+    records.sort_by_key(|r| r.iteration);
+    records
+}
@@ -0,0 +1,158 @@
+//! Per-client execution quotas for multi-tenant deployments.
+//!
+//! Each client may only use up a fixed budget ([`CLIENT_QUOTA_US`]) within a
+//! window of [`CLIENT_QUOTA_WINDOW_MS`], the usage is reset once the window
+//! of the client rolls over. Execution requests of clients that have used up
+//! their budget are rejected until then. The quotas are disabled by default,
+//! they are enabled by setting `CLIENT_QUOTA_US` to a non-zero value at
+//! compile time.
+//!
+//! Ideally, the budget would be a number of executed instructions. However,
+//! the rBPF interpreter doesn't count them, so the clients are billed for the
+//! time that the VMs spend executing the programs of their requests instead
+//! (see [`charge_execution`]). Loading and verifying the programs and
+//! handling the requests isn't billed, while the time spent in helpers (e.g.
+//! sleeping) is.
+//!
+//! At most [`MAX_QUOTA_CLIENTS`] clients are tracked at a time. The slots of
+//! clients whose window has expired are reused for new clients, if there are
+//! none, the requests of new clients are rejected until one expires.
+
+use alloc::{format, string::String};
+use macros::set_env_or_default;
+use riot_wrappers::{mutex::Mutex, thread};
+
+use crate::vm::clock;
+
+/// Execution time (in microseconds) that each client may use within a window,
+/// 0 disables the quotas.
+pub const CLIENT_QUOTA_US: usize = set_env_or_default!("CLIENT_QUOTA_US", 0);
+
+/// Length of the window after which the usage of a client is reset.
+pub const CLIENT_QUOTA_WINDOW_MS: usize = set_env_or_default!("CLIENT_QUOTA_WINDOW_MS", 60000);
+
+/// Number of clients whose usage can be tracked at the same time.
+pub const MAX_QUOTA_CLIENTS: usize = set_env_or_default!("MAX_QUOTA_CLIENTS", 8);
+
+pub const CLIENT_QUOTA_ENABLED: bool = CLIENT_QUOTA_US != 0;
+
+/// Maximum length of a client identifier, it is the length of an IPv6 address.
+pub const MAX_CLIENT_ID_LEN: usize = 16;
+
+/// Identifies the client that sent a request by its address, see `QuotaListener`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientId {
+    bytes: [u8; MAX_CLIENT_ID_LEN],
+    len: usize,
+}
+
+impl ClientId {
+    /// Creates the identifier from its bytes, the ones after the first
+    /// [`MAX_CLIENT_ID_LEN`] are ignored.
+    pub fn new(id: &[u8]) -> Self {
+        let len = id.len().min(MAX_CLIENT_ID_LEN);
+        let mut bytes = [0; MAX_CLIENT_ID_LEN];
+        bytes[..len].copy_from_slice(&id[..len]);
+        Self { bytes, len }
+    }
+
+    fn hex(&self) -> String {
+        self.bytes[..self.len].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ClientUsage {
+    client: ClientId,
+    /// Time (in ztimer msec ticks) at which the current window started.
+    window_start_ms: u32,
+    /// Execution time used within the current window.
+    used_us: u64,
+}
+
+impl ClientUsage {
+    fn window_expired(&self, now: u32) -> bool {
+        now.wrapping_sub(self.window_start_ms) >= CLIENT_QUOTA_WINDOW_MS as u32
+    }
+}
+
+const UNTRACKED: Option<ClientUsage> = None;
+static CLIENT_USAGE: Mutex<[Option<ClientUsage>; MAX_QUOTA_CLIENTS]> =
+    Mutex::new([UNTRACKED; MAX_QUOTA_CLIENTS]);
+
+/// Client whose request is being handled, together with the thread handling it.
+static CURRENT_REQUEST: Mutex<Option<(riot_sys::kernel_pid_t, ClientId)>> = Mutex::new(None);
+
+/// Checks whether the client can execute another request, i.e. it hasn't
+/// used up its quota in the current window. Clients which aren't tracked yet
+/// start with a new window. Returns the reason why the request needs to be
+/// rejected otherwise.
+pub fn admit(client: ClientId) -> Result<(), String> {
+    let now = clock::now_ms();
+    let mut usage = CLIENT_USAGE.lock();
+
+    if let Some(entry) = usage.iter_mut().flatten().find(|u| u.client == client) {
+        if entry.window_expired(now) {
+            entry.window_start_ms = now;
+            entry.used_us = 0;
+        }
+        if entry.used_us >= CLIENT_QUOTA_US as u64 {
+            let retry_in_ms = (CLIENT_QUOTA_WINDOW_MS as u32)
+                .saturating_sub(now.wrapping_sub(entry.window_start_ms));
+            Err(format!(
+                "Client {} used up its quota of {} us, retry in {} ms",
+                client.hex(),
+                CLIENT_QUOTA_US,
+                retry_in_ms
+            ))?;
+        }
+        return Ok(());
+    }
+
+    let Some(free) = usage
+        .iter_mut()
+        .find(|u| u.map_or(true, |u| u.window_expired(now)))
+    else {
+        return Err(format!(
+            "All {} client quotas are in use, retry later",
+            MAX_QUOTA_CLIENTS
+        ));
+    };
+    *free = Some(ClientUsage {
+        client,
+        window_start_ms: now,
+        used_us: 0,
+    });
+    Ok(())
+}
+
+/// Marks the start of handling a request admitted using [`admit`] on the
+/// current thread, the programs it executes until [`finish_request`] is called
+/// are charged to the client.
+pub fn start_request(client: ClientId) {
+    *CURRENT_REQUEST.lock() = Some((thread::get_pid().into(), client));
+}
+
+pub fn finish_request() {
+    *CURRENT_REQUEST.lock() = None;
+}
+
+/// Adds the execution time of a program to the usage of the client whose
+/// request is handled by the current thread. Executions outside of the
+/// requests (e.g. the long-running programs on the workers) aren't charged.
+pub fn charge_execution(elapsed_us: u32) {
+    if !CLIENT_QUOTA_ENABLED {
+        return;
+    }
+    let pid: riot_sys::kernel_pid_t = thread::get_pid().into();
+    let Some((request_pid, client)) = *CURRENT_REQUEST.lock() else {
+        return;
+    };
+    if request_pid != pid {
+        return;
+    }
+    let mut usage = CLIENT_USAGE.lock();
+    if let Some(entry) = usage.iter_mut().flatten().find(|u| u.client == client) {
+        entry.used_us = entry.used_us.saturating_add(elapsed_us as u64);
+    }
+}
@@ -0,0 +1,78 @@
+//! Registry of routes that dispatch CoAP requests to a deployed program which
+//! works on the packet (the `WithAccessToCoapPacket` model). Several routes
+//! can point to the same program, each of them with a different `path_id`,
+//! so a single program can serve multiple endpoints instead of deploying a
+//! near-identical copy of it for each of them.
+//!
+//! The routes are served under `/routed/<name>` and registered using the
+//! `/routes/<name>/<path_id>` endpoint, the payload of the registration is
+//! the execution request used to run the program. Routes registered with the
+//! `in-place` query run the program on the payload of the request instead,
+//! which it transforms into the response payload without it being copied out
+//! of the packet buffer.
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use log::debug;
+use macros::set_env_or_default;
+use micro_bpf_common::{HelperFunctionID, VMConfiguration};
+use riot_wrappers::mutex::Mutex;
+
+use crate::{infra::suit_storage, util::logger::targets};
+
+pub const MAX_COAP_ROUTES: usize = set_env_or_default!("MAX_COAP_ROUTES", 8);
+
+/// Program that handles the requests sent to a given route.
+#[derive(Clone)]
+pub struct CoapRoute {
+    pub configuration: VMConfiguration,
+    pub allowed_helpers: Vec<HelperFunctionID>,
+    /// Passed to the program in r2 so that it can tell which of the routes
+    /// sharing it the request was sent to.
+    pub path_id: u32,
+    /// The program works on the payload instead of the whole packet, see
+    /// `VirtualMachine::execute_on_payload`.
+    pub in_place: bool,
+}
+
+static COAP_ROUTES: Mutex<BTreeMap<String, CoapRoute>> = Mutex::new(BTreeMap::new());
+
+/// Registers the route under the given name, replacing the route that was
+/// previously registered under that name.
+pub fn register_route(name: &str, route: CoapRoute) -> Result<(), String> {
+    if name.is_empty() || name.contains('/') {
+        Err(format!("Invalid route name: {:?}", name))?;
+    }
+    if !suit_storage::is_suit_slot(route.configuration.suit_slot) {
+        Err(format!("Invalid SUIT slot: {}", route.configuration.suit_slot))?;
+    }
+    // The rBPF interpreter only initialises r1 (with the CoAP context), so
+    // the path id can only be passed to jitted programs. The programs working
+    // on the payload receive it in their context instead.
+    if !route.configuration.jit && !route.in_place {
+        Err("Routed programs need to be executed using the JIT")?;
+    }
+
+    let mut routes = COAP_ROUTES.lock();
+    if !routes.contains_key(name) && routes.len() >= MAX_COAP_ROUTES {
+        Err(format!("All {} routes are already registered", MAX_COAP_ROUTES))?;
+    }
+    debug!(
+        target: targets::COAP,
+        "Routing /{} to slot {} (path id {}, in place: {})",
+        name,
+        route.configuration.suit_slot,
+        route.path_id,
+        route.in_place
+    );
+    routes.insert(name.to_string(), route);
+    Ok(())
+}
+
+pub fn get_route(name: &str) -> Option<CoapRoute> {
+    COAP_ROUTES.lock().get(name).cloned()
+}
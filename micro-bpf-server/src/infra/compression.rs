@@ -0,0 +1,129 @@
+//! Support for deploying compressed programs.
+//!
+//! Raw object files are much larger than the bytecode they contain, so sending
+//! them over the constrained link is slow. To speed it up, the deploy tool can
+//! compress the program using the LZ4 block format and prepend it with the
+//! following header (all fields little-endian):
+//!
+//! ```text
+//! +----------------+--------------------------+----------------------+
+//! | magic (4B)     | decompressed length (4B) | LZ4 compressed block |
+//! +----------------+--------------------------+----------------------+
+//! ```
+//!
+//! After the SUIT fetch completes, the program is decompressed in place of the
+//! compressed one, before the relocations are resolved. Programs without the
+//! header are left unmodified, so compression is optional.
+
+use alloc::{format, string::String, vec::Vec};
+use core::convert::TryInto;
+use log::debug;
+
+use crate::util::logger::targets;
+
+/// Marks a program as compressed, it can't be confused with the start of
+/// an ELF file (0x7f 'E' 'L' 'F') or any of the supported program headers.
+pub const COMPRESSED_PROGRAM_MAGIC: [u8; 4] = *b"mbz4";
+const HEADER_SIZE: usize = 8;
+
+/// LZ4 requires that each match is at least 4 bytes long, the length stored
+/// in the sequence token is relative to that.
+const MIN_MATCH: usize = 4;
+
+pub fn is_compressed(program: &[u8]) -> bool {
+    program.len() >= HEADER_SIZE && program[..4] == COMPRESSED_PROGRAM_MAGIC
+}
+
+/// Decompresses a program with the header described in the module docs.
+/// The decompressed length is validated against both the header and the
+/// provided capacity (i.e. the size of the buffer it is going to be written to).
+pub fn decompress_program(program: &[u8], capacity: usize) -> Result<Vec<u8>, String> {
+    if !is_compressed(program) {
+        Err("Program is not compressed")?;
+    }
+    let expected_len = u32::from_le_bytes(program[4..HEADER_SIZE].try_into().unwrap()) as usize;
+    if expected_len > capacity {
+        Err(format!(
+            "Decompressed program ({} [B]) doesn't fit into the buffer ({} [B])",
+            expected_len, capacity
+        ))?;
+    }
+
+    let decompressed = lz4_decompress_block(&program[HEADER_SIZE..], expected_len)?;
+    if decompressed.len() != expected_len {
+        Err(format!(
+            "Decompressed length {} doesn't match the header: {}",
+            decompressed.len(),
+            expected_len
+        ))?;
+    }
+    debug!(
+        target: targets::STORAGE,
+        "Decompressed program: {} [B] -> {} [B]",
+        program.len(),
+        decompressed.len()
+    );
+    Ok(decompressed)
+}
+
+/// Decompresses a single LZ4 block, the output is not allowed to exceed
+/// `max_len` bytes.
+fn lz4_decompress_block(input: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+    let corrupted = || String::from("Corrupted LZ4 block");
+    let mut output: Vec<u8> = Vec::with_capacity(max_len);
+    let mut i = 0;
+
+    // Lengths of 15 are continued in the following bytes, each byte equal to
+    // 255 means that yet another byte follows.
+    let read_length = |i: &mut usize, mut length: usize| -> Result<usize, String> {
+        if length == 15 {
+            loop {
+                let byte = *input.get(*i).ok_or_else(corrupted)?;
+                *i += 1;
+                length += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    };
+
+    while i < input.len() {
+        let token = input[i];
+        i += 1;
+
+        let literals_len = read_length(&mut i, (token >> 4) as usize)?;
+        let literals = input.get(i..i + literals_len).ok_or_else(corrupted)?;
+        if output.len() + literals_len > max_len {
+            Err(corrupted())?;
+        }
+        output.extend_from_slice(literals);
+        i += literals_len;
+
+        // The last sequence consists only of literals.
+        if i == input.len() {
+            break;
+        }
+
+        let offset_bytes = input.get(i..i + 2).ok_or_else(corrupted)?;
+        let offset = u16::from_le_bytes([offset_bytes[0], offset_bytes[1]]) as usize;
+        i += 2;
+        if offset == 0 || offset > output.len() {
+            Err(corrupted())?;
+        }
+
+        let match_len = read_length(&mut i, (token & 0x0f) as usize)? + MIN_MATCH;
+        if output.len() + match_len > max_len {
+            Err(corrupted())?;
+        }
+        // The match can overlap with the bytes that it produces, so they need
+        // to be copied one by one.
+        let start = output.len() - offset;
+        for j in 0..match_len {
+            output.push(output[start + j]);
+        }
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,91 @@
+//! This module allows for finding out which program was running when the
+//! device crashed (e.g. hard fault or watchdog reset caused by a misbehaving
+//! program).
+//!
+//! Before a VM worker starts executing a program, it records the SUIT storage
+//! slot of that program in a small memory region that is not initialised on
+//! startup (the `.noinit` section), so its contents survive a reset. Once the
+//! execution finishes, the record is cleared. This means that if any slot is
+//! still recorded when the device boots, the program from that slot was being
+//! executed at the time of the reset.
+//!
+//! Note that on a power-on reset the contents of the region are undefined,
+//! which is why it is guarded by a magic value.
+
+use core::{mem::MaybeUninit, ptr::addr_of_mut};
+
+use log::error;
+use riot_wrappers::mutex::Mutex;
+
+use crate::util::logger::targets;
+
+/// Number of VM worker threads that can execute programs concurrently.
+pub const WORKERS: usize = crate::vm::NUM_WORKERS;
+
+/// Marks the retained region as initialised ("mbpf" in ASCII).
+const RETAINED_MAGIC: u32 = 0x6d62_7066;
+const NO_SLOT: i32 = -1;
+
+#[repr(C)]
+struct RetainedState {
+    magic: u32,
+    /// Slot of the program executed by each of the workers, -1 if idle.
+    running_slots: [i32; WORKERS],
+}
+
+#[link_section = ".noinit"]
+static mut RETAINED_STATE: MaybeUninit<RetainedState> = MaybeUninit::uninit();
+
+/// Slots that were being executed when the device was last reset, it is
+/// `None` if no program was running at that time.
+static LAST_CRASH: Mutex<Option<[i32; WORKERS]>> = Mutex::new(None);
+
+/// Reads the state retained from before the reset and then clears it. It
+/// needs to be called on startup before any of the workers is started.
+pub fn init() {
+    unsafe {
+        let state = (*addr_of_mut!(RETAINED_STATE)).as_mut_ptr();
+        let magic = core::ptr::read_volatile(addr_of_mut!((*state).magic));
+        if magic == RETAINED_MAGIC {
+            let slots = core::ptr::read_volatile(addr_of_mut!((*state).running_slots));
+            if slots.iter().any(|s| *s != NO_SLOT) {
+                error!(
+                    target: targets::WORKER,
+                    "Reset while executing programs, running slots: {:?}",
+                    slots,
+                );
+                *LAST_CRASH.lock() = Some(slots);
+            }
+        }
+        core::ptr::write_volatile(addr_of_mut!((*state).running_slots), [NO_SLOT; WORKERS]);
+        core::ptr::write_volatile(addr_of_mut!((*state).magic), RETAINED_MAGIC);
+    }
+}
+
+/// Records that the given worker started executing the program from the slot.
+pub fn mark_running(worker: usize, slot: usize) {
+    set_running_slot(worker, slot as i32);
+}
+
+/// Records that the given worker has finished executing its program.
+pub fn clear_running(worker: usize) {
+    set_running_slot(worker, NO_SLOT);
+}
+
+fn set_running_slot(worker: usize, slot: i32) {
+    if worker >= WORKERS {
+        return;
+    }
+    unsafe {
+        let state = (*addr_of_mut!(RETAINED_STATE)).as_mut_ptr();
+        // Volatile write ensures that the value is in memory before
+        // the program starts executing.
+        core::ptr::write_volatile(addr_of_mut!((*state).running_slots[worker]), slot);
+    }
+}
+
+/// Returns the slots (indexed by worker) that were being executed when the
+/// device was last reset, -1 means that the worker was idle.
+pub fn last_crash() -> Option<[i32; WORKERS]> {
+    *LAST_CRASH.lock()
+}
@@ -0,0 +1,95 @@
+//! Log of the most recent debug output printed by the executed programs (using
+//! `bpf_print_debug` and `bpf_printf`). The output is still printed to the
+//! console, the log allows for reading it remotely using the `/logs` endpoint
+//! when the device isn't connected over serial.
+//!
+//! The log is a ring buffer of [`EXECUTION_LOG_SIZE`] lines, once it is full,
+//! each new line overwrites the oldest one. Similarly to the benchmark log
+//! (see [`super::benchmark_log`]), every line is assigned a sequence number,
+//! gaps in those numbers mean that some lines were overwritten before they
+//! were read.
+
+use alloc::{format, string::String, vec::Vec};
+use macros::set_env_or_default;
+use riot_wrappers::{mutex::Mutex, thread};
+
+use super::local_storage;
+
+/// Number of lines kept in the log.
+pub const EXECUTION_LOG_SIZE: usize = set_env_or_default!("EXECUTION_LOG_SIZE", 16);
+
+/// Longer lines are truncated so that the log has a bounded size.
+pub const MAX_LOG_LINE_LEN: usize = 48;
+
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub sequence: u32,
+    /// PID of the thread which executed the program.
+    pub pid: riot_sys::kernel_pid_t,
+    /// SUIT storage slot of the program, if it is known for the thread.
+    pub slot: Option<usize>,
+    pub text: String,
+}
+
+impl LogLine {
+    /// Formats the line as `<sequence> <pid> <slot> <text>`, the slot is `-`
+    /// if it isn't known.
+    pub fn format(&self) -> String {
+        let slot = match self.slot {
+            Some(slot) => format!("{}", slot),
+            None => String::from("-"),
+        };
+        format!("{} {} {} {}\n", self.sequence, self.pid, slot, self.text)
+    }
+}
+
+struct ExecutionLog {
+    lines: [Option<LogLine>; EXECUTION_LOG_SIZE],
+    /// Sequence number assigned to the next line, the line is stored at the
+    /// index `next_sequence % EXECUTION_LOG_SIZE`.
+    next_sequence: u32,
+}
+
+const EMPTY_LINE: Option<LogLine> = None;
+
+static EXECUTION_LOG: Mutex<ExecutionLog> = Mutex::new(ExecutionLog {
+    lines: [EMPTY_LINE; EXECUTION_LOG_SIZE],
+    next_sequence: 0,
+});
+
+/// Adds a line printed by the program executed by the current thread to the
+/// log, overwriting the oldest one if it is full.
+pub fn record(text: &str) {
+    let text = text.trim_end_matches('\n');
+    let mut end = text.len().min(MAX_LOG_LINE_LEN);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let pid = thread::get_pid().into();
+    let slot = local_storage::current_thread_slot();
+
+    let mut log = EXECUTION_LOG.lock();
+    let sequence = log.next_sequence;
+    log.lines[sequence as usize % EXECUTION_LOG_SIZE] = Some(LogLine {
+        sequence,
+        pid,
+        slot,
+        text: String::from(&text[..end]),
+    });
+    log.next_sequence = sequence.wrapping_add(1);
+}
+
+/// Returns the lines with sequence numbers starting at `from_sequence`,
+/// oldest first.
+pub fn lines_since(from_sequence: u32) -> Vec<LogLine> {
+    let mut lines = EXECUTION_LOG
+        .lock()
+        .lines
+        .iter()
+        .flatten()
+        .filter(|l| l.sequence >= from_sequence)
+        .cloned()
+        .collect::<Vec<_>>();
+    lines.sort_by_key(|l| l.sequence);
+    lines
+}
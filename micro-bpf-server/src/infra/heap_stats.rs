@@ -0,0 +1,39 @@
+//! Statistics of the heap usage, they allow for detecting memory leaks by
+//! watching the usage across repeated executions of the same program (see the
+//! `/diagnostics/heap` endpoint).
+//!
+//! The allocator forwards the allocations to the RIOT `malloc`, which doesn't
+//! keep track of the allocated memory. The statistics are collected by the
+//! `malloc_monitor` module, which needs to be enabled using `HEAP_STATS=1` when
+//! building the firmware, as it adds overhead to every allocation. Without it,
+//! sampling the statistics fails.
+
+use alloc::{format, string::String};
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// Number of bytes currently allocated.
+    pub used: u32,
+    /// Largest number of bytes allocated at once since boot.
+    pub peak: u32,
+    /// Number of bytes that are still available, it is only known on the
+    /// platforms where the size of the heap is exposed by the linker script.
+    pub free: Option<u32>,
+}
+
+extern "C" {
+    fn heap_stats_sample(used: *mut u32, peak: *mut u32, total: *mut u32) -> i32;
+}
+
+pub fn sample() -> Result<HeapStats, String> {
+    let (mut used, mut peak, mut total) = (0, 0, 0);
+    let ret = unsafe { heap_stats_sample(&mut used, &mut peak, &mut total) };
+    if ret < 0 {
+        Err(format!("Heap statistics are not available ({})", ret))?;
+    }
+    Ok(HeapStats {
+        used,
+        peak,
+        free: (total > 0).then(|| total.saturating_sub(used)),
+    })
+}
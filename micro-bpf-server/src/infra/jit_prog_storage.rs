@@ -5,15 +5,28 @@
 //! will obtain a mutable reference to the contents of one of the slots,
 //! write the program there and then execute it by casting into a function pointer.
 
+#[cfg(feature = "jit-dump")]
+use alloc::vec::Vec;
 use alloc::{format, string::String};
 use log::debug;
+use macros::set_env_or_default;
 use riot_wrappers::mutex::{Mutex, MutexGuard};
 
-use super::suit_storage::{SUIT_STORAGE_SLOTS, SUIT_STORAGE_SLOT_SIZE};
+use super::{
+    relocation_check,
+    suit_storage::{self, SuitStorageSlotStatus, SUIT_STORAGE_SLOTS, SUIT_STORAGE_SLOT_SIZE},
+};
+
+use crate::util::logger::targets;
 
 pub const JIT_STORAGE_SLOTS_NUM: usize = SUIT_STORAGE_SLOTS / 2;
 pub const JIT_SLOT_SIZE: usize = SUIT_STORAGE_SLOT_SIZE;
 
+/// Upper bound on how many times larger the native code emitted by the JIT
+/// is than the eBPF bytecode of the `.text` section. It is used to estimate
+/// whether a program fits into a JIT slot without compiling it.
+const JIT_EXPANSION_FACTOR: usize = set_env_or_default!("JIT_EXPANSION_FACTOR", 2);
+
 /// Each slot is a tuple of the program bytes and an offset to the start of the
 /// .text section inside of the program
 static JIT_PROGRAM_SLOTS: [Mutex<([u8; JIT_SLOT_SIZE], usize)>; JIT_STORAGE_SLOTS_NUM] =
@@ -29,6 +42,11 @@ static JIT_SLOT_STATE: Mutex<[bool; JIT_STORAGE_SLOTS_NUM]> =
 static JIT_SLOT_TEXT_OFFSETS: Mutex<[usize; JIT_STORAGE_SLOTS_NUM]> =
     Mutex::new([0; JIT_STORAGE_SLOTS_NUM]);
 
+// Lengths of the jitted programs, the slots themselves are zero-padded so
+// the end of the program can't be determined from their contents.
+static JIT_SLOT_PROGRAM_LENGTHS: Mutex<[usize; JIT_STORAGE_SLOTS_NUM]> =
+    Mutex::new([0; JIT_STORAGE_SLOTS_NUM]);
+
 /// Should be used to get access to one jit storage slots to be able to write
 /// the jit-compiled program into it.
 pub fn acquire_storage_slot(
@@ -60,6 +78,7 @@ pub fn free_storage_slot(slot_index: usize) -> Result<(), String> {
     }
 
     slot_states[slot_index] = false;
+    JIT_SLOT_PROGRAM_LENGTHS.lock()[slot_index] = 0;
     let mut guard = JIT_PROGRAM_SLOTS[slot_index].lock();
     guard.0.fill(0);
     guard.1 = 0;
@@ -82,15 +101,130 @@ pub fn get_program_from_slot(
     }
 
     let mut guard = JIT_PROGRAM_SLOTS[slot_index].lock();
-    debug!("Loading previously jitted program from slot {}", slot_index);
+    debug!(target: targets::JIT, "Loading previously jitted program from slot {}", slot_index);
 
     let offset = guard.1.clone();
+    if offset >= JIT_SLOT_SIZE {
+        Err(format!("JIT overflow: .text offset {} outside of the slot", offset))?;
+    }
     Ok(rbpf::JitMemory::get_prog_from_slice(
         guard.0.as_mut(),
         offset,
     ))
 }
 
+/// Checks that the jitted program lies within its slot. The program is
+/// executed by jumping to the start of its `.text` section, so an offset
+/// outside of the slot (e.g. caused by a bug in the JIT compiler) would
+/// make the device jump into arbitrary memory.
+pub fn validate_jitted_program(text_offset: usize, program_length: usize) -> Result<(), String> {
+    let fits = text_offset < JIT_SLOT_SIZE
+        && program_length <= JIT_SLOT_SIZE
+        && text_offset <= program_length;
+    if !fits {
+        Err(format!(
+            "JIT overflow: program of {} [B] with .text at offset {} doesn't fit into the {} [B] slot",
+            program_length, text_offset, JIT_SLOT_SIZE
+        ))?;
+    }
+    Ok(())
+}
+
+/// Records the length of the program that was jitted into the given slot.
+/// It should be called once the program has been validated using
+/// [`validate_jitted_program`].
+pub fn set_program_length(slot_index: usize, program_length: usize) -> Result<(), String> {
+    validate_slot_index(slot_index)?;
+    JIT_SLOT_PROGRAM_LENGTHS.lock()[slot_index] = program_length;
+    Ok(())
+}
+
+/// Contents of a JIT storage slot returned by [`dump_program`].
+#[cfg(feature = "jit-dump")]
+pub struct JitProgramDump {
+    /// Up to the requested number of program bytes starting at the requested offset.
+    pub bytes: Vec<u8>,
+    /// Total length of the jitted program.
+    pub program_length: usize,
+    pub text_offset: usize,
+}
+
+/// Copies out the bytes of the program jitted into the given slot so that
+/// the generated machine code can be inspected (e.g. disassembled with objdump).
+/// Only `max_len` bytes starting at `offset` are returned, so that large
+/// programs can be read in chunks.
+#[cfg(feature = "jit-dump")]
+pub fn dump_program(
+    slot_index: usize,
+    offset: usize,
+    max_len: usize,
+) -> Result<JitProgramDump, String> {
+    validate_slot_index(slot_index)?;
+
+    // The length is only set once the compilation has finished successfully,
+    // so slots that are still being compiled into are reported as empty.
+    let program_length = JIT_SLOT_PROGRAM_LENGTHS.lock()[slot_index];
+    if !JIT_SLOT_STATE.lock()[slot_index] || program_length == 0 {
+        Err(format!(
+            "Slot index {} doesn't contain a jitted program",
+            slot_index
+        ))?;
+    }
+
+    if offset > program_length {
+        Err(format!(
+            "Offset {} outside of the jitted program of {} [B]",
+            offset, program_length
+        ))?;
+    }
+
+    let guard = JIT_PROGRAM_SLOTS[slot_index].lock();
+    let end = program_length.min(offset.saturating_add(max_len));
+    Ok(JitProgramDump {
+        bytes: Vec::from(&guard.0[offset..end]),
+        program_length,
+        text_offset: guard.1,
+    })
+}
+
+/// Checks whether the program loaded into the given SUIT storage slot can be
+/// JIT-compiled, without compiling it. The JIT only supports the
+/// `RawObjectFile` layout, the program needs to be loaded into a slot which
+/// has a corresponding JIT slot and its estimated size after the compilation
+/// (the `.text` section expanded by [`JIT_EXPANSION_FACTOR`] together with the
+/// remaining sections) needs to fit into [`JIT_SLOT_SIZE`]. The estimate is
+/// conservative, so the compilation of a compatible program can still fail
+/// for other reasons (e.g. an invalid instruction).
+pub fn jit_compatible(slot: usize) -> Result<bool, String> {
+    if !suit_storage::is_suit_slot(slot) {
+        Err(format!("Invalid SUIT slot: {}", slot))?;
+    }
+    if suit_storage::suit_slot_status(slot) == SuitStorageSlotStatus::Free {
+        Err(format!("Slot {} doesn't contain a program", slot))?;
+    }
+    if slot >= JIT_STORAGE_SLOTS_NUM {
+        return Ok(false);
+    }
+
+    debug!(target: targets::JIT, "Checking the program in slot {}", slot);
+    fits_into_jit_slot(suit_storage::load_program_static(slot))
+}
+
+/// Checks the layout and the estimated size of the program after the
+/// compilation, see [`jit_compatible`].
+fn fits_into_jit_slot(program: &[u8]) -> Result<bool, String> {
+    if !relocation_check::is_elf(program) {
+        return Ok(false);
+    }
+    let text_size = relocation_check::text_section_size(program)?;
+    let estimated_size = program.len().saturating_sub(text_size) + text_size * JIT_EXPANSION_FACTOR;
+    debug!(
+        target: targets::JIT,
+        "Estimated size of the jitted program: {} [B]", estimated_size
+    );
+    Ok(estimated_size <= JIT_SLOT_SIZE)
+}
+
 fn log_program_contents(program: &[u8], length: usize) {
     let mut prog_str: String = String::new();
     for (i, b) in program.iter().take(length).enumerate() {
@@ -99,13 +233,95 @@ fn log_program_contents(program: &[u8], length: usize) {
             prog_str.push_str("\n");
         }
     }
-    debug!("program bytes:\n{}", prog_str);
+    debug!(target: targets::JIT, "program bytes:\n{}", prog_str);
 }
 
 fn validate_slot_index(slot_index: usize) -> Result<(), String> {
-    if slot_index > JIT_STORAGE_SLOTS_NUM {
+    if slot_index >= JIT_STORAGE_SLOTS_NUM {
         Err(format!("Slot index {} out of bounds", slot_index))?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHSTRTAB: &[u8] = b"\0.text\0.shstrtab\0";
+
+    /// Builds a relocatable 64-bit ELF file with a `.text` section of the
+    /// given size and no relocations.
+    fn object_file(text_size: usize) -> Vec<u8> {
+        let text_offset = 64;
+        let shstrtab_offset = text_offset + text_size;
+        let shoff = (shstrtab_offset + SHSTRTAB.len() + 7) & !7;
+
+        let mut elf = Vec::new();
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01");
+        elf.resize(16, 0);
+        elf.extend_from_slice(&1u16.to_le_bytes()); // e_type: ET_REL
+        elf.extend_from_slice(&247u16.to_le_bytes()); // e_machine: EM_BPF
+        elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+        elf.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+        elf.extend_from_slice(&0u64.to_le_bytes()); // e_phoff
+        elf.extend_from_slice(&(shoff as u64).to_le_bytes()); // e_shoff
+        elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+        for half in [64u16, 56, 0, 64, 3, 2] {
+            // e_ehsize, e_phentsize, e_phnum, e_shentsize, e_shnum, e_shstrndx
+            elf.extend_from_slice(&half.to_le_bytes());
+        }
+        elf.resize(shstrtab_offset, 0x95);
+        elf.extend_from_slice(SHSTRTAB);
+        elf.resize(shoff + 64, 0);
+
+        // .text (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR) and .shstrtab
+        let sections = [
+            (1u32, 1u32, 6u64, text_offset, text_size),
+            (7, 3, 0, shstrtab_offset, SHSTRTAB.len()),
+        ];
+        for (name, kind, flags, offset, size) in sections {
+            elf.extend_from_slice(&name.to_le_bytes());
+            elf.extend_from_slice(&kind.to_le_bytes());
+            elf.extend_from_slice(&flags.to_le_bytes());
+            elf.extend_from_slice(&0u64.to_le_bytes()); // sh_addr
+            elf.extend_from_slice(&(offset as u64).to_le_bytes());
+            elf.extend_from_slice(&(size as u64).to_le_bytes());
+            elf.extend_from_slice(&[0; 8]); // sh_link, sh_info
+            elf.extend_from_slice(&1u64.to_le_bytes()); // sh_addralign
+            elf.extend_from_slice(&0u64.to_le_bytes()); // sh_entsize
+        }
+        elf
+    }
+
+    #[test]
+    fn small_object_files_fit() {
+        let program = object_file(64);
+        assert_eq!(relocation_check::text_section_size(&program), Ok(64));
+        assert_eq!(fits_into_jit_slot(&program), Ok(true));
+    }
+
+    #[test]
+    fn expanded_text_needs_to_fit() {
+        let text_size = JIT_SLOT_SIZE / JIT_EXPANSION_FACTOR;
+        // The program itself fits into the slot, only its compiled form
+        // doesn't.
+        assert!(object_file(text_size).len() <= JIT_SLOT_SIZE);
+        assert_eq!(fits_into_jit_slot(&object_file(text_size)), Ok(false));
+    }
+
+    #[test]
+    fn other_layouts_are_not_compatible() {
+        // Programs deployed using the other layouts start with the bytecode
+        // or a header, not the ELF magic.
+        assert_eq!(fits_into_jit_slot(&[0x95, 0, 0, 0, 0, 0, 0, 0]), Ok(false));
+        assert_eq!(fits_into_jit_slot(&[]), Ok(false));
+    }
+
+    #[test]
+    fn malformed_object_files_are_rejected() {
+        let mut program = object_file(64);
+        program.truncate(32);
+        assert!(fits_into_jit_slot(&program).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+//! The most recent execution result of the program in each SUIT storage slot.
+//!
+//! Every execution of a deployed program (both the short-lived ones executed
+//! by the CoAP server and the long-running ones executed by the VM workers)
+//! overwrites the result of the slot, so that clients (e.g. dashboards) can
+//! show the latest known value using the `/result/last/<slot>` endpoint without
+//! running the program again. Only one result is kept per slot, it is dropped
+//! once the slot is overwritten or erased.
+
+use riot_wrappers::mutex::Mutex;
+
+use super::suit_storage::SUIT_STORAGE_SLOTS;
+use crate::{model::results::ExecutionResult, vm::clock};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LastResult {
+    pub result: ExecutionResult,
+    /// Execution time of the program in microseconds.
+    pub elapsed_us: u32,
+    /// Time (in ztimer msec ticks) at which the execution finished.
+    pub finished_at_ms: u32,
+}
+
+impl LastResult {
+    /// Milliseconds elapsed since the execution finished.
+    pub fn age_ms(&self) -> u32 {
+        clock::since_ms(self.finished_at_ms)
+    }
+}
+
+const NO_RESULT: Option<LastResult> = None;
+static LAST_RESULTS: Mutex<[Option<LastResult>; SUIT_STORAGE_SLOTS]> =
+    Mutex::new([NO_RESULT; SUIT_STORAGE_SLOTS]);
+
+/// Replaces the last result of the slot with the one of the execution that
+/// has just finished.
+pub fn record(slot: usize, result: ExecutionResult, elapsed_us: u32) {
+    if let Some(last) = LAST_RESULTS.lock().get_mut(slot) {
+        *last = Some(LastResult {
+            result,
+            elapsed_us,
+            finished_at_ms: clock::now_ms(),
+        });
+    }
+}
+
+/// Returns the last result of the slot, `None` if the program in the slot
+/// hasn't been executed yet.
+pub fn get(slot: usize) -> Option<LastResult> {
+    LAST_RESULTS.lock().get(slot).copied().flatten()
+}
+
+/// Forgets the last result of the slot, e.g. when its program is replaced.
+pub fn clear(slot: usize) {
+    if let Some(last) = LAST_RESULTS.lock().get_mut(slot) {
+        *last = None;
+    }
+}
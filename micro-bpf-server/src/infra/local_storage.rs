@@ -27,6 +27,8 @@ use riot_wrappers::{mutex::Mutex, thread};
 
 use super::suit_storage::{self, SUIT_STORAGE_SLOTS};
 
+use crate::util::logger::targets;
+
 const EMPTY_MAP: BTreeMap<usize, i32> = BTreeMap::new();
 /// Each SUIT storage slot has its associated BTreeMap storage.
 static LOCAL_STORAGE: Mutex<[BTreeMap<usize, i32>; SUIT_STORAGE_SLOTS]> =
@@ -49,7 +51,10 @@ pub fn local_storage_store(key: usize, value: i32) -> i32 {
         storage[slot_number].insert(key, value);
         return value;
     } else {
-        error!("No slot number found corresponding to the current thread");
+        error!(
+            target: targets::STORAGE,
+            "No slot number found corresponding to the current thread",
+        );
         return 0;
     }
 }
@@ -58,7 +63,7 @@ pub fn local_storage_fetch(key: usize) -> Option<i32> {
     let slot_number = lookup_slot_number();
 
     if let None = slot_number {
-        error!("No slot number found corresponding the current thread");
+        error!(target: targets::STORAGE, "No slot number found corresponding the current thread");
         return None;
     }
 
@@ -66,6 +71,11 @@ pub fn local_storage_fetch(key: usize) -> Option<i32> {
     return storage[slot_number.unwrap()].get(&key).copied();
 }
 
+/// Returns the SUIT storage slot of the program executed by the current thread.
+pub fn current_thread_slot() -> Option<usize> {
+    lookup_slot_number()
+}
+
 fn lookup_slot_number() -> Option<usize> {
     let pid = thread::get_pid().into();
     let map = THREAD_TO_STORAGE_SLOT.lock();
@@ -77,11 +87,20 @@ fn lookup_slot_number() -> Option<usize> {
 /// don't wanto to allow other threads for loading programs in there.
 pub fn register_suit_slot(slot: usize) {
     let pid = thread::get_pid().into();
-    debug!("Registering SUIT slot {} for thread {}", slot, pid);
+    debug!(target: targets::STORAGE, "Registering SUIT slot {} for thread {}", slot, pid);
     let mut map = THREAD_TO_STORAGE_SLOT.lock();
     map.insert(pid, slot);
 }
 
+/// Moves the local storage of the program in slot `from` to the slot `to`,
+/// it is used when a running program is replaced by its new version which
+/// was loaded into a different slot. The storage of `to` is overwritten.
+pub fn move_slot_storage(from: usize, to: usize) {
+    debug!(target: targets::STORAGE, "Moving local storage from slot {} to slot {}", from, to);
+    let mut storage = LOCAL_STORAGE.lock();
+    storage[to] = core::mem::take(&mut storage[from]);
+}
+
 pub fn deregister_suit_slot(slot: usize) {
     let mut map = THREAD_TO_STORAGE_SLOT.lock();
     let pids_to_remove: Vec<riot_sys::kernel_pid_t> = map
@@ -90,6 +109,7 @@ pub fn deregister_suit_slot(slot: usize) {
         .map(|(p, _)| *p)
         .collect();
     debug!(
+        target: targets::STORAGE,
         "Deregistering SUIT slot {} from threads: {:?}",
         slot, pids_to_remove
     );
@@ -2,4 +2,19 @@ pub mod allocator;
 pub mod suit_storage;
 pub mod local_storage;
 pub mod jit_prog_storage;
+pub mod compression;
+pub mod crash_diagnostics;
+pub mod relocation_check;
+pub mod program_analysis;
+pub mod program_metadata;
+pub mod autostart;
+pub mod benchmark_log;
+pub mod coap_routes;
+pub mod execution_log;
+pub mod heap_stats;
+pub mod result_cache;
+pub mod last_results;
+pub mod client_quota;
+pub mod wcet;
 
+pub mod native_functions;
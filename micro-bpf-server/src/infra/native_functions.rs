@@ -0,0 +1,45 @@
+//! Registry of precompiled native functions that can be invoked by name using
+//! the `/native/<name>` CoAP endpoint. It is used for benchmarking the VMs
+//! against the native baseline: adding a new benchmark kernel only requires
+//! registering it here instead of implementing a new endpoint handler.
+
+use alloc::collections::BTreeMap;
+use log::debug;
+use riot_wrappers::mutex::Mutex;
+
+/// Native functions take no arguments, any data they operate on needs to be
+/// compiled into them. The returned value is sent back in the response.
+pub type NativeFunction = unsafe extern "C" fn() -> u32;
+
+static NATIVE_FUNCTIONS: Mutex<BTreeMap<&'static str, NativeFunction>> =
+    Mutex::new(BTreeMap::new());
+
+/// Registers a native function under the given name, replacing any function
+/// that was previously registered under that name.
+pub fn register_native_fn(name: &'static str, function: NativeFunction) {
+    debug!("Registering native function: {}", name);
+    NATIVE_FUNCTIONS.lock().insert(name, function);
+}
+
+pub fn get_native_fn(name: &str) -> Option<NativeFunction> {
+    NATIVE_FUNCTIONS.lock().get(name).copied()
+}
+
+extern "C" {
+    fn fletcher_16_80B() -> u32;
+    fn fletcher_16_160B() -> u32;
+    fn fletcher_16_320B() -> u32;
+    fn fletcher_16_640B() -> u32;
+    fn fletcher_16_1280B() -> u32;
+    fn fletcher_16_2560B() -> u32;
+}
+
+/// Registers the benchmark kernels implemented in `ffi/fletcher16_benchmarks.c`.
+pub fn register_benchmark_fns() {
+    register_native_fn("fletcher_16_80B", fletcher_16_80B);
+    register_native_fn("fletcher_16_160B", fletcher_16_160B);
+    register_native_fn("fletcher_16_320B", fletcher_16_320B);
+    register_native_fn("fletcher_16_640B", fletcher_16_640B);
+    register_native_fn("fletcher_16_1280B", fletcher_16_1280B);
+    register_native_fn("fletcher_16_2560B", fletcher_16_2560B);
+}
@@ -0,0 +1,341 @@
+//! Static analysis of the programs loaded into the SUIT storage. It reports
+//! the size and complexity of a program, which helps with optimising it and
+//! deciding whether it is worth JIT-compiling it.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::convert::TryInto;
+use goblin::elf::Elf;
+use micro_bpf_common::BinaryFileLayout;
+
+use crate::infra::relocation_check;
+
+const INSTRUCTION_SIZE: usize = 8;
+/// Size of the Femto-Containers header: magic, version, flags, lengths of the
+/// data, rodata and text sections and the number of functions (4B each).
+const FC_HEADER_SIZE: usize = 28;
+const FRAME_POINTER: u8 = 10;
+
+/// Optional section of raw object files in which the program can declare the
+/// size of the stack it needs, as a little-endian `u32`.
+const STACK_SIZE_SECTION: &str = ".stack_size";
+
+const OPCODE_LDDW: u8 = 0x18;
+const OPCODE_CALL: u8 = 0x85;
+/// Value of the source register of calls to functions defined in the program
+/// (as opposed to helper calls, where it is 0).
+const PSEUDO_CALL: u8 = 1;
+
+const CLASS_MASK: u8 = 0x07;
+const CLASS_LDX: u8 = 0x01;
+const CLASS_ST: u8 = 0x02;
+const CLASS_STX: u8 = 0x03;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProgramReport {
+    /// Number of instructions, `lddw` is counted as one instruction even
+    /// though it takes up two instruction slots.
+    pub instructions: usize,
+    pub helper_calls: usize,
+    pub function_calls: usize,
+    /// The largest offset below the frame pointer accessed by the program.
+    /// It is an approximation, accesses through copies of r10 aren't tracked.
+    pub max_stack_depth: usize,
+    /// Relocations of the text section, only raw object files contain them.
+    pub relocations: usize,
+}
+
+impl ProgramReport {
+    /// Formats the report as JSON object fields, without the enclosing braces.
+    pub fn json_fields(&self) -> String {
+        format!(
+            concat!(
+                "\"instructions\": {}, \"helper_calls\": {}, \"function_calls\": {}, ",
+                "\"max_stack_depth\": {}, \"relocations\": {}"
+            ),
+            self.instructions,
+            self.helper_calls,
+            self.function_calls,
+            self.max_stack_depth,
+            self.relocations
+        )
+    }
+}
+
+/// Locates the text section of the program according to its layout and
+/// scans its instructions. Layouts without relocation tables report zero
+/// relocations.
+pub fn analyze(program: &[u8], layout: BinaryFileLayout) -> Result<ProgramReport, String> {
+    let (text, relocations) = text_section(program, layout)?;
+
+    let mut report = ProgramReport {
+        relocations,
+        ..Default::default()
+    };
+    let mut i = 0;
+    while i + INSTRUCTION_SIZE <= text.len() {
+        let insn = &text[i..i + INSTRUCTION_SIZE];
+        let opcode = insn[0];
+        let dst = insn[1] & 0x0f;
+        let src = insn[1] >> 4;
+        let offset = i16::from_le_bytes([insn[2], insn[3]]);
+
+        report.instructions += 1;
+        i += INSTRUCTION_SIZE;
+        match opcode {
+            OPCODE_LDDW => i += INSTRUCTION_SIZE,
+            OPCODE_CALL if src == PSEUDO_CALL => report.function_calls += 1,
+            OPCODE_CALL => report.helper_calls += 1,
+            _ => {}
+        }
+
+        let stack_access = match opcode & CLASS_MASK {
+            CLASS_LDX => src == FRAME_POINTER,
+            CLASS_ST | CLASS_STX => dst == FRAME_POINTER,
+            _ => false,
+        };
+        if stack_access && offset < 0 {
+            report.max_stack_depth = report.max_stack_depth.max(offset.unsigned_abs() as usize);
+        }
+    }
+    Ok(report)
+}
+
+/// Returns the IDs of the helpers called by the program (each one only once),
+/// in the order of their first call.
+pub fn called_helpers(program: &[u8], layout: BinaryFileLayout) -> Result<Vec<u32>, String> {
+    let (text, _) = text_section(program, layout)?;
+    let mut helpers = Vec::new();
+    let mut i = 0;
+    while i + INSTRUCTION_SIZE <= text.len() {
+        let insn = &text[i..i + INSTRUCTION_SIZE];
+        i += if insn[0] == OPCODE_LDDW { 2 * INSTRUCTION_SIZE } else { INSTRUCTION_SIZE };
+        if insn[0] != OPCODE_CALL || insn[1] >> 4 == PSEUDO_CALL {
+            continue;
+        }
+        let id = u32::from_le_bytes(insn[4..8].try_into().unwrap());
+        if !helpers.contains(&id) {
+            helpers.push(id);
+        }
+    }
+    Ok(helpers)
+}
+
+/// Locates the text section of the program according to its layout, together
+/// with the number of its relocations.
+pub fn text_section(program: &[u8], layout: BinaryFileLayout) -> Result<(&[u8], usize), String> {
+    Ok(match layout {
+        BinaryFileLayout::OnlyTextSection => (program, 0),
+        BinaryFileLayout::FemtoContainersHeader => (femtocontainer_text(program)?, 0),
+        BinaryFileLayout::RawObjectFile => object_file_text(program)?,
+        BinaryFileLayout::ExtendedHeader => {
+            Err("Analysis of programs with the extended header is not supported")?
+        }
+    })
+}
+
+fn femtocontainer_text(program: &[u8]) -> Result<&[u8], String> {
+    if program.len() < FC_HEADER_SIZE {
+        Err("Program is too short to contain the header")?;
+    }
+    let field = |i: usize| {
+        u32::from_le_bytes(program[4 * i..4 * i + 4].try_into().unwrap()) as usize
+    };
+    let (data_len, rodata_len, text_len) = (field(3), field(4), field(5));
+    // The lengths come from the program, so their sum can overflow.
+    FC_HEADER_SIZE
+        .checked_add(data_len)
+        .and_then(|start| start.checked_add(rodata_len))
+        .and_then(|start| Some(start..start.checked_add(text_len)?))
+        .and_then(|text| program.get(text))
+        .ok_or_else(|| String::from("Text section outside of the program"))
+}
+
+fn object_file_text(program: &[u8]) -> Result<(&[u8], usize), String> {
+    let elf = relocation_check::parse_elf(program)?;
+    let Some((text_idx, text)) = elf
+        .section_headers
+        .iter()
+        .enumerate()
+        .find(|(_, sh)| elf.shdr_strtab.get_at(sh.sh_name) == Some(".text"))
+    else {
+        return Err("Program has no .text section".to_string());
+    };
+
+    let relocations = elf
+        .shdr_relocs
+        .iter()
+        .filter(|(idx, _)| elf.section_headers[*idx].sh_info as usize == text_idx)
+        .map(|(_, relocs)| relocs.len())
+        .sum();
+
+    let start = text.sh_offset as usize;
+    let text = program
+        .get(start..start + text.sh_size as usize)
+        .ok_or_else(|| String::from("Text section outside of the program"))?;
+    Ok((text, relocations))
+}
+
+/// Returns the stack size required by the program. Raw object files can
+/// declare it in the [`STACK_SIZE_SECTION`], otherwise it is estimated as the
+/// maximum stack depth found by [`analyze`]. Returns `None` if neither is
+/// available (e.g. for the extended header layout).
+pub fn required_stack_size(program: &[u8], layout: BinaryFileLayout) -> Option<usize> {
+    if layout == BinaryFileLayout::RawObjectFile {
+        if let Some(size) = declared_stack_size(program) {
+            return Some(size);
+        }
+    }
+    analyze(program, layout).ok().map(|r| r.max_stack_depth)
+}
+
+/// Checks that the stack required by the program fits into the one provided
+/// by the VM, running a program with a larger stack corrupts memory.
+pub fn check_stack_size(required: usize, available: usize) -> Result<(), String> {
+    if required > available {
+        Err(format!(
+            "Stack too large: program requires {} [B], the VM provides {} [B]",
+            required, available
+        ))?;
+    }
+    Ok(())
+}
+
+/// Checks that the program doesn't call any helpers, which is required in the
+/// strict sandbox (see [`crate::vm::middleware::helpers::STRICT_SANDBOX`]).
+/// Calls to the functions defined in the program are allowed.
+pub fn check_no_helper_calls(program: &[u8], layout: BinaryFileLayout) -> Result<(), String> {
+    let report = analyze(program, layout)?;
+    if report.helper_calls > 0 {
+        Err(format!(
+            "Helper calls are forbidden in the strict sandbox, the program contains {}",
+            report.helper_calls
+        ))?;
+    }
+    Ok(())
+}
+
+fn declared_stack_size(program: &[u8]) -> Option<usize> {
+    let elf = Elf::parse(program).ok()?;
+    let section = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(STACK_SIZE_SECTION))?;
+    let start = section.sh_offset as usize;
+    let bytes = program.get(start..start + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOV_R0_0: [u8; 8] = [0xb7, 0x00, 0, 0, 0, 0, 0, 0];
+    const EXIT: [u8; 8] = [0x95, 0, 0, 0, 0, 0, 0, 0];
+
+    fn text(instructions: &[[u8; 8]]) -> Vec<u8> {
+        instructions.concat()
+    }
+
+    fn call(src: u8, imm: u32) -> [u8; 8] {
+        let imm = imm.to_le_bytes();
+        [OPCODE_CALL, src << 4, 0, 0, imm[0], imm[1], imm[2], imm[3]]
+    }
+
+    /// Prepends the Femto-Containers header to the sections.
+    fn femtocontainer(data: &[u8], rodata: &[u8], text: &[u8]) -> Vec<u8> {
+        let mut program = Vec::new();
+        for field in [0x4250_4546, 0, 0, data.len(), rodata.len(), text.len(), 0] {
+            program.extend_from_slice(&(field as u32).to_le_bytes());
+        }
+        program.extend_from_slice(data);
+        program.extend_from_slice(rodata);
+        program.extend_from_slice(text);
+        program
+    }
+
+    #[test]
+    fn instructions_are_counted() {
+        // lddw r1, 0x1 takes up two slots.
+        let lddw = [[OPCODE_LDDW, 0x01, 0, 0, 1, 0, 0, 0], [0; 8]];
+        // *(u64 *)(r10 - 16) = r1 and r2 = *(u64 *)(r10 - 8)
+        let store = [0x7b, 0x1a, 0xf0, 0xff, 0, 0, 0, 0];
+        let load = [0x79, 0xa2, 0xf8, 0xff, 0, 0, 0, 0];
+        let program = text(&[
+            lddw[0],
+            lddw[1],
+            store,
+            load,
+            call(0, 2),
+            call(PSEUDO_CALL, 1),
+            MOV_R0_0,
+            EXIT,
+        ]);
+
+        let report = analyze(&program, BinaryFileLayout::OnlyTextSection).unwrap();
+        assert_eq!(report.instructions, 7);
+        assert_eq!(report.helper_calls, 1);
+        assert_eq!(report.function_calls, 1);
+        assert_eq!(report.max_stack_depth, 16);
+        assert_eq!(report.relocations, 0);
+    }
+
+    #[test]
+    fn called_helpers_are_listed_once() {
+        // The second slot of lddw looks like a call, but it isn't one.
+        let lddw = [[OPCODE_LDDW, 0x01, 0, 0, 0, 0, 0, 0], call(0, 9)];
+        let program = text(&[
+            call(0, 3),
+            lddw[0],
+            lddw[1],
+            call(0, 1),
+            call(PSEUDO_CALL, 5),
+            call(0, 3),
+            EXIT,
+        ]);
+        let helpers = called_helpers(&program, BinaryFileLayout::OnlyTextSection).unwrap();
+        assert_eq!(helpers, [3, 1]);
+    }
+
+    #[test]
+    fn femtocontainer_text_follows_the_data() {
+        let text_bytes = text(&[call(0, 4), EXIT]);
+        let program = femtocontainer(&[0xaa; 8], &[0xbb; 4], &text_bytes);
+        let (text, relocations) =
+            text_section(&program, BinaryFileLayout::FemtoContainersHeader).unwrap();
+        assert_eq!(text, &text_bytes[..]);
+        assert_eq!(relocations, 0);
+    }
+
+    #[test]
+    fn malformed_femtocontainer_headers_are_rejected() {
+        let layout = BinaryFileLayout::FemtoContainersHeader;
+        assert!(text_section(&[0; FC_HEADER_SIZE - 1], layout).is_err());
+
+        let mut program = femtocontainer(&[], &[], &text(&[EXIT]));
+        program.truncate(program.len() - 1);
+        assert!(text_section(&program, layout).is_err());
+
+        // Lengths whose sum overflows.
+        let mut program = femtocontainer(&[], &[], &text(&[EXIT]));
+        program[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        program[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        program[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(text_section(&program, layout).is_err());
+    }
+
+    #[test]
+    fn extended_header_is_not_analyzed() {
+        let program = text(&[MOV_R0_0, EXIT]);
+        assert!(analyze(&program, BinaryFileLayout::ExtendedHeader).is_err());
+    }
+
+    #[test]
+    fn stack_size_needs_to_fit() {
+        assert!(check_stack_size(512, 512).is_ok());
+        assert!(check_stack_size(513, 512).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Metadata describing the programs loaded into the SUIT storage slots.
+//!
+//! Slots are identified only by their index, so the deploy tool can prepend
+//! the program with a short name and version using the following header:
+//!
+//! ```text
+//! +------------+--------------+-----------------+------+---------+---------+
+//! | magic (4B) | name len (1B)| version len (1B)| name | version | program |
+//! +------------+--------------+-----------------+------+---------+---------+
+//! ```
+//!
+//! The header is independent of the binary layout of the program and is
+//! stripped after the SUIT fetch completes (before decompression and
+//! relocation resolution). Programs deployed without it (e.g. by older
+//! versions of the tool) simply have no metadata.
+
+use alloc::{format, string::String};
+use riot_wrappers::mutex::Mutex;
+
+use super::suit_storage::SUIT_STORAGE_SLOTS;
+
+/// Marks a program prefixed with the metadata header.
+pub const METADATA_MAGIC: [u8; 4] = *b"mbmd";
+const FIXED_HEADER_SIZE: usize = 6;
+pub const MAX_NAME_LEN: usize = 24;
+pub const MAX_VERSION_LEN: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramMetadata {
+    pub name: String,
+    pub version: String,
+}
+
+const NO_METADATA: Option<ProgramMetadata> = None;
+static SLOT_METADATA: Mutex<[Option<ProgramMetadata>; SUIT_STORAGE_SLOTS]> =
+    Mutex::new([NO_METADATA; SUIT_STORAGE_SLOTS]);
+
+pub fn has_metadata(program: &[u8]) -> bool {
+    program.len() >= FIXED_HEADER_SIZE && program[..4] == METADATA_MAGIC
+}
+
+/// Parses the metadata header, returning the metadata and the length of
+/// the header (i.e. the offset at which the program starts).
+pub fn parse_metadata(program: &[u8]) -> Result<(ProgramMetadata, usize), String> {
+    if !has_metadata(program) {
+        Err("Program has no metadata header")?;
+    }
+    let name_len = program[4] as usize;
+    let version_len = program[5] as usize;
+    if name_len > MAX_NAME_LEN || version_len > MAX_VERSION_LEN {
+        Err(format!(
+            "Metadata too long (name: {} [B], version: {} [B])",
+            name_len, version_len
+        ))?;
+    }
+    let header_len = FIXED_HEADER_SIZE + name_len + version_len;
+    if program.len() < header_len {
+        Err("Truncated metadata header")?;
+    }
+
+    let name = &program[FIXED_HEADER_SIZE..FIXED_HEADER_SIZE + name_len];
+    let version = &program[FIXED_HEADER_SIZE + name_len..header_len];
+    let to_string = |bytes| {
+        core::str::from_utf8(bytes)
+            .map(String::from)
+            .map_err(|_| String::from("Metadata is not valid UTF-8"))
+    };
+    let metadata = ProgramMetadata {
+        name: to_string(name)?,
+        version: to_string(version)?,
+    };
+    Ok((metadata, header_len))
+}
+
+pub fn set_slot_metadata(slot: usize, metadata: Option<ProgramMetadata>) {
+    SLOT_METADATA.lock()[slot] = metadata;
+}
+
+pub fn get_slot_metadata(slot: usize) -> Option<ProgramMetadata> {
+    SLOT_METADATA.lock().get(slot).cloned().flatten()
+}
@@ -0,0 +1,391 @@
+//! Detection of relocations which can't be applied to a program.
+//!
+//! When resolving the relocations of raw object files, the ones that can't be
+//! applied (e.g. their offset lies outside of the `.text` section or they refer
+//! to an undefined symbol) are skipped and only logged. Such programs fail at
+//! runtime once they reach the unpatched instruction. In strict mode, those
+//! relocations are detected when the program is loaded and the program is
+//! rejected instead.
+//!
+//! The relocations should always be resolved using [`resolve_relocations`]
+//! from this module, which checks the layout of the sections first. Programs
+//! are prepared for execution using [`prepare_program`], which decides based
+//! on the binary layout whether the relocations need to be resolved at all.
+
+use alloc::{format, string::String, vec::Vec};
+use goblin::elf::{
+    section_header::{SectionHeader, SHN_UNDEF, SHT_REL, SHT_RELA, SHT_STRTAB, SHT_SYMTAB},
+    Elf,
+};
+use log::debug;
+use macros::set_env_or_default;
+use micro_bpf_common::BinaryFileLayout;
+
+use crate::util::logger::targets;
+
+/// Enables rejecting programs with relocations that can't be applied. It is
+/// off by default for compatibility with the existing programs, however it is
+/// recommended to turn it on for production deployments by setting
+/// `STRICT_RELOCATIONS=1` at compile time.
+pub const STRICT_RELOCATIONS: bool = set_env_or_default!("STRICT_RELOCATIONS", 0) != 0;
+
+/// Size of a single eBPF instruction, the relocated instruction needs to fit
+/// into the text section.
+const INSTRUCTION_SIZE: u64 = 8;
+
+/// Number of bytes from the start of the program included in the error
+/// message when it can't be parsed as an ELF file.
+const ERROR_PREFIX_BYTES: usize = 16;
+
+/// Index of the section which `micro_bpf_elf_utils::resolve_relocations`
+/// treats as the `.text` section.
+const EXPECTED_TEXT_SECTION_INDEX: usize = 1;
+
+/// First bytes of an ELF file.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Returns true if programs in the given layout can contain relocations. The
+/// relocations of raw object files are resolved by [`prepare_program`] when
+/// the program is loaded, the ones of programs with the extended header are
+/// resolved by the interpreter using the metadata in the header. The other
+/// layouts only support self-contained programs.
+///
+/// Both the interpreter and the JIT execute the programs prepared by
+/// [`prepare_program`], so this is the only place where the support for
+/// relocations is decided. The match needs to stay exhaustive so that
+/// adding a layout forces the decision to be made for it.
+#[deny(
+    clippy::wildcard_enum_match_arm,
+    clippy::match_wildcard_for_single_variants
+)]
+pub fn layout_supports_relocations(layout: BinaryFileLayout) -> bool {
+    match layout {
+        BinaryFileLayout::RawObjectFile | BinaryFileLayout::ExtendedHeader => true,
+        BinaryFileLayout::OnlyTextSection | BinaryFileLayout::FemtoContainersHeader => false,
+    }
+}
+
+/// Prepares the program for execution according to its layout: the
+/// relocations of raw object files are resolved in place (after checking
+/// them in strict mode, see [`STRICT_RELOCATIONS`]). Programs in layouts
+/// without relocation support are rejected if they are ELF files, as their
+/// relocations would never be resolved and the program would fault at
+/// runtime.
+pub fn prepare_program(program: &mut [u8], layout: BinaryFileLayout) -> Result<(), String> {
+    if !layout_supports_relocations(layout) {
+        if is_elf(program) {
+            Err(format!(
+                "ELF files can't be deployed as {:?}, their relocations wouldn't be resolved",
+                layout
+            ))?;
+        }
+        return Ok(());
+    }
+    if layout == BinaryFileLayout::RawObjectFile {
+        debug!(target: targets::RELOC, "Resolving relocations");
+        if STRICT_RELOCATIONS {
+            check_relocations(program)?;
+        }
+        resolve_relocations(program)?;
+    }
+    Ok(())
+}
+
+/// Parses the program as an ELF file. On failure, the error reported by goblin
+/// is returned together with the first bytes of the program, so that it is
+/// clear if the program isn't an object file at all (e.g. it was deployed
+/// with the wrong binary layout).
+pub fn parse_elf(program: &[u8]) -> Result<Elf, String> {
+    Elf::parse(program).map_err(|e| {
+        let prefix = program
+            .iter()
+            .take(ERROR_PREFIX_BYTES)
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "Failed to parse the ELF file ({} [B]): {}, first bytes: [{}]",
+            program.len(),
+            e,
+            prefix
+        )
+    })
+}
+
+/// Returns true if the program is an ELF file, i.e. it was deployed using the
+/// `RawObjectFile` layout (the relocations are resolved in place, so the
+/// program stays an ELF file after it is prepared for execution).
+pub fn is_elf(program: &[u8]) -> bool {
+    program.starts_with(ELF_MAGIC)
+}
+
+/// Returns the size of the `.text` section of the ELF file in bytes.
+pub fn text_section_size(program: &[u8]) -> Result<usize, String> {
+    let elf = parse_elf(program)?;
+    let (_, text) = find_text_section(&elf)?;
+    Ok(text.sh_size as usize)
+}
+
+/// Finds the `.text` section by its name in the section header string table,
+/// the section ordering depends on the toolchain.
+fn find_text_section<'a>(elf: &'a Elf) -> Result<(usize, &'a SectionHeader), String> {
+    elf.section_headers
+        .iter()
+        .enumerate()
+        .find(|(_, sh)| elf.shdr_strtab.get_at(sh.sh_name) == Some(".text"))
+        .ok_or_else(|| String::from("Program has no .text section"))
+}
+
+/// Resolves the relocations of a raw object file in place. The resolution in
+/// `micro_bpf_elf_utils` assumes that `.text` is the section at index 1, so
+/// programs where the linker emitted the sections in a different order are
+/// rejected with an error instead of having the wrong section patched.
+pub fn resolve_relocations(program: &mut [u8]) -> Result<(), String> {
+    check_text_section(program)?;
+    micro_bpf_elf_utils::resolve_relocations(program)?;
+    Ok(())
+}
+
+/// Checks that the `.text` section is where the relocation resolution
+/// expects it, see [`resolve_relocations`].
+pub fn check_text_section(program: &[u8]) -> Result<(), String> {
+    let elf = parse_elf(program)?;
+    let (text_idx, _) = find_text_section(&elf)?;
+    if text_idx != EXPECTED_TEXT_SECTION_INDEX {
+        Err(format!(
+            "Unsupported section layout: .text is at index {}, expected {}",
+            text_idx, EXPECTED_TEXT_SECTION_INDEX
+        ))?;
+    }
+    Ok(())
+}
+
+/// Returns an error listing all relocations of the `.text` section which
+/// can't be applied, together with their offsets and symbols.
+pub fn check_relocations(program: &[u8]) -> Result<(), String> {
+    let elf = parse_elf(program)?;
+
+    let (text_idx, text) = find_text_section(&elf)?;
+
+    let mut skipped: Vec<String> = Vec::new();
+    for (reloc_section_idx, relocs) in elf.shdr_relocs.iter() {
+        // Only the relocations applied to the text section are resolved.
+        if elf.section_headers[*reloc_section_idx].sh_info as usize != text_idx {
+            continue;
+        }
+        for reloc in relocs.iter() {
+            let symbol = elf.syms.get(reloc.r_sym);
+            let name = symbol
+                .and_then(|s| elf.strtab.get_at(s.st_name))
+                .unwrap_or("<unknown>");
+
+            if reloc.r_offset + INSTRUCTION_SIZE > text.sh_size {
+                skipped.push(format!(
+                    "{:#x} ({}): outside of .text",
+                    reloc.r_offset, name
+                ));
+            } else if symbol.map_or(true, |s| s.st_shndx == SHN_UNDEF as usize) {
+                skipped.push(format!(
+                    "{:#x} ({}): undefined symbol",
+                    reloc.r_offset, name
+                ));
+            }
+        }
+    }
+
+    if !skipped.is_empty() {
+        Err(format!(
+            "{} relocation(s) can't be applied: {}",
+            skipped.len(),
+            skipped.join(", ")
+        ))?;
+    }
+    Ok(())
+}
+
+/// Checks that writing `len` bytes at `offset` into a prepared program (see
+/// [`prepare_program`]) leaves its relocations valid. The relocations of raw
+/// object files are resolved in place only once, when the program is loaded,
+/// so patches can't modify the relocated instructions (those would lose the
+/// resolved addresses) or the ELF structures that the resolution and the
+/// interpreter read: the headers, the relocation entries and the symbols.
+/// Everything else, e.g. the constants in `.rodata`, can be patched. Programs
+/// which aren't ELF files aren't checked.
+pub fn check_patch(program: &[u8], offset: usize, len: usize) -> Result<(), String> {
+    if !is_elf(program) {
+        return Ok(());
+    }
+    let elf = parse_elf(program)?;
+    let end = offset + len;
+    let overlaps = |start: u64, size: u64| (offset as u64) < start + size && start < end as u64;
+
+    let header = &elf.header;
+    let tables = [
+        ("the ELF header", 0, header.e_ehsize as u64),
+        (
+            "the section headers",
+            header.e_shoff,
+            header.e_shnum as u64 * header.e_shentsize as u64,
+        ),
+        (
+            "the program headers",
+            header.e_phoff,
+            header.e_phnum as u64 * header.e_phentsize as u64,
+        ),
+    ];
+    if let Some((name, _, _)) = tables
+        .iter()
+        .find(|(_, start, size)| overlaps(*start, *size))
+    {
+        Err(format!("The patch overlaps {}", name))?;
+    }
+
+    for sh in elf.section_headers.iter() {
+        let structural = matches!(sh.sh_type, SHT_REL | SHT_RELA | SHT_SYMTAB | SHT_STRTAB);
+        if structural && overlaps(sh.sh_offset, sh.sh_size) {
+            Err(format!(
+                "The patch overlaps the {} section",
+                elf.shdr_strtab.get_at(sh.sh_name).unwrap_or("<unknown>")
+            ))?;
+        }
+    }
+
+    let (text_idx, text) = find_text_section(&elf)?;
+    for (reloc_section_idx, relocs) in elf.shdr_relocs.iter() {
+        if elf.section_headers[*reloc_section_idx].sh_info as usize != text_idx {
+            continue;
+        }
+        // 64-bit loads (lddw) are relocated across both of their instruction slots.
+        if let Some(reloc) = relocs
+            .iter()
+            .find(|r| overlaps(text.sh_offset + r.r_offset, 2 * INSTRUCTION_SIZE))
+        {
+            Err(format!(
+                "The patch overlaps the relocated instruction at .text offset {:#x}",
+                reloc.r_offset
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a relocatable 64-bit ELF file with the given sections, which are
+    /// preceded by the null section and followed by `.shstrtab`.
+    fn object_file(sections: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut shstrtab = alloc::vec![0u8];
+        let mut headers = alloc::vec![(0u32, 0u32, 0u64, 0usize, 0usize)];
+        let mut elf = Vec::new();
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01");
+        elf.resize(64, 0);
+        for (name, data) in sections {
+            headers.push((shstrtab.len() as u32, 1, 6, elf.len(), data.len()));
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+            elf.extend_from_slice(data);
+        }
+        headers.push((shstrtab.len() as u32, 3, 0, elf.len(), 0));
+        shstrtab.extend_from_slice(b".shstrtab\0");
+        headers.last_mut().unwrap().4 = shstrtab.len();
+        elf.extend_from_slice(&shstrtab);
+        let shoff = (elf.len() + 7) & !7;
+        elf.resize(shoff, 0);
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&1u16.to_le_bytes()); // e_type: ET_REL
+        header.extend_from_slice(&247u16.to_le_bytes()); // e_machine: EM_BPF
+        header.extend_from_slice(&1u32.to_le_bytes()); // e_version
+        header.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+        header.extend_from_slice(&0u64.to_le_bytes()); // e_phoff
+        header.extend_from_slice(&(shoff as u64).to_le_bytes()); // e_shoff
+        header.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+        let shnum = headers.len() as u16;
+        for half in [64u16, 56, 0, 64, shnum, shnum - 1] {
+            // e_ehsize, e_phentsize, e_phnum, e_shentsize, e_shnum, e_shstrndx
+            header.extend_from_slice(&half.to_le_bytes());
+        }
+        elf[16..64].copy_from_slice(&header);
+
+        for (name, kind, flags, offset, size) in headers {
+            elf.extend_from_slice(&name.to_le_bytes());
+            elf.extend_from_slice(&kind.to_le_bytes());
+            elf.extend_from_slice(&flags.to_le_bytes());
+            elf.extend_from_slice(&0u64.to_le_bytes()); // sh_addr
+            elf.extend_from_slice(&(offset as u64).to_le_bytes());
+            elf.extend_from_slice(&(size as u64).to_le_bytes());
+            elf.extend_from_slice(&[0; 8]); // sh_link, sh_info
+            elf.extend_from_slice(&1u64.to_le_bytes()); // sh_addralign
+            elf.extend_from_slice(&0u64.to_le_bytes()); // sh_entsize
+        }
+        elf
+    }
+
+    const TEXT: &[u8] = &[0x95, 0, 0, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn text_section_is_found_by_name() {
+        let program = object_file(&[(".data", &[0; 4]), (".text", TEXT)]);
+        assert_eq!(text_section_size(&program), Ok(TEXT.len()));
+
+        let program = object_file(&[(".text", TEXT), (".data", &[0; 4])]);
+        assert_eq!(text_section_size(&program), Ok(TEXT.len()));
+        assert_eq!(check_text_section(&program), Ok(()));
+    }
+
+    #[test]
+    fn text_section_at_unexpected_index_is_rejected() {
+        let program = object_file(&[(".data", &[0; 4]), (".text", TEXT)]);
+        let error = check_text_section(&program).unwrap_err();
+        assert_eq!(
+            error,
+            "Unsupported section layout: .text is at index 2, expected 1"
+        );
+        // The program must not reach the resolution which would patch .data.
+        let mut program = program;
+        assert_eq!(resolve_relocations(&mut program), Err(error));
+    }
+
+    #[test]
+    fn missing_text_section_is_reported() {
+        let program = object_file(&[(".data", &[0; 4])]);
+        let error = String::from("Program has no .text section");
+        assert_eq!(check_text_section(&program), Err(error.clone()));
+        assert_eq!(text_section_size(&program), Err(error));
+    }
+
+    #[test]
+    fn relocation_support_of_each_layout() {
+        let layouts = [
+            (BinaryFileLayout::RawObjectFile, true),
+            (BinaryFileLayout::ExtendedHeader, true),
+            (BinaryFileLayout::OnlyTextSection, false),
+            (BinaryFileLayout::FemtoContainersHeader, false),
+        ];
+        for (layout, supports_relocations) in layouts {
+            assert_eq!(layout_supports_relocations(layout), supports_relocations);
+        }
+    }
+
+    #[test]
+    fn elf_files_are_rejected_in_layouts_without_relocations() {
+        for layout in [
+            BinaryFileLayout::OnlyTextSection,
+            BinaryFileLayout::FemtoContainersHeader,
+        ] {
+            let mut program = object_file(&[(".text", TEXT)]);
+            let error = prepare_program(&mut program, layout).unwrap_err();
+            assert!(error.starts_with("ELF files can't be deployed"));
+            assert_eq!(prepare_program(&mut TEXT.to_vec(), layout), Ok(()));
+        }
+        // The relocations of programs with the extended header are resolved
+        // by the interpreter, the program is left untouched.
+        let mut program = object_file(&[(".text", TEXT)]);
+        let original = program.clone();
+        let layout = BinaryFileLayout::ExtendedHeader;
+        assert_eq!(prepare_program(&mut program, layout), Ok(()));
+        assert_eq!(program, original);
+    }
+}
@@ -0,0 +1,159 @@
+//! Cache of the results of short-lived programs, keyed by a hash of the
+//! program bytes. Re-running a deterministic program returns the same value,
+//! so the cached result is returned instead of executing the program again.
+//!
+//! The cache is opt-in (it is enabled by setting `RESULT_CACHE=1` at compile
+//! time) and it is only used for pure programs, i.e. the ones which are only
+//! allowed to call the read-only, deterministic helpers (see [`is_pure`]).
+//! Programs allowed to read the clocks, sensors or peripherals are executed
+//! every time.
+//!
+//! Only successful executions are cached, failures can be caused by the
+//! environment and so they are re-executed.
+
+use macros::set_env_or_default;
+use micro_bpf_common::HelperFunctionID;
+use riot_wrappers::mutex::Mutex;
+
+use crate::{model::results::ExecutionResult, vm::middleware::helpers};
+
+/// Enables caching the results of pure programs.
+pub const RESULT_CACHE: bool = set_env_or_default!("RESULT_CACHE", 0) != 0;
+
+/// Number of cached results, once the cache is full the oldest one is evicted.
+pub const RESULT_CACHE_SIZE: usize = set_env_or_default!("RESULT_CACHE_SIZE", 8);
+const _: () = assert!(RESULT_CACHE_SIZE > 0, "RESULT_CACHE_SIZE must be at least 1");
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Identifies the executed program. The hash isn't checked against the whole
+/// program, so the length and the slot are compared as well to make a
+/// collision less likely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramKey {
+    hash: u64,
+    len: usize,
+    slot: usize,
+}
+
+#[derive(Clone, Copy)]
+struct CacheEntry {
+    program: ProgramKey,
+    result: ExecutionResult,
+}
+
+struct ResultCache {
+    entries: [Option<CacheEntry>; RESULT_CACHE_SIZE],
+    /// Index of the entry which is replaced next.
+    next: usize,
+    hits: u32,
+    misses: u32,
+}
+
+const EMPTY_ENTRY: Option<CacheEntry> = None;
+
+static RESULT_CACHE_STATE: Mutex<ResultCache> = Mutex::new(ResultCache {
+    entries: [EMPTY_ENTRY; RESULT_CACHE_SIZE],
+    next: 0,
+    hits: 0,
+    misses: 0,
+});
+
+/// Number of lookups which returned a cached result and the ones that didn't
+/// (each miss is followed by an execution of the program).
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u32,
+    pub misses: u32,
+}
+
+/// Hashes the bytes of the program loaded in the slot using the 64-bit FNV-1a,
+/// it is fast enough to be computed on every execution request.
+pub fn program_key(program: &[u8], slot: usize) -> ProgramKey {
+    let hash = program.iter().fold(FNV_OFFSET_BASIS, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(FNV_PRIME)
+    });
+    ProgramKey {
+        hash,
+        len: program.len(),
+        slot,
+    }
+}
+
+/// A program is pure if all helpers it is allowed to call are read-only and
+/// deterministic, so executing it has no side effects and its result only
+/// depends on the program.
+pub fn is_pure(allowed_helpers: &[HelperFunctionID]) -> bool {
+    allowed_helpers
+        .iter()
+        .all(|id| helpers::is_deterministic_read_only(*id))
+}
+
+/// Returns the cached result of executing the program, counting the lookup as
+/// a hit or a miss.
+pub fn lookup(program: ProgramKey) -> Option<ExecutionResult> {
+    let mut cache = RESULT_CACHE_STATE.lock();
+    let result = cache
+        .entries
+        .iter()
+        .flatten()
+        .find(|e| e.program == program)
+        .map(|e| e.result);
+    if result.is_some() {
+        cache.hits = cache.hits.wrapping_add(1);
+    } else {
+        cache.misses = cache.misses.wrapping_add(1);
+    }
+    result
+}
+
+/// Caches the result of a successful execution, evicting the oldest entry if
+/// the cache is full.
+pub fn insert(program: ProgramKey, result: ExecutionResult) {
+    if !result.is_ok() {
+        return;
+    }
+    let mut cache = RESULT_CACHE_STATE.lock();
+    let next = cache.next;
+    cache.entries[next] = Some(CacheEntry { program, result });
+    cache.next = (next + 1) % RESULT_CACHE_SIZE;
+}
+
+/// Removes all cached results, e.g. because the helpers available to the
+/// programs have changed and so re-executing them could fail.
+pub fn clear() {
+    let mut cache = RESULT_CACHE_STATE.lock();
+    cache.entries = [EMPTY_ENTRY; RESULT_CACHE_SIZE];
+    cache.next = 0;
+}
+
+pub fn stats() -> CacheStats {
+    let cache = RESULT_CACHE_STATE.lock();
+    CacheStats {
+        hits: cache.hits,
+        misses: cache.misses,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_key_uses_fnv1a() {
+        // Test vectors of the 64-bit FNV-1a.
+        assert_eq!(program_key(b"", 0).hash, 0xcbf2_9ce4_8422_2325);
+        assert_eq!(program_key(b"a", 0).hash, 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(program_key(b"foobar", 0).hash, 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn program_key_identifies_the_program() {
+        let key = program_key(&[0x95, 0, 0, 0, 0, 0, 0, 0], 1);
+        assert_eq!(key, program_key(&[0x95, 0, 0, 0, 0, 0, 0, 0], 1));
+        assert_ne!(key, program_key(&[0x95, 0, 0, 0, 0, 0, 0, 1], 1));
+        assert_ne!(key, program_key(&[0x95, 0, 0, 0, 0, 0, 0, 0], 0));
+        assert_ne!(key, program_key(&[0x95, 0, 0, 0, 0, 0, 0, 0, 0], 1));
+    }
+}
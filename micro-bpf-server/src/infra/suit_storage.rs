@@ -1,4 +1,8 @@
-use core::{ffi::c_int, slice::from_raw_parts_mut};
+use core::{
+    ffi::c_int,
+    slice::from_raw_parts_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use alloc::{
     format,
@@ -7,18 +11,20 @@ use alloc::{
 use log::debug;
 use macros::set_env_or_default;
 use micro_bpf_common::BinaryFileLayout;
-use riot_wrappers::{mutex::Mutex, thread};
-
-use crate::infra::local_storage;
-
-/// Size of each slot in the SUIT storage where the programs get loaded.
-/// It is important that this value is consistent with what is specified in
-/// the Makefile for this project using this line:
-/// CFLAGS += -DCONFIG_SUIT_STORAGE_RAM_REGIONS=2 -DCONFIG_SUIT_STORAGE_RAM_SIZE=2048
-/// You can override those by setting the following environment variables at compile
-/// time:
-/// - SUIT_STORAGE_SLOT_SIZE
-/// - SUIT_STORAGE_SLOTS
+use riot_wrappers::{
+    mutex::{Mutex, MutexGuard},
+    thread,
+};
+
+use crate::infra::{compression, last_results, local_storage, program_metadata, relocation_check};
+use crate::util::logger::targets;
+
+/// Number and size of the slots in the SUIT storage where the programs get loaded.
+/// They need to be consistent with the SUIT RAM storage configuration of RIOT,
+/// which is why both are set by the SUIT_STORAGE_SLOTS and SUIT_STORAGE_SLOT_SIZE
+/// variables in the Makefile of this project, the defaults below are only used
+/// when building without it. All other slot limits (e.g. the number of JIT
+/// slots) are derived from these.
 pub const SUIT_STORAGE_SLOTS: usize = set_env_or_default!("SUIT_STORAGE_SLOTS", 2);
 pub const SUIT_STORAGE_SLOT_SIZE: usize = set_env_or_default!("SUIT_STORAGE_SLOT_SIZE", 4096);
 
@@ -26,6 +32,24 @@ pub const SUIT_STORAGE_SLOT_SIZE: usize = set_env_or_default!("SUIT_STORAGE_SLOT
 pub static SUIT_STORAGE_STATE: Mutex<[SuitStorageSlotStatus; SUIT_STORAGE_SLOTS]> =
     Mutex::new([SuitStorageSlotStatus::Free; SUIT_STORAGE_SLOTS]);
 
+/// Serializes executions of the program loaded into each slot. Programs using
+/// the local storage helpers aren't safe to execute concurrently, whereas
+/// programs in different slots can still run in parallel. Deployments hold
+/// the lock as well (see [`lock_slot_for_deployment`]), so that a program is
+/// never loaded while it is being written into the slot.
+static SLOT_EXECUTION_LOCKS: [Mutex<()>; SUIT_STORAGE_SLOTS] = {
+    const UNLOCKED: Mutex<()> = Mutex::new(());
+    [UNLOCKED; SUIT_STORAGE_SLOTS]
+};
+
+/// Set while a program is being deployed into the slot, so that the colliding
+/// execution requests are rejected right away instead of waiting for the
+/// whole deployment to finish.
+static SLOT_DEPLOYMENTS: [AtomicBool; SUIT_STORAGE_SLOTS] = {
+    const NOT_DEPLOYING: AtomicBool = AtomicBool::new(false);
+    [NOT_DEPLOYING; SUIT_STORAGE_SLOTS]
+};
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum SuitStorageSlotStatus {
     Free,
@@ -52,6 +76,12 @@ extern "C" {
     /// Responsible for erasing a given SUIT storage slot
     fn handle_suit_storage_erase(location_id: *const u8);
     fn get_storage_ptr(location_id: *const u8, length_ret: *mut u32) -> u32;
+    /// Replaces the contents of the SUIT storage slot with the provided bytes.
+    fn write_bytes_to_suit_storage(
+        location_id: *const u8,
+        buffer: *const u8,
+        length: u32,
+    ) -> c_int;
 }
 
 /// Responsible for fetching data from a remote CoAP fileserver using a SUIT
@@ -89,8 +119,28 @@ pub fn suit_fetch(
     let suit_manifest = format!("{}\0", manifest);
     let netif = network_interface.parse::<c_int>().unwrap();
 
+    if !is_suit_slot(slot) {
+        Err(format!(
+            "Slot {} doesn't exist, the storage has {} slots",
+            slot, SUIT_STORAGE_SLOTS
+        ))?;
+    }
+
+    if suit_slot_status(slot) == SuitStorageSlotStatus::Running {
+        Err("Tried to overwrite a slot that belongs to a currently running program".to_string())?;
+    }
+    // Executions of the slot are excluded until the program is written and
+    // prepared for execution.
+    let _deployment = lock_slot_for_deployment(slot)?;
+
     let mut slots = SUIT_STORAGE_STATE.lock();
     if slots[slot] != SuitStorageSlotStatus::Free && !erase {
+        if !slots.contains(&SuitStorageSlotStatus::Free) {
+            Err(format!(
+                "Storage full: all {} slots are occupied, set the erase flag to overwrite one",
+                SUIT_STORAGE_SLOTS
+            ))?;
+        }
         Err("Tried to load a program into an occupied slot".to_string())?;
     }
 
@@ -99,10 +149,14 @@ pub fn suit_fetch(
     }
 
     let pid = thread::get_pid().into();
-    debug!("Thread {} initiating SUIT fetch...", pid);
+    debug!(target: targets::STORAGE, "Thread {} initiating SUIT fetch...", pid);
 
-    debug!("Deregistering the local storage associated with the exising slot");
+    debug!(
+        target: targets::STORAGE,
+        "Deregistering the local storage associated with the exising slot",
+    );
     local_storage::deregister_suit_slot(slot);
+    last_results::clear(slot);
 
     unsafe {
         initiate_suit_fetch(ip_addr.as_ptr(), netif, suit_manifest.as_ptr(), pid);
@@ -111,20 +165,153 @@ pub fn suit_fetch(
         let _ = riot_sys::msg_receive(&mut msg);
 
         const SUIT_FETCH_SUCCESS: u32 = 0;
-        if msg.content.value == SUIT_FETCH_SUCCESS {
-            slots[slot] = SuitStorageSlotStatus::Occupied;
-            debug!("SUIT fetch successful, marked slot {} as occupied.", slot);
-
-            if binary_layout == BinaryFileLayout::RawObjectFile {
-                let program = load_program_static(slot);
-                micro_bpf_elf_utils::resolve_relocations(program)?;
-            };
-            Ok(())
-        } else {
+        if msg.content.value != SUIT_FETCH_SUCCESS {
+            // The fetch could have failed after a part of the image was
+            // written into the slot.
+            clear_slot(slot);
             slots[slot] = SuitStorageSlotStatus::Free;
-            Err("SUIT fetch failed".to_string())
+            Err("SUIT fetch failed".to_string())?;
         }
     }
+
+    // The slot is only marked as occupied once the program can be executed,
+    // programs which can't be prepared are erased.
+    if let Err(e) = prepare_fetched_program(slot, binary_layout) {
+        clear_slot(slot);
+        slots[slot] = SuitStorageSlotStatus::Free;
+        Err(e)?;
+    }
+    slots[slot] = SuitStorageSlotStatus::Occupied;
+    debug!(
+        target: targets::STORAGE,
+        "SUIT fetch successful, marked slot {} as occupied.",
+        slot,
+    );
+    Ok(())
+}
+
+/// Prepares the program fetched into the slot for execution.
+fn prepare_fetched_program(slot: usize, binary_layout: BinaryFileLayout) -> Result<(), String> {
+    // The metadata header wraps the (possibly compressed) program,
+    // so it needs to be stripped first.
+    strip_metadata_in_slot(slot)?;
+
+    // Compressed programs need to be decompressed before relocations
+    // can be resolved.
+    decompress_program_in_slot(slot)?;
+
+    debug!(target: targets::STORAGE, "Preparing the program in slot {}", slot);
+    relocation_check::prepare_program(load_program_static(slot), binary_layout)
+}
+
+/// If the program loaded into the slot is compressed (see [`compression`]), it
+/// is replaced with its decompressed version. Otherwise the slot is left as is.
+fn decompress_program_in_slot(slot: usize) -> Result<(), String> {
+    let program = load_program_static(slot);
+    if !compression::is_compressed(program) {
+        return Ok(());
+    }
+
+    let decompressed = compression::decompress_program(program, SUIT_STORAGE_SLOT_SIZE)?;
+    replace_program_in_slot(slot, &decompressed)
+}
+
+/// Records the metadata of the program loaded into the slot (see
+/// [`program_metadata`]) and removes the metadata header from the program.
+/// Programs without the header are left as they are.
+fn strip_metadata_in_slot(slot: usize) -> Result<(), String> {
+    let program = load_program_static(slot);
+    if !program_metadata::has_metadata(program) {
+        program_metadata::set_slot_metadata(slot, None);
+        return Ok(());
+    }
+
+    let (metadata, header_len) = program_metadata::parse_metadata(program)?;
+    debug!(target: targets::STORAGE, "Program metadata in slot {}: {:?}", slot, metadata);
+    // The program needs to be copied out as it is written into the same slot.
+    let stripped = program[header_len..].to_vec();
+    replace_program_in_slot(slot, &stripped)?;
+    program_metadata::set_slot_metadata(slot, Some(metadata));
+    Ok(())
+}
+
+fn replace_program_in_slot(slot: usize, program: &[u8]) -> Result<(), String> {
+    let location = format!(".ram.{0}\0", slot);
+    let res = unsafe {
+        write_bytes_to_suit_storage(location.as_ptr(), program.as_ptr(), program.len() as u32)
+    };
+    if res < 0 {
+        Err(format!("Failed to write the program into slot {}: {}", slot, res))?;
+    }
+    Ok(())
+}
+
+/// Canonical indices of the SUIT storage slots in ascending order. Handlers
+/// which list, validate or otherwise iterate over the slots should use this
+/// (or [`is_suit_slot`]) so that they all agree on the same set of slots.
+pub fn suit_slots() -> impl Iterator<Item = usize> {
+    0..SUIT_STORAGE_SLOTS
+}
+
+/// Checks whether the index refers to one of the slots yielded by [`suit_slots`].
+pub fn is_suit_slot(slot: usize) -> bool {
+    slot < SUIT_STORAGE_SLOTS
+}
+
+/// Returns the number of slots that programs can be loaded into without
+/// overwriting any of the deployed ones.
+pub fn free_slot_count() -> usize {
+    SUIT_STORAGE_STATE
+        .lock()
+        .iter()
+        .filter(|s| **s == SuitStorageSlotStatus::Free)
+        .count()
+}
+
+pub fn suit_slot_status(slot: usize) -> SuitStorageSlotStatus {
+    SUIT_STORAGE_STATE.lock()[slot]
+}
+
+/// Acquires the execution lock of the slot, the program can be executed for
+/// as long as the returned guard is held. If the slot is being executed or
+/// deployed into, an error is returned right away. The lock is mostly taken
+/// on the CoAP thread, waiting there would stall all other requests (and
+/// long-running programs hold the lock for as long as they run).
+pub fn lock_slot_for_execution(slot: usize) -> Result<MutexGuard<'static, ()>, String> {
+    let lock = SLOT_EXECUTION_LOCKS
+        .get(slot)
+        .ok_or_else(|| format!("Invalid SUIT storage slot: {}", slot))?;
+    if SLOT_DEPLOYMENTS[slot].load(Ordering::Acquire) {
+        Err(format!("Slot {} is busy, deploy in progress", slot))?;
+    }
+    lock.try_lock()
+        .ok_or_else(|| format!("Slot {} is busy executing another request", slot))
+}
+
+/// Excludes executions of the slot while a program is deployed into it, see
+/// [`lock_slot_for_deployment`]. The slot is released when it is dropped.
+pub struct SlotDeployment {
+    slot: usize,
+    _execution_lock: MutexGuard<'static, ()>,
+}
+
+impl Drop for SlotDeployment {
+    fn drop(&mut self) {
+        SLOT_DEPLOYMENTS[self.slot].store(false, Ordering::Release);
+    }
+}
+
+/// Marks the slot as being deployed into and acquires its execution lock, so
+/// that the program isn't loaded until it is completely written. Deployments
+/// colliding with an execution of the slot are rejected, as are the
+/// executions requested while the deployment is in progress.
+pub fn lock_slot_for_deployment(slot: usize) -> Result<SlotDeployment, String> {
+    let execution_lock = lock_slot_for_execution(slot)?;
+    SLOT_DEPLOYMENTS[slot].store(true, Ordering::Release);
+    Ok(SlotDeployment {
+        slot,
+        _execution_lock: execution_lock,
+    })
 }
 
 pub fn suit_mark_slot_running(slot: usize) {
@@ -138,20 +325,72 @@ pub fn suit_mark_slot_occupied(slot: usize) {
 }
 
 /// Allows for erasing the SUIT storage containing a given program if e.g. it's
-/// helper function verification has failed and it cannot be executed
+/// helper function verification has failed and it cannot be executed.
+/// The local storage associated with the slot is cleared as well. Slots whose
+/// programs are currently running, executing or being deployed can't be erased.
 pub fn suit_erase(slot: usize) -> Result<(), String> {
-    let location = format!(".ram.{0}\0", slot);
+    // The program can't be executed while it is being erased.
+    let _deployment = lock_slot_for_deployment(slot)?;
     let mut slots = SUIT_STORAGE_STATE.lock();
+    if slots[slot] == SuitStorageSlotStatus::Running {
+        Err("Tried to erase a slot that belongs to a currently running program".to_string())?;
+    }
     if slots[slot] != SuitStorageSlotStatus::Occupied {
         Err("Requested to erase an empty SUIT slot".to_string())?;
     }
 
-    debug!("Erasing SUIT storage slot {}.", slot);
+    clear_slot(slot);
+    slots[slot] = SuitStorageSlotStatus::Free;
+    Ok(())
+}
+
+/// Erases the program in the slot together with the state associated with
+/// it, the caller is responsible for updating the status of the slot.
+fn clear_slot(slot: usize) {
+    debug!(target: targets::STORAGE, "Erasing SUIT storage slot {}.", slot);
+    let location = format!(".ram.{0}\0", slot);
     unsafe {
         let location_ptr = location.as_ptr();
         handle_suit_storage_erase(location_ptr);
     };
-    slots[slot] = SuitStorageSlotStatus::Free;
+    local_storage::deregister_suit_slot(slot);
+    program_metadata::set_slot_metadata(slot, None);
+    last_results::clear(slot);
+}
+
+/// Overwrites `patch.len()` bytes of the program in the slot starting at
+/// `offset`, e.g. to tune a constant without redeploying the whole program.
+/// The patched range needs to lie within the program and it can't modify
+/// any of its relocations (see [`relocation_check::check_patch`]). The caller
+/// needs to hold the deployment lock of the slot (see
+/// [`lock_slot_for_deployment`]) so that the program isn't executed while it is
+/// being patched.
+pub fn patch_program_in_slot(slot: usize, offset: usize, patch: &[u8]) -> Result<(), String> {
+    let program = load_program_static(slot);
+    let end = offset
+        .checked_add(patch.len())
+        .filter(|end| *end <= program.len())
+        .ok_or_else(|| {
+            format!(
+                "The patch ({}[B] at offset {}) doesn't fit into the {}[B] program in slot {}",
+                patch.len(),
+                offset,
+                program.len(),
+                slot
+            )
+        })?;
+    relocation_check::check_patch(program, offset, patch.len())?;
+
+    debug!(
+        target: targets::STORAGE,
+        "Patching bytes {}..{} of the program in slot {}.",
+        offset,
+        end,
+        slot,
+    );
+    program[offset..end].copy_from_slice(patch);
+    // The result was produced by the previous version of the program.
+    last_results::clear(slot);
     Ok(())
 }
 
@@ -170,12 +409,18 @@ pub fn load_program<'a>(program_buffer: &'a mut [u8], slot: usize) -> &'a mut [u
         len = load_bytes_from_suit_storage(buffer_ptr, location_ptr);
     };
 
-    debug!("{}[B] program loaded from SUIT storage slot {}.", len, slot);
+    debug!(target: targets::STORAGE, "{}[B] program loaded from SUIT storage slot {}.", len, slot);
     local_storage::register_suit_slot(slot);
 
     &mut program_buffer[..(len as usize)]
 }
 
+/// Returns the program stored in the given SUIT storage slot in place, without
+/// copying it into a separate buffer. This is what the VMs use when loading
+/// programs for execution, so repeated executions don't pay for zeroing or
+/// copying a program buffer, and the returned slice is bounded by the length
+/// of the stored program, so no bytes of a previous (larger) program in the
+/// same slot can be read.
 pub fn load_program_static(slot: usize) -> &'static mut [u8] {
     let location = format!(".ram.{0}\0", slot);
     let mut len: u32 = 0;
@@ -186,8 +431,28 @@ pub fn load_program_static(slot: usize) -> &'static mut [u8] {
         prog_buffer = from_raw_parts_mut(storage_ptr, len as usize);
     };
 
-    debug!("{}[B] program loaded from SUIT storage slot {}.", len, slot);
+    debug!(target: targets::STORAGE, "{}[B] program loaded from SUIT storage slot {}.", len, slot);
     local_storage::register_suit_slot(slot);
 
     &mut prog_buffer[..(len as usize)]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_listed_in_ascending_order() {
+        let slots: alloc::vec::Vec<usize> = suit_slots().collect();
+        assert_eq!(slots.len(), SUIT_STORAGE_SLOTS);
+        assert_eq!(slots.first(), Some(&0));
+        assert!(slots.windows(2).all(|pair| pair[0] + 1 == pair[1]));
+    }
+
+    #[test]
+    fn only_listed_slots_are_valid() {
+        assert!(suit_slots().all(is_suit_slot));
+        assert!(!is_suit_slot(SUIT_STORAGE_SLOTS));
+        assert!(!is_suit_slot(usize::MAX));
+    }
+}
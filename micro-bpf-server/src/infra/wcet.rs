@@ -0,0 +1,520 @@
+//! Static estimate of the worst-case execution time (WCET) of a program, it
+//! helps with budgeting the time of its executions.
+//!
+//! The estimate is an upper bound computed from a cost model: every executed
+//! instruction costs [`WCET_INSTRUCTION_COST_NS`] and every helper call costs
+//! additionally the estimate of the helper from [`HELPER_COSTS_US`] (or
+//! [`WCET_DEFAULT_HELPER_COST_US`]). All instructions are assumed to be
+//! executed, regardless of the branches taken, and the instructions in loops
+//! are counted once for each iteration of all loops enclosing them.
+//!
+//! Only simple counting loops can be bounded: the loop condition compares a
+//! counter with a constant (or a register holding a constant), the counter is
+//! set to a constant before the loop and it is changed by a constant step
+//! exactly once in every iteration. The number of iterations is then found by
+//! simulating the counter. Programs with other loops, calls to the functions
+//! defined in the program or calls to blocking helpers are reported as
+//! unbounded, their executions need to be limited by a watchdog instead.
+//!
+//! The default costs are rough estimates for the rBPF interpreter on a
+//! Cortex-M4 board, they should be calibrated for the target board (e.g. by
+//! comparing the estimates with the execution times of the programs in
+//! `examples/bpf/helper-tests/wcet-*.c`).
+
+use alloc::{format, string::String, vec, vec::Vec};
+use core::convert::TryInto;
+use macros::set_env_or_default;
+use micro_bpf_common::{BinaryFileLayout, HelperFunctionID as ID};
+
+use super::program_analysis;
+use crate::util::json;
+
+/// Estimated cost of interpreting a single instruction in nanoseconds.
+pub const WCET_INSTRUCTION_COST_NS: usize = set_env_or_default!("WCET_INSTRUCTION_COST_NS", 500);
+
+/// Estimated cost of the helpers that aren't listed in [`HELPER_COSTS_US`].
+pub const WCET_DEFAULT_HELPER_COST_US: usize =
+    set_env_or_default!("WCET_DEFAULT_HELPER_COST_US", 20);
+
+/// Estimated costs of the helpers which take considerably longer than the
+/// default, e.g. because they wait for a peripheral or write to the console.
+const HELPER_COSTS_US: &[(ID, u64)] = &[
+    (ID::BPF_DEBUG_PRINT_IDX, 500),
+    (ID::BPF_PRINTF_IDX, 2000),
+    (ID::BPF_SAUL_REG_READ_IDX, 1000),
+    (ID::BPF_SAUL_REG_WRITE_IDX, 1000),
+    (ID::BPF_SAUL_REG_READ_TEMP, 1000),
+    (ID::BPF_HD44780_INIT, 50000),
+    (ID::BPF_HD44780_CLEAR, 2000),
+    (ID::BPF_HD44780_PRINT, 5000),
+    (ID::BPF_HD44780_SET_CURSOR, 100),
+];
+
+/// Helpers that block the program for a time that can't be determined
+/// statically, programs calling them are unbounded.
+const BLOCKING_HELPERS: &[ID] = &[ID::BPF_PERIODIC_WAKEUP_IDX];
+
+/// Loops whose counter doesn't reach the exit condition within this many
+/// iterations are reported as unbounded.
+const MAX_SIMULATED_ITERATIONS: u64 = 1_000_000;
+
+const INSTRUCTION_SIZE: usize = 8;
+
+const OPCODE_LDDW: u8 = 0x18;
+const OPCODE_CALL: u8 = 0x85;
+const OPCODE_EXIT: u8 = 0x95;
+const OPCODE_JA: u8 = 0x05;
+const PSEUDO_CALL: u8 = 1;
+
+const CLASS_MASK: u8 = 0x07;
+const CLASS_LD: u8 = 0x00;
+const CLASS_LDX: u8 = 0x01;
+const CLASS_ALU: u8 = 0x04;
+const CLASS_JMP: u8 = 0x05;
+const CLASS_JMP32: u8 = 0x06;
+const CLASS_ALU64: u8 = 0x07;
+/// Set if the instruction uses the source register instead of the immediate.
+const SOURCE_REGISTER: u8 = 0x08;
+
+const OP_MASK: u8 = 0xf0;
+const OP_ADD: u8 = 0x00;
+const OP_SUB: u8 = 0x10;
+const OP_MOV: u8 = 0xb0;
+
+const JEQ: u8 = 0x10;
+const JGT: u8 = 0x20;
+const JGE: u8 = 0x30;
+const JSET: u8 = 0x40;
+const JNE: u8 = 0x50;
+const JSGT: u8 = 0x60;
+const JSGE: u8 = 0x70;
+const JLT: u8 = 0xa0;
+const JLE: u8 = 0xb0;
+const JSLT: u8 = 0xc0;
+const JSLE: u8 = 0xd0;
+
+/// Registers r0-r5 are clobbered by helper calls.
+const LAST_CALLER_SAVED_REGISTER: u8 = 5;
+
+#[derive(Debug, Clone)]
+pub enum WcetEstimate {
+    Bounded {
+        /// Upper bound on the execution time in microseconds.
+        wcet_us: u64,
+        /// Upper bound on the number of executed instructions.
+        instructions: u64,
+    },
+    /// The execution time can't be bounded statically, for the given reason.
+    Unbounded(String),
+}
+
+impl WcetEstimate {
+    /// Formats the estimate as JSON object fields, without the enclosing braces.
+    pub fn json_fields(&self) -> String {
+        match self {
+            WcetEstimate::Bounded {
+                wcet_us,
+                instructions,
+            } => format!("\"wcet_us\": {}, \"instructions\": {}", wcet_us, instructions),
+            WcetEstimate::Unbounded(reason) => format!(
+                "\"wcet_us\": \"unbounded\", \"reason\": \"{}\"",
+                json::escape(reason)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    opcode: u8,
+    dst: u8,
+    src: u8,
+    offset: i16,
+    imm: i32,
+}
+
+impl Instruction {
+    fn decode(bytes: &[u8]) -> Self {
+        Self {
+            opcode: bytes[0],
+            dst: bytes[1] & 0x0f,
+            src: bytes[1] >> 4,
+            offset: i16::from_le_bytes([bytes[2], bytes[3]]),
+            imm: i32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        }
+    }
+
+    fn class(&self) -> u8 {
+        self.opcode & CLASS_MASK
+    }
+
+    fn is_jump(&self) -> bool {
+        matches!(self.class(), CLASS_JMP | CLASS_JMP32)
+            && self.opcode != OPCODE_CALL
+            && self.opcode != OPCODE_EXIT
+    }
+
+    /// Index of the instruction slot that the jump at `pc` jumps to.
+    fn jump_target(&self, pc: usize) -> Option<usize> {
+        (pc as isize + 1 + self.offset as isize).try_into().ok()
+    }
+
+    /// Whether the instruction writes into the given register. Helper calls
+    /// clobber the caller-saved registers.
+    fn writes(&self, register: u8) -> bool {
+        match self.class() {
+            CLASS_ALU | CLASS_ALU64 | CLASS_LD | CLASS_LDX => self.dst == register,
+            _ if self.opcode == OPCODE_CALL => register <= LAST_CALLER_SAVED_REGISTER,
+            _ => false,
+        }
+    }
+
+    /// The constant that a `mov dst, imm` instruction sets its register to.
+    fn constant(&self) -> Option<i64> {
+        let is_mov_imm = self.opcode & (OP_MASK | SOURCE_REGISTER) == OP_MOV
+            && matches!(self.class(), CLASS_ALU | CLASS_ALU64);
+        is_mov_imm.then(|| match self.class() {
+            // 32-bit moves zero the upper half of the register.
+            CLASS_ALU => self.imm as u32 as i64,
+            _ => self.imm as i64,
+        })
+    }
+
+    /// The step of an `add dst, imm` or `sub dst, imm` instruction.
+    fn step(&self) -> Option<i64> {
+        if self.opcode & SOURCE_REGISTER != 0 || !matches!(self.class(), CLASS_ALU | CLASS_ALU64)
+        {
+            return None;
+        }
+        match self.opcode & OP_MASK {
+            OP_ADD => Some(self.imm as i64),
+            OP_SUB => Some(-(self.imm as i64)),
+            _ => None,
+        }
+    }
+}
+
+/// A loop formed by the backward jump at `latch` to `header`, its body spans
+/// the instruction slots `header..=latch`.
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    header: usize,
+    latch: usize,
+    iterations: u64,
+}
+
+impl Loop {
+    fn contains(&self, pc: usize) -> bool {
+        self.header <= pc && pc <= self.latch
+    }
+}
+
+/// Estimates the worst-case execution time of the program, see the module
+/// documentation for the cost model and the supported loops.
+pub fn estimate_wcet(program: &[u8], layout: BinaryFileLayout) -> Result<WcetEstimate, String> {
+    let (text, _) = program_analysis::text_section(program, layout)?;
+    let slots = text
+        .chunks_exact(INSTRUCTION_SIZE)
+        .map(Instruction::decode)
+        .collect::<Vec<_>>();
+    Ok(estimate(&slots).unwrap_or_else(WcetEstimate::Unbounded))
+}
+
+fn estimate(slots: &[Instruction]) -> Result<WcetEstimate, String> {
+    // The second slot of lddw holds the upper half of the immediate.
+    let mut is_instruction = vec![true; slots.len()];
+    let mut loops = Vec::new();
+    let mut pc = 0;
+    while pc < slots.len() {
+        let insn = slots[pc];
+        if insn.opcode == OPCODE_LDDW {
+            if let Some(next) = is_instruction.get_mut(pc + 1) {
+                *next = false;
+            }
+            pc += 2;
+            continue;
+        }
+        if insn.opcode == OPCODE_CALL && insn.src == PSEUDO_CALL {
+            Err(format!("call to a local function at {:#x}", pc * INSTRUCTION_SIZE))?;
+        }
+        if insn.opcode == OPCODE_CALL && BLOCKING_HELPERS.iter().any(|id| *id as i32 == insn.imm) {
+            Err(format!("blocking helper call at {:#x}", pc * INSTRUCTION_SIZE))?;
+        }
+        if insn.is_jump() {
+            let Some(target) = insn.jump_target(pc).filter(|t| *t < slots.len()) else {
+                let offset = pc * INSTRUCTION_SIZE;
+                return Err(format!("jump outside of the program at {:#x}", offset));
+            };
+            if target <= pc {
+                let iterations = loop_iterations(slots, target, pc)?;
+                loops.push(Loop {
+                    header: target,
+                    latch: pc,
+                    iterations,
+                });
+            }
+        }
+        pc += 1;
+    }
+
+    // The multipliers of the nested loops are only correct if the loops don't
+    // partially overlap.
+    for (i, a) in loops.iter().enumerate() {
+        for b in &loops[i + 1..] {
+            let disjoint = a.latch < b.header || b.latch < a.header;
+            let nested = (a.contains(b.header) && a.contains(b.latch))
+                || (b.contains(a.header) && b.contains(a.latch));
+            if !disjoint && !nested {
+                Err(format!(
+                    "overlapping loops at {:#x} and {:#x}",
+                    a.header * INSTRUCTION_SIZE,
+                    b.header * INSTRUCTION_SIZE
+                ))?;
+            }
+        }
+    }
+
+    let mut instructions: u64 = 0;
+    let mut cost_ns: u64 = 0;
+    for (pc, insn) in slots.iter().enumerate() {
+        if !is_instruction[pc] {
+            continue;
+        }
+        let executions = loops
+            .iter()
+            .filter(|l| l.contains(pc))
+            .fold(1u64, |n, l| n.saturating_mul(l.iterations));
+        let mut insn_cost_ns = WCET_INSTRUCTION_COST_NS as u64;
+        if insn.opcode == OPCODE_CALL {
+            insn_cost_ns += helper_cost_us(insn.imm).saturating_mul(1000);
+        }
+        instructions = instructions.saturating_add(executions);
+        cost_ns = cost_ns.saturating_add(executions.saturating_mul(insn_cost_ns));
+    }
+    Ok(WcetEstimate::Bounded {
+        wcet_us: cost_ns.saturating_add(999) / 1000,
+        instructions,
+    })
+}
+
+fn helper_cost_us(id: i32) -> u64 {
+    HELPER_COSTS_US
+        .iter()
+        .find(|(helper, _)| *helper as i32 == id)
+        .map_or(WCET_DEFAULT_HELPER_COST_US as u64, |(_, cost)| *cost)
+}
+
+/// Returns the maximum number of times the body of the loop formed by the
+/// conditional jump at `latch` to `header` is executed, or the reason why it
+/// can't be determined.
+fn loop_iterations(slots: &[Instruction], header: usize, latch: usize) -> Result<u64, String> {
+    let unbounded = |reason: &str| format!("{} at {:#x}", reason, header * INSTRUCTION_SIZE);
+    let condition = slots[latch];
+    if condition.opcode == OPCODE_JA {
+        Err(unbounded("loop without a condition"))?;
+    }
+    let counter = condition.dst;
+
+    // The limit is either the immediate or a register holding a constant
+    // which isn't modified in the loop.
+    let limit = if condition.opcode & SOURCE_REGISTER == 0 {
+        condition.imm as i64
+    } else {
+        let register = condition.src;
+        if (header..=latch).any(|pc| slots[pc].writes(register)) {
+            Err(unbounded("loop limit modified in the loop"))?;
+        }
+        constant_before(slots, register, header)
+            .ok_or_else(|| unbounded("loop limit isn't a constant"))?
+    };
+
+    let mut updates = (header..latch).filter(|pc| slots[*pc].writes(counter));
+    let (Some(update), None) = (updates.next(), updates.next()) else {
+        return Err(unbounded("loop counter isn't updated exactly once"));
+    };
+    let step = slots[update]
+        .step()
+        .ok_or_else(|| unbounded("loop counter isn't changed by a constant"))?;
+
+    // The update needs to be executed in every iteration, so no jump can
+    // skip it on the way to the latch.
+    let skipped = slots.iter().enumerate().any(|(pc, insn)| {
+        insn.is_jump()
+            && pc != latch
+            && (pc < update || pc > latch)
+            && insn.jump_target(pc).map_or(false, |t| update < t && t <= latch)
+    });
+    if skipped {
+        Err(unbounded("loop counter update can be skipped"))?;
+    }
+
+    let initial = constant_before(slots, counter, header)
+        .ok_or_else(|| unbounded("loop counter doesn't start at a constant"))?;
+    simulate(condition, initial, step, limit).ok_or_else(|| unbounded("unbounded loop"))
+}
+
+/// Returns the constant that the register holds at the `header`, if the last
+/// instruction writing it before the header is `mov register, imm`.
+fn constant_before(slots: &[Instruction], register: u8, header: usize) -> Option<i64> {
+    slots[..header]
+        .iter()
+        .rev()
+        .find(|insn| insn.writes(register))
+        .and_then(Instruction::constant)
+}
+
+/// Simulates the loop counter, returns the number of iterations after which
+/// the condition of the latch stops holding.
+fn simulate(condition: Instruction, initial: i64, step: i64, limit: i64) -> Option<u64> {
+    let is_32_bit = condition.class() == CLASS_JMP32;
+    let mut value = initial;
+    for iterations in 1..=MAX_SIMULATED_ITERATIONS {
+        value = value.wrapping_add(step);
+        if !jump_taken(condition.opcode & OP_MASK, value, limit, is_32_bit)? {
+            return Some(iterations);
+        }
+    }
+    None
+}
+
+fn jump_taken(op: u8, value: i64, limit: i64, is_32_bit: bool) -> Option<bool> {
+    let (unsigned, unsigned_limit, signed, signed_limit) = if is_32_bit {
+        (
+            value as u32 as u64,
+            limit as u32 as u64,
+            value as i32 as i64,
+            limit as i32 as i64,
+        )
+    } else {
+        (value as u64, limit as u64, value, limit)
+    };
+    Some(match op {
+        JEQ => unsigned == unsigned_limit,
+        JNE => unsigned != unsigned_limit,
+        JGT => unsigned > unsigned_limit,
+        JGE => unsigned >= unsigned_limit,
+        JLT => unsigned < unsigned_limit,
+        JLE => unsigned <= unsigned_limit,
+        JSET => unsigned & unsigned_limit != 0,
+        JSGT => signed > signed_limit,
+        JSGE => signed >= signed_limit,
+        JSLT => signed < signed_limit,
+        JSLE => signed <= signed_limit,
+        _ => return None,
+    })
+}
+
+/// Returns a short description of the estimate for the log.
+pub fn describe(estimate: &WcetEstimate) -> String {
+    match estimate {
+        WcetEstimate::Bounded { wcet_us, .. } => format!("{} [us]", wcet_us),
+        WcetEstimate::Unbounded(reason) => format!("unbounded ({})", reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOV_R0_0: [u8; 8] = [0xb7, 0x00, 0, 0, 0, 0, 0, 0];
+    const MOV_R1_0: [u8; 8] = [0xb7, 0x01, 0, 0, 0, 0, 0, 0];
+    const ADD_R1_1: [u8; 8] = [0x07, 0x01, 0, 0, 1, 0, 0, 0];
+    /// if r1 < 10 goto -2
+    const JLT_R1_10: [u8; 8] = [0xa5, 0x01, 0xfe, 0xff, 10, 0, 0, 0];
+    const EXIT: [u8; 8] = [OPCODE_EXIT, 0, 0, 0, 0, 0, 0, 0];
+
+    fn call(id: ID) -> [u8; 8] {
+        let imm = (id as i32).to_le_bytes();
+        [OPCODE_CALL, 0, 0, 0, imm[0], imm[1], imm[2], imm[3]]
+    }
+
+    fn estimate_program(instructions: &[[u8; 8]]) -> WcetEstimate {
+        let program = instructions.concat();
+        estimate_wcet(&program, BinaryFileLayout::OnlyTextSection).unwrap()
+    }
+
+    /// Returns the estimate of executing the number of instructions and
+    /// spending the given time in the helpers.
+    fn bounded(instructions: u64, helpers_us: u64) -> (u64, u64) {
+        let cost_ns = instructions * WCET_INSTRUCTION_COST_NS as u64 + helpers_us * 1000;
+        ((cost_ns + 999) / 1000, instructions)
+    }
+
+    fn unbounded_reason(estimate: WcetEstimate) -> String {
+        match estimate {
+            WcetEstimate::Unbounded(reason) => reason,
+            bounded => panic!("Expected an unbounded estimate, got {:?}", bounded),
+        }
+    }
+
+    fn assert_bounded(estimate: WcetEstimate, expected: (u64, u64)) {
+        match estimate {
+            WcetEstimate::Bounded {
+                wcet_us,
+                instructions,
+            } => assert_eq!((wcet_us, instructions), expected),
+            unbounded => panic!("Expected a bounded estimate, got {:?}", unbounded),
+        }
+    }
+
+    #[test]
+    fn straight_line_program() {
+        // The second slot of lddw isn't counted as an instruction.
+        let lddw = [[OPCODE_LDDW, 0x01, 0, 0, 1, 0, 0, 0], [0; 8]];
+        assert_bounded(
+            estimate_program(&[lddw[0], lddw[1], MOV_R0_0, EXIT]),
+            bounded(3, 0),
+        );
+    }
+
+    #[test]
+    fn helper_calls_add_their_cost() {
+        assert_bounded(
+            estimate_program(&[call(ID::BPF_PRINTF_IDX), EXIT]),
+            bounded(2, helper_cost_us(ID::BPF_PRINTF_IDX as i32)),
+        );
+        assert_eq!(helper_cost_us(-1), WCET_DEFAULT_HELPER_COST_US as u64);
+    }
+
+    #[test]
+    fn counting_loop_is_bounded() {
+        // The body (add and the jump) is executed 10 times.
+        let program = [MOV_R1_0, ADD_R1_1, JLT_R1_10, MOV_R0_0, EXIT];
+        assert_bounded(estimate_program(&program), bounded(1 + 2 * 10 + 2, 0));
+    }
+
+    #[test]
+    fn loop_without_counter_update_is_unbounded() {
+        let program = [MOV_R1_0, MOV_R0_0, JLT_R1_10, EXIT];
+        let reason = unbounded_reason(estimate_program(&program));
+        assert!(
+            reason.starts_with("loop counter isn't updated exactly once"),
+            "{}",
+            reason
+        );
+    }
+
+    #[test]
+    fn loop_counter_moving_away_from_the_limit_is_unbounded() {
+        // r1 -= 1 never reaches 10 when compared as a signed number.
+        let sub_r1_1 = [0x17, 0x01, 0, 0, 1, 0, 0, 0];
+        let jslt_r1_10 = [0xc5, 0x01, 0xfe, 0xff, 10, 0, 0, 0];
+        let program = [MOV_R1_0, sub_r1_1, jslt_r1_10, EXIT];
+        let reason = unbounded_reason(estimate_program(&program));
+        assert!(reason.starts_with("unbounded loop"), "{}", reason);
+    }
+
+    #[test]
+    fn blocking_helpers_and_jumps_outside_are_unbounded() {
+        let reason = unbounded_reason(estimate_program(&[call(ID::BPF_PERIODIC_WAKEUP_IDX), EXIT]));
+        assert!(reason.starts_with("blocking helper call"), "{}", reason);
+
+        let ja_outside = [OPCODE_JA, 0, 0x10, 0, 0, 0, 0, 0];
+        let reason = unbounded_reason(estimate_program(&[ja_outside, EXIT]));
+        assert!(
+            reason.starts_with("jump outside of the program"),
+            "{}",
+            reason
+        );
+    }
+}
@@ -30,6 +30,11 @@ mod vm;
 // The threading setup was adapted from here: https://gitlab.com/etonomy/riot-examples/-/tree/master/shell_threads?ref_type=heads
 static COAP_THREAD_STACK: Mutex<[u8; 8192]> = Mutex::new([0; 8192]);
 static SHELL_THREAD_STACK: Mutex<[u8; 4096]> = Mutex::new([0; 4096]);
+// The verification worker is only spawned (and its stack only takes up memory)
+// if the verification of inline programs is offloaded to it.
+const VERIFICATION_STACK_SIZE: usize = vm::verification_worker::VERIFICATION_WORKER_STACK_SIZE;
+static VERIFICATION_THREAD_STACK: Mutex<[u8; VERIFICATION_STACK_SIZE]> =
+    Mutex::new([0; VERIFICATION_STACK_SIZE]);
 
 riot_main!(main);
 
@@ -42,6 +47,9 @@ fn main(token: thread::StartToken) -> ((), thread::EndToken) {
         fn initialise_adc(adc_index: u8) -> u32;
     }
 
+    infra::crash_diagnostics::init();
+    infra::native_functions::register_benchmark_fns();
+
     unsafe {
         initialise_adc(0);
         initialise_adc(1);
@@ -63,12 +71,14 @@ fn main(token: thread::StartToken) -> ((), thread::EndToken) {
 
         let mut shell_stack = SHELL_THREAD_STACK.lock();
         let mut gcoap_stack = COAP_THREAD_STACK.lock();
+        let mut verification_stack = VERIFICATION_THREAD_STACK.lock();
 
         // Because of the implementation details of the thread scope below, we
         // need to declare the main closures of the threads here instead of
         // inlining them.
         let mut gcoap_main = || coap_server::gcoap_server_main(&send_port).unwrap();
         let mut shell_main = || shell::shell_main(&send_port).unwrap();
+        let mut verification_main = vm::verification_worker::verification_worker_main;
 
         let pri = riot_sys::THREAD_PRIORITY_MAIN;
 
@@ -76,6 +86,19 @@ fn main(token: thread::StartToken) -> ((), thread::EndToken) {
             let _gcoapthread =
                 spawn_thread!(scope, "CoAP server", gcoap_stack, gcoap_main, pri - 1);
             let _shellthread = spawn_thread!(scope, "Shell", shell_stack, shell_main, pri + 2);
+            // The verification runs below the CoAP server so that it can't
+            // stall it, see vm::verification_worker.
+            let _verificationthread = if vm::verification_worker::OFFLOAD_VERIFICATION {
+                Some(spawn_thread!(
+                    scope,
+                    "Verification",
+                    verification_stack,
+                    verification_main,
+                    pri + 1
+                ))
+            } else {
+                None
+            };
             vm_manager.start();
         });
         unreachable!();
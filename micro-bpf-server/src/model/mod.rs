@@ -1 +1,2 @@
 pub mod requests;
+pub mod results;
@@ -1,43 +1,115 @@
-use core::ffi::c_void;
+use core::{ffi::c_void, mem::size_of};
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, format, string::String};
 use micro_bpf_common::VMExecutionRequest;
 use riot_sys::msg_t;
 
+/// Maximum size of a message that can be sent over the RIOT IPC. The messages
+/// are copied into the content union of the `msg_t`, so they can't be larger
+/// than that (on 32-bit boards it is only 4 bytes).
+pub const MAX_IPC_MSG_SIZE: usize = size_of::<riot_sys::msg_t__bindgen_ty_1>();
+
 /// Wrapper around the [`micro_bpf_common::VMExecutionRequest`] to allow for sending
 /// it over the RIOT IPC.
 pub struct VMExecutionRequestIPC {
-    pub request: Box<VMExecutionRequest>,
+    pub job: Box<ExecutionJob>,
+}
+
+/// Execution request together with the worker that it should be dispatched to.
+pub struct ExecutionJob {
+    pub request: VMExecutionRequest,
+    pub worker: WorkerSelection,
+    /// If set, the result of the execution is kept under this ticket so that
+    /// the requester can wait for it, see [`crate::vm::wait_for_result`].
+    pub ticket: Option<u32>,
+    /// ID attached to the request by the client, it is included in the log
+    /// lines of the worker and reported together with the result so that the
+    /// client can match them with the request. It is part of the boxed job,
+    /// so it doesn't count towards the IPC message size.
+    pub correlation_id: Option<u32>,
+    /// Set when the supervisor restarts a crashed long-running program, the
+    /// worker waits for the given number of milliseconds before starting it.
+    pub restart_delay_ms: Option<u32>,
+}
+
+/// Specifies which of the VM workers can execute a given request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerSelection {
+    /// Any free worker, the request is rejected if all of them are busy.
+    Any,
+    /// Only the worker with the given index. If it is busy, the request is
+    /// queued until the worker finishes, unless `no_wait` is set, in which
+    /// case it is rejected.
+    Pinned { index: usize, no_wait: bool },
 }
 
-impl Into<msg_t> for &mut VMExecutionRequestIPC {
-    fn into(self) -> msg_t {
+// Ensure that the messages don't outgrow the IPC limit as new fields are added.
+// Larger payloads need to be boxed, as it is done above for the execution request.
+const _: () = assert!(size_of::<VMExecutionRequestIPC>() <= MAX_IPC_MSG_SIZE);
+const _: () = assert!(size_of::<VMExecutionCompleteMsg>() <= MAX_IPC_MSG_SIZE);
+
+impl VMExecutionRequestIPC {
+    /// Creates a request that can be executed by any of the workers.
+    pub fn new(request: VMExecutionRequest) -> Self {
+        Self::with_worker(request, WorkerSelection::Any)
+    }
+
+    pub fn with_worker(request: VMExecutionRequest, worker: WorkerSelection) -> Self {
+        VMExecutionRequestIPC {
+            job: Box::new(ExecutionJob {
+                request,
+                worker,
+                ticket: None,
+                correlation_id: None,
+                restart_delay_ms: None,
+            }),
+        }
+    }
+
+    /// Requests that the result is kept under the given ticket.
+    pub fn with_ticket(mut self, ticket: u32) -> Self {
+        self.job.ticket = Some(ticket);
+        self
+    }
+
+    pub fn with_correlation_id(mut self, correlation_id: Option<u32>) -> Self {
+        self.job.correlation_id = correlation_id;
+        self
+    }
+
+    /// Converts the request into a message that can be sent using the v1 RIOT
+    /// messaging API. The ownership of the boxed request is transferred into
+    /// the message, so it stays valid until the receiver takes it back using
+    /// [`VMExecutionRequestIPC::from_msg`].
+    pub fn into_msg(self) -> msg_t {
         let mut msg: msg_t = Default::default();
         msg.type_ = 0;
         msg.content = riot_sys::msg_t__bindgen_ty_1 {
-            ptr: self.request.as_mut() as *mut VMExecutionRequest as *mut c_void,
+            ptr: Box::into_raw(self.job) as *mut c_void,
         };
         msg
     }
-}
-
-impl From<msg_t> for VMExecutionRequestIPC {
-    fn from(msg: msg_t) -> Self {
-        let ptr: *mut c_void = unsafe { msg.content.ptr };
-
-        let req_ptr = ptr as *mut VMExecutionRequest;
 
-        unsafe {
-            return VMExecutionRequestIPC {
-                request: Box::new(VMExecutionRequest {
-                    configuration: (*req_ptr).configuration,
-                    allowed_helpers: (*req_ptr).allowed_helpers.clone(),
-                }),
-            };
+    /// Takes back the ownership of the request sent using
+    /// [`VMExecutionRequestIPC::into_msg`].
+    ///
+    /// # Safety
+    /// The message must have been created by `into_msg` and this function must
+    /// be called exactly once for it, otherwise the request is freed twice.
+    pub unsafe fn from_msg(msg: msg_t) -> Self {
+        VMExecutionRequestIPC {
+            job: Box::from_raw(msg.content.ptr as *mut ExecutionJob),
         }
     }
 }
 
+/// Formats the correlation ID of a request (see
+/// [`ExecutionJob::correlation_id`]) for the log lines, requests without one
+/// aren't tagged.
+pub fn correlation_tag(correlation_id: Option<u32>) -> String {
+    correlation_id.map_or(String::new(), |id| format!(" [cid {}]", id))
+}
+
 /// Responsible for notifying the VM manager that the execution of a given
 /// VM is finished and the worker can be allocated a new job.
 #[derive(Debug, Clone)]
@@ -0,0 +1,77 @@
+use alloc::{format, string::String};
+
+/// Status of a program execution. The return value of a program can be any
+/// 64-bit value (including ones that look like negative error codes), so
+/// whether the execution succeeded is reported separately from the value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmStatus {
+    Ok,
+    /// The program couldn't be loaded into the VM (or JIT-compiled).
+    InitializationFailed,
+    /// The program was rejected by the verifier.
+    VerificationFailed,
+    /// The program tried to access memory outside of the regions it was
+    /// given access to and was stopped by the interpreter.
+    MemoryAccessViolation,
+    /// The VM aborted the program during execution for any other reason.
+    ExecutionFailed,
+}
+
+impl VmStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VmStatus::Ok => "ok",
+            VmStatus::InitializationFailed => "init_error",
+            VmStatus::VerificationFailed => "verification_error",
+            VmStatus::MemoryAccessViolation => "memory_access_error",
+            VmStatus::ExecutionFailed => "execution_error",
+        }
+    }
+
+    /// Whether the program crashed while it was running, as opposed to
+    /// terminating cleanly or not starting at all.
+    pub fn is_fault(&self) -> bool {
+        matches!(self, VmStatus::MemoryAccessViolation | VmStatus::ExecutionFailed)
+    }
+}
+
+/// Result of executing a program. The value is the raw content of r0 and
+/// is only meaningful if the status is [`VmStatus::Ok`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionResult {
+    pub status: VmStatus,
+    pub value: u64,
+}
+
+impl ExecutionResult {
+    pub fn ok(value: u64) -> Self {
+        Self {
+            status: VmStatus::Ok,
+            value,
+        }
+    }
+
+    pub fn error(status: VmStatus) -> Self {
+        Self { status, value: 0 }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == VmStatus::Ok
+    }
+
+    /// Formats the result as JSON fields (without the enclosing braces) so
+    /// that it can be embedded into the responses of the handlers.
+    pub fn json_fields(&self) -> String {
+        format!(
+            "\"status\": \"{}\", \"value\": {}",
+            self.status.as_str(),
+            self.value
+        )
+    }
+}
+
+impl Default for ExecutionResult {
+    fn default() -> Self {
+        Self::ok(0)
+    }
+}
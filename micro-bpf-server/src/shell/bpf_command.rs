@@ -1,12 +1,16 @@
 use crate::{
-    model::requests::VMExecutionRequestIPC,
-    vm::{middleware::ALL_HELPERS, VM_EXEC_REQUEST},
+    model::requests::{VMExecutionRequestIPC, WorkerSelection},
+    vm::{
+        middleware::{ALL_HELPERS, HELPER_DESCRIPTIONS},
+        new_ticket, wait_for_result, DEFAULT_VM_TARGET, DEFAULT_VM_TARGET_NAME, NUM_WORKERS,
+        RUNNING_WORKERS, VM_EXEC_REQUEST,
+    },
 };
-use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
 use core::{fmt::Write, str::FromStr};
 use micro_bpf_common::{
-    BinaryFileLayout, HelperAccessListSource, HelperAccessVerification, TargetVM, VMConfiguration,
-    VMExecutionRequest,
+    BinaryFileLayout, HelperAccessListSource, HelperAccessVerification, HelperFunctionID, TargetVM,
+    VMConfiguration, VMExecutionRequest,
 };
 use riot_wrappers::{msg::v2::SendPort, mutex::Mutex};
 
@@ -29,30 +33,95 @@ impl VMExecutionShellCommandHandler {
         let mut usage = || {
             writeln!(
                 stdio,
-                "usage: {} [rBPF | FemtoContainer] <suit-storage-slot (int)> <bytecode-layout-option>",
+                "usage: {} [rBPF | FemtoContainer] <suit-storage-slot (int)> <bytecode-layout-option> [--helpers <name>,<name>,...] [--worker <index> [--no-wait]] [--wait <timeout-ms>]",
                 &args[0]
             )
             .unwrap();
             writeln!(
                 stdio,
-                "Available bytecode layout options: OnlyTextSection, FemtoContainersHeader, FunctionRelocationMetadata, RawObjectFile",
+                "If the VM is omitted, {} is used.",
+                DEFAULT_VM_TARGET_NAME
+            )
+            .unwrap();
+            writeln!(
+                stdio,
+                "By default all helpers are allowed, see bpf-helpers for the available helper names.",
+            )
+            .unwrap();
+            writeln!(
+                stdio,
+                "--worker runs the program on the given worker (0-{}), waiting for it if it is busy unless --no-wait is specified.",
+                NUM_WORKERS - 1
+            )
+            .unwrap();
+            writeln!(
+                stdio,
+                "--wait waits up to the given time for the program to finish and prints its result.",
+            )
+            .unwrap();
+            writeln!(
+                stdio,
+                "Available bytecode layout options: OnlyTextSection, FemtoContainersHeader, ExtendedHeader, RawObjectFile",
             )
             .unwrap();
         };
 
-        if args.len() < 3 {
+        if args.len() < 2 {
             return usage();
         }
 
-        let Ok(slot) = args[2].parse::<usize>() else {
-            return usage();
+        // The VM target is optional, if the first argument isn't one of the
+        // targets, the positional arguments are shifted by one.
+        let (vm_target, first_arg) = match TargetVM::from_str(&args[1]) {
+            Ok(vm_target) => (vm_target, 2),
+            Err(_) => (DEFAULT_VM_TARGET, 1),
         };
 
-        let Ok(vm_target) = TargetVM::from_str(&args[1]) else {
+        if args.len() < first_arg + 2 {
+            return usage();
+        }
+
+        let mut helper_names = None;
+        let mut worker = None;
+        let mut no_wait = false;
+        let mut wait_timeout_ms = None;
+        let mut i = first_arg + 2;
+        while i < args.len() {
+            match &args[i] {
+                "--helpers" if i + 1 < args.len() => {
+                    helper_names = Some(&args[i + 1]);
+                    i += 2;
+                }
+                "--worker" if i + 1 < args.len() => {
+                    let Ok(index) = args[i + 1].parse::<usize>() else {
+                        return usage();
+                    };
+                    worker = Some(index);
+                    i += 2;
+                }
+                "--wait" if i + 1 < args.len() => {
+                    let Ok(timeout) = args[i + 1].parse::<u32>() else {
+                        return usage();
+                    };
+                    wait_timeout_ms = Some(timeout);
+                    i += 2;
+                }
+                "--no-wait" => {
+                    no_wait = true;
+                    i += 1;
+                }
+                _ => return usage(),
+            }
+        }
+        if no_wait && worker.is_none() {
+            return usage();
+        }
+
+        let Ok(slot) = args[first_arg].parse::<usize>() else {
             return usage();
         };
 
-        let binary_layout = BinaryFileLayout::from_str(&args[3]).unwrap_or_else(|err| {
+        let binary_layout = BinaryFileLayout::from_str(&args[first_arg + 1]).unwrap_or_else(|err| {
             writeln!(stdio, "Invalid binary layout: {}", err).unwrap();
             BinaryFileLayout::ExtendedHeader
         });
@@ -67,20 +136,109 @@ impl VMExecutionShellCommandHandler {
             false,
         );
 
-        let allowed_helpers = Vec::from(ALL_HELPERS).into_iter().map(|f| f.id).collect();
+        let allowed_helpers = if let Some(names) = helper_names {
+            match parse_helper_names(names) {
+                Ok(helpers) => helpers,
+                Err(e) => {
+                    writeln!(stdio, "{}", e).unwrap();
+                    return;
+                }
+            }
+        } else {
+            ALL_HELPERS.iter().map(|f| f.id).collect()
+        };
 
         let request = VMExecutionRequest {
             configuration: vm_configuration,
             allowed_helpers,
         };
 
-        let message = VMExecutionRequestIPC {
-            request: Box::new(request),
+        let worker = match worker {
+            Some(index) => match check_worker_available(index, no_wait) {
+                Ok(()) => WorkerSelection::Pinned { index, no_wait },
+                Err(e) => {
+                    writeln!(stdio, "{}", e).unwrap();
+                    return;
+                }
+            },
+            None => WorkerSelection::Any,
         };
 
-        match self.execution_send.lock().try_send(message) {
-            Ok(_) => writeln!(stdio, "VM execution request sent successfully").unwrap(),
-            Err(_) => writeln!(stdio, "Failed to send VM execution request").unwrap(),
+        let mut message = VMExecutionRequestIPC::with_worker(request, worker);
+        let ticket = wait_timeout_ms.map(|_| new_ticket());
+        if let Some(ticket) = ticket {
+            message = message.with_ticket(ticket);
+        }
+
+        if self.execution_send.lock().try_send(message).is_err() {
+            writeln!(stdio, "Failed to send VM execution request").unwrap();
+            return;
+        }
+        writeln!(stdio, "VM execution request sent successfully").unwrap();
+
+        let (Some(ticket), Some(timeout_ms)) = (ticket, wait_timeout_ms) else {
+            return;
+        };
+        match wait_for_result(ticket, timeout_ms) {
+            Some((result, elapsed_us)) => writeln!(
+                stdio,
+                "Program returned: {} ({}), execution time: {} [us]",
+                result.value,
+                result.status.as_str(),
+                elapsed_us
+            )
+            .unwrap(),
+            None => writeln!(stdio, "No result within {} [ms]", timeout_ms).unwrap(),
         }
     }
 }
+
+/// Checks that the worker exists and, if the request can't wait for it, that
+/// it isn't currently executing another program. Note that the worker could
+/// still become busy before the request reaches the manager, in which case
+/// the manager rejects it.
+fn check_worker_available(index: usize, no_wait: bool) -> Result<(), String> {
+    if index >= NUM_WORKERS {
+        Err(format!(
+            "Invalid worker: {}, there are {} workers (0-{})",
+            index,
+            NUM_WORKERS,
+            NUM_WORKERS - 1
+        ))?;
+    }
+    if no_wait && RUNNING_WORKERS.lock()[index] {
+        Err(format!("Worker {} is busy", index))?;
+    }
+    Ok(())
+}
+
+/// Maps a comma-separated list of helper names (as printed by `bpf-helpers`)
+/// to their IDs. The `bpf_` prefix can be omitted, e.g. `ztimer_now,gpio_write`.
+/// Only the helpers compiled into the current build are accepted.
+fn parse_helper_names(names: &str) -> Result<Vec<HelperFunctionID>, String> {
+    names
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let full_name = if name.starts_with("bpf_") {
+                String::from(name)
+            } else {
+                format!("bpf_{}", name)
+            };
+            HELPER_DESCRIPTIONS
+                .iter()
+                .filter(|d| ALL_HELPERS.iter().any(|h| h.id == d.id))
+                .find(|d| d.name == full_name)
+                .map(|d| d.id)
+                .ok_or_else(|| {
+                    let valid = ALL_HELPERS
+                        .iter()
+                        .filter_map(|h| HELPER_DESCRIPTIONS.iter().find(|d| d.id == h.id))
+                        .map(|d| d.name.trim_start_matches("bpf_"))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    format!("Unknown helper: {}. Valid helpers: {}", name, valid)
+                })
+        })
+        .collect()
+}
@@ -0,0 +1,26 @@
+use core::fmt::Write;
+
+use crate::vm::middleware::{ALL_HELPERS, HELPER_DESCRIPTIONS};
+
+/// Prints all helpers compiled into the current build together with their IDs.
+/// Mutating helpers (ones that write to the program memory, global state or
+/// peripherals) are marked with `w`, the deterministic ones with `d`.
+pub fn handle_command(stdio: &mut riot_wrappers::stdio::Stdio, _args: riot_wrappers::shell::Args) {
+    writeln!(stdio, "ID   rwd name                       description").unwrap();
+    for helper in ALL_HELPERS {
+        let Some(desc) = HELPER_DESCRIPTIONS.iter().find(|d| d.id == helper.id) else {
+            writeln!(stdio, "0x{:02x} ?   <undocumented>", helper.id as u8).unwrap();
+            continue;
+        };
+        writeln!(
+            stdio,
+            "0x{:02x} {}{}  {:<26} {}",
+            helper.id as u8,
+            if desc.mutating { "w" } else { "r" },
+            if desc.deterministic { "d" } else { " " },
+            desc.name,
+            desc.description
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+use core::{fmt::Write, str::FromStr};
+
+use log::LevelFilter;
+
+use crate::util::logger::{self, targets};
+
+/// Changes the log level of one of the subsystems at runtime, e.g.
+/// `log-level mibpf::vm::worker debug`. Without arguments, it lists the
+/// available targets together with their current levels.
+pub fn handle_command(stdio: &mut riot_wrappers::stdio::Stdio, args: riot_wrappers::shell::Args) {
+    if args.len() == 1 {
+        for target in targets::ALL {
+            writeln!(stdio, "{:<20} {}", target, logger::target_level(target)).unwrap();
+        }
+        return;
+    }
+
+    if args.len() != 3 {
+        writeln!(stdio, "usage: {} [<target> <off|error|warn|info|debug|trace>]", &args[0]).unwrap();
+        return;
+    }
+
+    let Ok(level) = LevelFilter::from_str(&args[2]) else {
+        writeln!(stdio, "Invalid log level: {}", &args[2]).unwrap();
+        return;
+    };
+    match logger::set_target_level(&args[1], level) {
+        Ok(()) => writeln!(stdio, "Log level of {} set to {}", &args[1], level).unwrap(),
+        Err(e) => writeln!(stdio, "{}", e).unwrap(),
+    }
+}
@@ -1,5 +1,7 @@
 mod shell;
 mod bpf_command;
 mod gpio_command;
+mod helpers_command;
+mod log_command;
 pub use shell::shell_main;
 
@@ -6,7 +6,7 @@ use riot_wrappers::shell::CommandList;
 use riot_wrappers::cstr::cstr;
 
 use crate::model::requests::VMExecutionRequestIPC;
-use crate::shell::{bpf_command, gpio_command};
+use crate::shell::{bpf_command, gpio_command, helpers_command, log_command};
 use crate::vm::VM_EXEC_REQUEST;
 
 pub fn shell_main(
@@ -42,6 +42,18 @@ pub fn shell_main(
         },
     );
 
+    let commands = trait_identity(commands).and(
+        cstr!("bpf-helpers"),
+        cstr!("List available helper functions and their IDs"),
+        helpers_command::handle_command,
+    );
+
+    let commands = trait_identity(commands).and(
+        cstr!("log-level"),
+        cstr!("Show or change the log levels of the subsystems"),
+        log_command::handle_command,
+    );
+
     trait_identity(commands).run_forever_with_buf(&mut line_buf);
 }
 
@@ -0,0 +1,67 @@
+//! Helpers for writing JSON responses by hand. The responses are small and
+//! their structure is fixed, so they are formatted directly instead of
+//! pulling a serializer into the firmware. Strings which come from outside
+//! of the server (e.g. error messages or program metadata) need to be escaped
+//! before they are placed into a JSON string.
+
+use alloc::{format, string::String};
+
+/// Escapes the string so that it can be placed between the quotes of a JSON
+/// string.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        push_escaped(&mut escaped, c);
+    }
+    escaped
+}
+
+/// Escapes the string like [`escape`], but stops before the escaped string
+/// would exceed `max_len` bytes. Escape sequences are never cut in half.
+pub fn escape_truncated(s: &str, max_len: usize) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        let len = escaped.len();
+        push_escaped(&mut escaped, c);
+        if escaped.len() > max_len {
+            escaped.truncate(len);
+            break;
+        }
+    }
+    escaped
+}
+
+fn push_escaped(escaped: &mut String, c: char) {
+    match c {
+        '"' => escaped.push_str("\\\""),
+        '\\' => escaped.push_str("\\\\"),
+        '\n' => escaped.push_str("\\n"),
+        '\r' => escaped.push_str("\\r"),
+        '\t' => escaped.push_str("\\t"),
+        c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+        c => escaped.push(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_characters_are_escaped() {
+        assert_eq!(escape("plain text"), "plain text");
+        assert_eq!(escape("a \"b\" \\ c"), "a \\\"b\\\" \\\\ c");
+        assert_eq!(escape("line\nnext\ttab\r"), "line\\nnext\\ttab\\r");
+        assert_eq!(escape("\u{0}\u{1f}"), "\\u0000\\u001f");
+        assert_eq!(escape("µBPF"), "µBPF");
+    }
+
+    #[test]
+    fn escape_sequences_are_not_cut() {
+        assert_eq!(escape_truncated("abc", 10), "abc");
+        assert_eq!(escape_truncated("abcdef", 4), "abcd");
+        assert_eq!(escape_truncated("ab\"c", 3), "ab");
+        assert_eq!(escape_truncated("ab\"c", 4), "ab\\\"");
+        assert_eq!(escape_truncated("aµ", 2), "a");
+    }
+}
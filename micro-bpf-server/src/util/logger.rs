@@ -1,7 +1,29 @@
+use alloc::{collections::BTreeMap, format, string::String};
 use log::{
     debug, info, set_logger, set_max_level, LevelFilter, Log, Metadata, Record, SetLoggerError,
 };
-use riot_wrappers::{println, thread::CountedThread};
+use riot_wrappers::{mutex::Mutex, println, thread::CountedThread};
+
+/// Log targets of the subsystems of the server. Passing them to the logging
+/// macros (e.g. `debug!(target: targets::WORKER, ...)`) allows for changing
+/// the log level of each subsystem separately at runtime, see
+/// [`set_target_level`]. Targets are hierarchical, so setting the level of
+/// `mibpf::vm` also applies to `mibpf::vm::worker`.
+pub mod targets {
+    pub const VM: &str = "mibpf::vm";
+    pub const WORKER: &str = "mibpf::vm::worker";
+    pub const HELPERS: &str = "mibpf::vm::helpers";
+    pub const JIT: &str = "mibpf::jit";
+    pub const RELOC: &str = "mibpf::reloc";
+    pub const STORAGE: &str = "mibpf::storage";
+    pub const COAP: &str = "mibpf::coap";
+
+    pub const ALL: &[&str] = &[VM, WORKER, HELPERS, JIT, RELOC, STORAGE, COAP];
+}
+
+/// Levels of the targets which were changed at runtime, all other targets
+/// use the level that the logger was initialised with.
+static TARGET_LEVELS: Mutex<BTreeMap<&'static str, LevelFilter>> = Mutex::new(BTreeMap::new());
 
 /* Because we are running under no_std, we cannot use the set_boxed_logger
 function to tell the log crate which logger to use. Because of this, we
@@ -21,16 +43,19 @@ pub struct RiotLogger {
     level: LevelFilter,
 }
 
-pub fn initialise_logger() {
-    let log_level = env!("LOG_LEVEL");
-    let log_level = match log_level {
+/// The log level specified at compile time using the `LOG_LEVEL` variable.
+fn default_level() -> LevelFilter {
+    match env!("LOG_LEVEL") {
         "LOG_TRACE" => log::LevelFilter::Trace,
         "LOG_DEBUG" => log::LevelFilter::Debug,
         "LOG_WARNING" => log::LevelFilter::Warn,
         "LOG_ERROR" => log::LevelFilter::Error,
         _ => log::LevelFilter::Info,
-    };
-    if let Ok(()) = RiotLogger::init(log_level) {
+    }
+}
+
+pub fn initialise_logger() {
+    if let Ok(()) = RiotLogger::init(default_level()) {
         info!("Logger initialised");
     } else {
         println!("Failed to initialise logger");
@@ -54,9 +79,46 @@ impl RiotLogger {
         set_logger(logger)
     }
 }
+/// Sets the log level of one of the [`targets`] (and its sub-targets),
+/// overriding the level that the logger was initialised with.
+pub fn set_target_level(target: &str, level: LevelFilter) -> Result<(), String> {
+    let Some(target) = targets::ALL.iter().find(|t| **t == target) else {
+        Err(format!("Unknown log target: {}", target))?
+    };
+    let mut levels = TARGET_LEVELS.lock();
+    levels.insert(target, level);
+    // The logging macros skip all records above the global maximum level
+    // before they reach the logger, so it needs to allow the most verbose
+    // of the configured levels.
+    let max_level = levels.values().copied().fold(default_level(), LevelFilter::max);
+    set_max_level(max_level);
+    Ok(())
+}
+
+/// Returns the current log level of the target.
+pub fn target_level(target: &str) -> LevelFilter {
+    level_override(&TARGET_LEVELS.lock(), target).unwrap_or_else(default_level)
+}
+
+/// Finds the level of the most specific target that the given target is
+/// equal to or nested in.
+fn level_override(
+    levels: &BTreeMap<&'static str, LevelFilter>,
+    target: &str,
+) -> Option<LevelFilter> {
+    levels
+        .iter()
+        .filter(|(t, _)| {
+            target.strip_prefix(**t).map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|(t, _)| t.len())
+        .map(|(_, level)| *level)
+}
+
 impl Log for RiotLogger {
     fn enabled(&self, metadata: &Metadata<'_>) -> bool {
-        metadata.level() <= self.level
+        let level = level_override(&TARGET_LEVELS.lock(), metadata.target()).unwrap_or(self.level);
+        metadata.level() <= level
     }
 
     fn log(&self, record: &Record<'_>) {
@@ -1,3 +1,4 @@
 pub mod logger;
 pub mod macros;
 pub mod hacks;
+pub mod json;
@@ -0,0 +1,30 @@
+//! Readings of the ztimer clocks used to measure the executions.
+//!
+//! The 32-bit ztimer counters wrap around (the microsecond one after ~71
+//! minutes), so the time elapsed since a reading is computed using wrapping
+//! arithmetic, which stays correct as long as the interval itself fits into
+//! the counter.
+
+/// Current time of the microsecond clock.
+pub fn now_us() -> u32 {
+    unsafe {
+        riot_sys::inline::ztimer_now(riot_sys::ZTIMER_USEC as *mut riot_sys::inline::ztimer_clock_t)
+    }
+}
+
+/// Current time of the millisecond clock.
+pub fn now_ms() -> u32 {
+    unsafe {
+        riot_sys::inline::ztimer_now(riot_sys::ZTIMER_MSEC as *mut riot_sys::inline::ztimer_clock_t)
+    }
+}
+
+/// Microseconds elapsed since the `start` reading of [`now_us`].
+pub fn since_us(start: u32) -> u32 {
+    now_us().wrapping_sub(start)
+}
+
+/// Milliseconds elapsed since the `start` reading of [`now_ms`].
+pub fn since_ms(start: u32) -> u32 {
+    now_ms().wrapping_sub(start)
+}
@@ -0,0 +1,13 @@
+//! Start time of the current execution.
+//!
+//! The VMs record the time at which they start executing a program in the
+//! state of the execution (see [`super::execution_state`]), so that the
+//! execution time charged to the client quota (see [`super::ExecutionTimer`])
+//! doesn't include loading and verifying the program.
+
+use super::{clock, execution_state};
+
+/// Records that the current thread starts executing its program.
+pub fn start() {
+    execution_state::with_current(|state| state.started_at_us = clock::now_us());
+}
@@ -0,0 +1,40 @@
+//! State of the program executions tracked for each thread.
+//!
+//! The execution time charged to the client quotas (see
+//! [`super::ExecutionTimer`]) is measured for each execution. The VM workers
+//! and the CoAP handlers execute programs synchronously on their own threads,
+//! so the state is kept for each thread: it is created when the execution
+//! starts and removed once it finishes. Executions that aren't tracked (e.g.
+//! benchmarks) have no state.
+
+use alloc::collections::BTreeMap;
+use riot_wrappers::{mutex::Mutex, thread};
+
+#[derive(Debug)]
+pub struct ExecutionState {
+    /// Time (in ztimer usec ticks) at which the VM started executing the program.
+    pub started_at_us: u32,
+}
+
+static EXECUTION_STATES: Mutex<BTreeMap<riot_sys::kernel_pid_t, ExecutionState>> =
+    Mutex::new(BTreeMap::new());
+
+/// Starts tracking the execution of the current thread, replacing the state
+/// of its previous execution if it wasn't finished.
+pub fn start(state: ExecutionState) {
+    let pid = thread::get_pid().into();
+    EXECUTION_STATES.lock().insert(pid, state);
+}
+
+/// Stops tracking the execution of the current thread and returns its state.
+pub fn finish() -> Option<ExecutionState> {
+    let pid = thread::get_pid().into();
+    EXECUTION_STATES.lock().remove(&pid)
+}
+
+/// Applies `f` to the state of the execution of the current thread. Returns
+/// `None` if the thread isn't executing a tracked program.
+pub fn with_current<R>(f: impl FnOnce(&mut ExecutionState) -> R) -> Option<R> {
+    let pid = thread::get_pid().into();
+    EXECUTION_STATES.lock().get_mut(&pid).map(f)
+}
@@ -4,7 +4,13 @@ use alloc::{format, string::String};
 use log::debug;
 use riot_wrappers::{gcoap::PacketBuffer, println};
 
-use crate::{infra::suit_storage, vm::VirtualMachine};
+use micro_bpf_common::BinaryFileLayout;
+
+use crate::util::logger::targets;
+use crate::{
+    infra::{program_analysis, suit_storage},
+    vm::{middleware::helpers, VirtualMachine},
+};
 
 pub struct FemtoContainerVm<'a> {
     program: Option<&'a [u8]>,
@@ -25,6 +31,14 @@ impl<'a> VirtualMachine for FemtoContainerVm<'a> {
         let Some(program) = self.program else {
             Err("VM not initialised")?
         };
+        // The helpers of this VM are registered by the C implementation, so
+        // the strict sandbox is enforced by rejecting the helper calls here.
+        if helpers::STRICT_SANDBOX {
+            program_analysis::check_no_helper_calls(
+                program,
+                BinaryFileLayout::FemtoContainersHeader,
+            )?;
+        }
         let return_code = unsafe { verify_fc_program(program.as_ptr(), program.len()) };
 
         if return_code != 0 {
@@ -47,7 +61,7 @@ impl<'a> VirtualMachine for FemtoContainerVm<'a> {
     }
 
     fn execute(&mut self) -> Result<u64, String> {
-        debug!("Starting FemtoContainer VM execution.");
+        debug!(target: targets::VM, "Starting FemtoContainer VM execution.");
         let Some(program) = self.program else {
             Err("VM not initialised")?
         };
@@ -66,7 +80,7 @@ impl<'a> VirtualMachine for FemtoContainerVm<'a> {
     }
 
     fn execute_on_coap_pkt(&mut self, pkt: &mut PacketBuffer) -> Result<u64, String> {
-        debug!("Starting FemtoContainer VM execution.");
+        debug!(target: targets::VM, "Starting FemtoContainer VM execution.");
         let Some(program) = self.program else {
             Err("VM not initialised")?
         };
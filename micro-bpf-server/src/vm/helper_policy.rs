@@ -0,0 +1,77 @@
+//! Operator-level limit on the helpers available to the executed programs.
+//!
+//! In locked-down deployments, the operator can set a policy (using the
+//! `/config/helper-policy` endpoint) listing the helpers that the programs may
+//! call at most. The allowed helpers of every program are then clamped to the
+//! policy when the VM is created (see [`clamp`]), regardless of what the
+//! request asks for and of the endpoint that executes it, so even a request
+//! allowing all helpers only gets the ones in the policy. The helpers outside
+//! of the policy aren't registered with the VM, so the programs calling them
+//! fail the pre-flight verification (or the call fails at runtime if the
+//! helper access isn't verified pre-flight).
+//!
+//! Programs which read their allowed helpers from the binary metadata are
+//! clamped the same way. The helpers of the FemtoContainer VM are fixed by its
+//! runtime and can't be clamped, so it refuses to execute programs while a
+//! policy is set. Without a policy (the default), the programs are executed
+//! unchanged.
+
+use alloc::{format, string::String, vec::Vec};
+use log::warn;
+use micro_bpf_common::HelperFunctionID;
+use riot_wrappers::mutex::Mutex;
+
+use crate::{infra::result_cache, util::logger::targets};
+
+/// Helpers that the programs may call at most, `None` if no policy is set.
+static HELPER_POLICY: Mutex<Option<Vec<HelperFunctionID>>> = Mutex::new(None);
+
+/// Replaces the policy, the change applies to the executions started after it.
+/// The cached results are dropped, as they could have been produced using the
+/// helpers that the new policy forbids.
+pub fn set_policy(helpers: Vec<HelperFunctionID>) {
+    *HELPER_POLICY.lock() = Some(helpers);
+    result_cache::clear();
+}
+
+/// Removes the policy, the requests are executed unchanged afterwards.
+pub fn clear_policy() {
+    *HELPER_POLICY.lock() = None;
+    result_cache::clear();
+}
+
+pub fn is_set() -> bool {
+    HELPER_POLICY.lock().is_some()
+}
+
+pub fn policy() -> Option<Vec<HelperFunctionID>> {
+    HELPER_POLICY.lock().clone()
+}
+
+/// Formats the helpers the same way as the helper lists are encoded in the
+/// requests, i.e. as a hex string with two characters per helper ID.
+pub fn encode_helpers(helpers: &[HelperFunctionID]) -> String {
+    helpers.iter().map(|id| format!("{:02x}", *id as u8)).collect()
+}
+
+/// Clamps the helpers that the program in the given slot is allowed to call
+/// to the policy. It is applied by the rBPF VMs (both the interpreter and the
+/// JIT) when they are created and to the lists read from the binary metadata,
+/// so that it covers every endpoint executing the programs.
+pub fn clamp(helpers: Vec<HelperFunctionID>, suit_slot: usize) -> Vec<HelperFunctionID> {
+    let Some(policy) = policy() else {
+        return helpers;
+    };
+
+    let (allowed, forbidden): (Vec<_>, Vec<_>) =
+        helpers.into_iter().partition(|id| policy.contains(id));
+    if !forbidden.is_empty() {
+        warn!(
+            target: targets::VM,
+            "Helpers {} of the program in slot {} aren't allowed by the policy",
+            encode_helpers(&forbidden),
+            suit_slot
+        );
+    }
+    allowed
+}
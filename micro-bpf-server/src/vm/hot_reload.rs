@@ -0,0 +1,76 @@
+//! Hot-swapping of long-running programs.
+//!
+//! The worker executing a long-running program can't interrupt it, so the
+//! swap is cooperative:
+//!
+//! 1. The new version of the program is deployed into a free (staging) slot,
+//!    the slot of the running program can't be overwritten while it executes.
+//! 2. A reload is requested for the running slot using the
+//!    `/execute/reload/<slot>` endpoint, specifying the staging slot.
+//! 3. The running program returns on its own, there is no way of signalling
+//!    the request to it.
+//! 4. The worker verifies the program in the staging slot. If it passes, the
+//!    local storage of the old slot is moved over to the staging slot and the
+//!    new program is started in place of the old one. If the verification
+//!    fails, the reload is discarded and the old program is started again,
+//!    its local storage is left untouched.
+
+use alloc::{format, string::String, vec::Vec};
+use micro_bpf_common::{ExecutionModel, HelperFunctionID, VMConfiguration};
+use riot_wrappers::mutex::Mutex;
+
+use crate::infra::{
+    local_storage,
+    suit_storage::{self, SuitStorageSlotStatus, SUIT_STORAGE_SLOTS},
+};
+
+use super::construct_vm;
+
+/// For each running slot, the staging slot holding the program that should
+/// replace it.
+static PENDING_RELOADS: Mutex<[Option<usize>; SUIT_STORAGE_SLOTS]> =
+    Mutex::new([None; SUIT_STORAGE_SLOTS]);
+
+/// Requests replacing the program running in `slot` with the one loaded
+/// into `staging_slot`. The swap happens once the running program returns.
+pub fn request_reload(slot: usize, staging_slot: usize) -> Result<(), String> {
+    if !suit_storage::is_suit_slot(slot) || !suit_storage::is_suit_slot(staging_slot) {
+        Err("Invalid SUIT storage slot")?;
+    }
+    if slot == staging_slot {
+        Err("The new program needs to be loaded into a different slot")?;
+    }
+    if suit_storage::suit_slot_status(slot) != SuitStorageSlotStatus::Running {
+        Err(format!("No program is running in slot {}", slot))?;
+    }
+    if suit_storage::suit_slot_status(staging_slot) != SuitStorageSlotStatus::Occupied {
+        Err(format!("Slot {} doesn't contain a deployed program", staging_slot))?;
+    }
+    PENDING_RELOADS.lock()[slot] = Some(staging_slot);
+    Ok(())
+}
+
+/// Removes the pending reload request for the slot and returns the staging slot.
+pub fn take_pending_reload(slot: usize) -> Option<usize> {
+    PENDING_RELOADS.lock()[slot].take()
+}
+
+/// Verifies the program in the staging slot using the configuration of the
+/// program that it replaces. If it passes, the local storage is moved over
+/// and the configuration for executing the new program is returned.
+pub fn prepare_reload(
+    configuration: VMConfiguration,
+    allowed_helpers: &Vec<HelperFunctionID>,
+    staging_slot: usize,
+) -> Result<VMConfiguration, String> {
+    let mut new_configuration = configuration;
+    new_configuration.suit_slot = staging_slot;
+
+    let mut vm = construct_vm(new_configuration, allowed_helpers.clone())?;
+    vm.set_execution_model(ExecutionModel::LongRunning);
+    vm.initialize_vm()?;
+    vm.verify()?;
+
+    local_storage::move_slot_storage(configuration.suit_slot, staging_slot);
+    Ok(new_configuration)
+}
@@ -2,8 +2,12 @@ use core::num::ParseIntError;
 
 use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
 
-use super::ALL_HELPERS;
-use micro_bpf_common::HelperFunctionID;
+use log::debug;
+use macros::set_env_or_default;
+use micro_bpf_common::{ExecutionModel, HelperFunctionID};
+
+use super::{ALL_HELPERS, HELPER_DESCRIPTIONS};
+use crate::util::logger::targets;
 
 #[derive(Copy, Clone)]
 pub struct HelperFunction {
@@ -15,7 +19,8 @@ pub struct HelperFunction {
     /// arguments and the eBPF calling convention works by putting all arguments
     /// to the function into registers r1 - r5. One thing is that the helper functions
     /// can access all of those 5 registers even if the function doesn't actually
-    /// take in all 5 arguments.
+    /// take in all 5 arguments. The arguments and the return value are full
+    /// 64-bit register values, see the conventions in `riot_middleware`.
     pub function: fn(u64, u64, u64, u64, u64) -> u64,
 }
 
@@ -25,18 +30,108 @@ impl HelperFunction {
     }
 }
 
+/// Human-readable information about a helper function, used for listing the
+/// available helpers in the shell.
+#[derive(Copy, Clone)]
+pub struct HelperDescription {
+    pub id: HelperFunctionID,
+    /// Name of the helper as declared in the C header file.
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Whether the helper modifies the program memory, the global state of the
+    /// OS or the state of the peripherals.
+    pub mutating: bool,
+    /// Whether the value returned by the helper (and the memory it writes)
+    /// only depends on its arguments and the memory they point to. Helpers
+    /// reading clocks, sensors, peripherals or the state of the OS aren't
+    /// deterministic, even if they don't modify anything. Neither are the ones
+    /// writing to the console, as their output would be lost when the result
+    /// of the program is served from the cache.
+    pub deterministic: bool,
+}
+
+impl HelperDescription {
+    pub const fn new(
+        id: HelperFunctionID,
+        name: &'static str,
+        description: &'static str,
+        mutating: bool,
+        deterministic: bool,
+    ) -> Self {
+        HelperDescription {
+            id,
+            name,
+            description,
+            mutating,
+            deterministic,
+        }
+    }
+}
+
+/// Returns true if the helper doesn't modify any state (see
+/// [`HelperDescription::mutating`]). Helpers without a description are
+/// treated as mutating.
+pub fn is_read_only(id: HelperFunctionID) -> bool {
+    HELPER_DESCRIPTIONS
+        .iter()
+        .find(|d| d.id == id)
+        .map_or(false, |d| !d.mutating)
+}
+
+/// Returns true if the helper doesn't modify any state and its result only
+/// depends on its arguments (see [`HelperDescription::deterministic`]).
+/// Helpers without a description are treated as non-deterministic.
+pub fn is_deterministic_read_only(id: HelperFunctionID) -> bool {
+    HELPER_DESCRIPTIONS
+        .iter()
+        .find(|d| d.id == id)
+        .map_or(false, |d| !d.mutating && d.deterministic)
+}
+
+/// Helper IDs are encoded as a single byte (both in the requests and in the
+/// call instructions), so this is the maximum number of helpers that can be
+/// registered with the VM.
+pub const MAX_HELPERS: usize = u8::MAX as usize + 1;
+
 pub struct HelperAccessList(pub Vec<HelperFunction>);
 
-impl From<String> for HelperAccessList {
-    fn from(value: String) -> Self {
-        let allowed_helpers_ids = (0..value.len())
+impl HelperAccessList {
+    /// Maximum number of distinct helpers that an access list can refer to.
+    pub fn capacity() -> usize {
+        MAX_HELPERS
+    }
+
+    /// Number of helpers that are compiled in for the target board.
+    pub fn registered_count() -> usize {
+        ALL_HELPERS.len()
+    }
+
+    /// Parses a list of helper IDs encoded as a hex string (two characters per
+    /// ID). Unlike the `From` conversions, it fails if any of the IDs doesn't
+    /// refer to a helper that is registered on the device instead of
+    /// silently skipping it.
+    pub fn try_from_hex(value: &str) -> Result<Self, String> {
+        if value.len() % 2 != 0 || !value.is_ascii() {
+            Err(format!("Invalid helper list encoding: {}", value))?;
+        }
+        let ids = (0..value.len())
             .step_by(2)
             .map(|i| u8::from_str_radix(&value[i..i + 2], 16))
             .collect::<Result<Vec<u8>, ParseIntError>>()
-            .map_err(|e| format!("Unable to parse: {}", e))
-            .unwrap();
+            .map_err(|e| format!("Unable to parse the helper list: {}", e))?;
 
-        HelperAccessList::from(allowed_helpers_ids)
+        let mut helpers = Vec::with_capacity(ids.len());
+        for id in ids {
+            let helper = ALL_HELPERS.iter().find(|h| h.id as u8 == id).ok_or_else(|| {
+                format!(
+                    "Helper ID {:#04x} is not registered ({} helpers available)",
+                    id,
+                    Self::registered_count()
+                )
+            })?;
+            helpers.push(*helper);
+        }
+        Ok(HelperAccessList(helpers))
     }
 }
 
@@ -59,9 +154,11 @@ impl From<Vec<HelperFunctionID>> for HelperAccessList {
             .map(|h| (h.id, h.clone()))
             .collect::<BTreeMap<HelperFunctionID, HelperFunction>>();
 
+        // Helpers that weren't compiled in for the target board are skipped,
+        // the verifier then rejects programs which try to call them.
         let helpers = value
             .iter()
-            .map(|v| helper_map.get(v).unwrap().clone())
+            .filter_map(|v| helper_map.get(v).cloned())
             .collect::<Vec<HelperFunction>>();
         HelperAccessList(helpers)
     }
@@ -113,13 +210,65 @@ impl AcceptingHelpers for rbpf::EbpfVmMbuff<'_> {
 #[allow(dead_code)]
 pub fn register_all(vm: &mut impl AcceptingHelpers) {
     for helper in ALL_HELPERS {
-        vm.register_helper(helper);
+        vm.register_helper(*helper);
     }
 }
 
-#[allow(dead_code)]
-pub fn register_helpers(vm: &mut impl AcceptingHelpers, helpers: Vec<HelperFunction>) {
+/// Helpers which only make sense in some of the execution models, all other
+/// helpers are available in every model. The CoAP packet helpers operate on
+/// the packet passed in r1, which the other models don't provide.
+const MODEL_SPECIFIC_HELPERS: &[(HelperFunctionID, &[ExecutionModel])] = &[
+    (HelperFunctionID::BPF_GCOAP_RESP_INIT_IDX, &[ExecutionModel::WithAccessToCoapPacket]),
+    (HelperFunctionID::BPF_COAP_OPT_FINISH_IDX, &[ExecutionModel::WithAccessToCoapPacket]),
+    (HelperFunctionID::BPF_COAP_ADD_FORMAT_IDX, &[ExecutionModel::WithAccessToCoapPacket]),
+    (HelperFunctionID::BPF_COAP_GET_PDU_IDX, &[ExecutionModel::WithAccessToCoapPacket]),
+];
+
+/// Forbids all helper calls, intended for running maximally untrusted
+/// programs. Unlike allowing only the read-only helpers, no helpers are
+/// registered regardless of the allow-list of the request, and the programs
+/// containing any helper call are rejected by the verifier even if the helper
+/// access isn't verified pre-flight. It is enabled by setting
+/// `STRICT_SANDBOX=1` at compile time.
+pub const STRICT_SANDBOX: bool = set_env_or_default!("STRICT_SANDBOX", 0) != 0;
+
+/// Returns true if the helper can be called by programs executed in the given
+/// model, see [`MODEL_SPECIFIC_HELPERS`]. If the model isn't known (e.g. when
+/// benchmarking), all helpers are available. No helpers are available in the
+/// strict sandbox, see [`STRICT_SANDBOX`].
+pub fn is_available_in(id: HelperFunctionID, model: Option<ExecutionModel>) -> bool {
+    if STRICT_SANDBOX {
+        return false;
+    }
+    let Some(model) = model else {
+        return true;
+    };
+    MODEL_SPECIFIC_HELPERS
+        .iter()
+        .find(|(helper, _)| *helper == id)
+        .map_or(true, |(_, models)| models.contains(&model))
+}
+
+/// Registers the helpers with the VM, skipping the ones which aren't
+/// available in the execution model of the program. The programs calling them
+/// are then rejected by the verifier (when the helper access is verified
+/// pre-flight) or fail when the call is reached.
+pub fn register_helpers(
+    vm: &mut impl AcceptingHelpers,
+    helpers: Vec<HelperFunction>,
+    model: Option<ExecutionModel>,
+) {
     for helper in helpers {
+        if !is_available_in(helper.id, model) {
+            debug!(
+                target: targets::HELPERS,
+                "Helper {:?} isn't available in the {:?} model{}",
+                helper.id,
+                model,
+                if STRICT_SANDBOX { " (strict sandbox)" } else { "" }
+            );
+            continue;
+        }
         vm.register_helper(helper);
     }
 }
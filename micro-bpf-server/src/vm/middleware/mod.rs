@@ -1,4 +1,5 @@
 pub mod riot_middleware;
 pub mod helpers;
+pub mod safe_helpers;
 
 pub use riot_middleware::*;
@@ -5,27 +5,49 @@
 // The prototype for helpers follows the convention used by rBpF: five `u64` as arguments, and a
 // `u64` as a return value. Hence some helpers have unused arguments, or return a 0 value in all
 // cases, in order to respect this convention.
+//
+// Arguments and return values use the full width of the eBPF registers. Helpers should only
+// narrow an argument where the underlying RIOT API takes a smaller type (and the C declaration
+// in helpers.h should then use that type as well), and should return values through `as u64`
+// casts of the original type, so that negative error codes are sign-extended and 64-bit values
+// aren't truncated.
 
+use alloc::format;
 use core::ffi::{c_char, CStr};
 
 use log::debug;
+#[cfg(feature = "gpio")]
 use riot_wrappers::gpio;
 use riot_wrappers::stdio::println;
 
-use crate::{
-    infra::local_storage::{self, local_storage_store},
-    peripherals::{hd44780_lcd::{hd44780_t, HD44780LCD}, keypad_shield_buttons::KeypadShieldButtons},
+use crate::infra::{
+    execution_log,
+    local_storage::{self, local_storage_store},
+};
+#[cfg(feature = "hd44780")]
+use crate::peripherals::hd44780_lcd::{hd44780_t, HD44780LCD};
+#[cfg(feature = "keypad")]
+use crate::peripherals::keypad_shield_buttons::KeypadShieldButtons;
+use crate::util::logger::targets;
+
+use super::{
+    helpers::{HelperDescription, HelperFunction},
+    safe_helpers::{HelperMemory, SafeHelper, MAX_HELPER_REGION_SIZE},
 };
-
-use super::helpers::HelperFunction;
 use micro_bpf_common::HelperFunctionID as ID;
 
 // Alias the type to make the table below more concise
 type HF = HelperFunction;
+type HD = HelperDescription;
 
 /// List of all helpers together with their corresponding numbers (used
 /// directly as function pointers in the compiled eBPF bytecode).
-pub const ALL_HELPERS: [HelperFunction; 30] = [
+///
+/// Helpers that depend on board peripherals are grouped behind cargo features
+/// (`saul`, `gpio`, `hd44780`, `keypad`) so that boards lacking a given
+/// peripheral can be built without them. The IDs come from [`ID`], so they
+/// stay the same regardless of which helpers are compiled in.
+pub const ALL_HELPERS: &[HelperFunction] = &[
     HF::new(ID::BPF_DEBUG_PRINT_IDX, bpf_print_debug),
     HF::new(ID::BPF_PRINTF_IDX, bpf_printf),
     HF::new(ID::BPF_STORE_LOCAL_IDX, bpf_store_local),
@@ -36,10 +58,15 @@ pub const ALL_HELPERS: [HelperFunction; 30] = [
     HF::new(ID::BPF_NOW_MS_IDX, bpf_now_ms),
     HF::new(ID::BPF_ZTIMER_NOW_IDX, bpf_ztimer_now),
     HF::new(ID::BPF_PERIODIC_WAKEUP_IDX, bpf_periodic_wakeup),
+    #[cfg(feature = "saul")]
     HF::new(ID::BPF_SAUL_REG_FIND_NTH_IDX, bpf_saul_reg_find_nth),
+    #[cfg(feature = "saul")]
     HF::new(ID::BPF_SAUL_REG_FIND_TYPE_IDX, bpf_saul_reg_find_type),
+    #[cfg(feature = "saul")]
     HF::new(ID::BPF_SAUL_REG_WRITE_IDX, bpf_saul_reg_write),
+    #[cfg(feature = "saul")]
     HF::new(ID::BPF_SAUL_REG_READ_IDX, bpf_saul_reg_read),
+    #[cfg(feature = "saul")]
     HF::new(ID::BPF_SAUL_REG_READ_TEMP, bpf_saul_read_temp),
     HF::new(ID::BPF_GCOAP_RESP_INIT_IDX, bpf_gcoap_resp_init),
     HF::new(ID::BPF_COAP_OPT_FINISH_IDX, bpf_coap_opt_finish),
@@ -48,34 +75,98 @@ pub const ALL_HELPERS: [HelperFunction; 30] = [
     HF::new(ID::BPF_STRLEN_IDX, bpf_strlen),
     HF::new(ID::BPF_FMT_S16_DFP_IDX, bpf_fmt_s16_dfp),
     HF::new(ID::BPF_FMT_U32_DEC_IDX, bpf_fmt_u32_dec),
+    #[cfg(feature = "gpio")]
     HF::new(ID::BPF_GPIO_READ_INPUT, bpf_gpio_read_input),
+    #[cfg(feature = "gpio")]
     HF::new(ID::BPF_GPIO_READ_RAW, bpf_gpio_read_raw),
+    #[cfg(feature = "gpio")]
     HF::new(ID::BPF_GPIO_WRITE, bpf_gpio_write),
+    #[cfg(feature = "hd44780")]
     HF::new(ID::BPF_HD44780_INIT, bpf_hd44780_init),
+    #[cfg(feature = "hd44780")]
     HF::new(ID::BPF_HD44780_CLEAR, bpf_hd44780_clear),
+    #[cfg(feature = "hd44780")]
     HF::new(ID::BPF_HD44780_PRINT, bpf_hd44780_print),
+    #[cfg(feature = "hd44780")]
     HF::new(ID::BPF_HD44780_SET_CURSOR, bpf_hd44780_set_cursor),
+    #[cfg(feature = "keypad")]
     HF::new(ID::BPF_KEYPAD_GET_INPUT, bpf_keypad_get_input),
 ];
 
+/// Descriptions of the helpers listed in [`ALL_HELPERS`], they are printed by
+/// the `bpf-helpers` shell command. When adding a new helper, remember to
+/// add its description here as well.
+#[rustfmt::skip]
+pub const HELPER_DESCRIPTIONS: &[HelperDescription] = &[
+    HD::new(ID::BPF_DEBUG_PRINT_IDX, "bpf_print_debug", "print a single value", false, false),
+    HD::new(ID::BPF_PRINTF_IDX, "bpf_printf", "printf to the console", false, false),
+    HD::new(ID::BPF_STORE_LOCAL_IDX, "bpf_store_local", "store in local k/v", true, false),
+    HD::new(ID::BPF_STORE_GLOBAL_IDX, "bpf_store_global", "store in global k/v", true, false),
+    HD::new(ID::BPF_FETCH_LOCAL_IDX, "bpf_fetch_local", "fetch from local k/v", true, false),
+    HD::new(ID::BPF_FETCH_GLOBAL_IDX, "bpf_fetch_global", "fetch from global k/v", true, false),
+    HD::new(ID::BPF_MEMCPY_IDX, "bpf_memcpy", "copy memory", true, true),
+    HD::new(ID::BPF_NOW_MS_IDX, "bpf_now_ms", "time in ms", false, false),
+    HD::new(ID::BPF_ZTIMER_NOW_IDX, "bpf_ztimer_now", "ztimer time in us", false, false),
+    HD::new(ID::BPF_PERIODIC_WAKEUP_IDX, "bpf_ztimer_periodic_wakeup", "sleep until next period", true, false),
+    HD::new(ID::BPF_SAUL_REG_FIND_NTH_IDX, "bpf_saul_reg_find_nth", "find SAUL dev by index", false, true),
+    HD::new(ID::BPF_SAUL_REG_FIND_TYPE_IDX, "bpf_saul_reg_find_type", "find SAUL dev by type", false, true),
+    HD::new(ID::BPF_SAUL_REG_WRITE_IDX, "bpf_saul_reg_write", "write to SAUL dev", true, false),
+    HD::new(ID::BPF_SAUL_REG_READ_IDX, "bpf_saul_reg_read", "read from SAUL dev", true, false),
+    HD::new(ID::BPF_SAUL_REG_READ_TEMP, "bpf_saul_read_temp", "read temperature", true, false),
+    HD::new(ID::BPF_GCOAP_RESP_INIT_IDX, "bpf_gcoap_resp_init", "init CoAP response", true, false),
+    HD::new(ID::BPF_COAP_OPT_FINISH_IDX, "bpf_coap_opt_finish", "finish CoAP options", true, false),
+    HD::new(ID::BPF_COAP_ADD_FORMAT_IDX, "bpf_coap_add_format", "add CoAP content format", true, false),
+    HD::new(ID::BPF_COAP_GET_PDU_IDX, "bpf_coap_get_pdu", "unimplemented", false, true),
+    HD::new(ID::BPF_STRLEN_IDX, "bpf_strlen", "string length", false, true),
+    HD::new(ID::BPF_FMT_S16_DFP_IDX, "bpf_fmt_s16_dfp", "format s16 fixed point", true, true),
+    HD::new(ID::BPF_FMT_U32_DEC_IDX, "bpf_fmt_u32_dec", "format u32 decimal", true, true),
+    HD::new(ID::BPF_GPIO_READ_INPUT, "bpf_gpio_read_input", "read GPIO input", false, false),
+    HD::new(ID::BPF_GPIO_READ_RAW, "bpf_gpio_read_raw", "read raw GPIO state", false, false),
+    HD::new(ID::BPF_GPIO_WRITE, "bpf_gpio_write", "write GPIO pin", true, false),
+    HD::new(ID::BPF_HD44780_INIT, "bpf_hd44780_init", "init LCD display", true, false),
+    HD::new(ID::BPF_HD44780_CLEAR, "bpf_hd44780_clear", "clear LCD display", true, false),
+    HD::new(ID::BPF_HD44780_PRINT, "bpf_hd44780_print", "print on LCD display", true, false),
+    HD::new(ID::BPF_HD44780_SET_CURSOR, "bpf_hd44780_set_cursor", "set LCD cursor", true, false),
+    HD::new(ID::BPF_KEYPAD_GET_INPUT, "bpf_keypad_get_input", "read keypad button", false, false),
+];
+
 /* Print/debug helper functions - implementation */
 
 /// Allows for printing arbitrary text to the RIOT shell console output.
+/// Format strings which aren't terminated within [`MAX_HELPER_REGION_SIZE`]
+/// bytes are rejected and `u64::MAX` is returned. The arguments are passed to
+/// `printf` as they are, so `%s` conversions aren't checked.
 pub fn bpf_printf(fmt: u64, a1: u64, a2: u64, a3: u64, a4: u64) -> u64 {
     // We need to take in the format string dynamically, so format! or println!
     // won't work here. We need to call into C.
     extern "C" {
         fn printf(fmt: *const c_char, ...) -> i32;
+        fn snprintf(buf: *mut c_char, len: usize, fmt: *const c_char, ...) -> i32;
     }
+    // The output is also formatted into the execution log, so that it can be
+    // read remotely. Lines longer than the log allows are truncated there.
+    let mut line = [0u8; execution_log::MAX_LOG_LINE_LEN + 1];
+    let Some(fmt) = HelperMemory::new(fmt, MAX_HELPER_REGION_SIZE).filter(|f| f.c_str().is_some())
+    else {
+        return u64::MAX;
+    };
     unsafe {
-        printf(
-            CStr::from_ptr(fmt as *const i8).as_ptr() as *const c_char,
+        // The region contains the terminator, as checked above.
+        let fmt = fmt.mem().as_ptr() as *const c_char;
+        printf(fmt, a1 as u32, a2 as u32, a3 as u32, a4 as u32);
+        snprintf(
+            line.as_mut_ptr() as *mut c_char,
+            line.len(),
+            fmt,
             a1 as u32,
             a2 as u32,
             a3 as u32,
             a4 as u32,
         );
     }
+    if let Ok(text) = CStr::from_bytes_until_nul(&line).map(|l| l.to_string_lossy()) {
+        execution_log::record(&text);
+    }
     return 0;
 }
 
@@ -85,6 +176,7 @@ pub fn bpf_printf(fmt: u64, a1: u64, a2: u64, a3: u64, a4: u64) -> u64 {
 /// sections of the program.
 pub fn bpf_print_debug(a1: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     println!("[DEBUG]: {a1}");
+    execution_log::record(&format!("[DEBUG]: {a1}"));
     return 0;
 }
 
@@ -111,15 +203,16 @@ pub fn bpf_store_local(key: u64, value: u64, _a3: u64, _a4: u64, _a5: u64) -> u6
     local_storage::local_storage_store(key as usize, value as i32) as u64
 }
 pub fn bpf_fetch_local(key: u64, value: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let value = value as *mut i32;
-    unsafe {
-        *value = local_storage::local_storage_fetch(key as usize).unwrap_or(0);
-    }
+    let Some(mut value) = HelperMemory::for_value::<i32>(value) else {
+        return u64::MAX;
+    };
+    let fetched = local_storage::local_storage_fetch(key as usize).unwrap_or(0);
+    value.write_u32(0, fetched as u32);
     return 0;
 }
 
 pub fn bpf_store_global(key: u64, value: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    debug!("Storing key: {:#x}, value: {:#x}", key, value);
+    debug!(target: targets::HELPERS, "Storing key: {:#x}, value: {:#x}", key, value);
     //debug!("Arguments to the helper: {:#x}, {:#x}, {:#x}, {:#x}, {:#x}", key, value, _a3, _a4, _a5);
     // We need to truncate the values as for some reason the higher bits of the
     // registers that are passed in are still set.
@@ -127,28 +220,39 @@ pub fn bpf_store_global(key: u64, value: u64, _a3: u64, _a4: u64, _a5: u64) -> u
 }
 
 pub fn bpf_fetch_global(key: u64, value: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    debug!("Fetching key: {:#x}, value: {:#x}", key, value);
-    unsafe {
+    debug!(target: targets::HELPERS, "Fetching key: {:#x}, value: {:#x}", key, value);
+    let Some(mut value) = HelperMemory::for_value::<u32>(value) else {
+        return u64::MAX;
+    };
+    if let Some(current) = value.read_u32(0) {
         debug!(
+            target: targets::HELPERS,
             "Actual value in memory: {} ({:#x})",
-            *(value as *mut u32),
-            *(value as *mut u32)
+            current,
+            current
         );
     }
-    //debug!("Arguments to the helper: {:#x}, {:#x}, {:#x}, {:#x}, {:#x}", key, value, _a3, _a4, _a5);
-    unsafe { bpf_store_fetch_global(key as u32, value as *mut u32) as u64 }
+    unsafe { bpf_store_fetch_global(key as u32, value.mem_mut().as_mut_ptr() as *mut u32) as u64 }
 }
 
 /* Standard library functions */
 
+/// Copies `size` bytes from `src_p` to `dest_p` and returns the destination
+/// pointer (same as memcpy). Returns 0 if any of the regions is invalid.
 pub fn bpf_memcpy(dest_p: u64, src_p: u64, size: u64, _a4: u64, _a5: u64) -> u64 {
-    let dest: *mut riot_sys::libc::c_void = dest_p as *mut riot_sys::libc::c_void;
-    let src: *const riot_sys::libc::c_void = src_p as *const riot_sys::libc::c_void;
-    let size = size as u32;
-    debug!("Copying {} bytes from {:x} to {:x}", size, src_p, dest_p);
+    let size = size as u32 as usize;
+    debug!(target: targets::HELPERS, "Copying {} bytes from {:x} to {:x}", size, src_p, dest_p);
+    let (Some(mut dest), Some(src)) = (
+        HelperMemory::new(dest_p, size),
+        HelperMemory::new(src_p, size),
+    ) else {
+        return 0;
+    };
+    // The regions can overlap so we can't use copy_from_slice here.
     unsafe {
-        return riot_sys::memcpy(dest, src, size) as u64;
+        core::ptr::copy(src.mem().as_ptr(), dest.mem_mut().as_mut_ptr(), size);
     }
+    dest_p
 }
 
 /* Saul functions - implementation */
@@ -156,6 +260,7 @@ pub fn bpf_memcpy(dest_p: u64, src_p: u64, size: u64, _a4: u64, _a5: u64) -> u64
 /// Find a SAUL device by its position in the registry. It returns a pointer to
 /// the device which can then be used with reg_read / reg_write helpers to
 /// manipulate the device.
+#[cfg(feature = "saul")]
 pub fn bpf_saul_reg_find_nth(saul_dev_index: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     unsafe { return riot_sys::saul_reg_find_nth(saul_dev_index as i32) as u64 }
 }
@@ -163,15 +268,23 @@ pub fn bpf_saul_reg_find_nth(saul_dev_index: u64, _a2: u64, _a3: u64, _a4: u64,
 /// Find the first device of the given type. The saul_dev_type needs to match
 /// the list of all device classes is available here:
 /// https://api.riot-os.org/group__drivers__saul.html#:~:text=category%20ID.%20More...-,enum,-%7B%0A%C2%A0%C2%A0SAUL_ACT_ANY
+#[cfg(feature = "saul")]
 pub fn bpf_saul_reg_find_type(saul_dev_type: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     unsafe { return riot_sys::saul_reg_find_type(saul_dev_type as u8) as u64 }
 }
 
 /// Given a pointer to the SAUL device struct, it reads from the device into the
 /// provided phydat_t struct.
+#[cfg(feature = "saul")]
 pub fn bpf_saul_reg_read(dev_ptr: u64, data_ptr: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let dev: *mut riot_sys::saul_reg_t = dev_ptr as *mut riot_sys::saul_reg_t;
-    let data: *mut riot_sys::phydat_t = data_ptr as *mut riot_sys::phydat_t;
+    let (Some(dev), Some(mut data)) = (
+        HelperMemory::for_value::<riot_sys::saul_reg_t>(dev_ptr),
+        HelperMemory::for_value::<riot_sys::phydat_t>(data_ptr),
+    ) else {
+        return -(riot_sys::EINVAL as i64) as u64;
+    };
+    let dev = dev.mem().as_ptr() as *mut riot_sys::saul_reg_t;
+    let data = data.mem_mut().as_mut_ptr() as *mut riot_sys::phydat_t;
     //debug!("Reading from saul device at: {:x}", dev_ptr);
     //debug!("Reading into phydat at: {:x}", data_ptr);
     unsafe { riot_sys::saul_reg_read(dev, data) as u64 }
@@ -179,21 +292,33 @@ pub fn bpf_saul_reg_read(dev_ptr: u64, data_ptr: u64, _a3: u64, _a4: u64, _a5: u
 
 /// Given a pointer to the SAUL device struct, it reads the value 0 from the device into the
 /// provided integer.
+#[cfg(feature = "saul")]
 pub fn bpf_saul_read_temp(dev_ptr: u64, value_ptr: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let dev: *mut riot_sys::saul_reg_t = dev_ptr as *mut riot_sys::saul_reg_t;
+    let (Some(dev), Some(mut value)) = (
+        HelperMemory::for_value::<riot_sys::saul_reg_t>(dev_ptr),
+        HelperMemory::for_value::<u32>(value_ptr),
+    ) else {
+        return u64::MAX;
+    };
+    let dev = dev.mem().as_ptr() as *mut riot_sys::saul_reg_t;
     let mut reading: riot_sys::phydat_t = Default::default();
-    unsafe {
-        let result = riot_sys::saul_reg_read(dev, &mut reading as *mut riot_sys::phydat_t);
-        *(value_ptr as *mut u32) = reading.val[0] as u32;
-        result as u64
-    }
+    let result = unsafe { riot_sys::saul_reg_read(dev, &mut reading as *mut riot_sys::phydat_t) };
+    value.write_u32(0, reading.val[0] as u32);
+    result as u64
 }
 
 /// Given a pointer to the SAUL device struct, it writes the provided phydat_t
 /// struct (pointed to by data_ptr) into the device.
+#[cfg(feature = "saul")]
 pub fn bpf_saul_reg_write(dev_ptr: u64, data_ptr: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let dev: *mut riot_sys::saul_reg_t = dev_ptr as *mut riot_sys::saul_reg_t;
-    let data: *const riot_sys::phydat_t = data_ptr as *const riot_sys::phydat_t;
+    let (Some(dev), Some(data)) = (
+        HelperMemory::for_value::<riot_sys::saul_reg_t>(dev_ptr),
+        HelperMemory::for_value::<riot_sys::phydat_t>(data_ptr),
+    ) else {
+        return -(riot_sys::EINVAL as i64) as u64;
+    };
+    let dev = dev.mem().as_ptr() as *mut riot_sys::saul_reg_t;
+    let data = data.mem().as_ptr() as *const riot_sys::phydat_t;
     unsafe { riot_sys::saul_reg_write(dev, data) as u64 }
 }
 
@@ -204,24 +329,76 @@ pub struct CoapContext {
     pub len: usize,
 }
 
+/// Validates the pointer to the [`CoapContext`] that the program passed into
+/// a CoAP helper. Returns `None` if it doesn't point to a context with a packet.
+fn coap_context<'a>(coap_ctx_p: u64) -> Option<&'a CoapContext> {
+    let ctx = HelperMemory::for_value::<CoapContext>(coap_ctx_p)?;
+    // The region has been validated to hold exactly one context.
+    let ctx = unsafe { &*(ctx.mem().as_ptr() as *const CoapContext) };
+    if ctx.pkt.is_null() || ctx.buf.is_null() {
+        return None;
+    }
+    Some(ctx)
+}
+
+/// Context passed in r1 to the programs transforming the request payload into
+/// the response payload in place (see
+/// [`crate::vm::VirtualMachine::execute_on_payload`]). The fields have fixed
+/// sizes because the program reads them directly, they correspond to the
+/// following C struct:
+///
+/// ```c
+/// typedef struct {
+///     uint64_t payload;     /* ptr to the payload region */
+///     uint32_t request_len; /* length of the request payload */
+///     uint32_t capacity;    /* size of the payload region */
+///     uint64_t path_id;     /* path id of the route */
+/// } bpf_payload_ctx_t;
+/// ```
+#[derive(Debug)]
+#[repr(C)]
+pub struct PayloadContext {
+    pub payload: u64,
+    pub request_len: u32,
+    pub capacity: u32,
+    pub path_id: u64,
+}
+
+impl PayloadContext {
+    pub fn new(payload: &mut [u8], request_len: usize, path_id: u64) -> Self {
+        Self {
+            payload: payload.as_mut_ptr() as usize as u64,
+            request_len: request_len as u32,
+            capacity: payload.len() as u32,
+            path_id,
+        }
+    }
+}
+
 /* (g)coap functions */
 /// Initializes a CoAP response packet on a buffer.
 /// Initializes payload location within the buffer based on packet setup.
 pub fn bpf_gcoap_resp_init(coap_ctx_p: u64, resp_code: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let coap_ctx: *const CoapContext = coap_ctx_p as *const CoapContext;
+    let Some(coap_ctx) = coap_context(coap_ctx_p) else {
+        return -(riot_sys::EINVAL as i64) as u64;
+    };
 
     let resp_code = resp_code as u32;
 
     unsafe {
-        debug!("coap_ctx: {:?}", *coap_ctx);
-        debug!("coap pkt: {:?}", (*coap_ctx).pkt);
-        debug!("buf_len: {:?}", (*coap_ctx).len);
-        debug!("packet payload len: {:?}", (*(*coap_ctx).pkt).payload_len);
-        debug!("resp code: {:?}", resp_code);
+        debug!(target: targets::HELPERS, "coap_ctx: {:?}", coap_ctx);
+        debug!(target: targets::HELPERS, "coap pkt: {:?}", coap_ctx.pkt);
+        debug!(target: targets::HELPERS, "buf_len: {:?}", coap_ctx.len);
+        debug!(
+            target: targets::HELPERS,
+            "packet payload len: {:?}",
+            (*coap_ctx.pkt).payload_len,
+        );
+        debug!(target: targets::HELPERS, "resp code: {:?}", resp_code);
         let res = riot_sys::gcoap_resp_init(
-            (*coap_ctx).pkt,
-            (*coap_ctx).buf,
-            (*coap_ctx).len as u32,
+            coap_ctx.pkt,
+            coap_ctx.buf,
+            coap_ctx.len as u32,
             resp_code,
         ) as u64;
         return res;
@@ -229,26 +406,38 @@ pub fn bpf_gcoap_resp_init(coap_ctx_p: u64, resp_code: u64, _a3: u64, _a4: u64,
 }
 
 pub fn bpf_coap_opt_finish(coap_ctx_p: u64, flags_u: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let coap_ctx: *const CoapContext = coap_ctx_p as *const CoapContext;
+    let Some(coap_ctx) = coap_context(coap_ctx_p) else {
+        return -(riot_sys::EINVAL as i64) as u64;
+    };
     unsafe {
-        debug!("coap_ctx: {:?}", *coap_ctx);
-        debug!("buf_len: {:?}", (*coap_ctx).len);
-        debug!("packet payload len: {:?}", (*(*coap_ctx).pkt).payload_len);
-        return riot_sys::coap_opt_finish((*coap_ctx).pkt, flags_u as u16) as u64;
+        debug!(target: targets::HELPERS, "coap_ctx: {:?}", coap_ctx);
+        debug!(target: targets::HELPERS, "buf_len: {:?}", coap_ctx.len);
+        debug!(
+            target: targets::HELPERS,
+            "packet payload len: {:?}",
+            (*coap_ctx.pkt).payload_len,
+        );
+        return riot_sys::coap_opt_finish(coap_ctx.pkt, flags_u as u16) as u64;
     }
 }
 
 /// Append a Content-Format option to the pkt buffer.
 pub fn bpf_coap_add_format(coap_ctx_p: u64, format: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let coap_ctx: *const CoapContext = coap_ctx_p as *const CoapContext;
+    let Some(coap_ctx) = coap_context(coap_ctx_p) else {
+        return -(riot_sys::EINVAL as i64) as u64;
+    };
     unsafe {
-        debug!("coap_ctx: {:?}", *coap_ctx);
-        debug!("buf_len: {:?}", (*coap_ctx).len);
-        debug!("packet payload len: {:?}", (*(*coap_ctx).pkt).payload_len);
+        debug!(target: targets::HELPERS, "coap_ctx: {:?}", coap_ctx);
+        debug!(target: targets::HELPERS, "buf_len: {:?}", coap_ctx.len);
+        debug!(
+            target: targets::HELPERS,
+            "packet payload len: {:?}",
+            (*coap_ctx.pkt).payload_len,
+        );
         // Again the type cast hacking is needed because we are using the function
         // from the inline module.
         return riot_sys::inline::coap_opt_add_format(
-            (*coap_ctx).pkt as *mut riot_sys::inline::coap_pkt_t,
+            coap_ctx.pkt as *mut riot_sys::inline::coap_pkt_t,
             format as u16,
         ) as u64;
     }
@@ -279,7 +468,10 @@ pub fn bpf_ztimer_now(_a1: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
 
 /// Suspend the calling thread until the time (last_wakeup + period)
 pub fn bpf_periodic_wakeup(last_wakeup: u64, period: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let last_wakeup: *mut u32 = last_wakeup as *mut u32;
+    let Some(mut last_wakeup) = HelperMemory::for_value::<u32>(last_wakeup) else {
+        return u64::MAX;
+    };
+    let last_wakeup = last_wakeup.mem_mut().as_mut_ptr() as *mut u32;
     let period: u32 = period as u32;
     unsafe { riot_sys::ztimer_periodic_wakeup(riot_sys::ZTIMER_USEC, last_wakeup, period) }
 
@@ -288,26 +480,40 @@ pub fn bpf_periodic_wakeup(last_wakeup: u64, period: u64, _a3: u64, _a4: u64, _a
 
 /* Format and string functions - implementation */
 
+/// Returns the length of the NUL-terminated string. Strings longer than
+/// [`MAX_HELPER_REGION_SIZE`] are considered invalid, in which case
+/// `u64::MAX` is returned.
 pub fn bpf_strlen(str_ptr: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
-    let str_ptr = str_ptr as *const i8;
-    unsafe {
-        let c_str = CStr::from_ptr(str_ptr);
-        return c_str.to_bytes().len() as u64;
-    }
+    HelperMemory::new(str_ptr, MAX_HELPER_REGION_SIZE)
+        .and_then(|s| s.c_str().map(|s| s.len() as u64))
+        .unwrap_or(u64::MAX)
 }
 
 /// Convert 16-bit fixed point number to a decimal string.
 /// Returns the length of the resulting string.
 pub fn bpf_fmt_s16_dfp(out_p: u64, val: u64, fp_digits: u64, _a4: u64, _a5: u64) -> u64 {
-    debug!("Formatting s16 dfp args: {:?}, {:?}, {:?}, {:?}, {:?}", out_p, val, fp_digits, _a4, _a5);
+    debug!(
+        target: targets::HELPERS,
+        "Formatting s16 dfp args: {:?}, {:?}, {:?}, {:?}, {:?}",
+        out_p,
+        val,
+        fp_digits,
+        _a4,
+        _a5,
+    );
 
     extern "C" {
         fn fmt_s16_dfp(out: *mut u8, val: i16, fp_digits: i32) -> usize;
     }
 
-    let out = out_p as *mut u8;
+    // The longest output is e.g. "-0.0032768": the sign, the decimal point
+    // and up to 5 digits, padded with leading zeros.
+    const MAX_OUTPUT_LEN: usize = 11;
+    let Some(mut out) = HelperMemory::new(out_p, MAX_OUTPUT_LEN) else {
+        return 0;
+    };
     unsafe {
-        return fmt_s16_dfp(out, val as i16, fp_digits as i32) as u64;
+        return fmt_s16_dfp(out.mem_mut().as_mut_ptr(), val as i16, fp_digits as i32) as u64;
     }
 }
 
@@ -318,13 +524,18 @@ pub fn bpf_fmt_u32_dec(out_p: u64, val: u64, _a3: u64, _a4: u64, _a5: u64) -> u6
         fn fmt_u32_dec(out: *mut u8, val: u32) -> usize;
     }
 
-    let out = out_p as *mut u8;
+    // u32::MAX has 10 decimal digits.
+    const MAX_OUTPUT_LEN: usize = 10;
+    let Some(mut out) = HelperMemory::new(out_p, MAX_OUTPUT_LEN) else {
+        return 0;
+    };
     unsafe {
-        return fmt_u32_dec(out, val as u32) as u64;
+        return fmt_u32_dec(out.mem_mut().as_mut_ptr(), val as u32) as u64;
     }
 }
 
 /* GPIO functions - implementation */
+#[cfg(feature = "gpio")]
 pub fn bpf_gpio_read_input(port: u64, pin_num: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     let pin = gpio::GPIO::from_c(unsafe { riot_sys::macro_GPIO_PIN(port as u32, pin_num as u32) })
         .unwrap();
@@ -340,12 +551,14 @@ pub fn bpf_gpio_read_input(port: u64, pin_num: u64, _a3: u64, _a4: u64, _a5: u64
 /// changing it. E.g. if we have a pin powering a led and then turn it to input
 /// to read its state, it will return 0 as changing a pin to input changes its
 /// state
+#[cfg(feature = "gpio")]
 pub fn bpf_gpio_read_raw(port: u64, pin_num: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     let pin_state =
         unsafe { riot_sys::gpio_read(riot_sys::macro_GPIO_PIN(port as u32, pin_num as u32)) };
     return pin_state as u64;
 }
 
+#[cfg(feature = "gpio")]
 pub fn bpf_gpio_write(port: u64, pin_num: u64, val: u64, _a4: u64, _a5: u64) -> u64 {
     let pin = gpio::GPIO::from_c(unsafe { riot_sys::macro_GPIO_PIN(port as u32, pin_num as u32) })
         .unwrap();
@@ -357,32 +570,39 @@ pub fn bpf_gpio_write(port: u64, pin_num: u64, val: u64, _a4: u64, _a5: u64) ->
     return 0;
 }
 
+#[cfg(feature = "hd44780")]
 pub fn bpf_hd44780_init(_a1: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     let dev = HD44780LCD::new();
     let dev_ptr: *mut hd44780_t = dev.into();
     return dev_ptr as u64;
 }
 
+#[cfg(feature = "hd44780")]
 pub fn bpf_hd44780_clear(dev: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     let dev = HD44780LCD::from(dev as *mut hd44780_t);
     dev.clear();
     return 0;
 }
+#[cfg(feature = "hd44780")]
 pub fn bpf_hd44780_print(dev: u64, data: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     let dev = HD44780LCD::from(dev as *mut hd44780_t);
-    let string = unsafe { CStr::from_ptr(data as *const i8) };
-    if let Ok(str) = string.to_str() {
+    let Some(string) = HelperMemory::new(data, MAX_HELPER_REGION_SIZE) else {
+        return 0;
+    };
+    if let Some(Ok(str)) = string.c_str().map(core::str::from_utf8) {
         dev.print(str);
         return 0;
     }
     return 0;
 }
+#[cfg(feature = "hd44780")]
 pub fn bpf_hd44780_set_cursor(dev: u64, row: u64, column: u64, _a4: u64, _a5: u64) -> u64 {
     let dev = HD44780LCD::from(dev as *mut hd44780_t);
     dev.set_cursor(row as u8, column as u8);
     return 0;
 }
 
+#[cfg(feature = "keypad")]
 pub fn bpf_keypad_get_input(adc_index: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64) -> u64 {
     let dev = KeypadShieldButtons::new(adc_index as u8).unwrap();
     let direction = dev.read_direction();
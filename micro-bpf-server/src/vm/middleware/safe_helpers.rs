@@ -0,0 +1,90 @@
+//! Checked access to the memory passed into the helper functions.
+//!
+//! The helpers receive all of their arguments as `u64` values, so whenever an
+//! eBPF program passes in a pointer, the helper needs to turn it back into
+//! a reference before using it. Instead of dereferencing raw pointers directly,
+//! the helpers should wrap them into a [`HelperMemory`] which validates the
+//! region once and then allows only bounds-checked slice access to it.
+//!
+//! Note that this can't guarantee that the program actually owns the memory
+//! it passed in (the helpers have no access to the VM memory map), it only
+//! ensures that the helpers don't read or write outside of the region that
+//! the program declared.
+
+use core::{
+    convert::{TryFrom, TryInto},
+    mem::size_of,
+};
+
+use macros::set_env_or_default;
+
+/// Upper bound on the size of a single memory region that a program can pass
+/// into a helper. eBPF programs have 512B of stack, so anything significantly
+/// larger than the packet buffer indicates a corrupted length argument.
+pub const MAX_HELPER_REGION_SIZE: usize = set_env_or_default!("MAX_HELPER_REGION_SIZE", 4096);
+
+/// Provides a checked view of the memory that a helper was given access to.
+pub trait SafeHelper {
+    fn mem(&self) -> &[u8];
+    fn mem_mut(&mut self) -> &mut [u8];
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.mem().get(offset..offset + size_of::<u32>())?;
+        Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) -> Option<()> {
+        let bytes = self.mem_mut().get_mut(offset..offset + size_of::<u32>())?;
+        bytes.copy_from_slice(&value.to_ne_bytes());
+        Some(())
+    }
+
+    /// Returns the NUL-terminated string at the start of the region, excluding
+    /// the terminator. Returns `None` if there is no terminator in the region.
+    fn c_str(&self) -> Option<&[u8]> {
+        let mem = self.mem();
+        let len = mem.iter().position(|b| *b == 0)?;
+        Some(&mem[..len])
+    }
+}
+
+/// A region of memory passed into a helper as an address and a length.
+pub struct HelperMemory {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl HelperMemory {
+    /// Validates the region of `len` bytes starting at `addr`. Null pointers,
+    /// regions exceeding [`MAX_HELPER_REGION_SIZE`] and ones wrapping around
+    /// the address space are rejected.
+    pub fn new(addr: u64, len: usize) -> Option<Self> {
+        let addr = usize::try_from(addr).ok()?;
+        if addr == 0 || len > MAX_HELPER_REGION_SIZE || addr.checked_add(len).is_none() {
+            return None;
+        }
+        Some(Self {
+            ptr: addr as *mut u8,
+            len,
+        })
+    }
+
+    /// Validates the region holding a single value of type `T` at `addr`.
+    pub fn for_value<T>(addr: u64) -> Option<Self> {
+        if addr as usize % core::mem::align_of::<T>() != 0 {
+            return None;
+        }
+        Self::new(addr, size_of::<T>())
+    }
+}
+
+impl SafeHelper for HelperMemory {
+    fn mem(&self) -> &[u8] {
+        // The region has been validated on construction.
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    fn mem_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
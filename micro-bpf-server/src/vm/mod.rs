@@ -3,12 +3,24 @@ pub mod rbpf_vm;
 pub mod timed_vm;
 pub mod rbpf_jit;
 mod vm_manager;
+pub mod hot_reload;
+pub mod helper_policy;
+pub mod execution_clock;
+pub mod execution_state;
+pub mod clock;
+pub mod verification_worker;
 mod femtocontainer_vm;
 pub mod middleware;
-pub use vm::{VirtualMachine, construct_vm};
+pub use vm::{
+    VirtualMachine, ExecutionTimer, construct_vm, execution_result, run_short_lived,
+    DEFAULT_VM_TARGET, DEFAULT_VM_TARGET_NAME,
+};
 pub use rbpf_vm::RbpfVm;
 pub use timed_vm::TimedVm;
 pub use femtocontainer_vm::FemtoContainerVm;
 pub use vm_manager::VMExecutionManager;
 pub use vm_manager::VM_EXEC_REQUEST;
+pub use vm_manager::ExecutionSendPort;
 pub use vm_manager::RUNNING_WORKERS;
+pub use vm_manager::NUM_WORKERS;
+pub use vm_manager::{new_ticket, wait_for_result};
@@ -1,4 +1,5 @@
-use crate::vm::{middleware, VirtualMachine};
+use crate::util::logger::targets;
+use crate::vm::{execution_clock, helper_policy, middleware, VirtualMachine};
 use alloc::{
     collections::BTreeMap,
     format,
@@ -9,8 +10,8 @@ use alloc::{
 use core::{cell::RefCell, ops::DerefMut, slice::from_raw_parts_mut};
 use log::debug;
 use micro_bpf_common::{
-    BinaryFileLayout, HelperAccessListSource, HelperAccessVerification, HelperFunctionID,
-    VMConfiguration,
+    BinaryFileLayout, ExecutionModel, HelperAccessListSource, HelperAccessVerification,
+    HelperFunctionID, VMConfiguration,
 };
 use micro_bpf_elf_utils::extract_allowed_helpers;
 
@@ -21,12 +22,13 @@ use riot_wrappers::{gcoap::PacketBuffer, mutex::Mutex, stdio::println};
 
 use super::{
     middleware::{
-        helpers::{HelperAccessList, HelperFunction},
-        CoapContext,
+        helpers::{self, HelperAccessList, HelperFunction},
+        CoapContext, PayloadContext,
     },
     rbpf_vm::map_interpreter,
 };
 use crate::infra::jit_prog_storage::{self, JIT_SLOT_SIZE};
+use crate::infra::program_analysis;
 use crate::infra::suit_storage::{self, SUIT_STORAGE_SLOT_SIZE};
 
 pub struct RbpfJIT<'a> {
@@ -39,6 +41,10 @@ pub struct RbpfJIT<'a> {
     pub jit_prog_slot: usize,
     pub jit_program_length: usize,
     pub jitted_fn: Option<unsafe fn(*mut u8, usize, *mut u8, usize) -> u32>,
+    /// Arguments passed in r1-r4 when executing without input data.
+    pub args: [u64; 4],
+    /// Execution model of the program, it restricts the available helpers.
+    pub execution_model: Option<ExecutionModel>,
 }
 
 impl<'a> RbpfJIT<'a> {
@@ -46,13 +52,17 @@ impl<'a> RbpfJIT<'a> {
         RbpfJIT {
             program: None,
             layout: config.binary_layout,
-            allowed_helpers,
+            allowed_helpers: helper_policy::clamp(allowed_helpers, config.suit_slot),
             helper_access_verification: config.helper_access_verification,
             helper_access_list_source: config.helper_access_list_source,
-            recompile: config.jit_compile,
+            // A program compiled earlier could call the helpers that the
+            // policy forbids, so it is always recompiled under a policy.
+            recompile: config.jit_compile || helper_policy::is_set(),
             jit_prog_slot: config.suit_slot,
             jit_program_length: 0,
             jitted_fn: None,
+            args: [0; 4],
+            execution_model: None,
         }
     }
 }
@@ -69,23 +79,37 @@ impl<'a> VirtualMachine for RbpfJIT<'a> {
             Err("The JIT only supports raw object file binary layout")?;
         };
 
-        let _ = jit_prog_storage::free_storage_slot(self.jit_prog_slot);
         // We take the list of helpers from the execute request as this is the
         // only one way supported by the raw elf file binary layout that we use for the JIT.
         let mut helpers_map = BTreeMap::new();
         let helper_access_list = HelperAccessList::from(self.allowed_helpers.clone());
 
         for h in helper_access_list.0 {
-            helpers_map.insert(h.id as u32, h.function);
+            if helpers::is_available_in(h.id, self.execution_model) {
+                helpers_map.insert(h.id as u32, h.function);
+            }
+        }
+
+        // The JIT compiles calls to helpers missing from the map into calls
+        // that fault at runtime, so similarly to the pre-flight check of the
+        // interpreter, such programs are rejected before they are compiled.
+        for id in program_analysis::called_helpers(program, self.layout)? {
+            if !helpers_map.contains_key(&id) {
+                Err(format!("Helper not allowed: {:#04x} is called but isn't mapped", id))?;
+            }
         }
 
+        let _ = jit_prog_storage::free_storage_slot(self.jit_prog_slot);
+
         let jit_slot = self.jit_prog_slot;
 
+        // No global buffers are used during the compilation, so workers
+        // compiling programs from different slots can do so concurrently.
         // Here we acquire a pointer to global storage where the jitted
         // program will be written. The additional scope is introduced so
         // that the acquired MutexGuard goes out of scope at the end of it
         // and so the lock is released. (RAII)
-        {
+        let validation = {
             let mut slot_guard = jit_prog_storage::acquire_storage_slot(jit_slot).unwrap();
             let mut text_offset = 0;
 
@@ -102,14 +126,27 @@ impl<'a> VirtualMachine for RbpfJIT<'a> {
                 )
                 .unwrap();
                 self.jit_program_length = jit_memory.offset;
-                debug!("JIT compilation successful");
-                debug!("jitted program size: {} [B]", jit_memory.offset);
+                debug!(target: targets::JIT, "JIT compilation successful");
+                debug!(target: targets::JIT, "jitted program size: {} [B]", jit_memory.offset);
                 text_offset = jit_memory.text_offset;
             }
 
 
             self.program = Some(program_cell);
-            slot_guard.1 = text_offset;
+            // The slot is only marked as ready (the offset is set) if the
+            // program fits into it.
+            let validation =
+                jit_prog_storage::validate_jitted_program(text_offset, self.jit_program_length);
+            if validation.is_ok() {
+                slot_guard.1 = text_offset;
+            }
+            validation.and_then(|_| {
+                jit_prog_storage::set_program_length(jit_slot, self.jit_program_length)
+            })
+        };
+        if let Err(e) = validation {
+            let _ = jit_prog_storage::free_storage_slot(jit_slot);
+            return Err(e);
         }
         self.jitted_fn = Some(jit_prog_storage::get_program_from_slot(self.jit_prog_slot).unwrap());
         Ok(())
@@ -122,11 +159,20 @@ impl<'a> VirtualMachine for RbpfJIT<'a> {
         let prog_ref = prog_ref_cell.borrow();
         let interpreter = map_interpreter(self.layout);
         rbpf::EbpfVmMbuff::verify_program(interpreter, prog_ref.as_ref());
+        if let Some(required) = program_analysis::required_stack_size(prog_ref.as_ref(), self.layout)
+        {
+            program_analysis::check_stack_size(required, rbpf::ebpf::STACK_SIZE)?;
+        }
 
-        if self.helper_access_verification == HelperAccessVerification::PreFlight {
+        // In the strict sandbox no helpers are available, so the check rejects
+        // any program that calls one.
+        if helpers::STRICT_SANDBOX
+            || self.helper_access_verification == HelperAccessVerification::PreFlight
+        {
             let helpers_idxs = self
                 .allowed_helpers
                 .iter()
+                .filter(|id| helpers::is_available_in(**id, self.execution_model))
                 .map(|id| *id as u32)
                 .collect::<Vec<u32>>();
             rbpf::check_helpers(prog_ref.as_ref(), &helpers_idxs, interpreter)
@@ -136,34 +182,83 @@ impl<'a> VirtualMachine for RbpfJIT<'a> {
     }
 
     fn execute(&mut self) -> Result<u64, String> {
-        let mut ret = 0;
-        unsafe {
-            // We don't pass any meaningful arguments here as the program doesn't
-            // work on a COAP message packet buffer.
-            ret = self.jitted_fn.unwrap()(0 as *mut u8, 0, 0 as *mut u8, 0);
-        }
-        debug!("JIT execution successful: {}", ret);
+        execution_clock::start();
+        let [r1, r2, r3, r4] = self.args;
+        let ret = unsafe {
+            // The program doesn't work on a CoAP packet buffer, so all four
+            // argument registers are free to carry the caller-supplied integers.
+            self.jitted_fn.unwrap()(
+                r1 as usize as *mut u8,
+                r2 as usize,
+                r3 as usize as *mut u8,
+                r4 as usize,
+            )
+        };
+        debug!(target: targets::JIT, "JIT execution successful: {}", ret);
+        Ok(ret as u64)
+    }
+
+    fn execute_with_output(&mut self, output: &mut [u8]) -> Result<u64, String> {
+        execution_clock::start();
+        let ret = unsafe {
+            self.jitted_fn.unwrap()(output.as_mut_ptr(), output.len(), 0 as *mut u8, 0)
+        };
+        debug!(target: targets::JIT, "JIT execution successful: {}", ret);
         Ok(ret as u64)
     }
 
     fn execute_on_coap_pkt(&mut self, pkt: &mut PacketBuffer) -> Result<u64, String> {
+        execution_clock::start();
         let coap_context: &mut [u8] = unsafe {
             const CONTEXT_SIZE: usize = core::mem::size_of::<CoapContext>();
             let ctx = pkt as *mut _ as *mut CoapContext;
-            debug!("CoAP context: {:?}", *ctx);
+            debug!(target: targets::JIT, "CoAP context: {:?}", *ctx);
             from_raw_parts_mut(ctx as *mut u8, CONTEXT_SIZE)
         };
 
         let mut ret = 0;
         unsafe {
-            // We don't pass any meaningful arguments here as the program doesn't
-            // work on a COAP message packet buffer.
-            ret = self.jitted_fn.unwrap()(coap_context as *mut _ as *mut u8, 0, 0 as *mut u8, 0);
+            // The first argument is passed in r2 so that programs shared by
+            // several CoAP routes can tell which one the request was sent to,
+            // see `infra::coap_routes`.
+            ret = self.jitted_fn.unwrap()(
+                coap_context as *mut _ as *mut u8,
+                self.args[0] as usize,
+                0 as *mut u8,
+                0,
+            );
         }
-        debug!("JIT execution successful: {}", ret);
+        debug!(target: targets::JIT, "JIT execution successful: {}", ret);
+        Ok(ret as u64)
+    }
+
+    fn execute_on_payload(
+        &mut self,
+        payload: &mut [u8],
+        request_len: usize,
+    ) -> Result<u64, String> {
+        execution_clock::start();
+        let mut context = PayloadContext::new(payload, request_len, self.args[0]);
+        let ret = unsafe {
+            self.jitted_fn.unwrap()(
+                &mut context as *mut _ as *mut u8,
+                core::mem::size_of::<PayloadContext>(),
+                0 as *mut u8,
+                0,
+            )
+        };
+        debug!(target: targets::JIT, "JIT execution successful: {}", ret);
         Ok(ret as u64)
     }
 
+    fn set_args(&mut self, args: [u64; 4]) {
+        self.args = args;
+    }
+
+    fn set_execution_model(&mut self, model: ExecutionModel) {
+        self.execution_model = Some(model);
+    }
+
     fn get_program_length(&self) -> usize {
         self.jit_program_length
     }
@@ -1,6 +1,7 @@
+use crate::util::logger::targets;
 use crate::{
-    infra::suit_storage,
-    vm::{middleware, VirtualMachine},
+    infra::{program_analysis, suit_storage},
+    vm::{execution_clock, helper_policy, middleware, VirtualMachine},
 };
 use alloc::{
     format,
@@ -11,8 +12,8 @@ use alloc::{
 use log::debug;
 use core::{ops::DerefMut, slice::from_raw_parts_mut};
 use micro_bpf_common::{
-    BinaryFileLayout, HelperAccessListSource, HelperAccessVerification, HelperFunctionID,
-    VMConfiguration,
+    BinaryFileLayout, ExecutionModel, HelperAccessListSource, HelperAccessVerification,
+    HelperFunctionID, VMConfiguration,
 };
 use micro_bpf_elf_utils::extract_allowed_helpers;
 
@@ -23,7 +24,7 @@ use riot_wrappers::{gcoap::PacketBuffer, mutex::Mutex, stdio::println};
 
 use super::middleware::{
     helpers::{HelperAccessList, HelperFunction},
-    CoapContext,
+    CoapContext, PayloadContext,
 };
 
 /// An adapter struct which wraps around the rbpf VM so that it is compatible
@@ -36,6 +37,13 @@ pub struct RbpfVm<'a> {
     pub helper_access_list_source: HelperAccessListSource,
     pub program_length: usize,
     pub suit_slot: usize,
+    /// Arguments exposed to the program through r1 when executing without
+    /// input data.
+    pub args: [u64; 4],
+    /// Stack size required by the loaded program, if it could be determined.
+    pub required_stack_size: Option<usize>,
+    /// Execution model of the program, it restricts the available helpers.
+    pub execution_model: Option<ExecutionModel>,
 }
 
 impl<'a> RbpfVm<'a> {
@@ -46,28 +54,21 @@ impl<'a> RbpfVm<'a> {
         Ok(RbpfVm {
             vm: None,
             layout: config.binary_layout,
-            allowed_helpers,
+            allowed_helpers: helper_policy::clamp(allowed_helpers, config.suit_slot),
             helper_access_verification: config.helper_access_verification,
             helper_access_list_source: config.helper_access_list_source,
             program_length: 0,
             suit_slot: config.suit_slot,
+            args: [0; 4],
+            required_stack_size: None,
+            execution_model: None,
         })
     }
-}
-
-pub fn map_interpreter(layout: BinaryFileLayout) -> rbpf::InterpreterVariant {
-    match layout {
-        BinaryFileLayout::FemtoContainersHeader => rbpf::InterpreterVariant::FemtoContainersHeader,
-        BinaryFileLayout::ExtendedHeader => rbpf::InterpreterVariant::ExtendedHeader,
-        BinaryFileLayout::RawObjectFile => rbpf::InterpreterVariant::RawObjectFile,
-        BinaryFileLayout::OnlyTextSection => rbpf::InterpreterVariant::Default,
-    }
-}
-
-impl<'a> VirtualMachine for RbpfVm<'a> {
-    fn initialize_vm(&mut self) -> Result<(), String> {
-        let program = suit_storage::load_program_static(self.suit_slot);
 
+    /// Loads the given program into the VM instead of the one stored in the
+    /// SUIT storage slot specified in the configuration. It allows for
+    /// executing programs that were never deployed into the storage.
+    pub fn initialize_vm_from_bytes(&mut self, program: &'a [u8]) -> Result<(), String> {
         // We need to make a decision whether we use the helper list that was
         // sent in the request or read the allowed helpers from the metadata appended
         // to the program binary.
@@ -77,7 +78,8 @@ impl<'a> VirtualMachine for RbpfVm<'a> {
             }
             micro_bpf_common::HelperAccessListSource::BinaryMetadata => {
                 if self.layout == BinaryFileLayout::ExtendedHeader {
-                    HelperAccessList::from(extract_allowed_helpers(program))
+                    let helpers = extract_allowed_helpers(program);
+                    HelperAccessList::from(helper_policy::clamp(helpers, self.suit_slot))
                 } else {
                     Err("Tried to extract allowed helper function indices from an incompatible binary file")?
                 }
@@ -88,24 +90,58 @@ impl<'a> VirtualMachine for RbpfVm<'a> {
                 .map_err(|e| format!("Error: {:?}", e))?,
         );
         self.program_length = program.len();
+        self.required_stack_size = program_analysis::required_stack_size(program, self.layout);
         middleware::helpers::register_helpers(
             self.vm.as_mut().unwrap(),
             helper_access_list.0.clone(),
+            self.execution_model,
         );
         Ok(())
     }
+}
+
+/// Maps the binary layout of a program to the interpreter variant which
+/// understands it. The match needs to stay exhaustive without a wildcard arm,
+/// so that adding a new layout fails to compile until its variant is chosen
+/// here, instead of silently interpreting the program using a wrong one.
+#[deny(
+    clippy::wildcard_enum_match_arm,
+    clippy::match_wildcard_for_single_variants
+)]
+pub fn map_interpreter(layout: BinaryFileLayout) -> rbpf::InterpreterVariant {
+    match layout {
+        BinaryFileLayout::FemtoContainersHeader => rbpf::InterpreterVariant::FemtoContainersHeader,
+        BinaryFileLayout::ExtendedHeader => rbpf::InterpreterVariant::ExtendedHeader,
+        BinaryFileLayout::RawObjectFile => rbpf::InterpreterVariant::RawObjectFile,
+        BinaryFileLayout::OnlyTextSection => rbpf::InterpreterVariant::Default,
+    }
+}
+
+impl<'a> VirtualMachine for RbpfVm<'a> {
+    fn initialize_vm(&mut self) -> Result<(), String> {
+        let program = suit_storage::load_program_static(self.suit_slot);
+        self.initialize_vm_from_bytes(program)
+    }
 
     fn verify(&self) -> Result<(), String> {
         // The VM runs the verification when the new program is loaded into it.
         if let Some(vm) = self.vm.as_ref() {
             vm.verify_loaded_program()
                 .map_err(|e| format!("Error: {:?}", e))?;
+            if let Some(required) = self.required_stack_size {
+                program_analysis::check_stack_size(required, rbpf::ebpf::STACK_SIZE)?;
+            }
 
-            if self.helper_access_verification == HelperAccessVerification::PreFlight {
+            // In the strict sandbox no helpers are available, so the check
+            // rejects any program that calls one.
+            if middleware::helpers::STRICT_SANDBOX
+                || self.helper_access_verification == HelperAccessVerification::PreFlight
+            {
                 let interpreter = map_interpreter(self.layout);
                 let helpers_idxs = self
                     .allowed_helpers
                     .iter()
+                    .filter(|id| middleware::helpers::is_available_in(**id, self.execution_model))
                     .map(|id| *id as u32)
                     .collect::<Vec<u32>>();
                 vm.verify_helper_calls(&helpers_idxs, interpreter)
@@ -119,29 +155,51 @@ impl<'a> VirtualMachine for RbpfVm<'a> {
     }
 
     fn execute(&mut self) -> Result<u64, String> {
+        execution_clock::start();
+        // The interpreter only initialises r1 (with the address of the main
+        // memory region), so the arguments are passed in memory.
+        let mut args = [0u8; 32];
+        for (chunk, arg) in args.chunks_exact_mut(8).zip(self.args) {
+            chunk.copy_from_slice(&arg.to_ne_bytes());
+        }
+        if let Some(vm) = self.vm.as_mut() {
+            vm.execute_program(&args, &alloc::vec![], alloc::vec![])
+                .map_err(|e| format!("Error: {:?}", e))
+        } else {
+            Err("VM not initialised".to_string())
+        }
+    }
+    fn execute_with_output(&mut self, output: &mut [u8]) -> Result<u64, String> {
+        execution_clock::start();
         if let Some(vm) = self.vm.as_mut() {
-            vm.execute_program(&alloc::vec![], &alloc::vec![], alloc::vec![])
+            // The output buffer is passed as the main memory region so that
+            // the program is allowed to write into it.
+            vm.execute_program(output, &alloc::vec![], alloc::vec![])
                 .map_err(|e| format!("Error: {:?}", e))
         } else {
             Err("VM not initialised".to_string())
         }
     }
+
     fn execute_on_coap_pkt(&mut self, pkt: &mut PacketBuffer) -> Result<u64, String> {
+        execution_clock::start();
         /// Coap context struct containing information about the buffer,
         /// packet and its length. It is passed into the VM as the main buffer
         /// on which the program operates.
         let coap_context: &mut [u8] = unsafe {
             const CONTEXT_SIZE: usize = core::mem::size_of::<CoapContext>();
             let ctx = pkt as *mut _ as *mut CoapContext;
-            debug!("CoAP context: {:?}", *ctx);
+            debug!(target: targets::VM, "CoAP context: {:?}", *ctx);
             from_raw_parts_mut(ctx as *mut u8, CONTEXT_SIZE)
         };
 
-        // Actual packet struct
+        // Actual packet struct. Its size needs to be exact as the interpreter
+        // allows the program to access the whole region, the buffer that
+        // the packet points into is registered separately below.
         let mem = unsafe {
+            const PKT_SIZE: usize = core::mem::size_of::<riot_sys::coap_pkt_t>();
             let ctx = pkt as *mut _ as *mut CoapContext;
-            debug!("CoAP context: {:?}", *ctx);
-            from_raw_parts_mut((*ctx).pkt as *mut u8, (*ctx).len)
+            from_raw_parts_mut((*ctx).pkt as *mut u8, PKT_SIZE)
         };
 
         let pkt_buffer_region: (u64, u64) = unsafe {
@@ -152,7 +210,7 @@ impl<'a> VirtualMachine for RbpfVm<'a> {
         if let Some(vm) = self.vm.as_mut() {
             let result = vm.execute_program(mem, coap_context, alloc::vec![pkt_buffer_region])
                 .map_err(|e| format!("Error: {:?}", e));
-            debug!("CoAP execution result: {:?}", result.clone().unwrap_or(0));
+            debug!(target: targets::VM, "CoAP execution result: {:?}", result.clone().unwrap_or(0));
             return result;
 
         } else {
@@ -160,7 +218,64 @@ impl<'a> VirtualMachine for RbpfVm<'a> {
         }
     }
 
+    fn execute_on_payload(
+        &mut self,
+        payload: &mut [u8],
+        request_len: usize,
+    ) -> Result<u64, String> {
+        execution_clock::start();
+        let mut payload_context = PayloadContext::new(payload, request_len, self.args[0]);
+        let context: &mut [u8] = unsafe {
+            const CONTEXT_SIZE: usize = core::mem::size_of::<PayloadContext>();
+            from_raw_parts_mut(&mut payload_context as *mut _ as *mut u8, CONTEXT_SIZE)
+        };
+        if let Some(vm) = self.vm.as_mut() {
+            // The payload region is passed as the main memory region so that
+            // the program is allowed to write into it, no other part of the
+            // packet buffer is accessible.
+            vm.execute_program(payload, context, alloc::vec![])
+                .map_err(|e| format!("Error: {:?}", e))
+        } else {
+            Err("VM not initialised".to_string())
+        }
+    }
+
+    fn set_args(&mut self, args: [u64; 4]) {
+        self.args = args;
+    }
+
+    fn set_execution_model(&mut self, model: ExecutionModel) {
+        self.execution_model = Some(model);
+    }
+
     fn get_program_length(&self) -> usize {
         return self.program_length;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_layout_has_its_interpreter() {
+        use rbpf::InterpreterVariant as Variant;
+        assert!(matches!(
+            map_interpreter(BinaryFileLayout::FemtoContainersHeader),
+            Variant::FemtoContainersHeader
+        ));
+        assert!(matches!(
+            map_interpreter(BinaryFileLayout::ExtendedHeader),
+            Variant::ExtendedHeader
+        ));
+        assert!(matches!(
+            map_interpreter(BinaryFileLayout::RawObjectFile),
+            Variant::RawObjectFile
+        ));
+        // Programs consisting only of the .text section need no preprocessing.
+        assert!(matches!(
+            map_interpreter(BinaryFileLayout::OnlyTextSection),
+            Variant::Default
+        ));
+    }
+}
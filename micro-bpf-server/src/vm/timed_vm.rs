@@ -2,9 +2,12 @@ use core::cell::RefCell;
 
 use alloc::{boxed::Box, string::String};
 use log::debug;
+use micro_bpf_common::ExecutionModel;
 use riot_wrappers::gcoap::PacketBuffer;
 
-use super::VirtualMachine;
+use super::{vm::run_with_status, VirtualMachine};
+use crate::model::results::ExecutionResult;
+use crate::util::logger::targets;
 
 pub struct TimedVm {
     vm: Box<dyn VirtualMachine>,
@@ -69,6 +72,28 @@ impl VirtualMachine for TimedVm {
         result
     }
 
+    fn execute_with_output(&mut self, output: &mut [u8]) -> Result<u64, String> {
+        let start = self.time_now();
+        let result = self.vm.execute_with_output(output);
+        let end = self.time_now();
+
+        self.results.borrow_mut().execution_time = end - start;
+        result
+    }
+
+    fn execute_on_payload(
+        &mut self,
+        payload: &mut [u8],
+        request_len: usize,
+    ) -> Result<u64, String> {
+        let start = self.time_now();
+        let result = self.vm.execute_on_payload(payload, request_len);
+        let end = self.time_now();
+
+        self.results.borrow_mut().execution_time = end - start;
+        result
+    }
+
     fn full_run(&mut self) -> Result<u64, String> {
         let start = self.time_now();
         self.initialize_vm()?;
@@ -78,6 +103,13 @@ impl VirtualMachine for TimedVm {
         self.results.borrow_mut().total_time = end - start;
         result
     }
+    fn full_run_with_status(&mut self) -> ExecutionResult {
+        let start = self.time_now();
+        let result = run_with_status(self);
+        let end = self.time_now();
+        self.results.borrow_mut().total_time = end - start;
+        result
+    }
     fn full_run_on_coap_pkt(
         &mut self,
         pkt: &mut PacketBuffer,
@@ -86,12 +118,20 @@ impl VirtualMachine for TimedVm {
         self.initialize_vm()?;
         self.verify()?;
         let result = self.execute_on_coap_pkt(pkt);
-        debug!("Timed VM execution returned: {}.", result.clone().unwrap() as i64);
+        debug!(target: targets::VM, "Timed VM execution returned: {}.", result.clone().unwrap());
         let end = self.time_now();
         self.results.borrow_mut().total_time = end - start;
         result
     }
 
+    fn set_args(&mut self, args: [u64; 4]) {
+        self.vm.set_args(args)
+    }
+
+    fn set_execution_model(&mut self, model: ExecutionModel) {
+        self.vm.set_execution_model(model)
+    }
+
     fn get_program_length(&self) -> usize {
         self.vm.get_program_length()
     }
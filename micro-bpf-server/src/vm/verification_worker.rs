@@ -0,0 +1,170 @@
+//! Verification of the inline programs (see `VMInlineExecutionHandler`) on a
+//! dedicated worker thread.
+//!
+//! By default, the inline programs are verified by the CoAP handler right
+//! before they are executed. Verifying a large program can take a while and
+//! the CoAP thread runs at a high priority, so all lower-priority threads are
+//! stalled in the meantime and there is no upper bound on how long the
+//! handler is busy. When the offloading is enabled (by setting
+//! `OFFLOAD_VERIFICATION=1` at compile time), the handler sends the program
+//! to the verification worker instead, which runs at a lower priority and
+//! verifies it using its own stack. The handler sleeps until the verification
+//! completes and only executes the program if it passed. If the worker
+//! doesn't finish within [`VERIFICATION_TIMEOUT_MS`] (or it is still busy with
+//! a previous program), the request is rejected instead of running an
+//! unverified program.
+//!
+//! The trade-off is latency: the program is copied for the worker, loaded
+//! twice (once by the worker and once by the handler) and the completion is
+//! only noticed at the next poll of the handler (every
+//! [`RESULT_POLL_INTERVAL_MS`]), so small programs are executed slightly later
+//! than with the inline verification. It pays off for large programs and on
+//! boards where other threads (e.g. the VM workers) need to keep running
+//! while the programs are verified.
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::ffi::c_void;
+
+use log::{debug, error};
+use macros::set_env_or_default;
+use micro_bpf_common::{ExecutionModel, HelperFunctionID, VMConfiguration};
+use riot_sys::msg_t;
+use riot_wrappers::{mutex::Mutex, thread, ztimer};
+
+use crate::util::logger::targets;
+
+use super::{RbpfVm, VirtualMachine};
+
+/// Enables verifying the inline programs on the verification worker.
+pub const OFFLOAD_VERIFICATION: bool = set_env_or_default!("OFFLOAD_VERIFICATION", 0) != 0;
+
+/// Maximum time (in milliseconds) that the handler waits for the verification
+/// of a program to complete.
+pub const VERIFICATION_TIMEOUT_MS: usize = set_env_or_default!("VERIFICATION_TIMEOUT_MS", 1000);
+
+/// Interval at which the handler checks whether the verification completed.
+const RESULT_POLL_INTERVAL_MS: u32 = 5;
+
+/// Size of the stack of the verification worker, no memory is reserved for it
+/// unless the offloading is enabled.
+pub const VERIFICATION_WORKER_STACK_SIZE: usize = if OFFLOAD_VERIFICATION { 4096 } else { 0 };
+
+/// Results of the verifications that nobody waits for anymore (because the
+/// wait timed out) are dropped, starting with the oldest ones, once there are
+/// more of them.
+const MAX_VERIFICATION_RESULTS: usize = 4;
+
+/// PID of the verification worker, set once the worker starts.
+static WORKER_PID: Mutex<Option<i16>> = Mutex::new(None);
+
+/// Outcomes of the completed verifications keyed by their tickets.
+static VERIFICATION_RESULTS: Mutex<BTreeMap<u32, Result<(), String>>> =
+    Mutex::new(BTreeMap::new());
+static NEXT_TICKET: Mutex<u32> = Mutex::new(0);
+
+/// Everything the worker needs to load the program into a VM the same way
+/// the handler does, so that it is subject to the same checks.
+struct VerificationJob {
+    ticket: u32,
+    program: Vec<u8>,
+    configuration: VMConfiguration,
+    allowed_helpers: Vec<HelperFunctionID>,
+    execution_model: ExecutionModel,
+}
+
+fn new_ticket() -> u32 {
+    let mut next = NEXT_TICKET.lock();
+    let ticket = *next;
+    *next = next.wrapping_add(1);
+    ticket
+}
+
+/// Verifies the program on the verification worker and waits for the outcome.
+/// The outer error means that the program couldn't be verified (the worker
+/// is busy or didn't finish in time), whereas the inner one is the reason
+/// why the program was rejected by the verifier.
+pub fn verify_on_worker(
+    program: &[u8],
+    configuration: VMConfiguration,
+    allowed_helpers: Vec<HelperFunctionID>,
+    execution_model: ExecutionModel,
+) -> Result<Result<(), String>, String> {
+    let pid = WORKER_PID
+        .lock()
+        .ok_or_else(|| "The verification worker isn't running".to_string())?;
+
+    let ticket = new_ticket();
+    let job = Box::new(VerificationJob {
+        ticket,
+        program: program.to_vec(),
+        configuration,
+        allowed_helpers,
+        execution_model,
+    });
+    let mut msg: msg_t = Default::default();
+    msg.content = riot_sys::msg_t__bindgen_ty_1 {
+        ptr: Box::into_raw(job) as *mut c_void,
+    };
+    // The worker only accepts the job if it is waiting for one, otherwise it
+    // is still verifying a previous program.
+    if unsafe { riot_sys::msg_try_send(&mut msg as *mut msg_t, pid) } < 1 {
+        // Safety: the message wasn't delivered, so the job is still owned here.
+        drop(unsafe { Box::from_raw(msg.content.ptr as *mut VerificationJob) });
+        Err("The verification worker is busy".to_string())?;
+    }
+
+    let clock = ztimer::Clock::msec();
+    let mut waited_ms = 0;
+    loop {
+        if let Some(result) = VERIFICATION_RESULTS.lock().remove(&ticket) {
+            return Ok(result);
+        }
+        if waited_ms >= VERIFICATION_TIMEOUT_MS as u32 {
+            Err(format!(
+                "The verification didn't complete within {} ms",
+                VERIFICATION_TIMEOUT_MS
+            ))?;
+        }
+        clock.sleep_ticks(RESULT_POLL_INTERVAL_MS);
+        waited_ms += RESULT_POLL_INTERVAL_MS;
+    }
+}
+
+fn verify(job: &VerificationJob) -> Result<(), String> {
+    let mut vm = RbpfVm::new(job.configuration, job.allowed_helpers.clone())?;
+    vm.set_execution_model(job.execution_model);
+    vm.initialize_vm_from_bytes(&job.program)?;
+    vm.verify()
+}
+
+/// Main function of the verification worker thread.
+pub fn verification_worker_main() {
+    *WORKER_PID.lock() = Some(thread::get_pid().into());
+    loop {
+        let mut msg: msg_t = Default::default();
+        unsafe {
+            let _ = riot_sys::msg_receive(&mut msg);
+        }
+        // Safety: the messages are only sent by `verify_on_worker`, which
+        // transfers the ownership of the job into the message.
+        let job = unsafe { Box::from_raw(msg.content.ptr as *mut VerificationJob) };
+
+        let result = verify(&job);
+        match &result {
+            Ok(()) => debug!(target: targets::VM, "Program {} verified", job.ticket),
+            Err(e) => error!(target: targets::VM, "Program {} rejected: {}", job.ticket, e),
+        }
+
+        let mut results = VERIFICATION_RESULTS.lock();
+        results.insert(job.ticket, result);
+        while results.len() > MAX_VERIFICATION_RESULTS {
+            results.pop_first();
+        }
+    }
+}
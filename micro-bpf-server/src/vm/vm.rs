@@ -1,14 +1,30 @@
-use alloc::{boxed::Box, format, string::String, vec::Vec};
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 use micro_bpf_common::{
-    BinaryFileLayout, HelperAccessVerification, HelperFunctionID, TargetVM, VMConfiguration,
+    BinaryFileLayout, ExecutionModel, HelperAccessVerification, HelperFunctionID, TargetVM,
+    VMConfiguration,
 };
+use log::error;
 use micro_bpf_elf_utils::{extract_allowed_helpers, resolve_relocations};
 use riot_wrappers::gcoap::PacketBuffer;
 
-use crate::infra::{local_storage, suit_storage};
+use crate::util::logger::targets;
+use crate::{
+    infra::{client_quota, local_storage, suit_storage},
+    model::results::{ExecutionResult, VmStatus},
+};
 
 use super::{
-    middleware::helpers::HelperAccessList, rbpf_jit::RbpfJIT, rbpf_vm, FemtoContainerVm, RbpfVm,
+    clock,
+    execution_state::{self, ExecutionState},
+    helper_policy,
+    middleware::helpers::HelperAccessList,
+    rbpf_jit::RbpfJIT,
+    rbpf_vm, FemtoContainerVm, RbpfVm,
 };
 
 /// Structs implementing this interface should allow for executing eBPF programs
@@ -20,6 +36,16 @@ pub trait VirtualMachine {
         self.verify()?;
         self.execute()
     }
+    /// Same as [`VirtualMachine::full_run`], but reports the stage at which
+    /// the run failed separately from the value returned by the program.
+    fn full_run_with_status(&mut self) -> ExecutionResult {
+        run_with_status(self)
+    }
+    fn full_run_with_output(&mut self, output: &mut [u8]) -> Result<u64, String> {
+        self.initialize_vm()?;
+        self.verify()?;
+        self.execute_with_output(output)
+    }
     fn full_run_on_coap_pkt(
         &mut self,
         pkt: &mut PacketBuffer,
@@ -28,6 +54,15 @@ pub trait VirtualMachine {
         self.verify()?;
         self.execute_on_coap_pkt(pkt)
     }
+    fn full_run_on_payload(
+        &mut self,
+        payload: &mut [u8],
+        request_len: usize,
+    ) -> Result<u64, String> {
+        self.initialize_vm()?;
+        self.verify()?;
+        self.execute_on_payload(payload, request_len)
+    }
     /// Initializes the VM, in case of the JIT this step involves jit-compilation.
     /// In case of raw elf file binaries this is where the relocation resolution
     /// should take place. In all other case we simply attach all helper functions
@@ -35,7 +70,9 @@ pub trait VirtualMachine {
     fn initialize_vm(&mut self) -> Result<(), String>;
     /// Verifies the program bytecode after it has been loaded into the VM.
     fn verify(&self) -> Result<(), String>;
-    /// Executes a given program and returns its return value.
+    /// Executes a given program and returns its return value. The raw 64-bit
+    /// value of r0 is returned unmodified, it is up to the caller to interpret
+    /// its signedness.
     fn execute(&mut self) -> Result<u64, String>;
     /// Executes a given eBPF program giving it access to the provided PacketBuffer
     /// and returns the return value of the program. The value returned
@@ -43,12 +80,144 @@ pub trait VirtualMachine {
     /// the packet PDU + payload. The reason for this is that the handler then
     /// needs to know this length when sending the response back.
     fn execute_on_coap_pkt(&mut self, pkt: &mut PacketBuffer) -> Result<u64, String>;
+    /// Executes a given eBPF program giving it access to the output buffer
+    /// (its address is passed in r1). Programs producing structured results
+    /// write them into the buffer and return the number of bytes written,
+    /// the caller is then responsible for copying those bytes out.
+    fn execute_with_output(&mut self, _output: &mut [u8]) -> Result<u64, String> {
+        Err("Execution with an output buffer is not supported by this VM".to_string())
+    }
+    /// Executes a given eBPF program on the payload region of a CoAP packet.
+    /// The region starts with the request payload (`request_len` bytes), the
+    /// program transforms it into the response payload in place and returns
+    /// its length. The program receives a
+    /// [`super::middleware::PayloadContext`] in r1, which also carries the path
+    /// id (`args[0]`, see [`VirtualMachine::set_args`]). Interpreted programs
+    /// can only access the context and the region.
+    fn execute_on_payload(
+        &mut self,
+        _payload: &mut [u8],
+        _request_len: usize,
+    ) -> Result<u64, String> {
+        Err("Execution on the payload is not supported by this VM".to_string())
+    }
+    /// Sets the integer arguments passed to the program when it is executed
+    /// without any input data (see [`VirtualMachine::execute`]).
+    ///
+    /// The JIT passes them directly in r1-r4 (`args[0]` in r1, ..., `args[3]`
+    /// in r4). The rBPF interpreter can only set r1, so it receives a pointer
+    /// to the four arguments stored as consecutive `u64` values instead.
+    /// On 32-bit targets the JIT truncates each argument to its lower 32 bits.
+    /// When executing on a CoAP packet, the JIT passes `args[0]` in r2 (r1
+    /// holds the CoAP context) and the remaining arguments are ignored.
+    /// VMs that don't support arguments ignore them.
+    fn set_args(&mut self, _args: [u64; 4]) {}
+    /// Sets the execution model of the program, it needs to be called before
+    /// the VM is initialised. Helpers which aren't available in the model
+    /// (see [`super::middleware::helpers::is_available_in`]) aren't
+    /// registered and the programs calling them fail the pre-flight helper
+    /// access verification. If it isn't called, all allowed helpers are
+    /// available. VMs that don't register the helpers themselves ignore it.
+    fn set_execution_model(&mut self, _model: ExecutionModel) {}
     /// Returns the length of the program that is currently loaded into the VM.
     /// This is used for benchmarking, because when we are using the jit, we
     /// don't know the final program size until we execute it.
     fn get_program_length(&self) -> usize;
 }
 
+/// Runs all stages of the program execution, the failing stage determines the
+/// status of the result. It is the default implementation of
+/// [`VirtualMachine::full_run_with_status`], exposed so that wrappers
+/// overriding it (e.g. [`super::TimedVm`]) can reuse it.
+pub fn run_with_status<VM: VirtualMachine + ?Sized>(vm: &mut VM) -> ExecutionResult {
+    if let Err(e) = vm.initialize_vm() {
+        error!(target: targets::VM, "Failed to initialize the VM: {}", e);
+        return ExecutionResult::error(VmStatus::InitializationFailed);
+    }
+    if let Err(e) = vm.verify() {
+        error!(target: targets::VM, "Program verification failed: {}", e);
+        return ExecutionResult::error(VmStatus::VerificationFailed);
+    }
+    execution_result(vm.execute())
+}
+
+/// Converts the outcome of executing a program into an [`ExecutionResult`],
+/// the status is determined based on the error reported by the VM.
+pub fn execution_result(result: Result<u64, String>) -> ExecutionResult {
+    match result {
+        Ok(value) => ExecutionResult::ok(value),
+        // The interpreter reports the errors as strings, the memory checks
+        // fail with an 'out of bounds memory' error.
+        Err(e) if e.contains("out of bounds memory") => {
+            error!(target: targets::VM, "Program tried to access invalid memory: {}", e);
+            ExecutionResult::error(VmStatus::MemoryAccessViolation)
+        }
+        Err(e) => {
+            error!(target: targets::VM, "Program execution failed: {}", e);
+            ExecutionResult::error(VmStatus::ExecutionFailed)
+        }
+    }
+}
+
+/// Measures how long the current thread spends executing a program, the
+/// execution time is charged to the client quota of the request being handled
+/// by the thread (see [`client_quota`]). The start time is kept in the state of
+/// the execution of the current thread (see [`execution_state`]), so that it
+/// is measured the same way for programs executed by the VM workers and for
+/// the ones executed inline in the CoAP handlers.
+pub struct ExecutionTimer;
+
+impl ExecutionTimer {
+    /// Starts measuring the execution of the program about to be executed by
+    /// the current thread.
+    pub fn start() -> Self {
+        execution_state::start(ExecutionState {
+            started_at_us: clock::now_us(),
+        });
+        ExecutionTimer
+    }
+
+    /// Stops measuring the execution and returns the time (in microseconds)
+    /// that the VM spent executing the program.
+    pub fn finish(self) -> u32 {
+        let Some(state) = execution_state::finish() else {
+            return 0;
+        };
+        let elapsed_us = clock::since_us(state.started_at_us);
+        client_quota::charge_execution(elapsed_us);
+        elapsed_us
+    }
+}
+
+/// Constructs the VM and runs the short-lived program. The result is returned
+/// together with the execution time (in microseconds), see [`ExecutionTimer`].
+pub fn run_short_lived(
+    configuration: VMConfiguration,
+    allowed_helpers: Vec<HelperFunctionID>,
+) -> Result<(ExecutionResult, u32), String> {
+    let mut vm = construct_vm(configuration, allowed_helpers)?;
+    vm.set_execution_model(ExecutionModel::ShortLived);
+    let timer = ExecutionTimer::start();
+    let result = vm.full_run_with_status();
+    let elapsed_us = timer.finish();
+    Ok((result, elapsed_us))
+}
+
+/// VM used when the target isn't specified (e.g. by the `bpf-execute` shell
+/// command). Deployments which only ever use FemtoContainers can select it
+/// using the `femtocontainer-default` feature.
+pub const DEFAULT_VM_TARGET: TargetVM = if cfg!(feature = "femtocontainer-default") {
+    TargetVM::FemtoContainer
+} else {
+    TargetVM::Rbpf
+};
+
+/// Name of [`DEFAULT_VM_TARGET`], as accepted by `TargetVM::from_str`.
+pub const DEFAULT_VM_TARGET_NAME: &str = match DEFAULT_VM_TARGET {
+    TargetVM::Rbpf => "rBPF",
+    TargetVM::FemtoContainer => "FemtoContainer",
+};
+
 /// Responsible for constructing the VM. It loads the program bytecode from the
 /// SUIT storage, and initialises the correct version of the VM struct.
 /// The reason we do both of those things at the same time is that the lifetime
@@ -60,6 +229,10 @@ pub fn construct_vm<'a>(
 ) -> Result<Box<dyn VirtualMachine>, String> {
 
     if config.jit {
+        // The JIT compiler only supports the rBPF instruction set.
+        if !matches!(config.vm_target, TargetVM::Rbpf) {
+            Err(format!("JIT is not supported for {:?}", config.vm_target))?;
+        }
         return Ok(Box::new(RbpfJIT::new(config, allowed_helpers)));
     }
 
@@ -68,6 +241,10 @@ pub fn construct_vm<'a>(
             return Ok(Box::new(RbpfVm::new(config, allowed_helpers)?));
         }
         TargetVM::FemtoContainer => {
+            // Its helpers can't be restricted, see helper_policy.
+            if helper_policy::is_set() {
+                Err("FemtoContainer VM can't be used while a helper policy is set")?;
+            }
             return Ok(Box::new(FemtoContainerVm::new(config.suit_slot)));
         }
     }
@@ -1,25 +1,41 @@
-use core::ffi::c_void;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
-use log::{debug, error, info};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use log::{debug, error, info, log_enabled, trace, warn, Level};
+use macros::set_env_or_default;
+use micro_bpf_common::{ExecutionModel, VMExecutionRequest};
 
 use riot_wrappers::{
     msg::v2::{MessageSemantics, NoConfiguredMessages, Processing, ReceivePort, SendPort},
     mutex::Mutex,
     stdio::println,
     thread::{self},
+    ztimer,
 };
 
 use riot_sys;
 use riot_sys::msg_t;
 
-use micro_bpf_common::VMExecutionRequest;
-
 use crate::{
-    infra::suit_storage::{self, SUIT_STORAGE_SLOT_SIZE},
-    model::requests::{VMExecutionCompleteMsg, VMExecutionRequestIPC},
+    infra::{
+        crash_diagnostics, heap_stats, last_results,
+        suit_storage::{self, SUIT_STORAGE_SLOT_SIZE},
+    },
+    model::{
+        requests::{
+            correlation_tag, ExecutionJob, VMExecutionCompleteMsg, VMExecutionRequestIPC,
+            WorkerSelection,
+        },
+        results::{ExecutionResult, VmStatus},
+    },
     spawn_thread,
-    vm::construct_vm,
+    util::logger::targets,
+    vm::{construct_vm, hot_reload, ExecutionTimer},
 };
 
 // Because of the lifetime rules we need to preallocate the stacks of all of the
@@ -29,7 +45,134 @@ static VM_WORKER_1_STACK: Mutex<[u8; 4096]> = Mutex::new([0; 4096]);
 static VM_WORKER_2_STACK: Mutex<[u8; 4096]> = Mutex::new([0; 4096]);
 static VM_WORKER_3_STACK: Mutex<[u8; 4096]> = Mutex::new([0; 4096]);
 
-pub static RUNNING_WORKERS: Mutex<[bool; 4]> = Mutex::new([false; 4]);
+/// Number of VM worker threads, they are indexed from 0 to `NUM_WORKERS - 1`.
+pub const NUM_WORKERS: usize = 4;
+
+pub static RUNNING_WORKERS: Mutex<[bool; NUM_WORKERS]> = Mutex::new([false; NUM_WORKERS]);
+
+/// Number of attempts at sending the completion notification to the manager
+/// before falling back to [`PENDING_RELEASES`].
+const NOTIFICATION_SEND_ATTEMPTS: u32 = 3;
+/// Delay between the attempts at sending the completion notification.
+const NOTIFICATION_RETRY_DELAY_MS: u32 = 10;
+
+/// Set by a worker that finished executing its program, but couldn't notify
+/// the manager about it (e.g. because the message queue of the manager was
+/// full). The manager checks the flags every time it receives a message and
+/// releases the flagged workers, so that a lost notification doesn't take the
+/// worker out of the pool permanently. Each flag is only set by its worker
+/// and cleared by the manager, so no read-modify-write operations are needed.
+static PENDING_RELEASES: [AtomicBool; NUM_WORKERS] = [NOT_PENDING; NUM_WORKERS];
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Set once the global storage used by the VM helpers has been initialised.
+/// A mutex is used instead of an atomic flag, as the check and the update need
+/// to happen together and not all boards support atomic read-modify-write
+/// instructions.
+static GLOBAL_STORAGE_INITIALISED: Mutex<bool> = Mutex::new(false);
+
+/// Initialises the global storage for the VM helpers. Currently we repurpose
+/// the Femto-Container implementation, which doesn't guard against being
+/// initialised twice (it would drop all stored values), so the initialisation
+/// is only performed on the first call after boot, subsequent calls are
+/// skipped.
+fn init_global_storage() {
+    extern "C" {
+        fn bpf_store_init();
+    }
+
+    // The lock is held until the initialisation completes.
+    let mut initialised = GLOBAL_STORAGE_INITIALISED.lock();
+    if !claim_initialisation(&mut initialised) {
+        warn!(target: targets::VM, "Global storage is already initialised, skipping");
+        return;
+    }
+    unsafe {
+        bpf_store_init();
+    }
+}
+
+/// Marks the initialisation as performed, returns false if it already was.
+fn claim_initialisation(initialised: &mut bool) -> bool {
+    !core::mem::replace(initialised, true)
+}
+
+/// Results of the programs most recently executed by each of the workers
+/// (keyed by the worker PID). The return value is stored unmodified, its
+/// interpretation (e.g. signedness) is left to the client. They can't be sent
+/// in the completion notification as they don't fit into an IPC message.
+pub static WORKER_RESULTS: Mutex<BTreeMap<i16, ExecutionResult>> = Mutex::new(BTreeMap::new());
+
+/// Correlation IDs of the requests most recently executed by each of the
+/// workers (keyed by the worker PID), they are reported together with the
+/// results in [`WORKER_RESULTS`] for the same reason.
+static WORKER_CORRELATION_IDS: Mutex<BTreeMap<i16, u32>> = Mutex::new(BTreeMap::new());
+
+/// Number of times the supervisor restarts a long-running program which
+/// crashed (see [`VmStatus::is_fault`]) before giving up. The supervisor is
+/// disabled by default, it is enabled by setting `SUPERVISOR_MAX_RESTARTS` to
+/// a non-zero value at compile time.
+const SUPERVISOR_MAX_RESTARTS: usize = set_env_or_default!("SUPERVISOR_MAX_RESTARTS", 0);
+/// Delay before the first restart, it is doubled for every subsequent one.
+const SUPERVISOR_BACKOFF_MS: usize = set_env_or_default!("SUPERVISOR_BACKOFF_MS", 100);
+/// The backoff stops growing once it reaches this delay.
+const SUPERVISOR_MAX_BACKOFF_MS: u32 = 10_000;
+
+/// Jobs of the programs that crashed on each of the workers (keyed by the
+/// worker PID). They are put here by the worker before it notifies the
+/// manager, which then decides whether to restart them.
+static CRASHED_JOBS: Mutex<BTreeMap<i16, ExecutionJob>> = Mutex::new(BTreeMap::new());
+
+/// Number of times the program in each of the SUIT storage slots was
+/// restarted by the supervisor. The count is reset when a new execution
+/// request for the slot is received.
+static RESTART_COUNTS: Mutex<BTreeMap<usize, u32>> = Mutex::new(BTreeMap::new());
+
+/// Results of the executions requested with a ticket together with their
+/// execution time in microseconds, see [`wait_for_result`].
+static TICKET_RESULTS: Mutex<BTreeMap<u32, (ExecutionResult, u32)>> = Mutex::new(BTreeMap::new());
+static NEXT_TICKET: Mutex<u32> = Mutex::new(0);
+/// Results that nobody waits for (e.g. because the wait timed out) are
+/// dropped, starting with the oldest ones, once there are more of them.
+const MAX_TICKET_RESULTS: usize = 8;
+/// Interval at which [`wait_for_result`] checks whether the result is ready.
+const RESULT_POLL_INTERVAL_MS: u32 = 10;
+
+/// Returns a new ticket for an execution request, see [`wait_for_result`].
+pub fn new_ticket() -> u32 {
+    let mut next = NEXT_TICKET.lock();
+    let ticket = *next;
+    *next = next.wrapping_add(1);
+    ticket
+}
+
+fn complete_ticket(ticket: u32, result: ExecutionResult, elapsed_us: u32) {
+    let mut results = TICKET_RESULTS.lock();
+    results.insert(ticket, (result, elapsed_us));
+    while results.len() > MAX_TICKET_RESULTS {
+        results.pop_first();
+    }
+}
+
+/// Blocks until the execution requested with the ticket finishes and returns
+/// its result and execution time in microseconds. Returns `None` if it doesn't
+/// finish within the timeout (e.g. the request was rejected because all
+/// workers were busy, or the program is long-running).
+pub fn wait_for_result(ticket: u32, timeout_ms: u32) -> Option<(ExecutionResult, u32)> {
+    let clock = ztimer::Clock::msec();
+    let mut waited_ms = 0;
+    loop {
+        if let Some(result) = TICKET_RESULTS.lock().remove(&ticket) {
+            return Some(result);
+        }
+        if waited_ms >= timeout_ms {
+            return None;
+        }
+        clock.sleep_ticks(RESULT_POLL_INTERVAL_MS);
+        waited_ms += RESULT_POLL_INTERVAL_MS;
+    }
+}
 
 /// The unique identifier of the request type used to start the execution of the VM.
 pub const VM_EXEC_REQUEST: u16 = 23;
@@ -40,6 +183,78 @@ pub type VMExecutionCompletePort = ReceivePort<VMExecutionCompleteMsg, VM_COMPLE
 pub type ExecutionSendPort = Arc<Mutex<SendPort<VMExecutionRequestIPC, VM_EXEC_REQUEST>>>;
 pub type CompletionSendPort = Arc<Mutex<SendPort<VMExecutionCompleteMsg, VM_COMPLETE_NOTIFY>>>;
 
+/// Keeps track of which of the worker threads are free to be assigned a new
+/// execution request. Workers are identified by their PIDs, the index of
+/// each worker (its position in the order the workers were spawned in) is
+/// used for indexing the per-worker state (e.g. [`RUNNING_WORKERS`]).
+struct WorkerPool {
+    free_workers: Vec<i16>,
+    pid_to_worker_index: BTreeMap<i16, usize>,
+    /// Requests pinned to a worker that was busy when they arrived, keyed by
+    /// the worker index. They are dispatched once the worker finishes.
+    queued: BTreeMap<usize, VecDeque<VMExecutionRequestIPC>>,
+}
+
+impl WorkerPool {
+    fn new(worker_pids: &[i16]) -> Self {
+        Self {
+            free_workers: worker_pids.to_vec(),
+            pid_to_worker_index: worker_pids
+                .iter()
+                .enumerate()
+                .map(|(i, pid)| (*pid, i))
+                .collect(),
+            queued: BTreeMap::new(),
+        }
+    }
+
+    /// Takes one of the free workers out of the pool, returning its PID and
+    /// index, or `None` if all workers are busy.
+    fn try_dispatch(&mut self) -> Option<(i16, usize)> {
+        let pid = self.free_workers.pop()?;
+        Some((pid, self.pid_to_worker_index[&pid]))
+    }
+
+    /// Takes the worker with the given index out of the pool, returning its
+    /// PID, or `None` if it is busy or there is no such worker.
+    fn try_dispatch_to(&mut self, index: usize) -> Option<i16> {
+        let position = self
+            .free_workers
+            .iter()
+            .position(|pid| self.pid_to_worker_index[pid] == index)?;
+        Some(self.free_workers.remove(position))
+    }
+
+    /// Queues a request until the worker with the given index becomes free.
+    fn enqueue(&mut self, index: usize, request: VMExecutionRequestIPC) {
+        self.queued.entry(index).or_default().push_back(request);
+    }
+
+    /// Returns the oldest request queued for the worker with the given index.
+    fn take_queued(&mut self, index: usize) -> Option<VMExecutionRequestIPC> {
+        self.queued.get_mut(&index)?.pop_front()
+    }
+
+    /// Returns the PID of the worker with the given index.
+    fn pid_of(&self, index: usize) -> Option<i16> {
+        self.pid_to_worker_index
+            .iter()
+            .find(|(_, i)| **i == index)
+            .map(|(pid, _)| *pid)
+    }
+
+    /// Puts the worker back into the pool and returns its index. Unknown
+    /// PIDs and workers that are already free are rejected.
+    fn release(&mut self, pid: i16) -> Option<usize> {
+        let index = *self.pid_to_worker_index.get(&pid)?;
+        if self.free_workers.contains(&pid) {
+            return None;
+        }
+        self.free_workers.push(pid);
+        Some(index)
+    }
+}
+
 /// Responsible for managing execution of long-running eBPF programs. It receives
 /// messages from other parts of the system that are requesting that a particular
 /// instance of the VM should be started and execute a specified program.
@@ -93,15 +308,7 @@ impl VMExecutionManager {
     /// eBPF programs. It spawns worker threads and then sends messages to them to
     /// start executing long running eBPF programs.
     pub fn start(&self) {
-        extern "C" {
-            fn bpf_store_init();
-        }
-
-        // We need to initialise the global storage for the VM helpers.
-        // Currently we repurpose the Femto-Container implementation
-        unsafe {
-            bpf_store_init();
-        }
+        init_global_storage();
 
         let mut worker_0_stack = VM_WORKER_0_STACK.lock();
         let mut worker_1_stack = VM_WORKER_1_STACK.lock();
@@ -110,10 +317,10 @@ impl VMExecutionManager {
 
         let notification_port = self.notification_send_port.clone();
 
-        let mut worker_0_main = || vm_main_thread(&notification_port);
-        let mut worker_1_main = worker_0_main.clone();
-        let mut worker_2_main = worker_0_main.clone();
-        let mut worker_3_main = worker_0_main.clone();
+        let mut worker_0_main = || vm_main_thread(0, &notification_port);
+        let mut worker_1_main = || vm_main_thread(1, &notification_port);
+        let mut worker_2_main = || vm_main_thread(2, &notification_port);
+        let mut worker_3_main = || vm_main_thread(3, &notification_port);
 
         thread::scope(|ts| {
             let pri = riot_sys::THREAD_PRIORITY_MAIN;
@@ -127,35 +334,34 @@ impl VMExecutionManager {
             let worker_2 = spawn_thread!(ts, "Worker 2", worker_2_stack, worker_2_main, pri - 3);
             let worker_3 = spawn_thread!(ts, "Worker 3", worker_3_stack, worker_3_main, pri - 2);
 
-            let mut pid_to_worker_index: BTreeMap<i16, usize> = BTreeMap::new();
-            pid_to_worker_index.insert(worker_0.pid().into(), 0);
-            pid_to_worker_index.insert(worker_1.pid().into(), 1);
-            pid_to_worker_index.insert(worker_2.pid().into(), 2);
-            pid_to_worker_index.insert(worker_3.pid().into(), 3);
-
-            let mut free_workers: Vec<i16> = alloc::vec![
+            // The order of the PIDs determines the worker indices.
+            let mut workers = WorkerPool::new(&[
                 worker_0.pid().into(),
                 worker_1.pid().into(),
                 worker_2.pid().into(),
                 worker_3.pid().into(),
-            ];
+            ]);
 
             loop {
                 let message = self.message_semantics.receive();
 
+                // Workers that failed to send their completion notification
+                // are released before processing the message.
+                Self::handle_pending_releases(&mut workers);
+
                 // First process any completion notifications
                 let result = message.decode(&self.notification_receive_port, |_s, notification| {
-                    Self::handle_job_complete_notification(&mut free_workers, &notification, pid_to_worker_index.clone())
+                    Self::handle_job_complete_notification(&mut workers, &notification)
                 });
 
                 // Now handle any execution requests
                 let code = if let Err(message) = result {
                     message
                         .decode(&self.request_receive_port, |_s, execution_request| {
-                            Self::handle_execution_request(&mut free_workers, execution_request, pid_to_worker_index.clone())
+                            Self::handle_execution_request(&mut workers, execution_request)
                         })
                         .unwrap_or_else(|_m| {
-                            error!("Failed to decode message.");
+                            error!(target: targets::WORKER, "Failed to decode message.");
                         });
                 } else {
                     result.unwrap();
@@ -166,38 +372,168 @@ impl VMExecutionManager {
         });
     }
 
-    pub fn handle_execution_request(workers: &mut Vec<i16>, request: VMExecutionRequestIPC, pid_to_worker_index: BTreeMap<i16, usize>) {
-        if workers.is_empty() {
-            error!("No free workers to execute the request.");
+    fn handle_execution_request(workers: &mut WorkerPool, request: VMExecutionRequestIPC) {
+        if request.job.restart_delay_ms.is_none() {
+            RESTART_COUNTS
+                .lock()
+                .remove(&request.job.request.configuration.suit_slot);
+        }
+        let dispatched = match request.job.worker {
+            WorkerSelection::Any => workers.try_dispatch(),
+            WorkerSelection::Pinned { index, .. } if index >= NUM_WORKERS => {
+                error!(target: targets::WORKER, "Invalid worker index: {}", index);
+                return;
+            }
+            WorkerSelection::Pinned { index, no_wait } => match workers.try_dispatch_to(index) {
+                Some(pid) => Some((pid, index)),
+                None if no_wait => {
+                    error!(target: targets::WORKER, "Worker {} is busy, request rejected.", index);
+                    return;
+                }
+                None => {
+                    info!(target: targets::WORKER, "Worker {} is busy, request queued.", index);
+                    workers.enqueue(index, request);
+                    return;
+                }
+            },
+        };
+        let Some((pid, worker_index)) = dispatched else {
+            error!(target: targets::WORKER, "No free workers to execute the request.");
             return;
+        };
+
+        RUNNING_WORKERS.lock()[worker_index] = true;
+        info!(target: targets::WORKER, "Sending execution request to the worker with PID: {}", pid);
+        let mut msg = request.into_msg();
+        // The worker takes the ownership of the request when it receives
+        // the message, if it can't be delivered, it needs to be freed here.
+        if unsafe { riot_sys::msg_send(&mut msg as *mut msg_t, pid) } < 1 {
+            error!(target: targets::WORKER, "Failed to send the request to the worker: {}", pid);
+            drop(unsafe { VMExecutionRequestIPC::from_msg(msg) });
+            // The worker never started, so it is put back into the pool.
+            RUNNING_WORKERS.lock()[worker_index] = false;
+            workers.release(pid);
         }
-        let pid: riot_sys::kernel_pid_t = workers.pop().unwrap();
+    }
 
-        let mut guard = RUNNING_WORKERS.lock();
-        guard[pid_to_worker_index[(&pid).into()]] = true;
-        info!("Sending execution request to the worker with PID: {}", pid);
-        let mut execution_request = *request.request;
-        let mut msg: msg_t = Default::default();
-        msg.type_ = 0;
-        msg.content.ptr = &mut execution_request as *mut VMExecutionRequest as *mut c_void;
-        unsafe {
-            riot_sys::msg_send(&mut msg as *mut msg_t, pid);
-        };
+    fn handle_pending_releases(workers: &mut WorkerPool) {
+        for (worker_index, pending) in PENDING_RELEASES.iter().enumerate() {
+            if !pending.load(Ordering::Acquire) {
+                continue;
+            }
+            pending.store(false, Ordering::Release);
+            let Some(worker_pid) = workers.pid_of(worker_index) else {
+                continue;
+            };
+            info!(
+                target: targets::WORKER,
+                "Worker {} failed to send its completion notification, releasing it",
+                worker_index
+            );
+            Self::handle_job_complete_notification(
+                workers,
+                &VMExecutionCompleteMsg::new(worker_pid),
+            );
+        }
     }
 
-    pub fn handle_job_complete_notification(
-        workers: &mut Vec<i16>,
+    fn handle_job_complete_notification(
+        workers: &mut WorkerPool,
         notification: &VMExecutionCompleteMsg,
-pid_to_worker_index: BTreeMap<i16, usize>
     ) {
         info!(
+            target: targets::WORKER,
             "Received notification from worker with PID: {}
             Adding worker back to the pool of free workers.",
             notification.worker_pid
         );
-        workers.push(notification.worker_pid);
-        let mut guard = RUNNING_WORKERS.lock();
-        guard[pid_to_worker_index[&notification.worker_pid]] = false;
+        if let Some(result) = WORKER_RESULTS.lock().get(&notification.worker_pid) {
+            let correlation_id = WORKER_CORRELATION_IDS
+                .lock()
+                .get(&notification.worker_pid)
+                .copied();
+            info!(
+                target: targets::WORKER,
+                "Program returned: {:#x} ({:?}){}",
+                result.value,
+                result.status,
+                correlation_tag(correlation_id),
+            );
+        }
+        let Some(worker_index) = workers.release(notification.worker_pid) else {
+            error!(
+                target: targets::WORKER,
+                "Unexpected completion notification from PID: {}",
+                notification.worker_pid
+            );
+            return;
+        };
+        RUNNING_WORKERS.lock()[worker_index] = false;
+
+        if let Some(job) = CRASHED_JOBS.lock().remove(&notification.worker_pid) {
+            Self::supervise_crashed_job(workers, job);
+        }
+
+        if let Some(request) = workers.take_queued(worker_index) {
+            info!(target: targets::WORKER, "Dispatching queued request to worker {}", worker_index);
+            Self::handle_execution_request(workers, request);
+        }
+    }
+
+    /// Re-dispatches the job of a program that crashed, unless it was already
+    /// restarted [`SUPERVISOR_MAX_RESTARTS`] times. The restarts are delayed
+    /// with an exponential backoff so that a program crashing right after it
+    /// starts doesn't keep its worker busy.
+    fn supervise_crashed_job(workers: &mut WorkerPool, mut job: ExecutionJob) {
+        let slot = job.request.configuration.suit_slot;
+        let tag = correlation_tag(job.correlation_id);
+        let restarts = *RESTART_COUNTS.lock().get(&slot).unwrap_or(&0);
+        if restarts as usize >= SUPERVISOR_MAX_RESTARTS {
+            error!(
+                target: targets::WORKER,
+                "Program in slot {} crashed after {} restarts, giving up{}",
+                slot,
+                restarts,
+                tag
+            );
+            return;
+        }
+        RESTART_COUNTS.lock().insert(slot, restarts + 1);
+        let delay_ms = (SUPERVISOR_BACKOFF_MS as u32)
+            .saturating_mul(1 << restarts.min(16))
+            .min(SUPERVISOR_MAX_BACKOFF_MS);
+        warn!(
+            target: targets::WORKER,
+            "Program in slot {} crashed, restarting it in {} [ms] ({}/{}){}",
+            slot,
+            delay_ms,
+            restarts + 1,
+            SUPERVISOR_MAX_RESTARTS,
+            tag
+        );
+        job.restart_delay_ms = Some(delay_ms);
+        // The ticket was already completed with the result of the crashed run.
+        job.ticket = None;
+        Self::handle_execution_request(workers, VMExecutionRequestIPC { job: Box::new(job) });
+    }
+}
+
+/// Logs the heap usage at the trace level, comparing the usage before and
+/// after the executions of the same program allows for spotting leaks. The
+/// statistics are only sampled if the trace level is enabled for the workers.
+fn log_heap_usage(when: &str, slot: usize) {
+    if !log_enabled!(target: targets::WORKER, Level::Trace) {
+        return;
+    }
+    if let Ok(stats) = heap_stats::sample() {
+        trace!(
+            target: targets::WORKER,
+            "Heap usage {} executing slot {}: {} [B] (peak: {} [B])",
+            when,
+            slot,
+            stats.used,
+            stats.peak
+        );
     }
 }
 
@@ -206,7 +542,7 @@ pid_to_worker_index: BTreeMap<i16, usize>
 /// a message is received, the worker starts executing the program until it
 /// terminates. Current limitation is that the worker has no way of preempting
 /// the executing program unless it crashes or voluntarily terminates.
-fn vm_main_thread(send_port: &CompletionSendPort) {
+fn vm_main_thread(worker_index: usize, send_port: &CompletionSendPort) {
     loop {
         // Here we use the msg v1 RIOT API as each VM worker cannot pass the
         // send port back to the VM manager (who created it).
@@ -215,41 +551,200 @@ fn vm_main_thread(send_port: &CompletionSendPort) {
             let _ = riot_sys::msg_receive(&mut msg);
         }
 
-        let wrapper: VMExecutionRequestIPC = msg.into();
-        let request = *wrapper.request;
+        // Safety: the manager only sends messages created using `into_msg`.
+        let wrapper = unsafe { VMExecutionRequestIPC::from_msg(msg) };
+        let job = *wrapper.job;
+        let (request, ticket) = (job.request, job.ticket);
+        let tag = correlation_tag(job.correlation_id);
+
+        if let Some(delay_ms) = job.restart_delay_ms {
+            ztimer::Clock::msec().sleep_ticks(delay_ms);
+        }
 
         info!(
-            "Received an execution request to spawn a VM with configuration: {:?}",
+            target: targets::WORKER,
+            "Received an execution request{} to spawn a VM with configuration: {:?}",
+            tag,
             request.configuration
         );
+        let worker_pid: i16 = thread::get_pid().into();
+        match job.correlation_id {
+            Some(id) => WORKER_CORRELATION_IDS.lock().insert(worker_pid, id),
+            None => WORKER_CORRELATION_IDS.lock().remove(&worker_pid),
+        };
 
-        if let Ok(mut vm) = construct_vm(
-            request.configuration,
-            request.allowed_helpers,
-        ) {
-            // We notify everyone that the slot we are using holds a long running VM.
-            suit_storage::suit_mark_slot_running(request.configuration.suit_slot as usize);
-
-            let execution_result = vm.full_run();
-            if let Ok(result) = execution_result {
-                info!("return: {}", result);
-            } else  {
-                error!("Error: {:?}", execution_result.unwrap_err());
+        let mut configuration = request.configuration;
+        // Outcome of the last run, it is reported if the request has a ticket.
+        let mut outcome = (ExecutionResult::error(VmStatus::InitializationFailed), 0);
+        loop {
+            let slot = configuration.suit_slot;
+            let slot_lock = suit_storage::lock_slot_for_execution(slot);
+            if let Err(e) = slot_lock {
+                error!(target: targets::WORKER, "{}{}", e, tag);
+            } else if let Ok(mut vm) = construct_vm(configuration, request.allowed_helpers.clone())
+            {
+                vm.set_execution_model(ExecutionModel::LongRunning);
+                // We notify everyone that the slot we are using holds a long running VM.
+                suit_storage::suit_mark_slot_running(slot);
+
+                // Record the slot so that it can be reported if the program
+                // crashes the device.
+                crash_diagnostics::mark_running(worker_index, slot);
+                log_heap_usage("before", slot);
+                let timer = ExecutionTimer::start();
+                let result = vm.full_run_with_status();
+                let elapsed_us = timer.finish();
+                outcome = (result, elapsed_us);
+                last_results::record(slot, outcome.0, outcome.1);
+                crash_diagnostics::clear_running(worker_index);
+                drop(vm);
+                log_heap_usage("after", slot);
+                info!(
+                    target: targets::WORKER,
+                    "return: {} ({:?}){}",
+                    result.value,
+                    result.status,
+                    tag
+                );
+                WORKER_RESULTS.lock().insert(worker_pid, result);
+                // Now we mark that the slot still contains the program but noone is currently
+                // executing it
+                suit_storage::suit_mark_slot_occupied(slot);
+            } else {
+                error!(target: targets::WORKER, "Failed to initialize the VM.");
             };
-            // Now we mark that the slot still contains the program but noone is currently
-            // executing it
-            suit_storage::suit_mark_slot_occupied(request.configuration.suit_slot as usize);
-        } else {
-            error!("Failed to initialize the VM.");
-        };
+            drop(slot_lock);
+
+            // If a reload was requested while the program was running, we
+            // continue with the new version (or restart the old one if the
+            // new version is rejected), see hot_reload for more details.
+            let Some(staging_slot) = hot_reload::take_pending_reload(slot) else {
+                break;
+            };
+            match hot_reload::prepare_reload(configuration, &request.allowed_helpers, staging_slot)
+            {
+                Ok(new_configuration) => {
+                    info!(
+                        target: targets::WORKER,
+                        "Replacing program in slot {} with slot {}",
+                        slot,
+                        staging_slot,
+                    );
+                    configuration = new_configuration;
+                }
+                Err(e) => error!(
+                    target: targets::WORKER,
+                    "Reload rejected, restarting the old program: {}",
+                    e
+                ),
+            }
+        }
+
+        if let Some(ticket) = ticket {
+            complete_ticket(ticket, outcome.0, outcome.1);
+        }
+
+        // The supervisor decides whether to restart the crashed program once
+        // it receives the completion notification.
+        if SUPERVISOR_MAX_RESTARTS > 0 && outcome.0.status.is_fault() {
+            let job = ExecutionJob {
+                request: VMExecutionRequest {
+                    configuration,
+                    allowed_helpers: request.allowed_helpers,
+                },
+                worker: job.worker,
+                ticket: None,
+                correlation_id: job.correlation_id,
+                restart_delay_ms: None,
+            };
+            CRASHED_JOBS.lock().insert(worker_pid, job);
+        }
 
         // Now we notify the VM execution manager that the eBPF program has
         // terminated and so the manager add us to the pool of free workers
         // and send new execution requests
-        let completion_notification = VMExecutionCompleteMsg::new(thread::get_pid().into());
-        match send_port.lock().try_send(completion_notification) {
-            Ok(()) => info!("VM execution completion notification sent successfully"),
-            Err(_) => error!("Failed to send notification message."),
+        notify_completion(worker_index, send_port);
+    }
+}
+
+/// Sends the completion notification to the manager. The manager could be
+/// busy (its message queue full), so the sending is retried a few times.
+/// If it still fails, the worker is flagged in [`PENDING_RELEASES`] instead.
+fn notify_completion(worker_index: usize, send_port: &CompletionSendPort) {
+    let mut notification = VMExecutionCompleteMsg::new(thread::get_pid().into());
+    for attempt in 1..=NOTIFICATION_SEND_ATTEMPTS {
+        // The message is handed back if it can't be delivered.
+        match send_port.lock().try_send(notification) {
+            Ok(()) => {
+                info!(target: targets::WORKER, "Completion notification sent successfully");
+                return;
+            }
+            Err(returned) => notification = returned,
         }
+        // Non-blocking sends only fail if the manager isn't waiting for
+        // a message and its message queue is full.
+        error!(
+            target: targets::WORKER,
+            "Failed to send completion notification ({}/{}): manager message queue full",
+            attempt,
+            NOTIFICATION_SEND_ATTEMPTS
+        );
+        if attempt < NOTIFICATION_SEND_ATTEMPTS {
+            ztimer::Clock::msec().sleep_ticks(NOTIFICATION_RETRY_DELAY_MS);
+        }
+    }
+    error!(
+        target: targets::WORKER,
+        "Worker {} couldn't notify the manager, flagging it for release",
+        worker_index
+    );
+    PENDING_RELEASES[worker_index].store(true, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_storage_is_initialised_once() {
+        let mut initialised = false;
+        assert!(claim_initialisation(&mut initialised));
+        assert!(!claim_initialisation(&mut initialised));
+        assert!(initialised);
+    }
+
+    #[test]
+    fn workers_are_dispatched_until_the_pool_is_empty() {
+        let mut workers = WorkerPool::new(&[10, 11]);
+        let first = workers.try_dispatch().unwrap();
+        let second = workers.try_dispatch().unwrap();
+        assert_ne!(first, second);
+        assert!([(10, 0), (11, 1)].contains(&first));
+        assert!(workers.try_dispatch().is_none());
+
+        assert_eq!(workers.release(first.0), Some(first.1));
+        assert_eq!(workers.try_dispatch(), Some(first));
+    }
+
+    #[test]
+    fn pinned_dispatch_takes_the_given_worker() {
+        let mut workers = WorkerPool::new(&[10, 11, 12]);
+        assert_eq!(workers.try_dispatch_to(1), Some(11));
+        assert_eq!(workers.try_dispatch_to(1), None);
+        assert_eq!(workers.try_dispatch_to(3), None);
+        assert_eq!(workers.pid_of(2), Some(12));
+        assert_eq!(workers.release(11), Some(1));
+        assert_eq!(workers.try_dispatch_to(1), Some(11));
+    }
+
+    #[test]
+    fn only_busy_workers_are_released() {
+        let mut workers = WorkerPool::new(&[10]);
+        // Releasing a free worker would let it be dispatched twice.
+        assert_eq!(workers.release(10), None);
+        assert_eq!(workers.release(42), None);
+        assert_eq!(workers.try_dispatch(), Some((10, 0)));
+        assert_eq!(workers.release(10), Some(0));
+        assert_eq!(workers.release(10), None);
     }
 }
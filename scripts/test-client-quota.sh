@@ -0,0 +1,82 @@
+# Checks that a client exceeding its execution quota is throttled while the
+# other clients aren't. The firmware needs to be built with a small quota and
+# a long window, e.g. CLIENT_QUOTA_US=5000 CLIENT_QUOTA_WINDOW_MS=60000. The
+# request payload is an encoded execution request (either in the compact
+# encoding used by the tools or as JSON) of a program deployed on the device.
+# The clients are identified by the source addresses of their requests, so
+# the script checks that changing the token doesn't reset the quota. If a
+# second address of the host (assigned to the same interface) is given, it
+# also checks that the requests sent from it are still admitted.
+
+if [[ $# -lt 3 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <request-payload>" \
+        "[max-requests] [second-source-address]"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+payload=$3
+max_requests=${4:-100}
+other_address=${5:-}
+
+python3 - "$network_interface" "$ip_address" "$payload" "$max_requests" "$other_address" \
+    <<'PYTHON'
+import random
+import socket
+import sys
+
+interface, address, payload = sys.argv[1], sys.argv[2], sys.argv[3].encode()
+max_requests, other_address = int(sys.argv[4]), sys.argv[5]
+TOO_MANY_REQUESTS = (4 << 5) | 29
+
+target = socket.getaddrinfo(
+    f"{address}%{interface}", 5683, socket.AF_INET6, socket.SOCK_DGRAM
+)[0][4]
+
+def client(source=None):
+    """Creates a socket sending the requests from the given source address."""
+    sock = socket.socket(socket.AF_INET6, socket.SOCK_DGRAM)
+    sock.settimeout(5)
+    if source:
+        sock.bind(socket.getaddrinfo(
+            f"{source}%{interface}", 0, socket.AF_INET6, socket.SOCK_DGRAM
+        )[0][4])
+    return sock
+
+def post(sock, token):
+    """Sends a confirmable POST to /short-execution, returns the response code
+    and the payload."""
+    message_id = random.getrandbits(16)
+    path = b"short-execution"
+    header = bytes([0x40 | len(token), 0x02]) + message_id.to_bytes(2, "big")
+    # Uri-Path (option 11) with a one byte extended length.
+    option = bytes([(11 << 4) | 13, len(path) - 13]) + path
+    sock.sendto(header + token + option + b"\xff" + payload, target)
+    while True:
+        response, _ = sock.recvfrom(1024)
+        if response[4:4 + (response[0] & 0x0F)] == token:
+            marker = response.find(b"\xff", 4 + len(token))
+            return response[1], response[marker + 1:] if marker >= 0 else b""
+
+throttled_client = client()
+for i in range(1, max_requests + 1):
+    code, body = post(throttled_client, b"tenant-1")
+    if code == TOO_MANY_REQUESTS:
+        print(f"Client throttled after {i - 1} requests: {body.decode()}")
+        break
+else:
+    sys.exit(f"The client wasn't throttled after {max_requests} requests")
+
+code, body = post(throttled_client, b"tenant-2")
+if code != TOO_MANY_REQUESTS:
+    sys.exit("Changing the token allowed the throttled client to execute again")
+
+if not other_address:
+    sys.exit(0)
+
+code, body = post(client(other_address), b"tenant-1")
+if code == TOO_MANY_REQUESTS:
+    sys.exit(f"The other client was throttled as well: {body.decode()}")
+print("The other client was still allowed to execute")
+PYTHON
@@ -0,0 +1,29 @@
+
+# Checks that the correlation ID given in the `cid` URI query of an execution
+# request is echoed back in the responses of both the `/short-execution` and
+# the `/long-running` endpoints. The request payload is an encoded execution
+# request (either in the compact encoding used by the tools or as JSON) of a
+# program that is already deployed on the device.
+
+if [[ $# -lt 3 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <request-payload> [correlation-id]"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+payload=$3
+cid=${4:-1234}
+
+status=0
+for endpoint in short-execution long-running ; do
+    response=$(aiocoap-client -m POST "coap://[$ip_address%$network_interface]/$endpoint?cid=$cid" \
+        --payload "$payload") || exit 1
+    if echo "$response" | grep -q "$cid" ; then
+        echo "/$endpoint echoed the correlation ID $cid"
+    else
+        echo "/$endpoint didn't echo the correlation ID $cid, response: $response"
+        status=1
+    fi
+done
+exit $status
@@ -0,0 +1,53 @@
+
+# Deploys a program into a slot while repeatedly executing the same slot, to
+# check that the executions colliding with the deployment are rejected with
+# the "deploy in progress" error instead of loading a half-written program.
+# Each execution has to either succeed or be rejected, the device must not
+# crash and the final execution has to succeed.
+
+if [[ $# -lt 5 ]] ; then
+    echo "Usage: $0 <bpf-source-file> <network-interface> <board-ip-address> <host-ip-address> <suit-storage-slot>"
+    exit 1
+fi
+
+source_file=$1
+network_interface=$2
+ip_address=$3
+host_ip_address=$4
+slot=$5
+tools=$(dirname "$0")/../tools/target/release/mibpf-tools
+out_dir=$(mktemp -d)
+trap 'rm -rf "$out_dir"' EXIT
+
+execute() {
+    $tools execute --riot-ipv6-addr "$ip_address" --suit-storage-slot "$slot" \
+        --host-network-interface "$network_interface" --target rBPF 2>&1
+}
+
+deploy() {
+    $tools deploy --bpf-source-file "$source_file" --out-dir "$out_dir" -s "$slot" \
+        --riot-ipv6-addr "$ip_address" --host-ipv6-addr "$host_ip_address" \
+        --host-network-interface "$network_interface" --target rBPF --erase > /dev/null
+}
+
+# The program is deployed once up front, so that the executions preceding the
+# overlapping deployment succeed.
+deploy || { echo "Initial deployment failed" ; exit 1 ; }
+
+deploy &
+deploy_pid=$!
+
+rejected=0
+while kill -0 $deploy_pid 2> /dev/null ; do
+    if ! output=$(execute) ; then
+        if ! echo "$output" | grep -q "5.03\|Service Unavailable" ; then
+            echo "Execution failed during the deployment: $output"
+            exit 1
+        fi
+        rejected=$((rejected + 1))
+    fi
+done
+wait $deploy_pid || { echo "Deployment failed" ; exit 1 ; }
+
+execute > /dev/null || { echo "Execution after the deployment failed" ; exit 1 ; }
+echo "Deployment finished, $rejected overlapping executions were rejected"
@@ -0,0 +1,98 @@
+# Checks that the string representations of the micro-bpf-common enums
+# round-trip, i.e. that `from_str(x.to_string())` returns `x` for every
+# variant, and that every string emitted by the clients is accepted by
+# `from_str`. The admin website selectors, the server (/capabilities and the
+# shell usage) and the tools exchange the variants as strings, so any drift
+# between `Display` and `FromStr` makes them send a string the other side can't
+# parse. The variants are taken from `ExecutionModel::all()` and from the
+# strings listed in the website selectors and the server sources, a small
+# program checking them is built against tools/common.
+
+script_dir=$(cd "$(dirname "$0")" && pwd)
+root_dir=$(dirname "$script_dir")
+common_dir="$root_dir/tools/common"
+app="$root_dir/examples/weather-station/admin-tools-website/src/app.rs"
+
+if [ ! -f "$common_dir/Cargo.toml" ] ; then
+    echo "micro-bpf-common not found in $common_dir, check out the tools submodule"
+    exit 1
+fi
+
+# Formats the strings as a Rust array: ["a", "b"]
+rust_array() {
+    echo "[$(sed 's/.*/"&"/' | paste -sd, -)]"
+}
+
+target_vms=$( {
+    grep -o 'value=target_vm is="[^"]*"' "$app" | cut -d'"' -f2
+    grep -o 'TargetVM::[A-Za-z]* => "[^"]*"' "$root_dir/micro-bpf-server/src/vm/vm.rs" \
+        | cut -d'"' -f2
+} | sort -u | rust_array)
+binary_layouts=$( {
+    grep -o 'value=binary_layout is="[^"]*"' "$app" | cut -d'"' -f2
+    grep -o 'layout options: [A-Za-z, ]*' "$root_dir/micro-bpf-server/src/shell/bpf_command.rs" \
+        | cut -d: -f2 | tr ',' '\n' | tr -d ' '
+} | sort -u | rust_array)
+
+project=$(mktemp -d)
+trap 'rm -rf "$project"' EXIT
+mkdir "$project/src"
+cat > "$project/Cargo.toml" <<TOML
+[package]
+name = "enum-round-trip"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+micro-bpf-common = { path = "$common_dir" }
+TOML
+cat > "$project/src/main.rs" <<RUST
+use std::{fmt::Debug, str::FromStr};
+
+use micro_bpf_common::{BinaryFileLayout, ExecutionModel, TargetVM};
+
+/// Parses every input and checks that the parsed value round-trips through
+/// its Display representation, returns the number of failures.
+fn check<T: FromStr + ToString + Debug>(name: &str, inputs: &[String]) -> usize {
+    let mut failures = 0;
+    for input in inputs {
+        let Ok(value) = T::from_str(input) else {
+            println!("{}: \"{}\" isn't accepted by from_str", name, input);
+            failures += 1;
+            continue;
+        };
+        let displayed = value.to_string();
+        let parsed = T::from_str(&displayed).map(|v| format!("{:?}", v)).ok();
+        if parsed != Some(format!("{:?}", value)) {
+            println!(
+                "{}: {:?} is displayed as \"{}\", which parses as {:?}",
+                name, value, displayed, parsed
+            );
+            failures += 1;
+        }
+    }
+    println!("{}: checked {} strings", name, inputs.len());
+    failures
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+fn main() {
+    // The server reports the execution models using their Debug names.
+    let execution_models = ExecutionModel::all()
+        .iter()
+        .flat_map(|m| [format!("{:?}", m), m.to_string()])
+        .collect::<Vec<_>>();
+    let failures = check::<TargetVM>("TargetVM", &strings(&$target_vms))
+        + check::<BinaryFileLayout>("BinaryFileLayout", &strings(&$binary_layouts))
+        + check::<ExecutionModel>("ExecutionModel", &execution_models);
+    if failures > 0 {
+        println!("{} strings don't round-trip", failures);
+        std::process::exit(1);
+    }
+}
+RUST
+
+cargo run --quiet --manifest-path "$project/Cargo.toml"
@@ -0,0 +1,33 @@
+
+# Checks that failing requests are answered with the uniform error body
+# {"error": "<message>", "code": <code>}. The failures are forced by sending
+# an invalid execution request to /short-execution and by calling a native
+# function which doesn't exist.
+
+if [[ $# -lt 2 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+base_url="coap://[$ip_address%$network_interface]"
+
+# Checks the shape of the error body and that it carries the expected code.
+check_error_body() {
+    echo "$2" | python3 -c "
+import json, sys
+body = json.load(sys.stdin)
+assert set(body) == {'error', 'code'}, body
+assert isinstance(body['error'], str) and body['error'], body
+assert body['code'] == $1, body
+" || { echo "Unexpected error body: $2" ; exit 1 ; }
+}
+
+response=$(aiocoap-client -m POST "$base_url/short-execution" --payload "not a request")
+check_error_body 400 "$response"
+
+response=$(aiocoap-client -m POST "$base_url/native/no_such_function")
+check_error_body 404 "$response"
+
+echo "The error bodies have the expected shape"
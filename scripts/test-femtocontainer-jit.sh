@@ -0,0 +1,34 @@
+# Checks that the JIT is only offered for rBPF. /capabilities needs to list
+# rBPF as the only VM supporting the JIT and a request executing a program
+# in FemtoContainer with the JIT enabled needs to be rejected when the VM is
+# constructed. The request payload is an encoded execution request (either in
+# the compact encoding used by the tools or as JSON) targeting FemtoContainer
+# with the use_jit flag set, the program needs to be deployed beforehand.
+
+if [[ $# -lt 3 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <request-payload>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+payload=$3
+base_url="coap://[$ip_address%$network_interface]"
+
+capabilities=$(aiocoap-client -m GET "$base_url/capabilities") || exit 1
+jit_targets=$(echo "$capabilities" | python3 -c "import json, sys; print(json.load(sys.stdin)['jit'])")
+if [ "$jit_targets" != "['rBPF']" ] ; then
+    echo "Expected only rBPF to support the JIT, got: $capabilities"
+    exit 1
+fi
+
+# The output endpoint constructs the VM while processing the request, so the
+# error is reported in the response.
+response=$(aiocoap-client -m POST "$base_url/short-execution/output" --payload "$payload")
+echo "$response" | python3 -c "
+import json, sys
+body = json.load(sys.stdin)
+assert body['code'] == 500, body
+assert body['error'].startswith('JIT is not supported'), body
+" || { echo "The FemtoContainer JIT request wasn't rejected: $response" ; exit 1 ; }
+echo "The FemtoContainer JIT request was rejected"
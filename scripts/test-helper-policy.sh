@@ -0,0 +1,61 @@
+# Checks that the helper policy clamps the allowed helpers of both the
+# short-lived and the long-running programs. The program needs to be
+# helper-tests/sandbox-helper-call.c (which calls bpf_ztimer_now) deployed into
+# the given slot, the request payload is an encoded execution request (either
+# in the compact encoding used by the tools or as JSON) of that program with
+# all helpers on its allowed list and the pre-flight helper access
+# verification. The program is expected to run on both endpoints without a
+# policy and to fail the verification once the policy forbids all helpers. The
+# policy is removed at the end.
+
+if [[ $# -lt 4 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <slot> <request-payload>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+slot=$3
+payload=$4
+base_url="coap://[$ip_address%$network_interface]"
+
+trap 'aiocoap-client -m DELETE "$base_url/config/helper-policy" > /dev/null' EXIT
+
+# Executes the program on a VM worker and prints the status of the execution.
+execute_long_running() {
+    aiocoap-client -m POST "$base_url/long-running" --payload "$payload" > /dev/null || exit 1
+    sleep 1
+    aiocoap-client -m GET "$base_url/result/last/$slot" \
+        | grep -o '"status": "[a-z_]*"' | cut -d '"' -f 4
+}
+
+# Executes the program inline in the CoAP handler and prints the response, it
+# contains the status of the execution (also if it failed).
+execute_short_lived() {
+    aiocoap-client -m POST "$base_url/short-execution" --payload "$payload" 2>&1
+}
+
+aiocoap-client -m DELETE "$base_url/config/helper-policy" || exit 1
+status=$(execute_long_running)
+if [[ "$status" != "ok" ]] ; then
+    echo "Expected the long-running program to run without a policy, got status: $status"
+    exit 1
+fi
+if ! execute_short_lived | grep -q '"status": "ok"' ; then
+    echo "Expected the short-lived program to run without a policy"
+    exit 1
+fi
+echo "The program ran without a policy"
+
+# An empty policy forbids all helpers.
+aiocoap-client -m POST "$base_url/config/helper-policy" --payload "" || exit 1
+status=$(execute_long_running)
+if [[ "$status" != "verification_error" ]] ; then
+    echo "Expected the long-running program to fail the verification, got status: $status"
+    exit 1
+fi
+if ! execute_short_lived | grep -q "verification_error" ; then
+    echo "Expected the short-lived program to fail the verification"
+    exit 1
+fi
+echo "The helpers of the requests were clamped to the policy on both endpoints"
@@ -0,0 +1,32 @@
+# Checks the in-place execution on the request payload. The program needs to
+# be examples/bpf/helper-tests/coap-payload-in-place.c deployed into the slot
+# referenced by the request payload, which is an encoded execution request
+# (either in the compact encoding used by the tools or as JSON). The program
+# is registered under /routed/shout and is expected to echo the payload in
+# upper case, while a response overflowing the payload region is rejected.
+
+if [[ $# -lt 3 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <request-payload>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+payload=$3
+base_url="coap://[$ip_address%$network_interface]"
+
+aiocoap-client -m POST "$base_url/routes/shout/0?in-place" --payload "$payload" || exit 1
+
+response=$(aiocoap-client -m POST "$base_url/routed/shout" --payload "hello, world")
+if [[ "$response" != "HELLO, WORLD!" ]] ; then
+    echo "Expected the modified payload to be echoed, got: $response"
+    exit 1
+fi
+echo "The modified payload was echoed"
+
+response=$(aiocoap-client -m POST "$base_url/routed/shout" --payload "!overflow")
+if ! echo "$response" | grep -q '"code": 500' ; then
+    echo "Expected the response overflowing the payload region to be rejected, got: $response"
+    exit 1
+fi
+echo "The response overflowing the payload region was rejected"
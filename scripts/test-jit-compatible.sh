@@ -0,0 +1,32 @@
+
+# Checks that /storage/list reports which slots hold programs that can be
+# JIT-compiled. It expects a RawObjectFile program to be deployed into the
+# first slot (e.g. helper-tests/basic-add.c using deploy.sh) and a program
+# using any other layout into the second one.
+
+if [[ $# -lt 4 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <compatible-slot> <incompatible-slot>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+compatible_slot=$3
+incompatible_slot=$4
+
+response=$(aiocoap-client -m GET "coap://[$ip_address%$network_interface]/storage/list") || exit 1
+echo "$response"
+
+jit_compatible() {
+    echo "$response" | python3 -c "import json, sys; sys.exit($1 not in json.load(sys.stdin)['jit_compatible'])"
+}
+
+if ! jit_compatible "$compatible_slot" ; then
+    echo "Slot $compatible_slot should be JIT-compatible"
+    exit 1
+fi
+if jit_compatible "$incompatible_slot" ; then
+    echo "Slot $incompatible_slot shouldn't be JIT-compatible"
+    exit 1
+fi
+echo "JIT compatibility reported correctly"
@@ -0,0 +1,26 @@
+
+# Checks that the JIT rejects a program calling a helper which isn't on its
+# allowed list before compiling it. The request payload is an encoded
+# execution request (either in the compact encoding used by the tools or as
+# JSON) of helper-tests/jit-unmapped-helper.c deployed as a RawObjectFile.
+# It needs to target the JIT with recompilation enabled and must not allow
+# bpf_ztimer_now.
+
+if [[ $# -lt 3 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <request-payload>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+payload=$3
+
+response=$(aiocoap-client -m POST "coap://[$ip_address%$network_interface]/short-execution" \
+    --payload "$payload") || exit 1
+code=$(echo "$response" | python3 -c "import json, sys; print(json.load(sys.stdin).get('code'))")
+
+if [ "$code" != "500" ] ; then
+    echo "The program calling an unmapped helper wasn't rejected: $response"
+    exit 1
+fi
+echo "The JIT rejected the program calling an unmapped helper"
@@ -0,0 +1,52 @@
+# Checks that the last execution result of a slot is updated after the program
+# in it is executed. The request payload is an encoded execution request (either
+# in the compact encoding used by the tools or as JSON) of a program that is
+# already deployed into the given slot. The program should be deterministic and
+# its result must not be served from the result cache, as cached results don't
+# update the last result.
+
+if [[ $# -lt 4 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <slot> <request-payload>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+slot=$3
+payload=$4
+base_url="coap://[$ip_address%$network_interface]"
+# Upper bound on the time between the execution and reading its result.
+max_age_ms=2000
+
+last_result() {
+    aiocoap-client -m GET "$base_url/result/last/$slot"
+}
+
+before=$(last_result) || exit 1
+echo "Last result before the execution: $before"
+
+execution=$(aiocoap-client -m POST "$base_url/short-execution" --payload "$payload") || exit 1
+echo "Execution response: $execution"
+
+after=$(last_result) || exit 1
+echo "Last result after the execution: $after"
+
+if echo "$after" | grep -q "never_executed" ; then
+    echo "The last result of slot $slot wasn't updated"
+    exit 1
+fi
+
+# The status and the value reported by both endpoints need to match.
+expected=$(echo "$execution" | grep -o '"status": "[a-z_]*", "value": [0-9]*')
+if [ -z "$expected" ] || ! echo "$after" | grep -qF "$expected" ; then
+    echo "The last result doesn't match the execution response"
+    exit 1
+fi
+
+# The result could be the same as before, the age shows that it was replaced.
+age_ms=$(echo "$after" | grep -o '"age_ms": [0-9]*' | grep -o '[0-9]*$')
+if [ -z "$age_ms" ] || (( age_ms > max_age_ms )) ; then
+    echo "The last result of slot $slot is older than the execution: ${age_ms} ms"
+    exit 1
+fi
+echo "The last result of slot $slot was updated ${age_ms} ms ago"
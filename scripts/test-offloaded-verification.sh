@@ -0,0 +1,47 @@
+# Checks that the verification of inline programs still decides whether they
+# are executed. It is intended for firmware built with OFFLOAD_VERIFICATION=1,
+# but passes with the inline verification as well. The request is an encoded
+# execution request (in the compact encoding used by the tools) for the rBPF
+# interpreter with the OnlyTextSection layout and an empty list of allowed
+# helpers (specified in the request). The valid program is the text section
+# of a program without helper calls (e.g. helper-tests/basic-add.c), the
+# invalid one is derived from it by replacing its first instruction with an
+# unknown opcode.
+
+if [[ $# -lt 4 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <encoded-request> <text-section-file>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+request=$3
+program=$4
+base_url="coap://[$ip_address%$network_interface]"
+
+workdir=$(mktemp -d)
+trap 'rm -rf "$workdir"' EXIT
+
+# The payload is the request followed by a NUL byte and the program.
+make_payload() {
+    { printf '%s\0' "$request" ; cat "$1" ; } > "$2"
+}
+
+make_payload "$program" "$workdir/valid"
+{ printf '\xff\0\0\0\0\0\0\0' ; tail -c +9 "$program" ; } > "$workdir/invalid-program"
+make_payload "$workdir/invalid-program" "$workdir/invalid"
+
+response=$(aiocoap-client -m POST "$base_url/run" --payload "@$workdir/valid")
+echo "Valid program: $response"
+if ! echo "$response" | grep -q '"status": "ok"' ; then
+    echo "The valid program wasn't executed"
+    exit 1
+fi
+
+response=$(aiocoap-client -m POST "$base_url/run" --payload "@$workdir/invalid")
+echo "Invalid program: $response"
+if ! echo "$response" | grep -q '"status": "verification_error"' ; then
+    echo "The invalid program wasn't rejected by the verifier"
+    exit 1
+fi
+echo "The verification result gated the execution of both programs"
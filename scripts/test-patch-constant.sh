@@ -0,0 +1,66 @@
+# Checks that patching a constant of a deployed program changes the value it
+# returns. The program needs to be examples/bpf/helper-tests/patch-constant.c
+# deployed into the given slot as a raw object file, its compiled object file
+# is used to find the offset of the constant. The request payload is an
+# encoded execution request (either in the compact encoding used by the tools
+# or as JSON) of the program using the interpreter (or the JIT with the
+# jit_compile flag set). The result cache needs to be disabled.
+
+if [[ $# -lt 5 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <slot> <object-file> <request-payload>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+slot=$3
+object_file=$4
+payload=$5
+base_url="coap://[$ip_address%$network_interface]"
+
+initial_value=$((0x1234abcd))
+patched_value=42
+
+# Offset of the little-endian encoding of the initial value in the object file.
+offset=$(python3 -c "
+import sys
+data = open(sys.argv[1], 'rb').read()
+marker = ($initial_value).to_bytes(4, 'little')
+if data.count(marker) != 1:
+    sys.exit('The constant needs to occur exactly once in the object file')
+print(data.index(marker))
+" "$object_file") || exit 1
+echo "The constant is at offset $offset"
+
+execute() {
+    aiocoap-client -m POST "$base_url/short-execution" --payload "$payload" \
+        | grep -o '"value": [0-9]*' | grep -o '[0-9]*$'
+}
+
+value=$(execute)
+if [[ "$value" != "$initial_value" ]] ; then
+    echo "Expected the program to return $initial_value before patching, got: $value"
+    exit 1
+fi
+
+patch_file=$(mktemp)
+trap 'rm -f "$patch_file"' EXIT
+python3 -c "import sys; sys.stdout.buffer.write(($patched_value).to_bytes(4, 'little'))" \
+    > "$patch_file"
+aiocoap-client -m POST "$base_url/storage/patch/$slot/$offset" --payload "@$patch_file" \
+    || exit 1
+
+value=$(execute)
+if [[ "$value" != "$patched_value" ]] ; then
+    echo "Expected the program to return $patched_value after patching, got: $value"
+    exit 1
+fi
+echo "The patched constant is returned by the program"
+
+# Patches overlapping the ELF header need to be rejected.
+if aiocoap-client -m POST "$base_url/storage/patch/$slot/0" --payload "@$patch_file" \
+    | grep -q '"offset"' ; then
+    echo "A patch overwriting the ELF header was accepted"
+    exit 1
+fi
+echo "A patch overwriting the ELF header was rejected"
@@ -0,0 +1,39 @@
+
+# Checks that executing a pure program twice with the same arguments only
+# executes it once when the firmware is built with RESULT_CACHE=1. The program
+# in the given slot can only be allowed to call read-only helpers (e.g.
+# helper-tests/basic-add.c). Each cache miss is followed by an execution, so
+# the second request needs to be reported as a hit without another miss.
+
+if [[ $# -lt 3 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <suit-storage-slot>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+slot=$3
+script_dir=$(dirname "$0")
+
+cache_stats() {
+    aiocoap-client -m GET "coap://[$ip_address%$network_interface]/diagnostics/result-cache" \
+        | python3 -c "import json, sys; s = json.load(sys.stdin); print(s['hits'], s['misses'])"
+}
+
+execute() {
+    $script_dir/../tools/target/release/mibpf-tools execute --riot-ipv6-addr "$ip_address" \
+        --suit-storage-slot "$slot" --host-network-interface "$network_interface" \
+        --target rBPF > /dev/null || exit 1
+}
+
+execute
+read hits_before misses_before <<< "$(cache_stats)"
+execute
+read hits_after misses_after <<< "$(cache_stats)"
+
+if (( hits_after != hits_before + 1 || misses_after != misses_before )) ; then
+    echo "The second execution wasn't served from the cache:" \
+        "hits $hits_before -> $hits_after, misses $misses_before -> $misses_after"
+    exit 1
+fi
+echo "The second execution was served from the cache"
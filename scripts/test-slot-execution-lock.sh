@@ -0,0 +1,64 @@
+# Checks that the executions of the same slot don't interleave. A long-running
+# execution of helper-tests/slot-lock.c is started and a short-lived execution
+# of the same slot is requested while it is running, which needs to be
+# rejected right away with 5.03 Service Unavailable. The start and end lines
+# that the program prints into the execution log must then alternate. The
+# request payload is an encoded execution request (either in the compact
+# encoding used by the tools or as JSON) of the deployed program.
+
+if [[ $# -lt 3 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <request-payload>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+payload=$3
+base_url="coap://[$ip_address%$network_interface]"
+
+# Prints all lines of the execution log starting at the given sequence number.
+read_logs() {
+    sequence=$1
+    while true ; do
+        lines=$(aiocoap-client -m GET "$base_url/logs/$sequence") || exit 1
+        [ -z "$lines" ] && break
+        echo "$lines"
+        sequence=$(( $(echo "$lines" | tail -n 1 | cut -d ' ' -f 1) + 1 ))
+    done
+}
+
+last_line=$(read_logs 0 | tail -n 1)
+start_sequence=0
+if [ -n "$last_line" ] ; then
+    start_sequence=$(( $(echo "$last_line" | cut -d ' ' -f 1) + 1 ))
+fi
+
+aiocoap-client -m POST "$base_url/long-running" --payload "$payload" || exit 1
+sleep 0.5
+
+start=$(date +%s%N)
+output=$(aiocoap-client -m POST "$base_url/short-execution" --payload "$payload" 2>&1)
+elapsed_ms=$(( ($(date +%s%N) - start) / 1000000 ))
+if ! echo "$output" | grep -q "5.03\|Service Unavailable" ; then
+    echo "Expected the execution of a busy slot to be rejected, got: $output"
+    exit 1
+fi
+# The program sleeps for 2 seconds, an execution waiting for it to finish
+# would take at least that long.
+if (( elapsed_ms >= 1000 )) ; then
+    echo "Rejecting the execution took $elapsed_ms [ms], the request waited for the slot"
+    exit 1
+fi
+echo "The execution of the busy slot was rejected after $elapsed_ms [ms]"
+
+sleep 3
+aiocoap-client -m POST "$base_url/short-execution" --payload "$payload" > /dev/null || exit 1
+
+events=$(read_logs "$start_sequence" | grep -o "slot-lock \(start\|end\)" | cut -d ' ' -f 2)
+expected=$(printf "start\nend\nstart\nend")
+if [[ "$events" != "$expected" ]] ; then
+    echo "The executions interleaved, the program printed:"
+    echo "$events"
+    exit 1
+fi
+echo "The executions of the slot didn't interleave"
@@ -0,0 +1,34 @@
+
+# Checks that the strict sandbox rejects programs calling any helper. The
+# firmware needs to be built with STRICT_SANDBOX=1, the first slot needs to
+# hold a program calling a helper (e.g. helper-tests/sandbox-helper-call.c)
+# and the second one a program without helper calls (e.g.
+# helper-tests/basic-add.c). Both programs are executed with all helpers on
+# their allowed list, only the second one is expected to run.
+
+if [[ $# -lt 4 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <helper-call-slot> <no-helpers-slot>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+helper_call_slot=$3
+no_helpers_slot=$4
+script_dir=$(dirname "$0")
+
+execute() {
+    $script_dir/../tools/target/release/mibpf-tools execute --riot-ipv6-addr "$ip_address" \
+        --suit-storage-slot "$1" --host-network-interface "$network_interface" \
+        --target rBPF
+}
+
+if execute "$helper_call_slot" ; then
+    echo "The program in slot $helper_call_slot calls a helper and shouldn't have been executed"
+    exit 1
+fi
+if ! execute "$no_helpers_slot" ; then
+    echo "The program in slot $no_helpers_slot doesn't call any helpers and should have been executed"
+    exit 1
+fi
+echo "The strict sandbox rejected the program calling a helper"
@@ -0,0 +1,49 @@
+
+# Checks that the supervisor restarts a crashed long-running program the
+# configured number of times and then gives up. The firmware needs to be built
+# with SUPERVISOR_MAX_RESTARTS=<max-restarts> and helper-tests/crash-restart.c
+# needs to be deployed, the request payload is an encoded execution request
+# (either in the compact encoding used by the tools or as JSON) of that
+# program. The starts are counted using the lines that the program prints into
+# the execution log.
+
+if [[ $# -lt 4 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <request-payload> <max-restarts>"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+payload=$3
+max_restarts=$4
+base_url="coap://[$ip_address%$network_interface]"
+
+# Prints all lines of the execution log starting at the given sequence number.
+read_logs() {
+    sequence=$1
+    while true ; do
+        lines=$(aiocoap-client -m GET "$base_url/logs/$sequence") || exit 1
+        [ -z "$lines" ] && break
+        echo "$lines"
+        sequence=$(( $(echo "$lines" | tail -n 1 | cut -d ' ' -f 1) + 1 ))
+    done
+}
+
+last_line=$(read_logs 0 | tail -n 1)
+start_sequence=0
+if [ -n "$last_line" ] ; then
+    start_sequence=$(( $(echo "$last_line" | cut -d ' ' -f 1) + 1 ))
+fi
+
+aiocoap-client -m POST "$base_url/long-running" --payload "$payload" || exit 1
+
+# The backoff starts at 100 [ms] and doubles with every restart.
+sleep $(( (1 << max_restarts) / 10 + 2 ))
+
+starts=$(read_logs "$start_sequence" | grep -c "crash-restart start")
+expected=$((max_restarts + 1))
+if (( starts != expected )) ; then
+    echo "The program was started $starts times, expected $expected"
+    exit 1
+fi
+echo "The crashed program was restarted $max_restarts times"
@@ -0,0 +1,54 @@
+# Checks the WCET estimate of a deployed program. For the programs that are
+# expected to be bounded (e.g. examples/bpf/helper-tests/wcet-counted-loop.c),
+# the program is executed and its measured execution time needs to be within
+# the estimate. For the ones that are expected to be unbounded (e.g.
+# wcet-unbounded.c), the estimate needs to report it. The request payload is an
+# encoded execution request (either in the compact encoding used by the tools
+# or as JSON) of the program deployed in the given slot using the interpreter.
+# The result cache needs to be disabled.
+
+if [[ $# -lt 5 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <slot> <request-payload> bounded|unbounded"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+slot=$3
+payload=$4
+expected=$5
+base_url="coap://[$ip_address%$network_interface]"
+
+estimate=$(aiocoap-client -m POST "$base_url/storage/wcet/$slot" --payload "$payload") \
+    || exit 1
+echo "Estimate: $estimate"
+
+if [[ "$expected" == "unbounded" ]] ; then
+    if ! echo "$estimate" | grep -q '"wcet_us": "unbounded"' ; then
+        echo "Expected the program to be reported as unbounded"
+        exit 1
+    fi
+    echo "The program was reported as unbounded"
+    exit 0
+fi
+
+wcet_us=$(echo "$estimate" | grep -o '"wcet_us": [0-9]*' | grep -o '[0-9]*$')
+if [[ -z "$wcet_us" ]] ; then
+    echo "Expected the program to be bounded"
+    exit 1
+fi
+
+aiocoap-client -m POST "$base_url/short-execution" --payload "$payload" || exit 1
+measured_us=$(aiocoap-client -m GET "$base_url/result/last/$slot" \
+    | grep -o '"execution_time": [0-9]*' | grep -o '[0-9]*$')
+if [[ -z "$measured_us" ]] ; then
+    echo "The execution time of the program wasn't recorded"
+    exit 1
+fi
+
+echo "Measured $measured_us us, estimated at most $wcet_us us"
+if (( measured_us > wcet_us )) ; then
+    echo "The measured execution time exceeds the estimate, the costs need to be calibrated"
+    exit 1
+fi
+echo "The measured execution time is within the estimate"
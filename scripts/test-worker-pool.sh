@@ -0,0 +1,44 @@
+# Checks that the workers are returned to the pool once their programs finish.
+# More long-running executions than there are workers are requested at once,
+# the ones that don't get a worker are rejected. Once the programs finish, all
+# workers need to be reported as free by /running_vm and a new execution needs
+# to be dispatched again. The request payload is an encoded execution request
+# (either in the compact encoding used by the tools or as JSON) of a deployed
+# program which finishes within a few seconds (e.g. helper-tests/slot-lock.c
+# for a long-lived execution, which needs to be deployed into the requested
+# slot).
+
+if [[ $# -lt 3 ]] ; then
+    echo "Usage: $0 <network-interface> <board-ip-address> <request-payload> [wait-seconds]"
+    exit 1
+fi
+
+network_interface=$1
+ip_address=$2
+payload=$3
+wait_seconds=${4:-5}
+base_url="coap://[$ip_address%$network_interface]"
+
+running_vms() {
+    aiocoap-client -m GET "$base_url/running_vm" || exit 1
+}
+
+workers=$(running_vms | python3 -c "import json, sys; print(len(json.load(sys.stdin)))")
+for _ in $(seq $(( workers + 1 ))) ; do
+    aiocoap-client -m POST "$base_url/long-running" --payload "$payload" > /dev/null &
+done
+wait
+
+sleep "$wait_seconds"
+if running_vms | python3 -c "import json, sys; sys.exit(not any(json.load(sys.stdin)))" ; then
+    echo "Workers weren't released after their programs finished: $(running_vms)"
+    exit 1
+fi
+
+aiocoap-client -m POST "$base_url/long-running" --payload "$payload" > /dev/null || exit 1
+sleep 0.5
+if ! running_vms | python3 -c "import json, sys; sys.exit(not any(json.load(sys.stdin)))" ; then
+    echo "The execution requested after the workers were released wasn't dispatched"
+    exit 1
+fi
+echo "All $workers workers were released and dispatched again"